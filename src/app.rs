@@ -1,39 +1,303 @@
 use crate::barrier::Barrier;
-use crate::channel::{AppMsg, CanvasMsg, AppReceiver, CanvasSender};
+use crate::camp::suggest_camps;
+use crate::channel::{AppMsg, CanvasMsg, AppReceiver, AppSender, Viewport,
+                     CanvasSender, MarkerKind};
 use crate::config::CONFIG;
-use crate::params::Params;
+use crate::control;
+use crate::cost::Cost;
+use crate::crs::crs_name;
+use crate::debug::DebugLog;
+use crate::device::device_file;
+use crate::download::DownloadSource;
+use crate::elevation::ElevationSource;
+use crate::escape::{Escape, find_escapes, long_stretches};
+use crate::geotiff::GeoTiffSource;
+use crate::gps::Gps;
+use crate::export::{export_path, format_from_fname};
+use crate::mapdirs::MapDirs;
+use crate::mapsheet::{DEFAULT_SCALE, write_mapsheets};
+use crate::params::{Hiker, Params};
 use crate::path::Path;
-use crate::path::Segment;
+use crate::poi::{Poi, PoiDb, PoiKind};
+use crate::progress::Progress;
+use crate::report::write_report;
+use crate::routecard::write_routecard;
+use crate::schedule::{Schedule, format_clock, parse_target};
+use crate::starts::evaluate_starts;
+use crate::upload::{store_token, upload_gpx};
 
 use cmdui::{CmdApp, CmdUI, CommandPart, KeywordExpander};
-use crossbeam_channel::{RecvTimeoutError, unbounded};
-use hoydedata::{Atlas, Coord, MsgReceiver, MsgSender};
+use crossbeam_channel::{RecvTimeoutError, Sender, unbounded};
+use hoydedata::{Coord, MsgReceiver, MsgSender};
+use parking_lot::Mutex;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::fs;
 use std::ops::Range;
 use std::str::FromStr;
-use std::time::Duration;
+use std::cell::Cell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
-const COMMAND_LIST: &'static [&'static str] = &[
+pub const COMMAND_LIST: &'static [&'static str] = &[
     "add point <coord> <pos>",
     "rm point <coord> <pos>",
     "update point [<coord>|map] <pos>",
+    "snap point <pos>",
+    "goto <name> <pos>",
     "add barrier <coord1> <coord2> ...",
     "rm barrier <pos>",
+    "add gate <coord1> <coord2> ...",
+    "rm gate <pos>",
+    "add corridor <coord1> <coord2> ...",
+    "rm corridor",
+    "add ford <coord>",
+    "rm ford <pos>",
+    "add crossings",
+    "pin point <coord>",
+    "unpin point <pos>",
+    "pin area <coord1> <coord2> ...",
+    "unpin area <pos>",
+    "add exit <coord>",
+    "rm exit <pos>",
+    "add hiker <name> <fitness> <pack_kg>",
+    "rm hiker <pos>",
     "read params <filename>",
+    "share",
+    "open share <string>",
     "store params <filename>",
     "show params",
+    "diff params <filename>",
     "show cost",
     "show track info",
+    "show track stats",
+    "show maps",
+    "show gps",
+    "show pois <bool>",
     "set <param> <value>",
     "open track <filename>",
     "store track <filename>",
+    "store report <filename>",
+    "store routecard <filename>",
+    "store mapsheet <filename> <scale>",
+    "upload token <service> <token>",
+    "upload <service>",
+    "send device <mountpoint>",
+    "compare track <filename>",
+    "compare current",
+    "compare off",
+    "store searcharea <filename>",
+    "store graph-debug <prefix>",
     "compute",
+    "compute view",
+    "compute leg <pos>",
+    "compute escapes <interval_km>",
+    "evaluate starts <filename>",
+    "reroute from-here",
+    "optimize <iterations>",
+    "status",
+    "cancel",
+    "undo",
+    "suggest camps",
+    "debug graph <bool>",
+    "debug explored <bool>",
+    "debug heatmap <bool>",
+    "debug keep-graph <bool>",
     "flush maps",
     "help",
 ];
 
-pub fn run_cmdui(app: &mut App) {
+// Time to wait for the user to answer a request in the map window
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+const REQUEST_POLL: Duration = Duration::from_millis(100);
+// How often the message dispatcher checks for finished computations
+const COMPUTE_POLL: Duration = Duration::from_millis(500);
+// How often the progress is checked when writing it as JSON lines
+const JSON_PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+// Set while waiting for an answer from the map window, and when Ctrl+C has
+// been pressed during the wait.
+static WAITING_FOR_MAP: AtomicBool = AtomicBool::new(false);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+pub fn run_cmdui(app: App) {
+    let mut shared = SharedApp::new(app);
+    shared.start_control();
+
+    let kw_exp = StiKeywordExpander::new();
+    CmdUI::new(&mut shared, Some(&kw_exp)).read_commands();
+}
+
+// Run the command line interface together with a map window. Messages from
+// the map window are read by a separate thread. Replies to requests made by
+// the app (selected points and barriers) are forwarded on the reply channel,
+// other messages are queued and handled as soon as the app is not busy
+// executing a command, at the latest within COMPUTE_POLL.
+pub fn run_cmdui_with_canvas(app: App, rx: AppReceiver, reply_tx: AppSender) {
+    let mut shared = SharedApp::new(app);
+
+    // Ctrl+C cancels a request to the map window. Otherwise it quits as
+    // usual.
+    let _ = ctrlc::set_handler(|| {
+        if WAITING_FOR_MAP.load(Ordering::SeqCst) {
+            INTERRUPTED.store(true, Ordering::SeqCst);
+        }
+        else {
+            std::process::exit(130);
+        }
+    });
+
+    let app_cloned = shared.app.clone();
+    let pending_cloned = shared.pending.clone();
+    std::thread::spawn(move || dispatch_app_messages(
+        rx, reply_tx, None, app_cloned, pending_cloned));
+
+    shared.start_control();
+
     let kw_exp = StiKeywordExpander::new();
-    CmdUI::new(app, Some(&kw_exp)).read_commands();
+    CmdUI::new(&mut shared, Some(&kw_exp)).read_commands();
+}
+
+// Run the app with commands typed in the console of the map window. The
+// commands are executed in this thread as they arrive from the message
+// dispatcher, which ends when the map window is closed.
+pub fn run_gui(app: App, rx: AppReceiver, reply_tx: AppSender) {
+    let mut shared = SharedApp::new(app);
+
+    let (console_tx, console_rx) = unbounded();
+    let app_cloned = shared.app.clone();
+    let pending_cloned = shared.pending.clone();
+    std::thread::spawn(move || dispatch_app_messages(
+        rx, reply_tx, Some(console_tx), app_cloned, pending_cloned));
+
+    shared.start_control();
+    println!("Type help for a list of commands");
+
+    for line in console_rx {
+        println!("> {}", line);
+
+        let res = App::split_command(&line)
+            .and_then(|(cmd, args)| shared.execute_line(&cmd, &args));
+
+        if let Err(e) = res {
+            println!("{}", e);
+        }
+    }
+
+    shared.exit();
+}
+
+// Thread for reading messages from the map window
+fn dispatch_app_messages(rx: AppReceiver, reply_tx: AppSender,
+                         console_tx: Option<Sender<String>>,
+                         app: Arc<Mutex<App>>,
+                         pending: Arc<Mutex<VecDeque<AppMsg>>>) {
+    loop {
+        let msg = match rx.recv_timeout(COMPUTE_POLL) {
+            Ok(msg) => msg,
+            Err(RecvTimeoutError::Timeout) => {
+                // Handle messages queued while the app was busy, which
+                // arrived too late for the command to handle them, and
+                // report a computation finished in the background
+                if let Some(mut app) = app.try_lock() {
+                    SharedApp::handle_pending(&mut app, &pending);
+                    app.poll_compute();
+                }
+                continue;
+            },
+            Err(RecvTimeoutError::Disconnected) => {
+                break;
+            },
+        };
+
+        match msg {
+            AppMsg::SelectPoint(..) | AppMsg::CreateBarrier(..)
+                | AppMsg::RequestCancelled(_) => {
+                let _ = reply_tx.send(msg);
+            },
+            // Console commands are executed by the controller thread, so
+            // that they can wait for answers from the map window
+            AppMsg::ConsoleLine(line) => {
+                if let Some(tx) = &console_tx {
+                    let _ = tx.send(line);
+                }
+            },
+            AppMsg::Quit => {
+                break;
+            },
+            msg => {
+                pending.lock().push_back(msg);
+
+                if let Some(mut app) = app.try_lock() {
+                    SharedApp::handle_pending(&mut app, &pending);
+                }
+            },
+        }
+    }
+}
+
+// App shared between the command line interface, the message dispatcher
+// and clients of the control socket
+#[derive(Clone)]
+struct SharedApp {
+    app: Arc<Mutex<App>>,
+    pending: Arc<Mutex<VecDeque<AppMsg>>>,
+}
+
+impl SharedApp {
+    fn new(app: App) -> Self {
+        Self {
+            app: Arc::new(Mutex::new(app)),
+            pending: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    // Accept commands on the control socket, if one is given
+    fn start_control(&self) {
+        if CONFIG.control_socket == "" {
+            return;
+        }
+
+        let shared = self.clone();
+        let res = control::serve(&CONFIG.control_socket, move |line| {
+            App::split_command(line).and_then(
+                |(cmd, args)| shared.clone().execute_line(&cmd, &args))
+        });
+
+        if let Err(e) = res {
+            println!("{}", e);
+        }
+    }
+
+    fn handle_pending(app: &mut App, pending: &Mutex<VecDeque<AppMsg>>) {
+        loop {
+            // Don't hold the queue lock while handling the message
+            let opt_msg = pending.lock().pop_front();
+            let Some(msg) = opt_msg else { break; };
+            app.handle_app_msg(msg);
+        }
+    }
+}
+
+impl CmdApp for SharedApp {
+    fn command_list<'a>(&self) -> &'a [&'a str] {
+        return COMMAND_LIST;
+    }
+
+    fn execute_line(&mut self, cmd: &str, args: &Vec<String>)
+                    -> Result<(), String> {
+        let mut app = self.app.lock();
+        let res = app.execute_line(cmd, args);
+        SharedApp::handle_pending(&mut app, &self.pending);
+
+        return res;
+    }
+
+    fn exit(&mut self) {
+        self.app.lock().exit();
+    }
 }
 
 // Thread for outputting hoydedata messages
@@ -63,15 +327,59 @@ impl StiKeywordExpander {
     fn expand_param(&self) -> Vec<String> {
         return vec![
             "covering_length".to_string(),
+            "access_distance".to_string(),
+            "aspect_multipliers".to_string(),
+            "ascent_threshold".to_string(),
+            "barrier".to_string(),
+            "body_mass".to_string(),
+            "camp_corridor".to_string(),
+            "camp_max_slope".to_string(),
+            "camp_water_distance".to_string(),
+            "connectivity".to_string(),
+            "cost_curve".to_string(),
+            "cost_model".to_string(),
+            "cost_script".to_string(),
             "covering_width".to_string(),
+            "cross_slope_weight".to_string(),
+            "darkness_factor".to_string(),
+            "deadline".to_string(),
+            "declination".to_string(),
+            "deviation_alarm".to_string(),
+            "escape_max_time".to_string(),
+            "export_km_markers".to_string(),
+            "fluid_rate".to_string(),
+            "ford_radius".to_string(),
+            "grid_orientation".to_string(),
             "grid_size_pass1".to_string(),
             "grid_size_pass2".to_string(),
+            "grid_topology".to_string(),
+            "impassable_slope".to_string(),
+            "km_markers".to_string(),
+            "landmarks".to_string(),
+            "max_memory_mb".to_string(),
+            "north".to_string(),
+            "optimize_convergence".to_string(),
+            "optimize_join".to_string(),
+            "optimize_method".to_string(),
+            "optimize_probe".to_string(),
+            "optimize_range".to_string(),
+            "optimize_split".to_string(),
+            "passes".to_string(),
             "path_width_pass2".to_string(),
+            "point".to_string(),
+            "return_distance".to_string(),
+            "return_penalty".to_string(),
+            "roughness_weight".to_string(),
+            "season".to_string(),
+            "start_time".to_string(),
+            "symbology".to_string(),
+            "symbols".to_string(),
+            "utc_offset".to_string(),
         ];
     }
 
     fn expand_coord(&self) -> Vec<String> {
-        return vec!["from-map".to_string()];
+        return vec!["from-map".to_string(), "map-center".to_string()];
     }
 }
 
@@ -96,12 +404,70 @@ impl KeywordExpander for StiKeywordExpander {
 // 'neighbourhood' distance to objects when selecting them on map
 const NEARBY: f32 = 20.0;
 
+// Commands changing the params, which can be undone
+const UNDOABLE: &'static [&'static str] = &[
+    "add point", "rm point", "update point", "snap point", "goto",
+    "add barrier", "rm barrier", "add gate", "rm gate", "add corridor",
+    "rm corridor", "add ford", "rm ford", "add crossings", "pin point",
+    "unpin point", "pin area", "unpin area", "add exit", "rm exit",
+    "add hiker", "rm hiker", "read params", "open share",
+    "reroute from-here", "set",
+];
+// Largest map view (meters across) whose elevation data is prefetched
+const PREFETCH_EXTENT: f32 = 10000.0;
+// Number of changes which can be undone
+const MAX_UNDO: usize = 50;
+// Walking distance (meters) equal in effort to one meter of ascent
+// (Naismith's rule)
+const CLIMB_DISTANCE: f32 = 8.0;
+
+// Result of a computation in a worker thread
+enum JobResult {
+    // The path, if it can be walked, and the debug log of the computation
+    Path(Option<Path>, DebugLog),
+    // Fastest ways to an exit along the track
+    Escapes(Vec<Escape>),
+}
+
+// Computation running in a worker thread
+struct ComputeJob {
+    points: Vec<Coord>,
+    started: Instant,
+    progress: Arc<Progress>,
+    handle: JoinHandle<JobResult>,
+}
+
 pub struct App {
-    atlas: Atlas,
+    // Elevation data. The hoydedata atlas unless another backend is used.
+    atlas: Arc<dyn ElevationSource>,
+    // The map directories, when the hoydedata atlas is used
+    map_dirs: Option<Arc<MapDirs>>,
+    // GPS receiver, if one is configured
+    gps: Option<Arc<Gps>>,
     opt_path: Option<Path>,
     path_stored: bool,
     params: Params,
     params_stored: bool,
+    pois: PoiDb,
+    // Road ends and trailheads where a route can start or end
+    roads: PoiDb,
+    // Debug information from the last computation
+    debug: DebugLog,
+    // Show graph nodes and explored nodes while computing
+    debug_graph: bool,
+    debug_explored: bool,
+    // Show the time to reach the area of the first pass as a heatmap
+    debug_heatmap: bool,
+    // Keep the last graph searched for store graph-debug
+    debug_keep_graph: bool,
+    // Computation running in the background, if any
+    job: Option<ComputeJob>,
+    // Area shown in the map window
+    viewport: Option<Viewport>,
+    // Id of the last request to the map window
+    last_request: Cell<u64>,
+    // Params as they were before each undoable change, latest last
+    undo_stack: Vec<Params>,
     opt_tx: Option<CanvasSender>,
     opt_rx: Option<AppReceiver>,
 }
@@ -113,52 +479,249 @@ impl App {
 
         std::thread::spawn(move || hoydedata_output(mrx));
 
-        let params = if CONFIG.params_fname == "" {
+        let mut params = if CONFIG.params_fname == "" {
             Params::from_config()
         }
         else {
             Params::from_file(&CONFIG.params_fname)?
         };
 
+        // A missing poi database is only an error if it was asked for
+        // explicitly.
+        let pois = if CONFIG.pois == "" {
+            PoiDb::from_file(&CONFIG.poi_fname()).unwrap_or(PoiDb::new())
+        }
+        else {
+            PoiDb::from_file(&CONFIG.pois)?
+        };
+
+        let roads = if CONFIG.roads == "" {
+            PoiDb::new()
+        }
+        else {
+            PoiDb::from_file(&CONFIG.roads)?
+        };
+
+        // Params given on the command line override the params file
+        for (key, value) in CONFIG.overrides()? {
+            params.set(&key, &value)
+                .map_err(|e| format!("--set {}={}: {}", key, value, e))?;
+        }
+
+        // Points given on the command line replace those of the params
+        if CONFIG.points != "" {
+            params.clear_points();
+
+            for (n, (c, name)) in App::parse_points(&CONFIG.points, &pois)?
+                .into_iter().enumerate() {
+                params.push_point(c);
+                if name != "" {
+                    params.set_point(n, "name", &name)?;
+                }
+            }
+        }
+
         // Send initial viewpoint data to the map window (this should be done
         // before creating the Atlas because the latter takes some time).
         if let Some(tx) = &opt_tx {
             let _ = tx.send(CanvasMsg::SetCoveringArea(
                 params.covering_length, params.covering_width));
             let _ = tx.send(CanvasMsg::SetWaypoints(
-                params.points.clone()));
+                params.points.clone(), params.point_names(),
+                params.point_types()));
             let _ = tx.send(CanvasMsg::SetBarriers(
                 params.barriers.clone()));
+            let _ = tx.send(CanvasMsg::SetCorridor(
+                params.corridor.clone()));
+            let _ = tx.send(CanvasMsg::SetGates(params.gates.clone()));
+            let _ = tx.send(CanvasMsg::SetPois(pois.pois().clone()));
+            let _ = tx.send(CanvasMsg::SetMarkers(
+                MarkerKind::Ford, App::ford_markers(&params)));
+            let _ = tx.send(CanvasMsg::SetMarkers(
+                MarkerKind::Pin, App::pin_markers(&params)));
+            let _ = tx.send(CanvasMsg::SetPinAreas(params.pin_areas.clone()));
+            let _ = tx.send(CanvasMsg::SetNorth(params.north_declination(),
+                                                params.north != "true"));
+            let _ = tx.send(CanvasMsg::SetSymbology(params.symbology()));
+            let _ = tx.send(CanvasMsg::SetMarkers(
+                MarkerKind::Exit, App::exit_markers(&params)));
+            let _ = tx.send(CanvasMsg::SetCostCurve(
+                App::cost_samples(&params), App::cost_controls(&params)));
             let _ = tx.send(CanvasMsg::ResetView);
         }
 
+        let mut map_dirs = None;
+        let atlas: Arc<dyn ElevationSource> = if CONFIG.geotiff != "" {
+            Arc::new(GeoTiffSource::open_dir(&CONFIG.geotiff)?)
+        }
+        else if CONFIG.download != "" {
+            // Downloaded tiles are cached in the map directory
+            let cache_dir = format!("{}download", CONFIG.map_dir());
+            Arc::new(DownloadSource::new(&CONFIG.download, &cache_dir)?)
+        }
+        else {
+            let dirs = Arc::new(MapDirs::open(&CONFIG.map_dirs(), mtx)?);
+            map_dirs = Some(dirs.clone());
+            dirs
+        };
+
+        let gps = (CONFIG.gps != "")
+            .then(|| Gps::start(&CONFIG.gps, opt_tx.clone()));
+
         Ok(Self {
-            atlas: Atlas::new(1.0, Some(mtx)).unwrap(),
+            atlas: atlas,
+            map_dirs: map_dirs,
+            gps: gps,
             opt_path: None,
             path_stored: false,
             params: params,
             params_stored: true,
+            pois: pois,
+            roads: roads,
+            debug: DebugLog::new(),
+            debug_graph: false,
+            debug_explored: false,
+            debug_heatmap: false,
+            debug_keep_graph: false,
+            job: None,
+            viewport: None,
+            last_request: Cell::new(0),
+            undo_stack: vec![],
             opt_tx: opt_tx,
             opt_rx: opt_rx,
         })
     }
 
+    // Compute the path and wait for it
     pub fn compute(&mut self) -> Result<(), String> {
+        self.start_compute()?;
+
+        let job = self.job.take().unwrap();
+
+        if CONFIG.json {
+            App::stream_progress(&job);
+        }
+
+        self.finish_compute(job)
+    }
+
+    // Write progress events as JSON lines to stderr until a computation
+    // finishes. An event is written when the progress changes.
+    fn stream_progress(job: &ComputeJob) {
+        let mut last = None;
+
+        loop {
+            let finished = job.handle.is_finished();
+            let p = &job.progress;
+            let event = json!({
+                "event": if finished { "done" } else { "progress" },
+                "phase": p.phase().0,
+                "percent": if finished { 100 } else { p.percent() },
+                "nodes": p.nodes(),
+                "best_time": p.best_time(),
+            });
+
+            if last.as_ref() != Some(&event) {
+                eprintln!("{}", event);
+                last = Some(event);
+            }
+
+            if finished {
+                break;
+            }
+
+            std::thread::sleep(JSON_PROGRESS_INTERVAL);
+        }
+    }
+
+    // Start computing the path in a worker thread. The result is picked up
+    // by poll_compute.
+    fn start_compute(&mut self) -> Result<(), String> {
+        self.check_idle()?;
+
         if self.params.points.len() < 2 {
             return Err("Not enough waypoints".to_string());
         }
 
-        if let Some(p) =  Path::from_points(&self.params, &self.atlas) {
-            p.print_summary(&self.atlas);
+        for (i, c) in self.params.points.iter().enumerate() {
+            if !self.atlas.covers(c) {
+                return Err(format!("Point {} ({}) is outside the elevation \
+                                    data", i + 1, c));
+            }
+        }
+
+        self.reset_debug();
+
+        let mut debug = std::mem::replace(&mut self.debug, DebugLog::new());
+        let progress = debug.progress.clone();
+        let params = self.params.clone();
+        let atlas = self.atlas.clone();
+        // Pinned points of the current path are kept
+        let old = self.opt_path.clone();
+
+        let handle = std::thread::spawn(move || {
+            let opt_path = Path::from_points_with_debug(
+                &params, atlas.as_ref(), &mut debug, old.as_ref());
+            JobResult::Path(opt_path, debug)
+        });
+
+        self.job = Some(ComputeJob {
+            points: self.params.points.clone(),
+            started: Instant::now(),
+            progress: progress,
+            handle: handle,
+        });
+
+        Ok(())
+    }
+
+    // Pick up the result of a computation if it has finished
+    pub fn poll_compute(&mut self) {
+        if !self.job.as_ref().is_some_and(|j| j.handle.is_finished()) {
+            return;
+        }
+
+        let job = self.job.take().unwrap();
+        if let Err(e) = self.finish_compute(job) {
+            println!("{}", e);
+        }
+    }
+
+    // Wait for a computation to finish and show the result
+    fn finish_compute(&mut self, job: ComputeJob) -> Result<(), String> {
+        let result = job.handle.join()
+            .map_err(|_| "The computation failed".to_string())?;
+
+        let (opt_path, debug) = match result {
+            JobResult::Path(opt_path, debug) => (opt_path, debug),
+            JobResult::Escapes(_) if job.progress.is_cancelled() => {
+                println!("Computation cancelled");
+                return Ok(());
+            },
+            JobResult::Escapes(escapes) => {
+                self.show_escapes(&escapes);
+                return Ok(());
+            },
+        };
+        self.debug = debug;
+
+        if job.progress.is_cancelled() {
+            println!("Computation cancelled");
+        }
+        else if let Some(p) = opt_path {
+            println!("Computed path in {:.1} s",
+                     job.started.elapsed().as_secs_f32());
             self.opt_path.replace(p.clone());
             self.path_stored = false;
 
             if let Some(tx) = &self.opt_tx {
                 let _ = tx.send(CanvasMsg::SetPath(p));
             }
+
+            self.show_path_info();
         }
         else {
-            println!("Path {} cannot be walked", self.params.points.iter()
+            println!("Path {} cannot be walked", job.points.iter()
                      .map(|c| c.to_string())
                      .collect::<Vec<String>>()
                      .join(", "));
@@ -167,6 +730,184 @@ impl App {
         Ok(())
     }
 
+    fn check_idle(&self) -> Result<(), String> {
+        if self.job.is_some() {
+            return Err("A computation is running. Use status or cancel."
+                       .to_string());
+        }
+
+        Ok(())
+    }
+
+    fn show_status(&self) {
+        let Some(job) = &self.job else {
+            println!("No computation running");
+            return;
+        };
+
+        let (phase, _, _) = job.progress.phase();
+        let percent = job.progress.percent();
+        println!("{}{} ({}% done, {:.0} s elapsed)", phase,
+                 if job.progress.is_cancelled() { ", cancelling" } else { "" },
+                 percent, job.started.elapsed().as_secs_f32());
+    }
+
+    fn cancel_compute(&self) -> Result<(), String> {
+        let Some(job) = &self.job else {
+            return Err("No computation running".to_string());
+        };
+
+        job.progress.cancel();
+        println!("Cancelling. The computation stops after the current \
+                  step.");
+        Ok(())
+    }
+
+    fn reset_debug(&mut self) {
+        self.debug = DebugLog::new();
+        self.debug.show_graph = self.debug_graph;
+        self.debug.show_explored = self.debug_explored;
+        self.debug.show_heatmap = self.debug_heatmap;
+        self.debug.keep_graph = self.debug_keep_graph;
+        self.debug.opt_tx = self.opt_tx.clone();
+    }
+
+    // Recompute one leg of the path, and the neighbouring legs if their
+    // ends have moved
+    fn compute_leg(&mut self, legstr: &str) -> Result<(), String> {
+        let num_legs = self.opt_path.as_ref().map(|p| p.num_legs());
+
+        if num_legs.unwrap_or(0) == 0 {
+            return Err("No computed track. Run compute first.".to_string());
+        }
+
+        let n = App::parse_int_range(legstr, 1..num_legs.unwrap() + 1)? - 1;
+        self.check_idle()?;
+
+        self.reset_debug();
+        let path = self.opt_path.as_mut().unwrap();
+
+        let legs = path.recompute_leg(n, &self.params, self.atlas.as_ref(),
+                                      &mut self.debug)?;
+        println!("Recomputed leg {}", legs.iter()
+                 .map(|l| (l + 1).to_string())
+                 .collect::<Vec<String>>()
+                 .join(", "));
+
+        self.path_stored = false;
+
+        if let Some(tx) = &self.opt_tx {
+            let _ = tx.send(CanvasMsg::SetPath(path.clone()));
+        }
+
+        self.show_path_info();
+        Ok(())
+    }
+
+    // Replan from the GPS position. The waypoints already passed are
+    // removed, the route starts at the GPS position, and the current leg is
+    // recomputed from there.
+    fn reroute_from_here(&mut self) -> Result<(), String> {
+        let Some(gps) = &self.gps else {
+            return Err("No GPS. Set gps to gpsd or an NMEA device."
+                       .to_string());
+        };
+
+        let Some((c, _)) = gps.fix() else {
+            return Err("No position from the GPS yet".to_string());
+        };
+
+        let num_legs = self.opt_path.as_ref().map(|p| p.num_legs());
+
+        if num_legs.unwrap_or(0) == 0 {
+            return Err("No computed track. Run compute first.".to_string());
+        }
+
+        if num_legs.unwrap() + 1 != self.params.points.len() {
+            return Err("The waypoints have changed since the path was \
+                        computed. Run compute.".to_string());
+        }
+
+        self.check_idle()?;
+
+        // The current leg is the one passing nearest to the position
+        let path = self.opt_path.as_mut().unwrap();
+        let nearest = path.into_iter()
+            .enumerate()
+            .min_by(|a, b| (*a.1 - c).abs_sq()
+                    .total_cmp(&(*b.1 - c).abs_sq()))
+            .map(|(k, _)| k)
+            .unwrap_or(0);
+        let n = path.leg_at(nearest).unwrap_or(0);
+
+        path.remove_legs_before(n);
+        self.params.start_from(n, c);
+
+        self.reset_debug();
+        let path = self.opt_path.as_mut().unwrap();
+        let legs = path.recompute_leg(0, &self.params, self.atlas.as_ref(),
+                                      &mut self.debug)?;
+        println!("Rerouted from {}, recomputed {} leg(s)", c, legs.len());
+
+        self.path_stored = false;
+        self.update_waypoints();
+        self.update_gates();
+
+        if let Some(tx) = &self.opt_tx {
+            let _ = tx.send(CanvasMsg::SetPath(path.clone()));
+        }
+
+        self.show_path_info();
+        Ok(())
+    }
+
+    // Optimize the current track, computed or read from a file
+    fn optimize(&mut self, opt_iterations: Option<&str>)
+                -> Result<(), String> {
+        self.check_idle()?;
+
+        let max_iterations = match opt_iterations {
+            Some(s) => Some(App::parse_int_range(s, 1..usize::MAX)?),
+            None => None,
+        };
+
+        let Some(path) = self.opt_path.as_mut() else {
+            return Err("No track".to_string());
+        };
+
+        let cost = Cost::from_params(&self.params);
+        path.reoptimize(self.atlas.as_ref(), &cost, &self.params,
+                        max_iterations);
+        self.path_stored = false;
+
+        if let Some(tx) = &self.opt_tx {
+            let _ = tx.send(CanvasMsg::SetPath(path.clone()));
+        }
+
+        self.show_path_info();
+        Ok(())
+    }
+
+    // Compute a path within the area shown in the map window
+    fn compute_in_view(&mut self) -> Result<(), String> {
+        let Some(v) = self.viewport else {
+            return Err("No map window.".to_string());
+        };
+
+        for (i, c) in self.params.points.iter().enumerate() {
+            if c.e < v.sw.e || c.e > v.ne.e || c.n < v.sw.n || c.n > v.ne.n {
+                return Err(format!("Point {} ({}) is outside the map view",
+                                   i + 1, c));
+            }
+        }
+
+        self.params.bounds = Some((v.sw, v.ne));
+        let res = self.start_compute();
+        self.params.bounds = None;
+
+        res
+    }
+
     pub fn parse_int_range(intstr: &str, range: Range<usize>)
                            -> Result<usize, String> {
         if let Ok(length) = intstr.parse() {
@@ -241,8 +982,9 @@ impl App {
             return Err("Too many arguments".to_string());
         }
 
-        self.params.points.insert(n, c);
+        self.params.insert_point(n, c);
         self.update_waypoints();
+        self.check_access(n);
         Ok(())
     }
 
@@ -282,10 +1024,85 @@ impl App {
 
         self.params.points[n] = c;
         self.update_waypoints();
+        self.check_access(n);
         Ok(())
     }
 
-    fn rm_point(&mut self, args: &Vec<String>) -> Result<(), String> {
+    // Warn if the start or end point is far from the road ends and
+    // parkings, and offer to move it to the one which is easiest to walk
+    // to. Ascent counts as extra distance.
+    fn check_access(&mut self, n: usize) {
+        let len = self.params.points.len();
+        let max = self.params.access_distance;
+
+        if (n != 0 && n + 1 != len) || max <= 0.0 {
+            return;
+        }
+
+        let c = self.params.points[n];
+        let height = self.atlas.lookup(&c);
+        let mut access: Vec<(f32, &Poi)> = self.roads.pois().iter()
+            .chain(self.pois.pois().iter()
+                   .filter(|p| p.kind == PoiKind::Parking))
+            .map(|p| ((p.coord - c).abs(), p))
+            .collect();
+        access.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut best = None;
+        let mut effort_min = f32::INFINITY;
+
+        // Elevation is only looked up for the nearest entries, until the
+        // distance alone is more than the least effort found
+        for (dist, p) in access {
+            if dist >= effort_min {
+                break;
+            }
+
+            let climb = match (height, self.atlas.lookup(&p.coord)) {
+                (Some(h), Some(ha)) => (h - ha).abs(),
+                _ => 0.0,
+            };
+            let effort = dist + CLIMB_DISTANCE*climb;
+            if effort < effort_min {
+                effort_min = effort;
+                best = Some((p.clone(), climb));
+            }
+
+            // Within reach, no need to look further
+            if effort_min <= max {
+                return;
+            }
+        }
+
+        let Some((poi, climb)) = best else {
+            return;
+        };
+
+        if effort_min <= max {
+            return;
+        }
+
+        let which = if n == 0 { "Start" } else { "End" };
+        println!("{} point is {:.1} km and {:.0} m up or down from road \
+                  access at {}", which, (poi.coord - c).abs()/1000.0, climb,
+                 poi);
+
+        // There is no terminal to answer in
+        if !App::can_prompt() {
+            println!("Run update point {} {} to move it there.", poi.coord,
+                     n + 1);
+            return;
+        }
+
+        println!("Snap to {}? (Y/n)", poi.name);
+        if self.confirm_yes_no() {
+            self.params.points[n] = poi.coord;
+            self.update_waypoints();
+        }
+    }
+
+    // Move waypoint to the nearest point of interest
+    fn snap_point(&mut self, args: &Vec<String>) -> Result<(), String> {
         let len = self.params.points.len();
         let n;
 
@@ -293,9 +1110,13 @@ impl App {
             return Err(format!("No points defined"));
         }
 
+        if self.pois.len() == 0 {
+            return Err(format!("No points of interest loaded"));
+        }
+
         if args.len() == 1 {
-            // One argument (int): remove point at position
-            n = App::parse_int_range(&args[0], 1..len)? - 1;
+            // One argument (int): snap point at position
+            n = App::parse_int_range(&args[0], 1..len + 1)? - 1;
         }
         else if args.len() == 0 {
             // No arguments: select point on map
@@ -305,111 +1126,1060 @@ impl App {
             return Err("Too many arguments".to_string());
         }
 
-        self.params.points.remove(n);
+        let c = self.params.points[n];
+        let poi = self.pois.nearest(&c).unwrap();
+        println!("Snapping point {} to {}, {:.0}m away", n + 1, poi,
+                 (poi.coord - c).abs());
+
+        self.params.points[n] = poi.coord;
         self.update_waypoints();
         Ok(())
     }
 
-    fn add_barrier(&mut self, args: &Vec<String>) -> Result<(), String> {
-        let mut added_barrier;
-
-        if args.len() == 0 {
-            // No arguments. Select points on map.
-            if let Some(rx) = &self.opt_rx {
-                println!("Left click on first and intermediate points. Right click to finish.");
-
-                if let Some(tx) = &self.opt_tx {
-                    let _ = tx.send(CanvasMsg::RequestBarrier);
-                }
-
-                loop {
-                    match rx.recv() {
-                        Ok(AppMsg::CreateBarrier(b)) => {
-                           if b.len() >= 2 {
-                                added_barrier = b;
-                            }
-                            else {
-                                added_barrier = Barrier::new();
-                            }
-                            break;
-                        },
-                        _ => { },
-                    }
-                }
-            }
-            else {
-                return Err(format!("No map window."));
+    // Center map on a named place. Optionally add a waypoint there.
+    fn goto(&mut self, args: &Vec<String>) -> Result<(), String> {
+        let mut name_args = &args[..];
+        let mut opt_n = None;
+
+        if args.len() >= 2 {
+            // Last argument (int): add point to position
+            let len = self.params.points.len();
+            let last = &args[args.len() - 1];
+            if let Ok(n) = App::parse_int_range(last, 1..len + 2) {
+                opt_n = Some(n - 1);
+                name_args = &args[..args.len() - 1];
             }
         }
-        else {
-            added_barrier = Barrier::new();
 
-            for cstr in args {
-                added_barrier.add_point(Coord::from_str(cstr)?);
-            }
+        let name = name_args.join(" ");
+        let Some(poi) = self.pois.search(&name) else {
+            return Err(format!("No place named '{}'", name));
+        };
+        println!("Found {}", poi);
+
+        let c = poi.coord;
+
+        if let Some(tx) = &self.opt_tx {
+            let _ = tx.send(CanvasMsg::CenterOn(c));
         }
 
-        if added_barrier.points.len() >= 2 {
-            self.params.barriers.push(added_barrier);
-            self.update_barriers();
+        if let Some(n) = opt_n {
+            self.params.insert_point(n, c);
+            self.update_waypoints();
         }
 
         Ok(())
     }
 
-    fn rm_barrier(&mut self, args: &Vec<String>) -> Result<(), String> {
-        let mut n = self.params.barriers.len();
-
-        if n == 0 {
+    fn rm_point(&mut self, args: &Vec<String>) -> Result<(), String> {
+        let len = self.params.points.len();
+        let n;
+
+        if len == 0 {
+            return Err(format!("No points defined"));
+        }
+
+        if args.len() == 1 {
+            // One argument (int): remove point at position
+            n = App::parse_int_range(&args[0], 1..len)? - 1;
+        }
+        else if args.len() == 0 {
+            // No arguments: select point on map
+            n = self.select_point_on_map()?;
+        }
+        else {
+            return Err("Too many arguments".to_string());
+        }
+
+        self.params.remove_point(n);
+        self.update_waypoints();
+        Ok(())
+    }
+
+    // Send a request to the map window and wait for the answer. Answers to
+    // earlier requests are ignored. The request is cancelled by Esc in the
+    // map window or Ctrl+C in the terminal, or when no answer has come
+    // within the timeout.
+    fn request_from_map(&self, request: fn(u64) -> CanvasMsg)
+                        -> Result<AppMsg, String> {
+        let (Some(rx), Some(tx)) = (&self.opt_rx, &self.opt_tx) else {
+            return Err(format!("No map window."));
+        };
+
+        let id = self.last_request.get() + 1;
+        self.last_request.set(id);
+
+        if tx.send(request(id)).is_err() {
+            return Err("Map window closed".to_string());
+        }
+
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        WAITING_FOR_MAP.store(true, Ordering::SeqCst);
+        let start = Instant::now();
+
+        let res = loop {
+            if INTERRUPTED.load(Ordering::SeqCst) {
+                let _ = tx.send(CanvasMsg::CancelRequest(id));
+                break Err("Cancelled".to_string());
+            }
+
+            if start.elapsed() > REQUEST_TIMEOUT {
+                let _ = tx.send(CanvasMsg::CancelRequest(id));
+                break Err("Timed out waiting for the map".to_string());
+            }
+
+            match rx.recv_timeout(REQUEST_POLL) {
+                Ok(AppMsg::SelectPoint(i, c)) if i == id => {
+                    break Ok(AppMsg::SelectPoint(i, c));
+                },
+                Ok(AppMsg::CreateBarrier(i, b)) if i == id => {
+                    break Ok(AppMsg::CreateBarrier(i, b));
+                },
+                Ok(AppMsg::RequestCancelled(i)) if i == id => {
+                    break Err("Cancelled".to_string());
+                },
+                Ok(_) | Err(RecvTimeoutError::Timeout) => { },
+                Err(RecvTimeoutError::Disconnected) => {
+                    break Err("Map window closed".to_string());
+                },
+            }
+        };
+
+        WAITING_FOR_MAP.store(false, Ordering::SeqCst);
+        res
+    }
+
+    // Get a line of points from the map. Returns an empty line if less than
+    // two points were selected.
+    fn get_line_from_map(&self) -> Result<Barrier, String> {
+        println!("Left click on first and intermediate points. Right click to finish.");
+
+        match self.request_from_map(CanvasMsg::RequestBarrier)? {
+            AppMsg::CreateBarrier(_, b) if b.len() >= 2 => Ok(b),
+            _ => Ok(Barrier::new()),
+        }
+    }
+
+    fn parse_line(&self, args: &Vec<String>) -> Result<Barrier, String> {
+        let mut line = Barrier::new();
+
+        for cstr in args {
+            line.add_point(Coord::from_str(cstr)?);
+        }
+
+        Ok(line)
+    }
+
+    fn add_barrier(&mut self, args: &Vec<String>) -> Result<(), String> {
+        let added_barrier = if args.len() == 0 {
+            // No arguments. Select points on map.
+            self.get_line_from_map()?
+        }
+        else {
+            self.parse_line(args)?
+        };
+
+        if added_barrier.points.len() >= 2 {
+            self.params.barriers.push(added_barrier.clone());
+            self.update_barriers();
+            self.offer_recompute(&added_barrier)?;
+        }
+
+        Ok(())
+    }
+
+    // Offer to recompute the legs of the path whose covering areas are
+    // crossed by an added or removed barrier. The other legs are kept.
+    fn offer_recompute(&mut self, barrier: &Barrier) -> Result<(), String> {
+        let Some(path) = &self.opt_path else {
+            return Ok(());
+        };
+
+        if path.num_legs() + 1 != self.params.points.len() {
+            return Ok(());
+        }
+
+        let legs = self.params.covering_legs(barrier);
+
+        if legs.is_empty() {
+            return Ok(());
+        }
+
+        let names = legs.iter()
+            .map(|l| (l + 1).to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        // There is no terminal to answer in
+        if !App::can_prompt() {
+            println!("The barrier affects leg {}. Run compute leg to update \
+                      the path.", names);
+            return Ok(());
+        }
+
+        println!("The barrier affects leg {}. Recompute? (Y/n)", names);
+        if !self.confirm_yes_no() {
+            return Ok(());
+        }
+
+        self.check_idle()?;
+        self.reset_debug();
+        let path = self.opt_path.as_mut().unwrap();
+        let mut recomputed: Vec<usize> = vec![];
+
+        // Recomputing a leg may recompute its neighbours too
+        for n in legs {
+            if !recomputed.contains(&n) {
+                recomputed.extend(path.recompute_leg(
+                    n, &self.params, self.atlas.as_ref(), &mut self.debug)?);
+            }
+        }
+
+        recomputed.sort();
+        recomputed.dedup();
+        println!("Recomputed leg {}", recomputed.iter()
+                 .map(|l| (l + 1).to_string())
+                 .collect::<Vec<String>>()
+                 .join(", "));
+
+        self.path_stored = false;
+
+        if let Some(tx) = &self.opt_tx {
+            let _ = tx.send(CanvasMsg::UpdatePathLegs(path.clone(),
+                                                      recomputed));
+        }
+
+        self.show_path_info();
+        Ok(())
+    }
+
+    // Add a line which the route must cross
+    fn add_gate(&mut self, args: &Vec<String>) -> Result<(), String> {
+        let gate = if args.len() == 0 {
+            // No arguments. Select points on map.
+            self.get_line_from_map()?
+        }
+        else {
+            self.parse_line(args)?
+        };
+
+        if gate.len() >= 2 {
+            self.params.gates.push(gate);
+            self.update_gates();
+        }
+
+        Ok(())
+    }
+
+    fn rm_gate(&mut self, args: &Vec<String>) -> Result<(), String> {
+        let len = self.params.gates.len();
+        let n;
+
+        if len == 0 {
+            return Err("No gates defined.".to_string());
+        }
+
+        if args.len() == 1 {
+            // One argument (int): remove gate at position
+            n = App::parse_int_range(&args[0], 1..len + 1)? - 1;
+        }
+        else if args.len() == 0 {
+            let pm = self.get_coord_from_map("Select a gate on map")?;
+            n = self.params.gates.iter()
+                .position(|g| g.distance_sq(&pm) < NEARBY*NEARBY)
+                .ok_or("No gates selected".to_string())?;
+        }
+        else {
+            return Err("Too many arguments".to_string());
+        }
+
+        self.params.gates.remove(n);
+        self.update_gates();
+        Ok(())
+    }
+
+    // Set the polygon which the route must stay inside
+    fn add_corridor(&mut self, args: &Vec<String>) -> Result<(), String> {
+        let corridor = if args.len() == 0 {
+            // No arguments. Select points on map.
+            self.get_line_from_map()?
+        }
+        else {
+            self.parse_line(args)?
+        };
+
+        if corridor.len() < 3 {
+            return Err("Expected at least three corridor points".to_string());
+        }
+
+        self.params.corridor = corridor;
+        self.update_corridor();
+        Ok(())
+    }
+
+    fn rm_corridor(&mut self) -> Result<(), String> {
+        if self.params.corridor.len() == 0 {
+            return Err("No corridor defined.".to_string());
+        }
+
+        self.params.corridor = Barrier::new();
+        self.update_corridor();
+        Ok(())
+    }
+
+    fn rm_barrier(&mut self, args: &Vec<String>) -> Result<(), String> {
+        let mut n = self.params.barriers.len();
+
+        if n == 0 {
             return Err("No barriers defined.".to_string());
         }
 
-        if args.len() == 1 {
-            // One argument (int): remove barrier at position
-            n = App::parse_int_range(&args[0], 1..n + 1)? - 1;
+        if args.len() == 1 {
+            // One argument (int): remove barrier at position
+            n = App::parse_int_range(&args[0], 1..n + 1)? - 1;
+        }
+        else if args.len() == 0 {
+            n = self.select_barrier_on_map()?;
+        }
+        else {
+            return Err("Too many arguments".to_string());
+        }
+
+        let removed = self.params.barriers.remove(n);
+        self.update_barriers();
+        self.offer_recompute(&removed)
+    }
+
+    // Add point where barriers may be crossed
+    fn add_ford(&mut self, args: &Vec<String>) -> Result<(), String> {
+        let c;
+
+        if args.len() == 1 {
+            c = self.parse_coord(&args[0])?;
+        }
+        else if args.len() == 0 {
+            c = self.get_coord_from_map("Select a ford on map")?;
+        }
+        else {
+            return Err("Too many arguments".to_string());
+        }
+
+        self.params.fords.push(c);
+        self.update_fords();
+        Ok(())
+    }
+
+    fn rm_ford(&mut self, args: &Vec<String>) -> Result<(), String> {
+        let len = self.params.fords.len();
+        let n;
+
+        if len == 0 {
+            return Err("No fords defined.".to_string());
+        }
+
+        if args.len() == 1 {
+            // One argument (int): remove ford at position
+            n = App::parse_int_range(&args[0], 1..len + 1)? - 1;
+        }
+        else if args.len() == 0 {
+            let pm = self.get_coord_from_map("Select a ford on map")?;
+            n = self.params.fords.iter()
+                .position(|f| (pm - *f).abs_sq() < NEARBY*NEARBY)
+                .ok_or("No fords selected".to_string())?;
+        }
+        else {
+            return Err("Too many arguments".to_string());
+        }
+
+        self.params.fords.remove(n);
+        self.update_fords();
+        Ok(())
+    }
+
+    // Route the legs crossing a barrier through a crossing point, by
+    // inserting a waypoint with the ford radius there. The crossing points
+    // are the fords, and the bridges and fords among the points of
+    // interest, within the ford radius of the barrier. The one giving the
+    // shortest detour is chosen. Crossing points of interest become fords.
+    fn add_crossings(&mut self) -> Result<(), String> {
+        let r = self.params.ford_radius;
+        let is_ford = |fords: &[Coord], c: &Coord| fords.iter()
+            .any(|f| (*f - *c).abs_sq() < r*r);
+        let candidates: Vec<Coord> = self.params.fords.iter().copied()
+            .chain(self.pois.pois().iter()
+                   .filter(|p| p.kind.is_crossing())
+                   .map(|p| p.coord))
+            .collect();
+        let mut added = 0;
+        let mut i = 0;
+
+        while i + 1 < self.params.points.len() {
+            let (p1, p2) = (self.params.points[i], self.params.points[i + 1]);
+            let mut crossings: Vec<Coord> = vec![];
+
+            for (k, b) in self.params.barriers.iter().enumerate() {
+                if b.crossing_points(&p1, &p2).iter()
+                    .all(|x| is_ford(&self.params.fords, x)) {
+                    continue;
+                }
+
+                let detour = |c: &Coord| (*c - p1).abs() + (p2 - *c).abs();
+                let best = candidates.iter()
+                    .filter(|c| b.distance_sq(c) < r*r)
+                    .min_by(|x, y| detour(x).total_cmp(&detour(y)));
+
+                match best {
+                    Some(c) => crossings.push(*c),
+                    None => println!("Leg {} crosses barrier {}, which has \
+                                      no crossing points", i + 1, k + 1),
+                }
+            }
+
+            // In order along the leg
+            crossings.sort_by(|a, b| (*a - p1).abs_sq()
+                              .total_cmp(&(*b - p1).abs_sq()));
+            crossings.dedup();
+
+            for (j, c) in crossings.iter().enumerate() {
+                if !is_ford(&self.params.fords, c) {
+                    self.params.fords.push(*c);
+                }
+
+                self.params.insert_point(i + j + 1, *c);
+                let info = &mut self.params.point_info[i + j + 1];
+                info.name = format!("crossing {}", added + 1);
+                info.radius = r;
+                added += 1;
+            }
+
+            i += crossings.len() + 1;
+        }
+
+        if added == 0 {
+            println!("No legs need new crossing points");
+            return Ok(());
+        }
+
+        println!("Added {} crossing points", added);
+        self.update_waypoints();
+        self.update_fords();
+        Ok(())
+    }
+
+    // Pin the path point nearest to a coordinate, so that optimization
+    // leaves it in place
+    fn pin_point(&mut self, args: &Vec<String>) -> Result<(), String> {
+        let Some(path) = &self.opt_path else {
+            return Err("No path computed.".to_string());
+        };
+
+        let c = if args.len() == 1 {
+            self.parse_coord(&args[0])?
+        }
+        else if args.len() == 0 {
+            self.get_coord_from_map("Select a path point on map")?
+        }
+        else {
+            return Err("Too many arguments".to_string());
+        };
+
+        let p = path.into_iter()
+            .min_by(|a, b| (c - **a).abs_sq().total_cmp(&(c - **b).abs_sq()))
+            .copied()
+            .ok_or("The path has no points".to_string())?;
+
+        if self.params.is_pinned(&p) {
+            return Err("The point is already pinned".to_string());
+        }
+
+        self.params.pins.push(p);
+        self.update_pins();
+        Ok(())
+    }
+
+    // Reshape a leg by dragging the track in the map window. The path
+    // point where the drag started is moved to where it ended and pinned,
+    // and the leg is recomputed through it.
+    fn drag_track(&mut self, from: Coord, to: Coord) -> Result<(), String> {
+        let Some(path) = &self.opt_path else {
+            return Err("No path computed.".to_string());
+        };
+
+        if path.num_legs() + 1 != self.params.points.len() {
+            return Err("The waypoints have changed since the path was \
+                        computed. Run compute.".to_string());
+        }
+
+        self.check_idle()?;
+
+        let k = path.into_iter()
+            .enumerate()
+            .min_by(|a, b| (*a.1 - from).abs_sq()
+                    .total_cmp(&(*b.1 - from).abs_sq()))
+            .map(|(k, _)| k)
+            .ok_or("The path has no points".to_string())?;
+        let n = path.leg_at(k).unwrap_or(0);
+        let old = path.clone();
+
+        let path = self.opt_path.as_mut().unwrap();
+        path.move_point(k, to)?;
+        self.push_undo(self.params.clone());
+        self.params.pins.push(to);
+
+        self.reset_debug();
+        let path = self.opt_path.as_mut().unwrap();
+        let result = path.recompute_leg(n, &self.params, self.atlas.as_ref(),
+                                        &mut self.debug);
+
+        let legs = match result {
+            Ok(legs) => legs,
+            Err(e) => {
+                // Leave the path and the pins as they were
+                self.opt_path = Some(old);
+                self.params.pins.pop();
+                self.undo_stack.pop();
+                return Err(e);
+            },
+        };
+
+        println!("Leg {} now passes {}, recomputed {} leg(s)", n + 1, to,
+                 legs.len());
+
+        if let Some(tx) = &self.opt_tx {
+            let _ = tx.send(CanvasMsg::SetPath(path.clone()));
+        }
+
+        self.params_stored = false;
+        self.path_stored = false;
+        self.update_pins();
+        self.show_path_info();
+        Ok(())
+    }
+
+    fn unpin_point(&mut self, args: &Vec<String>) -> Result<(), String> {
+        let len = self.params.pins.len();
+        let n;
+
+        if len == 0 {
+            return Err("No pinned points.".to_string());
+        }
+
+        if args.len() == 1 {
+            // One argument (int): remove pin at position
+            n = App::parse_int_range(&args[0], 1..len + 1)? - 1;
+        }
+        else if args.len() == 0 {
+            let pm = self.get_coord_from_map("Select a pinned point on map")?;
+            n = self.params.pins.iter()
+                .position(|p| (pm - *p).abs_sq() < NEARBY*NEARBY)
+                .ok_or("No pinned points selected".to_string())?;
+        }
+        else {
+            return Err("Too many arguments".to_string());
+        }
+
+        self.params.pins.remove(n);
+        self.update_pins();
+        Ok(())
+    }
+
+    // Add a polygon inside which path points are pinned
+    fn pin_area(&mut self, args: &Vec<String>) -> Result<(), String> {
+        let area = if args.len() == 0 {
+            // No arguments. Select points on map.
+            self.get_line_from_map()?
+        }
+        else {
+            self.parse_line(args)?
+        };
+
+        if area.len() < 3 {
+            return Err("Expected at least three area points".to_string());
+        }
+
+        self.params.pin_areas.push(area);
+        self.update_pins();
+        Ok(())
+    }
+
+    fn unpin_area(&mut self, args: &Vec<String>) -> Result<(), String> {
+        let len = self.params.pin_areas.len();
+        let n;
+
+        if len == 0 {
+            return Err("No pinned areas.".to_string());
+        }
+
+        if args.len() == 1 {
+            // One argument (int): remove area at position
+            n = App::parse_int_range(&args[0], 1..len + 1)? - 1;
+        }
+        else if args.len() == 0 {
+            let pm = self.get_coord_from_map("Select a pinned area on map")?;
+            n = self.params.pin_areas.iter()
+                .position(|a| a.closed().contains(&pm)
+                          || a.distance_sq(&pm) < NEARBY*NEARBY)
+                .ok_or("No pinned areas selected".to_string())?;
+        }
+        else {
+            return Err("Too many arguments".to_string());
+        }
+
+        self.params.pin_areas.remove(n);
+        self.update_pins();
+        Ok(())
+    }
+
+    // Add a place to escape to, e.g. a road or a trailhead
+    fn add_exit(&mut self, args: &Vec<String>) -> Result<(), String> {
+        let c;
+
+        if args.len() == 1 {
+            c = self.parse_coord(&args[0])?;
+        }
+        else if args.len() == 0 {
+            c = self.get_coord_from_map("Select an exit on map")?;
+        }
+        else {
+            return Err("Too many arguments".to_string());
+        }
+
+        self.params.exits.push(c);
+        self.update_exits();
+        Ok(())
+    }
+
+    fn rm_exit(&mut self, args: &Vec<String>) -> Result<(), String> {
+        let len = self.params.exits.len();
+        let n;
+
+        if len == 0 {
+            return Err("No exits defined.".to_string());
+        }
+
+        if args.len() == 1 {
+            // One argument (int): remove exit at position
+            n = App::parse_int_range(&args[0], 1..len + 1)? - 1;
+        }
+        else if args.len() == 0 {
+            let pm = self.get_coord_from_map("Select an exit on map")?;
+            n = self.params.exits.iter()
+                .position(|e| (pm - *e).abs_sq() < NEARBY*NEARBY)
+                .ok_or("No exits selected".to_string())?;
+        }
+        else {
+            return Err("Too many arguments".to_string());
+        }
+
+        self.params.exits.remove(n);
+        self.update_exits();
+        Ok(())
+    }
+
+    // Add a member to the group. Times are estimated for the slowest
+    // member.
+    fn add_hiker(&mut self, name: &str, fitness: &str, pack: &str)
+                 -> Result<(), String> {
+        self.params.group.push(Hiker::parse(name, fitness, pack)?);
+        self.params_stored = false;
+        Ok(())
+    }
+
+    fn rm_hiker(&mut self, pos: &str) -> Result<(), String> {
+        let len = self.params.group.len();
+
+        if len == 0 {
+            return Err("No hikers defined.".to_string());
+        }
+
+        let n = App::parse_int_range(pos, 1..len + 1)? - 1;
+        self.params.group.remove(n);
+        self.params_stored = false;
+        Ok(())
+    }
+
+    fn show_params(&self) {
+        self.params.print_params();
+        println!("crs:              {}", crs_name());
+    }
+
+    fn show_cost(&self) {
+        let cost = Cost::from_params(&self.params);
+
+        println!("Cost model: {}", &self.params.cost_model);
+        println!("Slope (deg)      Distance/hour (km)      Elevation/hour (m)");
+
+        for i in 0..21 {
+            // slope in degrees
+            let r = (i as f32)*5.0 - 50.0;
+            // slope as the ratio h/d
+            let s = (r*std::f32::consts::PI/180.0).tan();
+            // time cost
+            let c = cost.time_by_steepness(s, s.abs());
+            // horizontal distance per time, km/h
+            let dpt = 3.6/c;
+            // elevation per time, m/h;
+            let ept = 3600.0*s/c;
+            println!("{:6.2}          {:6.2}                  {:8.2}",
+                     r, dpt, ept);
+        }
+
+        if let Some(tx) = &self.opt_tx {
+            let _ = tx.send(CanvasMsg::ShowCostCurve);
+        }
+    }
+
+    // Time per meter of the cost model for each degree of slope
+    fn cost_samples(params: &Params) -> Vec<(f32, f32)> {
+        let cost = Cost::from_params(params);
+
+        (-45..=45).map(|r| {
+            let s = (r as f32).to_radians().tan();
+            (r as f32, cost.time_by_steepness(s, s.abs()))
+        }).collect()
+    }
+
+    // Control points for editing the cost model, which are the samples of
+    // the curve model. The other models can't be edited, as a curve doesn't
+    // keep their dependency on the gradient of the terrain.
+    fn cost_controls(params: &Params) -> Vec<(f32, f32)> {
+        if params.cost_model == "curve" {
+            return params.cost_curve.clone();
+        }
+
+        vec![]
+    }
+
+    fn update_cost_curve(&self) {
+        if let Some(tx) = &self.opt_tx {
+            let _ = tx.send(CanvasMsg::SetCostCurve(
+                App::cost_samples(&self.params),
+                App::cost_controls(&self.params)));
+        }
+    }
+
+    // Map directories in search order, with the tiles mounted from each
+    fn show_maps(&self) -> Result<(), String> {
+        let Some(map_dirs) = &self.map_dirs else {
+            return Err("Map directories are not in use.".to_string());
+        };
+
+        map_dirs.print();
+
+        Ok(())
+    }
+
+    // Print the last GPS position, and its distance from the track
+    fn show_gps(&self) -> Result<(), String> {
+        let Some(gps) = &self.gps else {
+            return Err("No GPS. Set gps to gpsd or an NMEA device."
+                       .to_string());
+        };
+
+        let Some((c, age)) = gps.fix() else {
+            println!("No position from {} yet", gps.source());
+            return Ok(());
+        };
+
+        println!("Position {} ({} s ago)", c, age.as_secs());
+
+        if let Some(path) = &self.opt_path {
+            let points: Vec<Coord> = path.into_iter().cloned().collect();
+            let track = Barrier::from_vec(points.clone());
+
+            if track.len() >= 2 {
+                // Distance along the track to the nearest track point
+                let nearest = (0..points.len())
+                    .min_by(|i, j| (points[*i] - c).abs_sq()
+                            .total_cmp(&(points[*j] - c).abs_sq()))
+                    .unwrap_or(0);
+                let along: f32 = points[..=nearest].windows(2)
+                    .map(|w| (w[1] - w[0]).abs())
+                    .sum();
+
+                println!("{:.0} m off the track, near km {:.1} of {:.1}",
+                         track.distance_sq(&c).sqrt(), along/1000.0,
+                         path.len()/1000.0);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn show_pois(&self, value: &str) -> Result<(), String> {
+        let show = App::parse_bool(value)?;
+
+        if let Some(tx) = &self.opt_tx {
+            let _ = tx.send(CanvasMsg::ShowPois(show));
+            Ok(())
         }
-        else if args.len() == 0 {
-            n = self.select_barrier_on_map()?;
+        else {
+            Err(format!("No map window."))
+        }
+    }
+
+    // Distance and time by slope class and direction
+    fn show_path_stats(&self) -> Result<(), String> {
+        let Some(path) = &self.opt_path else {
+            return Err("No computed track. Run compute first.".to_string());
+        };
+
+        let cost = Cost::from_params(&self.params);
+        path.stats(&self.params.points, self.atlas.as_ref(), &cost)
+            .slopes.print();
+
+        Ok(())
+    }
+
+    fn show_path_info(&self) {
+        self.update_route_alarm();
+        self.update_km_markers();
+
+        if let Some(path) = &self.opt_path {
+            let cost = Cost::from_params(&self.params);
+            let stats = path.stats(&self.params.points, self.atlas.as_ref(),
+                                   &cost);
+            println!("Path: {}", path);
+            stats.print_summary();
+            stats.print_legs();
+
+            let mut night = vec![];
+            let mut turnaround = vec![];
+
+            if self.params.start_time != "" {
+                match Schedule::new(path, self.atlas.as_ref(), &cost,
+                                    &self.params) {
+                    Ok(schedule) => {
+                        schedule.print_summary();
+                        schedule.print_targets(path, &self.params);
+                        night = schedule.dark_sections(path);
+                        turnaround = self.show_turnaround(path, &schedule,
+                                                          &cost);
+                    },
+                    Err(e) => {
+                        println!("{}", e);
+                    },
+                }
+            }
+
+            else if self.params.point_info.iter().any(|i| i.target != "") {
+                println!("Set start_time for checking target times");
+            }
+            else if self.params.deadline != "" {
+                println!("Set start_time for finding the point of no return");
+            }
+
+            if let Some(tx) = &self.opt_tx {
+                let _ = tx.send(CanvasMsg::SetNightTrack(night));
+                let _ = tx.send(CanvasMsg::SetMarkers(MarkerKind::Turnaround,
+                                                      turnaround));
+            }
         }
         else {
-            return Err("Too many arguments".to_string());
+            println!("No track");
         }
+    }
+
+    // Print where to turn around for being back at the start by the
+    // deadline. Returns the marker of the point of no return.
+    fn show_turnaround(&self, path: &Path, schedule: &Schedule, cost: &Cost)
+                       -> Vec<(Coord, String)> {
+        let Ok(deadline) = parse_target(&self.params.deadline) else {
+            return vec![];
+        };
+
+        let Some((i, back)) = schedule.point_of_no_return(
+            path, self.atlas.as_ref(), cost, &self.params, deadline) else {
+            println!("Deadline {} is before the start", &self.params.deadline);
+            return vec![];
+        };
+
+        let points: Vec<Coord> = path.into_iter().cloned().collect();
+        let turn = format_clock(schedule.times[i]);
+
+        if i == points.len() - 1 {
+            println!("The whole route can be walked out and back by the \
+                      deadline {}, back at {}", &self.params.deadline,
+                     format_clock(back));
+            return vec![];
+        }
+
+        let dist: f32 = points[..=i].windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .sum();
+        println!("Point of no return: {:.1} km along the route, reached at \
+                  {}. Turning around there brings you back at {} \
+                  (deadline {})", dist/1000.0, turn, format_clock(back),
+                 &self.params.deadline);
+
+        vec![(points[i], format!("turn around {}", turn))]
+    }
+
+    // Start finding the fastest ways to an exit along the track in a worker
+    // thread. The result is picked up by poll_compute.
+    fn compute_escapes(&mut self, intervalstr: &str) -> Result<(), String> {
+        let Ok(interval) = intervalstr.parse::<f32>() else {
+            return Err(format!("Expected number, got '{}'", intervalstr));
+        };
+
+        if interval <= 0.0 {
+            return Err("The interval must be positive".to_string());
+        }
+
+        let Some(path) = &self.opt_path else {
+            return Err("No track".to_string());
+        };
+
+        self.check_idle()?;
+
+        let mut exits: Vec<(Coord, String)> = self.params.exits.iter()
+            .enumerate()
+            .map(|(i, e)| (*e, format!("exit {}", i + 1)))
+            .collect();
+        exits.extend(self.pois.pois().iter()
+                     .filter(|p| p.kind == PoiKind::Parking)
+                     .map(|p| (p.coord, p.name.clone())));
+
+        if exits.is_empty() {
+            return Err("No exits. Add exits or load parking places as \
+                        points of interest.".to_string());
+        }
+
+        let progress = Arc::new(Progress::new());
+        let job_progress = progress.clone();
+        let path = path.clone();
+        let params = self.params.clone();
+        let atlas = self.atlas.clone();
+
+        let handle = std::thread::spawn(move || {
+            JobResult::Escapes(find_escapes(&path, atlas.as_ref(), &params,
+                                            &exits, interval*1000.0,
+                                            &job_progress))
+        });
+
+        self.job = Some(ComputeJob {
+            points: self.params.points.clone(),
+            started: Instant::now(),
+            progress: progress,
+            handle: handle,
+        });
 
-        self.params.barriers.remove(n);
-        self.update_barriers();
         Ok(())
     }
 
-    fn show_params(&self) {
-        self.params.print_params();
+    fn show_escapes(&self, escapes: &[Escape]) {
+        let max_time = self.params.escape_max_time*60.0;
+
+        for e in escapes {
+            println!("{}{}", e, if e.is_long(max_time) { " (!)" } else { "" });
+        }
+
+        let stretches = long_stretches(escapes, max_time);
+
+        if stretches.is_empty() {
+            println!("An exit is within {} min all along the track",
+                     self.params.escape_max_time);
+        }
+
+        for (a, b) in &stretches {
+            println!("Warning: more than {} min to an exit from km {:.1} to \
+                      {:.1}", self.params.escape_max_time, a/1000.0,
+                     b/1000.0);
+        }
     }
 
-    fn show_cost(&self) {
-        println!("Slope (deg)      Distance/hour (km)      Elevation/hour (m)");
+    // Rank candidate starts, read from a file in the format of the points
+    // of interest, by the time of the route from each through the rest of
+    // the waypoints
+    fn evaluate_starts(&self, fname: &str) -> Result<(), String> {
+        if self.params.points.len() < 2 {
+            return Err("At least two waypoints are needed".to_string());
+        }
 
-        for i in 0..21 {
-            // slope in degrees
-            let r = (i as f32)*5.0 - 50.0;
-            // slope as the ratio h/d
-            let s = (r*std::f32::consts::PI/180.0).tan();
-            // time cost
-            let c = Segment::time_by_steepness(s, s.abs());
-            // horizontal distance per time, km/h
-            let dpt = 3.6/c;
-            // elevation per time, m/h;
-            let ept = 3600.0*s/c;
-            println!("{:6.2}          {:6.2}                  {:8.2}",
-                     r, dpt, ept);
+        self.check_idle()?;
+
+        let candidates = PoiDb::from_file(fname)?;
+
+        if candidates.len() == 0 {
+            return Err(format!("No candidate starts in {}", fname));
+        }
+
+        let options = evaluate_starts(&self.params, self.atlas.as_ref(),
+                                      candidates.pois());
+
+        for (i, o) in options.iter().enumerate() {
+            println!("{:2}. {}", i + 1, o);
+        }
+
+        if let Some(best) = options.first().filter(|o| o.route.is_some()) {
+            println!("Run update point {} 1 to start at {}.", best.coord,
+                     best.name);
         }
+
+        Ok(())
     }
 
-    fn show_path_info(&self) {
-        if let Some(path) = &self.opt_path {
-            path.print_summary(&self.atlas);
+    fn suggest_camps(&self) -> Result<(), String> {
+        let Some(path) = &self.opt_path else {
+            return Err("No track".to_string());
+        };
+
+        // Lake outlets, springs and the fords of the route
+        let water: Vec<Coord> = self.pois.pois().iter()
+            .filter(|p| p.kind.is_water())
+            .map(|p| p.coord)
+            .chain(self.params.fords.iter().copied())
+            .collect();
+
+        if water.is_empty() {
+            println!("No water points loaded. Ignoring distance to water.");
         }
-        else {
-            println!("No track");
+
+        let camps = suggest_camps(path, self.atlas.as_ref(), &self.params,
+                                  &water);
+
+        if camps.is_empty() {
+            println!("No camp sites found");
+        }
+
+        for (i, c) in camps.iter().enumerate() {
+            println!("{:2}. {}", i + 1, c);
         }
+
+        if let Some(tx) = &self.opt_tx {
+            let _ = tx.send(CanvasMsg::SetMarkers(
+                MarkerKind::Camp, camps.iter().enumerate()
+                    .map(|(i, c)| (c.coord, format!("camp {}", i + 1)))
+                    .collect()));
+        }
+
+        Ok(())
+    }
+
+    // Set a waypoint attribute, e.g. 'set point 3 target 12:30',
+    // 'set point 1 name "Turtagrø"' or 'set point 2 type hut'
+    fn set_point(&mut self, args: &Vec<String>) -> Result<(), String> {
+        App::expects_num_arguments(args, 4)?;
+
+        let len = self.params.points.len();
+        let n = App::parse_int_range(&args[1], 1..len + 1)? - 1;
+        self.params.set_point(n, &args[2], &args[3])?;
+        self.params_stored = false;
+        self.update_waypoints();
+
+        Ok(())
+    }
+
+    // Set a barrier attribute, e.g. 'set barrier 2 buffer 30'
+    fn set_barrier(&mut self, args: &Vec<String>) -> Result<(), String> {
+        App::expects_num_arguments(args, 4)?;
+
+        let len = self.params.barriers.len();
+        let n = App::parse_int_range(&args[1], 1..len + 1)? - 1;
+        self.params.set_barrier(n, &args[2], &args[3])?;
+        self.params_stored = false;
+
+        Ok(())
     }
 
     fn set_param(&mut self, param: &str, value: &str) -> Result<(), String> {
@@ -422,57 +2192,305 @@ impl App {
                 ));
             }
         }
+        if param == "declination" || param == "north" {
+            self.update_north();
+        }
+        if param == "deviation_alarm" {
+            self.update_route_alarm();
+        }
+        if param == "km_markers" {
+            self.update_km_markers();
+        }
+        if param == "symbology" || param == "symbols" {
+            self.update_symbology();
+        }
+        self.update_cost_curve();
         return ret;
     }
 
-    fn read_params(&mut self, fname: &str) -> Result<(), String> {
-        self.params = Params::from_file(fname)?;
-        self.params_stored = true;
-        self.reset_view();
+    fn read_params(&mut self, fname: &str) -> Result<(), String> {
+        self.params = Params::from_file(fname)?;
+        self.params_stored = true;
+        self.update_cost_curve();
+        self.reset_view();
+
+        Ok(())
+    }
+
+    // Load params shared as a string. They aren't stored until written to
+    // a file.
+    fn open_share(&mut self, share: &str) -> Result<(), String> {
+        self.params = Params::from_share(share)?;
+        self.params_stored = false;
+        self.update_cost_curve();
+        self.reset_view();
+
+        Ok(())
+    }
+
+    fn store_params(&mut self, opt_fname: Option<&str>) -> Result<(), String> {
+        let res = self.params.write_params(opt_fname);
+        if let Ok(()) = res {
+            self.params_stored = true;
+        }
+
+        return res;
+    }
+
+    fn read_path(&mut self, opt_fname: Option<&str>) -> Result<(), String> {
+        let fname = opt_fname.unwrap_or(&self.params.output_fname);
+
+        let mut p = Path::read_gpx(fname)?;
+        p.update_cumulative(self.atlas.as_ref(),
+                            &Cost::from_params(&self.params));
+        self.opt_path.replace(p.clone());
+        self.path_stored = true;
+        self.update_route_alarm();
+        self.update_km_markers();
+
+        if let Some(tx) = &self.opt_tx {
+            let _ = tx.send(CanvasMsg::SetPath(p));
+        }
+
+        Ok(())
+    }
+
+    // Show a track in a comparison pane beside the map. With no file name,
+    // the current track is shown, so that it can be compared with the track
+    // computed after changing the params.
+    fn compare_path(&self, opt_fname: Option<&str>) -> Result<(), String> {
+        let Some(tx) = &self.opt_tx else {
+            return Err("No map window.".to_string());
+        };
+
+        let (path, label) = match opt_fname {
+            Some(fname) => (Path::read_gpx(fname)?, fname.to_string()),
+            None => {
+                let Some(path) = &self.opt_path else {
+                    return Err("No track".to_string());
+                };
+                (path.clone(), "Previous track".to_string())
+            },
+        };
+
+        let _ = tx.send(CanvasMsg::SetCompareTrack(Some((path, label))));
+        Ok(())
+    }
+
+    // Track name with the placeholders expanded
+    fn track_name(&self, path: &Path) -> String {
+        self.params.expand_template(&self.params.track_name, path.len())
+    }
+
+    fn store_path(&mut self, opt_fname: Option<&str>)
+                  -> Result<(), String> {
+        if let Some(path) = &self.opt_path {
+            let fname;
+
+            if let Some(some_fname) = opt_fname {
+                fname = some_fname;
+                self.params.output_fname = fname.to_string();
+                self.params_stored = false;
+            }
+            else {
+                fname = &self.params.output_fname;
+            }
+
+            // The format is given by the file extension, default is GPX
+            let fname = self.params.expand_fname(fname, path.len());
+            let format = format_from_fname(&fname).unwrap_or("gpx");
+            let stats = path.stats(&self.params.points, self.atlas.as_ref(),
+                                   &Cost::from_params(&self.params));
+            export_path(path, &fname, format, &self.track_name(path),
+                        &self.params.export_waypoints(path),
+                        Some(self.atlas.as_ref()), Some(&stats))?;
+            self.path_stored = true;
+        }
+        else {
+            println!("No track");
+        }
+
+        Ok(())
+    }
+
+    // Write a trip report of the path as HTML or Markdown
+    fn store_report(&self, fname: &str) -> Result<(), String> {
+        let Some(path) = &self.opt_path else {
+            return Err("No computed track. Run compute first.".to_string());
+        };
+
+        write_report(fname, path, &self.params, self.atlas.as_ref())?;
+        println!("Wrote {}", fname);
+
+        Ok(())
+    }
+
+    fn store_routecard(&self, fname: &str) -> Result<(), String> {
+        let Some(path) = &self.opt_path else {
+            return Err("No computed track. Run compute first.".to_string());
+        };
+
+        write_routecard(fname, path, &self.params, self.atlas.as_ref())?;
+        println!("Wrote {}", fname);
+
+        Ok(())
+    }
+
+    // Write the path on A4 map sheets as PDF. The scale is given as e.g.
+    // 25000 or 1:25000.
+    fn store_mapsheet(&self, fname: &str, opt_scale: Option<&String>)
+                      -> Result<(), String> {
+        let Some(path) = &self.opt_path else {
+            return Err("No computed track. Run compute first.".to_string());
+        };
+
+        let scale = match opt_scale {
+            Some(s) => s.trim_start_matches("1:").parse::<f32>()
+                .map_err(|_| format!("Invalid scale '{}'", s))?,
+            None => DEFAULT_SCALE,
+        };
+
+        let sheets = write_mapsheets(fname, path, &self.params, scale)?;
+        println!("Wrote {} sheets to {}", sheets, fname);
+
+        Ok(())
+    }
+
+    // Upload the path as GPX to a route service
+    fn upload(&self, service: &str) -> Result<(), String> {
+        let Some(path) = &self.opt_path else {
+            return Err("No computed track. Run compute first.".to_string());
+        };
+
+        let fname = std::env::temp_dir().join("stivalg_upload.gpx")
+            .to_string_lossy().to_string();
+        let stats = path.stats(&self.params.points, self.atlas.as_ref(),
+                               &Cost::from_params(&self.params));
+        export_path(path, &fname, "gpx", &self.track_name(path),
+                    &self.params.export_waypoints(path),
+                    Some(self.atlas.as_ref()), Some(&stats))?;
+        let gpx = fs::read(&fname)
+            .map_err(|e| format!("Unable to read {}: {}", fname, e))?;
+
+        let response = upload_gpx(service, &gpx, &self.track_name(path))?;
+        println!("Uploaded to {}: {}", service, response);
+
+        Ok(())
+    }
+
+    // Write the path as a course on a Garmin device mounted as USB mass
+    // storage
+    fn send_to_device(&self, mountpoint: &str) -> Result<(), String> {
+        let Some(path) = &self.opt_path else {
+            return Err("No computed track. Run compute first.".to_string());
+        };
+
+        let (fname, format) = device_file(mountpoint,
+                                          &self.track_name(path))?;
+        let stats = path.stats(&self.params.points, self.atlas.as_ref(),
+                               &Cost::from_params(&self.params));
+        export_path(path, &fname, format, &self.track_name(path),
+                    &self.params.export_waypoints(path),
+                    Some(self.atlas.as_ref()), Some(&stats))?;
+        println!("Wrote {}", fname);
+
+        Ok(())
+    }
+
+    fn set_debug(&mut self, what: &str, value: &str) -> Result<(), String> {
+        let on = App::parse_bool(value)?;
+
+        if self.opt_tx.is_none() {
+            return Err(format!("No map window."));
+        }
+
+        match what {
+            "graph" => self.debug_graph = on,
+            "explored" => self.debug_explored = on,
+            _ => self.debug_heatmap = on,
+        }
+
+        // Clear the layers when turned off
+        if !self.debug_graph && !self.debug_explored {
+            if let Some(tx) = &self.opt_tx {
+                let _ = tx.send(CanvasMsg::SetDebugGraph(vec![], vec![]));
+            }
+        }
+
+        if !self.debug_heatmap {
+            if let Some(tx) = &self.opt_tx {
+                let _ = tx.send(CanvasMsg::SetHeatmap(vec![]));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Write the areas searched by the last computation as GeoJSON
+    fn store_search_area(&self, fname: &str) -> Result<(), String> {
+        if self.debug.search_areas.is_empty() {
+            return Err("No search areas. Run compute first.".to_string());
+        }
+
+        self.debug.write_search_areas(fname)
+    }
+
+    fn store_graph_debug(&self, prefix: &str) -> Result<(), String> {
+        if !self.debug_keep_graph {
+            return Err("No graph kept. Run debug keep-graph true and \
+                        compute again.".to_string());
+        }
+
+        if self.debug.last_nodes.is_empty() {
+            return Err("No graph. Run compute first.".to_string());
+        }
+
+        for fname in self.debug.write_graph(prefix)? {
+            println!("Wrote {}", fname);
+        }
 
         Ok(())
     }
 
-    fn store_params(&mut self, opt_fname: Option<&str>) -> Result<(), String> {
-        let res = self.params.write_params(opt_fname);
-        if let Ok(()) = res {
-            self.params_stored = true;
+    // Remember the params before an undoable change. The params get a new
+    // generation, so that results computed from the old params are
+    // recomputed.
+    fn push_undo(&mut self, params: Params) {
+        if self.undo_stack.len() >= MAX_UNDO {
+            self.undo_stack.remove(0);
         }
 
-        return res;
+        self.undo_stack.push(params);
+        self.params.touch();
     }
 
-    fn read_path(&mut self, opt_fname: Option<&str>) {
-        let fname = opt_fname.unwrap_or(&self.params.output_fname);
+    fn undo(&mut self) -> Result<(), String> {
+        let Some(params) = self.undo_stack.pop() else {
+            return Err("Nothing to undo".to_string());
+        };
 
-        let p = Path::read_gpx(fname);
-        self.opt_path.replace(p.clone());
-        self.path_stored = true;
+        self.params = params;
+        self.params.touch();
+        self.params_stored = false;
+
+        self.update_waypoints();
+        self.update_barriers();
+        self.update_gates();
+        self.update_corridor();
+        self.update_fords();
+        self.update_pins();
+        self.update_exits();
+        self.update_north();
+        self.update_symbology();
+        self.update_route_alarm();
+        self.update_cost_curve();
 
         if let Some(tx) = &self.opt_tx {
-            let _ = tx.send(CanvasMsg::SetPath(p));
+            let _ = tx.send(CanvasMsg::SetCoveringArea(
+                self.params.covering_length, self.params.covering_width));
         }
-    }
-
-    fn store_path(&mut self, opt_fname: Option<&str>) {
-        if let Some(path) = &self.opt_path {
-            let fname;
-
-            if let Some(some_fname) = opt_fname {
-                fname = some_fname;
-                self.params.output_fname = fname.to_string();
-                self.params_stored = false;
-            }
-            else {
-                fname = &self.params.output_fname;
-            }
 
-            path.write_gpx(fname, &self.params.track_name, &self.atlas);
-            self.path_stored = true;
-        }
-        else {
-            println!("No track");
-        }
+        Ok(())
     }
 
     fn help(&self) {
@@ -483,6 +2501,44 @@ impl App {
         );
     }
 
+    // Join arguments which are quoted, e.g. '"Store', 'Skagastølstind"'
+    // into one argument without quotes.
+    fn join_quoted(args: &Vec<String>) -> Vec<String> {
+        let mut joined: Vec<String> = vec![];
+        let mut open: Option<String> = None;
+
+        for a in args {
+            if let Some(mut o) = open.take() {
+                o.push(' ');
+                if let Some(stripped) = a.strip_suffix('"') {
+                    o.push_str(stripped);
+                    joined.push(o);
+                }
+                else {
+                    o.push_str(a);
+                    open.replace(o);
+                }
+            }
+            else if let Some(stripped) = a.strip_prefix('"') {
+                if let Some(inner) = stripped.strip_suffix('"') {
+                    joined.push(inner.to_string());
+                }
+                else {
+                    open.replace(stripped.to_string());
+                }
+            }
+            else {
+                joined.push(a.clone());
+            }
+        }
+
+        if let Some(o) = open {
+            joined.push(o);
+        }
+
+        joined
+    }
+
     fn expects_num_arguments(parts: &Vec<String>, n: usize)
                              -> Result<(), String> {
         if parts.len() < n {
@@ -494,42 +2550,253 @@ impl App {
     }
 
     fn get_coord_from_map(&self, msg: &str) -> Result<Coord, String> {
-        if let Some(rx) = &self.opt_rx {
-            // request point from canvas
-            println!("{}", msg);
+        println!("{}", msg);
 
-            if let Some(tx) = &self.opt_tx {
-                let _ = tx.send(CanvasMsg::RequestPoint);
+        match self.request_from_map(CanvasMsg::RequestPoint)? {
+            AppMsg::SelectPoint(_, c) => Ok(c),
+            _ => Err("No point selected".to_string()),
+        }
+    }
+
+    // Ask whether to store the params and the track, if they have changed
+    fn offer_to_store(&mut self) {
+        if !self.params_stored {
+            println!("Save params to {}? (Y/n)", &self.params.params_fname);
+            if self.confirm_yes_no() {
+                let _ = self.params.write_params(None);
+                // FIXME: Handle error.
+            }
+        }
+
+        if !self.path_stored {
+            println!("Save track to {}? (Y/n)", &self.params.output_fname);
+            if self.confirm_yes_no() {
+                if let Err(e) = self.store_path(None) {
+                    println!("{}", e);
+                }
             }
+        }
+    }
 
-            // Wait for selected point from canvas
-            loop {
-                match rx.recv() {
-                    Ok(AppMsg::SelectPoint(c)) => {
-                        return Ok(c);
-                    },
-                    _ => { },
+    // Split a command line into the command, as in COMMAND_LIST, and its
+    // arguments, the way the command line interface does. Arguments may be
+    // quoted with "" to include spaces. Each word of the command may be
+    // abbreviated as long as it is unambiguous. The longest matching
+    // command is used.
+    fn split_command(line: &str) -> Result<(String, Vec<String>), String> {
+        let words = App::split_words(line)?;
+
+        let commands: Vec<Vec<&str>> = COMMAND_LIST.iter()
+            .map(|c| c.split(' ')
+                 .take_while(|w| !w.starts_with(['<', '[']) && *w != "...")
+                 .collect())
+            .collect();
+
+        // Expand the words one by one. A word matching a keyword exactly
+        // is taken as is, even if it abbreviates other keywords.
+        let mut cmd: Vec<&str> = vec![];
+        let mut best: Option<usize> = None;
+
+        for (i, word) in words.iter().enumerate() {
+            let following: Vec<&str> = commands.iter()
+                .filter(|c| c.len() > i && c.starts_with(&cmd))
+                .map(|c| c[i])
+                .collect();
+
+            let keyword = if following.contains(&word.as_str()) {
+                word.as_str()
+            }
+            else {
+                let mut matching: Vec<&str> = following.into_iter()
+                    .filter(|k| k.starts_with(word.as_str()))
+                    .collect();
+                matching.sort();
+                matching.dedup();
+
+                match matching.len() {
+                    0 => break,
+                    1 => matching[0],
+                    _ => return Err(format!("Ambiguous command '{}': {}",
+                                            word, matching.join(", "))),
                 }
+            };
+
+            cmd.push(keyword);
+            if commands.contains(&cmd) {
+                best = Some(cmd.len());
             }
         }
-        else {
-            return Err(format!("No map window."));
+
+        let Some(n) = best else {
+            return Err(format!("Unknown command '{}'", line));
+        };
+
+        Ok((cmd[..n].join(" "), words[n..].to_vec()))
+    }
+
+    // Split a line into words separated by whitespace. Text within "" is
+    // part of one word.
+    fn split_words(line: &str) -> Result<Vec<String>, String> {
+        let mut words = vec![];
+        let mut word: Option<String> = None;
+        let mut quoted = false;
+
+        for ch in line.chars() {
+            if ch == '"' {
+                quoted = !quoted;
+                word.get_or_insert_with(String::new);
+            }
+            else if ch.is_whitespace() && !quoted {
+                words.extend(word.take());
+            }
+            else {
+                word.get_or_insert_with(String::new).push(ch);
+            }
+        }
+
+        if quoted {
+            return Err("Unterminated quote".to_string());
+        }
+        words.extend(word);
+
+        Ok(words)
+    }
+
+    // Whether questions can be answered in the terminal. Not in GUI-only
+    // mode, nor while running a command from the control socket.
+    fn can_prompt() -> bool {
+        !CONFIG.gui_only && !control::is_remote()
+    }
+
+    fn parse_bool(boolstr: &str) -> Result<bool, String> {
+        match boolstr {
+            "on" => Ok(true),
+            "off" => Ok(false),
+            s => Err(format!("Expected on/off, got '{}'", s)),
+        }
+    }
+
+    // Waypoints separated by ',', given as coords or place names. Place
+    // names are kept as waypoint names.
+    fn parse_points(pointstr: &str, pois: &PoiDb)
+                    -> Result<Vec<(Coord, String)>, String> {
+        let points = pointstr.split(',')
+            .map(|p| p.trim())
+            .map(|p| match (p.parse(), pois.find(p)) {
+                (Ok(c), _) => Ok((c, "".to_string())),
+                (_, Some(poi)) => Ok((poi.coord, poi.name.clone())),
+                _ => Err(format!("Expected coord or place name, got '{}'",
+                                 p)),
+            })
+            .collect::<Result<Vec<(Coord, String)>, String>>()?;
+
+        if points.len() < 2 {
+            return Err("At least two points are needed".to_string());
         }
+
+        Ok(points)
     }
 
     fn parse_coord(&self, coordstr: &str) -> Result<Coord, String> {
         if let Ok(coord) = coordstr.parse() {
             return Ok(coord);
         }
+        else if let Some(poi) = self.pois.find(coordstr) {
+            // Place name
+            return Ok(poi.coord);
+        }
+        else if coordstr == "map-center" {
+            return match &self.viewport {
+                Some(v) => Ok(v.center),
+                None => Err("No map window.".to_string()),
+            };
+        }
         else {
-            return Err(format!("Expected coord, got '{}'", coordstr));
+            return Err(format!("Expected coord or place name, got '{}'",
+                               coordstr));
+        }
+    }
+
+    // Handle a message sent from the map window on its own initiative
+    fn handle_app_msg(&mut self, msg: AppMsg) {
+        match msg {
+            AppMsg::AddPoi(poi) => {
+                println!("Adding waypoint {}", poi);
+                self.push_undo(self.params.clone());
+                self.params.push_point(poi.coord);
+                self.params_stored = false;
+                self.update_waypoints();
+            },
+            AppMsg::AddPoint(c) => {
+                println!("Adding waypoint {}", c);
+                self.push_undo(self.params.clone());
+                self.params.push_point(c);
+                self.params_stored = false;
+                self.update_waypoints();
+            },
+            AppMsg::SetCostCurve(curve) => {
+                // The curve replaces the current cost model
+                self.push_undo(self.params.clone());
+                self.params.cost_curve = curve;
+                self.params.cost_model = "curve".to_string();
+                self.params_stored = false;
+                self.update_cost_curve();
+            },
+            AppMsg::RemovePoint(n) if n < self.params.points.len() => {
+                println!("Removing waypoint {}", n + 1);
+                self.push_undo(self.params.clone());
+                self.params.remove_point(n);
+                self.params_stored = false;
+                self.update_waypoints();
+            },
+            AppMsg::SetPointName(n, name) if n < self.params.points.len() => {
+                self.push_undo(self.params.clone());
+                self.params.point_info[n].name = name;
+                self.params_stored = false;
+                self.update_waypoints();
+            },
+            AppMsg::RemoveBarrier(n) if n < self.params.barriers.len() => {
+                println!("Removing barrier {}", n + 1);
+                self.push_undo(self.params.clone());
+                self.params.barriers.remove(n);
+                self.params_stored = false;
+                self.update_barriers();
+            },
+            AppMsg::RemovePath => {
+                println!("Removing track");
+                self.opt_path = None;
+                self.path_stored = true;
+                self.update_route_alarm();
+                self.update_km_markers();
+                if let Some(tx) = &self.opt_tx {
+                    let _ = tx.send(CanvasMsg::SetPath(Path::new()));
+                }
+            },
+            AppMsg::SetViewport(v) => {
+                if (v.ne.e - v.sw.e).max(v.ne.n - v.sw.n) <= PREFETCH_EXTENT {
+                    self.atlas.prefetch(&v.sw, &v.ne);
+                }
+                self.viewport = Some(v);
+            },
+            AppMsg::DragTrack(from, to) => {
+                if let Err(e) = self.drag_track(from, to) {
+                    println!("{}", e);
+                }
+            },
+            AppMsg::Command(cmd) => {
+                if let Err(e) = self.execute_line(&cmd, &vec![]) {
+                    println!("{}", e);
+                }
+            },
+            _ => { },
         }
     }
 
     fn update_waypoints(&self) {
         if let Some(tx) = &self.opt_tx {
             let _ = tx.send(CanvasMsg::SetWaypoints(
-                self.params.points.clone()));
+                self.params.points.clone(), self.params.point_names(),
+                self.params.point_types()));
         }
     }
 
@@ -540,6 +2807,91 @@ impl App {
         }
     }
 
+    fn update_gates(&self) {
+        if let Some(tx) = &self.opt_tx {
+            let _ = tx.send(CanvasMsg::SetGates(self.params.gates.clone()));
+        }
+    }
+
+    fn update_corridor(&self) {
+        if let Some(tx) = &self.opt_tx {
+            let _ = tx.send(CanvasMsg::SetCorridor(
+                self.params.corridor.clone()));
+        }
+    }
+
+    fn ford_markers(params: &Params) -> Vec<(Coord, String)> {
+        params.fords.iter().enumerate()
+            .map(|(i, f)| (*f, format!("ford {}", i + 1)))
+            .collect()
+    }
+
+    fn update_fords(&self) {
+        if let Some(tx) = &self.opt_tx {
+            let _ = tx.send(CanvasMsg::SetMarkers(
+                MarkerKind::Ford, App::ford_markers(&self.params)));
+        }
+    }
+
+    fn pin_markers(params: &Params) -> Vec<(Coord, String)> {
+        params.pins.iter().enumerate()
+            .map(|(i, p)| (*p, format!("pin {}", i + 1)))
+            .collect()
+    }
+
+    fn update_pins(&self) {
+        if let Some(tx) = &self.opt_tx {
+            let _ = tx.send(CanvasMsg::SetMarkers(
+                MarkerKind::Pin, App::pin_markers(&self.params)));
+            let _ = tx.send(CanvasMsg::SetPinAreas(
+                self.params.pin_areas.clone()));
+        }
+    }
+
+    fn exit_markers(params: &Params) -> Vec<(Coord, String)> {
+        params.exits.iter().enumerate()
+            .map(|(i, e)| (*e, format!("exit {}", i + 1)))
+            .collect()
+    }
+
+    fn update_exits(&self) {
+        if let Some(tx) = &self.opt_tx {
+            let _ = tx.send(CanvasMsg::SetMarkers(
+                MarkerKind::Exit, App::exit_markers(&self.params)));
+        }
+    }
+
+    fn update_north(&self) {
+        if let Some(tx) = &self.opt_tx {
+            let _ = tx.send(CanvasMsg::SetNorth(
+                self.params.north_declination(), self.params.north != "true"));
+        }
+    }
+
+    fn update_km_markers(&self) {
+        if let Some(tx) = &self.opt_tx {
+            let markers = self.opt_path.as_ref()
+                .map(|p| p.distance_markers(self.params.km_markers*1000.0))
+                .unwrap_or_default();
+            let _ = tx.send(CanvasMsg::SetMarkers(MarkerKind::Km, markers));
+        }
+    }
+
+    fn update_symbology(&self) {
+        if let Some(tx) = &self.opt_tx {
+            let _ = tx.send(CanvasMsg::SetSymbology(self.params.symbology()));
+        }
+    }
+
+    // Watch the GPS position against the current path
+    fn update_route_alarm(&self) {
+        if let Some(gps) = &self.gps {
+            let route = self.opt_path.as_ref()
+                .map(|p| Barrier::from_vec(p.into_iter().cloned().collect()));
+            gps.set_route(route, self.params.deviation_alarm);
+        }
+    }
+
     fn reset_view(&self) {
         if let Some(tx) = &self.opt_tx {
             let _ = tx.send(CanvasMsg::ResetView);
@@ -554,7 +2906,12 @@ impl CmdApp for App {
 
     fn execute_line(&mut self, cmd: &str, args: &Vec<String>)
                     -> Result<(), String> {
+        self.poll_compute();
         println!("Executing command {} - {}", cmd, args.join(" "));
+        let args = &App::join_quoted(args);
+        // Failing commands return early and leave nothing to undo
+        let before = UNDOABLE.contains(&cmd).then(|| self.params.clone());
+
         match cmd {
             "add point" => {
                 self.add_point(args)?;
@@ -562,6 +2919,13 @@ impl CmdApp for App {
             "update point" => {
                 self.update_point(args)?
             },
+            "snap point" => {
+                self.snap_point(args)?;
+            },
+            "goto" => {
+                App::expects_num_arguments(args, 1)?;
+                self.goto(args)?;
+            },
             "rm point" => {
                 self.rm_point(args)?;
             },
@@ -571,10 +2935,68 @@ impl CmdApp for App {
             "rm barrier" => {
                 self.rm_barrier(args)?;
             },
+            "add gate" => {
+                self.add_gate(args)?;
+            },
+            "rm gate" => {
+                self.rm_gate(args)?;
+            },
+            "add corridor" => {
+                self.add_corridor(args)?;
+            },
+            "rm corridor" => {
+                self.rm_corridor()?;
+            },
+            "add ford" => {
+                self.add_ford(args)?;
+            },
+            "rm ford" => {
+                self.rm_ford(args)?;
+            },
+            "add crossings" => {
+                self.add_crossings()?;
+            },
+            "pin point" => {
+                self.pin_point(args)?;
+            },
+            "unpin point" => {
+                self.unpin_point(args)?;
+            },
+            "pin area" => {
+                self.pin_area(args)?;
+            },
+            "unpin area" => {
+                self.unpin_area(args)?;
+            },
+            "add exit" => {
+                self.add_exit(args)?;
+            },
+            "rm exit" => {
+                self.rm_exit(args)?;
+            },
+            "add hiker" => {
+                App::expects_num_arguments(args, 3)?;
+                self.add_hiker(&args[0], &args[1], &args[2])?;
+            },
+            "rm hiker" => {
+                App::expects_num_arguments(args, 1)?;
+                self.rm_hiker(&args[0])?;
+            },
             "read params" => {
                 App::expects_num_arguments(args, 1)?;
                 self.read_params(&args[0])?;
             },
+            "share" => {
+                println!("{}", self.params.to_share());
+            },
+            "open share" => {
+                App::expects_num_arguments(args, 1)?;
+                self.open_share(&args[0])?;
+            },
+            "diff params" => {
+                App::expects_num_arguments(args, 1)?;
+                self.params.print_diff(&Params::from_file(&args[0])?);
+            },
             "store params" => {
                 self.store_params(<dyn CmdApp>::opt_part(args, 0))?;
             },
@@ -587,18 +3009,132 @@ impl CmdApp for App {
             "show track info" => {
                 self.show_path_info();
             },
+            "show track stats" => {
+                self.show_path_stats()?;
+            },
+            "show maps" => {
+                self.show_maps()?;
+            },
+            "show gps" => {
+                self.show_gps()?;
+            },
+            "show pois" => {
+                App::expects_num_arguments(args, 1)?;
+                self.show_pois(&args[0])?;
+            },
             "set" => {
                 App::expects_num_arguments(args, 2)?;
-                self.set_param(&args[0], &args[1])?;
+                if args[0] == "point" {
+                    self.set_point(args)?;
+                }
+                else if args[0] == "barrier" {
+                    self.set_barrier(args)?;
+                }
+                else {
+                    self.set_param(&args[0], &args[1])?;
+                }
             },
             "open track" => {
-                self.read_path(<dyn CmdApp>::opt_part(args, 0));
+                self.read_path(<dyn CmdApp>::opt_part(args, 0))?;
+            },
+            "compare track" => {
+                App::expects_num_arguments(args, 1)?;
+                self.compare_path(Some(&args[0]))?;
+            },
+            "compare current" => {
+                self.compare_path(None)?;
+            },
+            "compare off" => {
+                if let Some(tx) = &self.opt_tx {
+                    let _ = tx.send(CanvasMsg::SetCompareTrack(None));
+                }
             },
             "store track" => {
-                self.store_path(<dyn CmdApp>::opt_part(args, 0));
+                self.store_path(<dyn CmdApp>::opt_part(args, 0))?;
+            },
+            "store report" => {
+                App::expects_num_arguments(args, 1)?;
+                self.store_report(&args[0])?;
+            },
+            "store routecard" => {
+                App::expects_num_arguments(args, 1)?;
+                self.store_routecard(&args[0])?;
+            },
+            "store mapsheet" => {
+                App::expects_num_arguments(args, 1)?;
+                self.store_mapsheet(&args[0], args.get(1))?;
+            },
+            "upload token" => {
+                App::expects_num_arguments(args, 2)?;
+                store_token(&args[0], &args[1])?;
+            },
+            "upload" => {
+                App::expects_num_arguments(args, 1)?;
+                self.upload(&args[0])?;
+            },
+            "send device" => {
+                App::expects_num_arguments(args, 1)?;
+                self.send_to_device(&args[0])?;
+            },
+            "store searcharea" => {
+                App::expects_num_arguments(args, 1)?;
+                self.store_search_area(&args[0])?;
+            },
+            "store graph-debug" => {
+                App::expects_num_arguments(args, 1)?;
+                self.store_graph_debug(&args[0])?;
             },
             "compute" => {
-                self.compute()?;
+                self.start_compute()?;
+            },
+            "compute view" => {
+                self.compute_in_view()?;
+            },
+            "compute leg" => {
+                App::expects_num_arguments(args, 1)?;
+                self.compute_leg(&args[0])?;
+            },
+            "compute escapes" => {
+                App::expects_num_arguments(args, 1)?;
+                self.compute_escapes(&args[0])?;
+            },
+            "evaluate starts" => {
+                App::expects_num_arguments(args, 1)?;
+                self.evaluate_starts(&args[0])?;
+            },
+            "reroute from-here" => {
+                self.reroute_from_here()?;
+            },
+            "optimize" => {
+                self.optimize(<dyn CmdApp>::opt_part(args, 0))?;
+            },
+            "status" => {
+                self.show_status();
+            },
+            "cancel" => {
+                self.cancel_compute()?;
+            },
+            "undo" => {
+                self.undo()?;
+            },
+            "suggest camps" => {
+                self.suggest_camps()?;
+            },
+            "debug graph" => {
+                App::expects_num_arguments(args, 1)?;
+                self.set_debug("graph", &args[0])?;
+            },
+            "debug explored" => {
+                App::expects_num_arguments(args, 1)?;
+                self.set_debug("explored", &args[0])?;
+            },
+            "debug heatmap" => {
+                App::expects_num_arguments(args, 1)?;
+                self.set_debug("heatmap", &args[0])?;
+            },
+            "debug keep-graph" => {
+                App::expects_num_arguments(args, 1)?;
+                self.debug_keep_graph = App::parse_bool(&args[0])?;
             },
             "flush maps" => {
                 println!("Not implemented.");
@@ -611,24 +3147,29 @@ impl CmdApp for App {
             },
         }
 
+        if let Some(params) = before {
+            self.push_undo(params);
+        }
+
         Ok(())
     }
 
     fn exit(&mut self) {
-        if !self.params_stored {
-            println!("Save params to {}? (Y/n)", &self.params.params_fname);
-            if self.confirm_yes_no() {
-                let _ = self.params.write_params(None);
-                // FIXME: Handle error.
-            }
+        if let Some(job) = self.job.take() {
+            job.progress.cancel();
+            let _ = self.finish_compute(job);
         }
 
-        if !self.path_stored {
-            println!("Save track to {}? (Y/n)", &self.params.output_fname);
-            if self.confirm_yes_no() {
-                self.store_path(None);
+        // There is no terminal to answer in GUI-only mode or on the
+        // control socket
+        if !App::can_prompt() {
+            if !self.params_stored {
+                println!("Quitting without storing params");
             }
         }
+        else {
+            self.offer_to_store();
+        }
 
         if let Some(tx) = &self.opt_tx {
             let _ = tx.send(CanvasMsg::Quit);
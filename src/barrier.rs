@@ -4,21 +4,64 @@ use std::fmt;
 use std::fmt::Display;
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
-#[serde(transparent)]
+#[serde(from = "BarrierRepr", into = "BarrierRepr")]
 pub struct Barrier {
     pub points: Vec<Coord>,
+    // Minimum distance (meters) the route must keep from the barrier
+    pub buffer_m: f32,
+}
+
+// Barriers without a buffer are stored as a plain list of points, as in
+// params files made before the buffer was added.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum BarrierRepr {
+    Points(Vec<Coord>),
+    Buffered {
+        points: Vec<Coord>,
+        #[serde(default)]
+        buffer_m: f32,
+    },
+}
+
+impl From<BarrierRepr> for Barrier {
+    fn from(repr: BarrierRepr) -> Self {
+        match repr {
+            BarrierRepr::Points(points) => Barrier::from_vec(points),
+            BarrierRepr::Buffered { points, buffer_m } => Self {
+                points: points,
+                buffer_m: buffer_m,
+            },
+        }
+    }
+}
+
+impl From<Barrier> for BarrierRepr {
+    fn from(b: Barrier) -> Self {
+        if b.buffer_m > 0.0 {
+            BarrierRepr::Buffered {
+                points: b.points,
+                buffer_m: b.buffer_m,
+            }
+        }
+        else {
+            BarrierRepr::Points(b.points)
+        }
+    }
 }
 
 impl Barrier {
     pub fn new() -> Self {
         Self {
             points: vec![],
+            buffer_m: 0.0,
         }
     }
 
     pub fn from_vec(points: Vec<Coord>) -> Self {
         Self {
             points: points,
+            buffer_m: 0.0,
         }
     }
 
@@ -58,6 +101,27 @@ impl Barrier {
             Barrier::is_crossing_line(p1, p2, a1, a2);
     }
 
+    // Points where a line segment crosses the barrier
+    pub fn crossing_points(&self, p1: &Coord, p2: &Coord) -> Vec<Coord> {
+        let mut points = vec![];
+        let len = self.points.len();
+
+        for i in 0..len - 1 {
+            if self.is_crossing_segment(i, p1, p2) {
+                let a1 = &self.points[i];
+                let a2 = &self.points[i + 1];
+                // The areas are proportional to the distances of p1 and p2
+                // from the barrier segment
+                let area1 = Barrier::triangle_area(a1, a2, p1);
+                let area2 = Barrier::triangle_area(a1, a2, p2);
+                let t = area1/(area1 - area2);
+                points.push(*p1 + (*p2 - *p1)*t);
+            }
+        }
+
+        points
+    }
+
     // Check whether a line segment crosses the barrier
     pub fn is_crossing(&self, p1: &Coord, p2: &Coord) -> bool {
         let len = self.points.len();
@@ -71,6 +135,47 @@ impl Barrier {
         false
     }
 
+    // Close the barrier into a polygon by connecting the last point to the
+    // first.
+    pub fn closed(&self) -> Self {
+        let mut points = self.points.clone();
+
+        if let Some(first) = self.points.first() {
+            points.push(*first);
+        }
+
+        Self {
+            points: points,
+            buffer_m: self.buffer_m,
+        }
+    }
+
+    // Check whether a point is inside the polygon formed by the barrier
+    // points. Uses the even-odd rule.
+    pub fn contains(&self, p: &Coord) -> bool {
+        let len = self.points.len();
+        let mut inside = false;
+
+        if len < 3 {
+            return false;
+        }
+
+        let mut j = len - 1;
+        for i in 0..len {
+            let a = &self.points[i];
+            let b = &self.points[j];
+
+            if (a.n > p.n) != (b.n > p.n)
+                && p.e < (b.e - a.e)*(p.n - a.n)/(b.n - a.n) + a.e {
+                inside = !inside;
+            }
+
+            j = i;
+        }
+
+        inside
+    }
+
     pub fn distance_from_segment_sq(&self, i: usize, p: &Coord) -> f32 {
         let p1 = &self.points[i];
         let p2 = &self.points[i + 1];
@@ -105,6 +210,44 @@ impl Barrier {
         return (*p - pp).abs_sq();
     }
 
+    // Check whether a line segment comes closer to the barrier than its
+    // buffer distance
+    pub fn is_within_buffer(&self, p1: &Coord, p2: &Coord) -> bool {
+        let d = self.buffer_m;
+        let len = self.points.len();
+
+        if d <= 0.0 || len < 2 {
+            return false;
+        }
+
+        // Quick rejection by bounding boxes
+        let (mut n, mut s, mut e, mut w) = (
+            f32::NEG_INFINITY, f32::INFINITY,
+            f32::NEG_INFINITY, f32::INFINITY,
+        );
+        for b in &self.points {
+            n = n.max(b.n);
+            s = s.min(b.n);
+            e = e.max(b.e);
+            w = w.min(b.e);
+        }
+        if p1.n.min(p2.n) > n + d || p1.n.max(p2.n) < s - d
+            || p1.e.min(p2.e) > e + d || p1.e.max(p2.e) < w - d {
+            return false;
+        }
+
+        if self.is_crossing(p1, p2) {
+            return true;
+        }
+
+        // Without crossing, the shortest distance between two segments is
+        // from an end point of one of them to the other.
+        let segment = Barrier::from_vec(vec![*p1, *p2]);
+
+        self.distance_sq(p1) < d*d || self.distance_sq(p2) < d*d
+            || self.points.iter().any(|b| segment.distance_sq(b) < d*d)
+    }
+
     pub fn distance_sq(&self, p: &Coord) -> f32 {
         let mut dsq = f32::INFINITY;
         let len = self.points.len();
@@ -125,6 +268,9 @@ impl Display for Barrier {
             .join(", ");
 
         write!(formatter, "{}", str)?;
+        if self.buffer_m > 0.0 {
+            write!(formatter, " (buffer {}m)", self.buffer_m)?;
+        }
         Ok(())
     }
 }
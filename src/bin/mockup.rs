@@ -1,12 +1,57 @@
-use stivalg::{Path, Params};
-use hoydedata::Atlas;
+use stivalg::{Cost, ElevationSource, Path, Params, SyntheticTerrain};
+use clap::arg;
+use hoydedata::{Atlas, Coord};
 
-fn main() {
-    let atlas = Atlas::new_mockup();
-    let params = Params::from_config();
+// Compute a path on the mockup atlas, or on a synthetic terrain, so that
+// algorithm changes can be tested deterministically without real map data.
+// Synthetic terrains are laid out around the midpoint of the start and end
+// points.
+fn main() -> Result<(), String> {
+    let matches = clap::Command::new("mockup")
+        .args([
+            arg!(-t --terrain <TERRAIN>
+                 "Synthetic terrain: plane, ridge, valley or fractal"),
+            arg!(--seed <SEED> "Seed of the fractal terrain"),
+            arg!(--start <COORD> "Start point"),
+            arg!(--end <COORD> "End point"),
+        ])
+        .get_matches();
 
-    if let Some(p) = Path::from_points(&params, &atlas) {
-        p.print_summary(&atlas);
+    let mut params = Params::from_config();
+
+    for name in ["start", "end"] {
+        if let Some(cstr) = matches.get_one::<String>(name) {
+            params.push_point(cstr.parse::<Coord>()?);
+        }
+    }
+
+    let seed = match matches.get_one::<String>("seed") {
+        Some(s) => s.parse::<u64>()
+            .map_err(|_| format!("Expected integer seed, got '{}'", s))?,
+        None => 0,
+    };
+
+    let atlas: Box<dyn ElevationSource> =
+        match matches.get_one::<String>("terrain") {
+            Some(name) => {
+                if params.points.len() < 2 {
+                    return Err(format!("Terrain {} needs start and end \
+                                        points", name));
+                }
+
+                let origin = (params.points[0] + params.points[1])*0.5;
+                let terrain = SyntheticTerrain::new(name, origin, seed)?;
+                params.barriers.append(&mut terrain.barriers());
+
+                Box::new(terrain)
+            },
+            None => Box::new(Atlas::new_mockup()),
+        };
+
+    if let Some(p) = Path::from_points(&params, atlas.as_ref()) {
+        println!("Path: {}", p);
+        p.stats(&params.points, atlas.as_ref(), &Cost::from_params(&params))
+            .print_summary();
 //        println!("Storing track to {}", &params.output);
 //        p.write_gpx(&params.output);
     }
@@ -16,4 +61,6 @@ fn main() {
                  .collect::<Vec<String>>()
                  .join(", "))
     }
+
+    Ok(())
 }
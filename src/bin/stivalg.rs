@@ -1,9 +1,39 @@
-use stivalg::{CONFIG, App, init_with_canvas, run_cmdui};
+use stivalg::{CONFIG, App, export_file, format_from_fname, init_with_canvas,
+              print_file_info, run_cmdui, run_selftest, set_crs, Params};
 
 use hoydedata::{set_map_dir, unmount_all_maps};
 use cmdui::CmdApp;
 
 fn run_headless() -> Result<(), String> {
+    if CONFIG.command == "selftest" {
+        if !run_selftest() {
+            return Err("Self test failed".to_string());
+        }
+
+        return Ok(());
+    }
+    else if CONFIG.command == "info" {
+        return print_file_info(&CONFIG.input_fname);
+    }
+    else if CONFIG.command == "export" {
+        // Default to the format given by the output file name
+        let format = if CONFIG.export_format == "" {
+            format_from_fname(&CONFIG.output_fname).unwrap_or("gpx")
+        }
+        else {
+            &CONFIG.export_format
+        };
+
+        return export_file(&CONFIG.input_fname, format,
+                           &CONFIG.output_fname);
+    }
+    else if CONFIG.command == "diff" {
+        let params = Params::from_file(&CONFIG.input_fname)?;
+        params.print_diff(&Params::from_file(&CONFIG.diff_fname)?);
+
+        return Ok(());
+    }
+
     let mut app = App::new(None, None)?;
 
     match CONFIG.command.as_str() {
@@ -13,7 +43,7 @@ fn run_headless() -> Result<(), String> {
             app.exit();
         }
         "" => {
-            run_cmdui(&mut app);
+            run_cmdui(app);
         },
         _ => {
             println!("Invalid command");
@@ -25,6 +55,7 @@ fn run_headless() -> Result<(), String> {
 
 fn main() -> Result<(), String> {
     set_map_dir(&CONFIG.map_dir());
+    set_crs(&CONFIG.crs)?;
 
     if CONFIG.headless {
         run_headless()?;
@@ -0,0 +1,170 @@
+use crate::barrier::Barrier;
+use crate::elevation::ElevationSource;
+use crate::params::Params;
+use crate::path::Path;
+
+use hoydedata::Coord;
+use std::collections::BTreeMap;
+use std::fmt;
+
+// Spacing of candidate positions (meters)
+const CAMP_GRID: f32 = 20.0;
+// Half size of the area needed for a tent (meters)
+const TENT_SIZE: f32 = 2.0;
+// Minimum distance between suggested camps (meters)
+const CAMP_SEPARATION: f32 = 200.0;
+// Number of camps to suggest
+const MAX_CAMPS: usize = 10;
+
+pub struct Camp {
+    pub coord: Coord,
+    // Steepest slope within the tent area (degrees)
+    pub slope: f32,
+    pub track_distance: f32,
+    pub water_distance: Option<f32>,
+}
+
+impl fmt::Display for Camp {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(formatter, "{} slope {:.1}°, {:.0}m from track", self.coord,
+               self.slope, self.track_distance)?;
+
+        if let Some(d) = self.water_distance {
+            write!(formatter, ", {:.0}m from water", d)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Steepest gradient within a tent sized window around c
+fn max_gradient(c: &Coord, atlas: &dyn ElevationSource) -> f32 {
+    let mut max: f32 = 0.0;
+
+    for (de, dn) in [(0.0, 0.0), (-TENT_SIZE, -TENT_SIZE),
+                     (TENT_SIZE, -TENT_SIZE), (-TENT_SIZE, TENT_SIZE),
+                     (TENT_SIZE, TENT_SIZE)] {
+        let Some((_, dx, dy)) = atlas.lookup_with_gradient(
+            &(*c + Coord::new(de, dn))) else {
+            // No camping outside the elevation data
+            return f32::INFINITY;
+        };
+        max = max.max((dx*dx + dy*dy).sqrt());
+    }
+
+    max
+}
+
+// Grid cells within the corridor of a track, with their distance to the
+// track. Only the cells near each point of the track are looked at.
+fn corridor_cells(track: &Barrier, corridor: f32)
+                  -> BTreeMap<(i64, i64), f32> {
+    let reach = (corridor/CAMP_GRID).ceil() as i64 + 1;
+    let mut cells = BTreeMap::new();
+
+    for i in 0..track.len() {
+        // The segments before and after the point
+        let near = Barrier::from_vec(
+            track.points[i.saturating_sub(1)..(i + 2).min(track.len())]
+                .to_vec());
+        let p = track.points[i];
+        let (ge, gn) = ((p.e/CAMP_GRID).round() as i64,
+                        (p.n/CAMP_GRID).round() as i64);
+
+        for gn in gn - reach..=gn + reach {
+            for ge in ge - reach..=ge + reach {
+                let c = Coord::new(ge as f32*CAMP_GRID, gn as f32*CAMP_GRID);
+                let d = near.distance_sq(&c).sqrt();
+                if d > corridor {
+                    continue;
+                }
+
+                cells.entry((ge, gn))
+                    .and_modify(|v: &mut f32| *v = v.min(d))
+                    .or_insert(d);
+            }
+        }
+    }
+
+    cells
+}
+
+// Find flat areas suitable for camping in a corridor around the path. If
+// any water points are given, camps must be near water. Camps are ranked by
+// flatness.
+pub fn suggest_camps(path: &Path, atlas: &dyn ElevationSource,
+                     params: &Params, water: &[Coord]) -> Vec<Camp> {
+    let corridor = params.camp_corridor;
+    let max_gradient_allowed = params.camp_max_slope.to_radians().tan();
+
+    // Use a simplified path, with long segments split, so that the cells
+    // around its points cover the corridor
+    let spacing = CAMP_GRID.max(corridor/4.0);
+    let mut track = Barrier::new();
+    for c in path {
+        let Some(last) = track.points.last().copied() else {
+            track.add_point(*c);
+            continue;
+        };
+        let d = (*c - last).abs();
+        if d <= spacing {
+            continue;
+        }
+
+        let steps = (d/spacing).ceil() as usize;
+        for i in 1..steps {
+            track.add_point(last + (*c - last)*(i as f32/steps as f32));
+        }
+        track.add_point(*c);
+    }
+
+    if track.len() < 2 {
+        return vec![];
+    }
+
+    let mut candidates = vec![];
+
+    for ((ge, gn), track_distance) in corridor_cells(&track, corridor) {
+        let c = Coord::new(ge as f32*CAMP_GRID, gn as f32*CAMP_GRID);
+
+        let water_distance = water.iter()
+            .map(|wp| (*wp - c).abs())
+            .reduce(f32::min);
+        if let Some(d) = water_distance {
+            if d > params.camp_water_distance {
+                continue;
+            }
+        }
+
+        let g = max_gradient(&c, atlas);
+        if g > max_gradient_allowed {
+            continue;
+        }
+
+        candidates.push(Camp {
+            coord: c,
+            slope: g.atan().to_degrees(),
+            track_distance: track_distance,
+            water_distance: water_distance,
+        });
+    }
+
+    // Flattest first, then nearest to the track
+    candidates.sort_by(|a, b| a.slope.total_cmp(&b.slope)
+                       .then(a.track_distance.total_cmp(&b.track_distance)));
+
+    // Skip candidates too close to a better one
+    let mut camps: Vec<Camp> = vec![];
+
+    for c in candidates {
+        if camps.len() >= MAX_CAMPS {
+            break;
+        }
+
+        if camps.iter().all(|k| (k.coord - c.coord).abs() > CAMP_SEPARATION) {
+            camps.push(c);
+        }
+    }
+
+    camps
+}
@@ -1,17 +1,25 @@
-use crate::app::{App, run_cmdui};
+use crate::app::{App, COMMAND_LIST, run_cmdui_with_canvas, run_gui};
 use crate::barrier::Barrier;
 use crate::channel::{AppMsg, CanvasMsg, CanvasReceiver, CanvasSender,
-                     AppReceiver, AppSender,
+                     AppReceiver, AppSender, MarkerKind, Viewport,
                      create_canvas_channel, create_app_channel};
+use crate::config::CONFIG;
+use crate::console::{Console, capture_stdout};
+use crate::params::point_label;
+use crate::pick::{PickIndex, Pickable};
 use crate::path::Path;
+use crate::cost_plot::CostPlot;
+use crate::crs::{bearing, from_latlon, to_latlon};
+use crate::poi::{Poi, PoiKind, search};
+use crate::symbology::{LineLayer, Rgb, Symbology};
 use crate::egui_map::{init_with_app, EguiMapState};
 
 use eframe::CreationContext;
-use egui::ViewportCommand;
+use egui::{Key, Modifiers, ViewportCommand};
 use galileo::{Color, MapBuilder, MapView, Map};
 use galileo::control::{EventPropagation, MouseButton, UserEvent,
                        UserEventHandler};
-use galileo::layer::{FeatureId, FeatureLayer};
+use galileo::layer::{FeatureId, FeatureLayer, RasterTileLayer};
 use galileo::layer::feature_layer::Feature;
 use galileo::layer::raster_tile_layer::{RasterTileLayerBuilder,
                                         RestTileProvider};
@@ -19,7 +27,7 @@ use galileo::render::point_paint::PointPaint;
 use galileo::render::render_bundle::RenderBundle;
 use galileo::render::text::{TextStyle, RustybuzzRasterizer};
 use galileo::render::text::text_service::TextService;
-use galileo::symbol::SimpleContourSymbol;
+use galileo::symbol::{CirclePointSymbol, SimpleContourSymbol};
 use galileo::symbol::Symbol;
 use galileo_types::Geometry;
 use galileo_types::cartesian::{Point2, Point3, Vector2};
@@ -30,15 +38,19 @@ use galileo_types::geometry_type::{CartesianSpace2d, GeoSpace2d};
 use galileo_types::impls::Contour;
 use hoydedata::Coord;
 use parking_lot::RwLock;
+use std::collections::HashMap;
 use std::f32::consts::PI;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use galileo::control::MapController;
 
 fn terminal_controller(tx: CanvasSender, rx: AppReceiver) {
-    let app_result = App::new(Some(tx), Some(rx));
+    // Replies to requests from the app are forwarded on a separate channel
+    let (reply_tx, reply_rx) = create_app_channel();
+    let app_result = App::new(Some(tx), Some(reply_rx));
     match app_result {
-        Ok(mut app) => {
-            run_cmdui(&mut app);
+        Ok(app) => {
+            run_cmdui_with_canvas(app, rx, reply_tx);
         },
         Err(s) => {
             // FIXME: send exit msg to tx before quitting
@@ -53,17 +65,41 @@ fn initialize_font_service() {
         "data/fonts");
 }
 
+// Controller thread of the GUI-only mode. Commands are typed in the
+// console of the map window instead of the terminal.
+fn gui_controller(tx: CanvasSender, rx: AppReceiver) {
+    let (reply_tx, reply_rx) = create_app_channel();
+
+    if let Err(e) = capture_stdout(tx.clone()) {
+        println!("{}", e);
+    }
+
+    match App::new(Some(tx), Some(reply_rx)) {
+        Ok(app) => {
+            run_gui(app, rx, reply_tx);
+        },
+        Err(s) => {
+            println!("Error {}", s);
+        }
+    }
+}
+
 pub fn init_with_canvas() {
     initialize_font_service();
 
-    // Create canvas <-> app channels and spawn off terminal controller
-    // thread
+    // Create canvas <-> app channels and spawn off the controller thread,
+    // reading commands from the terminal or the console
     let (canvas_tx, canvas_rx) = create_canvas_channel();
     let (app_tx, app_rx) = create_app_channel();
 
     let canvas_tx_cloned = canvas_tx.clone();
-    let handler = std::thread::spawn(move || terminal_controller(
-        canvas_tx_cloned, app_rx));
+    let handler = if CONFIG.gui_only {
+        std::thread::spawn(move || gui_controller(canvas_tx_cloned, app_rx))
+    }
+    else {
+        std::thread::spawn(move || terminal_controller(
+            canvas_tx_cloned, app_rx))
+    };
 
     init_with_app(Box::new(|cc| Ok(Box::new(Canvas::new(
         cc,
@@ -77,24 +113,175 @@ pub fn init_with_canvas() {
     handler.join().unwrap();
 }
 
+// Radius (in pixels) for picking features on the map
+const PICK_RADIUS: f32 = 10.0;
+// How often the console is redrawn for new output
+const CONSOLE_REFRESH: Duration = Duration::from_millis(250);
+// Distance between the arrowheads along the track, and their length
+// (pixels)
+const ARROW_SPACING: f64 = 150.0;
+const ARROW_SIZE: f64 = 10.0;
+// How long the map flashes for an alarm, and the period of the flashes
+const FLASH_DURATION: Duration = Duration::from_secs(3);
+const FLASH_PERIOD: f32 = 0.5;
+
+// Feature selected by clicking on the map
+#[derive(Clone, Copy, PartialEq)]
+enum Selection {
+    Waypoint(usize),
+    Barrier(usize),
+    Track,
+}
+
 struct FeaturesState {
     points: Vec<Coord>,
+    names: Vec<String>,
+    types: Vec<String>,
     barriers: Vec<Barrier>,
+    pois: Vec<Poi>,
+    show_pois: bool,
+    track: Barrier,
+    selected: Option<Selection>,
+    // Barrier being drawn, and id of the request for it
     tmp_barrier: Option<Barrier>,
-    req_point: bool,
+    req_barrier: u64,
+    // Id of the request for a point
+    req_point: Option<u64>,
+    // Waypoint whose name is being edited, and the edited name
+    edit_name: Option<(usize, String)>,
+    // Add waypoints by clicking. Holding shift adds waypoints as well.
+    add_points: bool,
+    shift_held: bool,
+    // Map position of the mouse pointer
+    pointer: Option<Coord>,
+    // Track being dragged: where the drag started, and the waypoints at
+    // the ends of the leg, between which the rubber band is drawn
+    drag: Option<(Coord, Coord, Coord)>,
+    // Index for picking features, rebuilt when they have changed
+    index: PickIndex,
+    index_dirty: bool,
 }
 
 impl FeaturesState {
     fn new() -> Self {
         Self {
             points: vec![],
+            names: vec![],
+            types: vec![],
             barriers: vec![],
+            pois: vec![],
+            show_pois: false,
+            track: Barrier::new(),
+            selected: None,
             tmp_barrier: None,
-            req_point: false,
+            req_barrier: 0,
+            req_point: None,
+            edit_name: None,
+            add_points: false,
+            shift_held: false,
+            pointer: None,
+            drag: None,
+            index: PickIndex::new(),
+            index_dirty: true,
+        }
+    }
+
+    // Project a line to map coordinates
+    fn project_line(points: &[Coord]) -> Vec<Point2> {
+        let proj = Crs::EPSG3857
+            .get_projection::<GeoPoint2d, Point2>()
+            .unwrap();
+
+        points.iter()
+            .map(|c| {
+                let (lat, lon) = to_latlon(c);
+                proj.project(&GeoPoint2d::latlon(lat, lon)).unwrap()
+            })
+            .collect()
+    }
+
+    // Bring the pick index up to date with the features and the map
+    // resolution
+    fn update_index(&mut self, resolution: f64) {
+        if self.index_dirty {
+            self.index.clear();
+
+            for (i, p) in FeaturesState::project_line(&self.points)
+                .iter().enumerate() {
+                self.index.add_point(Pickable::Waypoint(i), p);
+            }
+
+            for (i, b) in self.barriers.iter().enumerate() {
+                self.index.add_line(Pickable::Barrier(i),
+                                    &FeaturesState::project_line(&b.points));
+            }
+
+            self.index.add_line(Pickable::Track,
+                                &FeaturesState::project_line(
+                                    &self.track.points));
+
+            if self.show_pois {
+                let coords: Vec<Coord> = self.pois.iter()
+                    .map(|p| p.coord)
+                    .collect();
+
+                for (i, p) in FeaturesState::project_line(&coords)
+                    .iter().enumerate() {
+                    self.index.add_point(Pickable::Poi(i), p);
+                }
+            }
+
+            self.index_dirty = false;
         }
+
+        self.index.refresh(resolution*PICK_RADIUS as f64);
+    }
+
+    // Find the visible poi nearest to p (map coordinates)
+    fn pick_poi(&self, p: &Point2) -> Option<&Poi> {
+        match self.index.pick(p, |i| matches!(i, Pickable::Poi(_))) {
+            Some(Pickable::Poi(i)) => Some(&self.pois[i]),
+            _ => None,
+        }
+    }
+
+    // Find the waypoint, barrier or track nearest to p (map coordinates).
+    // Waypoints are preferred to lines.
+    fn pick_feature(&self, p: &Point2) -> Option<Selection> {
+        let waypoint = self.index.pick(
+            p, |i| matches!(i, Pickable::Waypoint(_)));
+
+        match waypoint.or_else(|| self.index.pick(
+            p, |i| matches!(i, Pickable::Barrier(_) | Pickable::Track))) {
+            Some(Pickable::Waypoint(i)) => Some(Selection::Waypoint(i)),
+            Some(Pickable::Barrier(i)) => Some(Selection::Barrier(i)),
+            Some(Pickable::Track) => Some(Selection::Track),
+            _ => None,
+        }
+    }
+
+    // Waypoints at the ends of the leg of the track point nearest to c
+    fn leg_ends(&self, c: &Coord) -> Option<(Coord, Coord)> {
+        let track = &self.track.points;
+        let nearest = |w: &Coord| (0..track.len())
+            .min_by(|a, b| (track[*a] - *w).abs_sq()
+                    .total_cmp(&(track[*b] - *w).abs_sq()));
+        let k = nearest(c)?;
+        let ends: Vec<(usize, Coord)> = self.points.iter()
+            .filter_map(|w| nearest(w).map(|i| (i, *w)))
+            .collect();
+
+        ends.windows(2)
+            .find(|w| w[0].0 <= k && k <= w[1].0)
+            .map(|w| (w[0].1, w[1].1))
     }
 }
 
+// Length of a line (meters)
+fn line_length(points: &[Coord]) -> f32 {
+    points.windows(2).map(|w| (w[1] - w[0]).abs()).sum()
+}
+
 struct MouseHandler {
     state: Arc<RwLock<FeaturesState>>,
     canvas_tx: CanvasSender,
@@ -126,7 +313,7 @@ impl UserEventHandler for MouseHandler {
                     .screen_to_map(mouse_event.screen_pointer_position) {
                     if let Some(b) = state.tmp_barrier.as_mut() {
                         let gp = proj.unproject(&position).unwrap();
-                        let c = Coord::from_latlon(gp.lat(), gp.lon());
+                        let c = from_latlon(gp.lat(), gp.lon());
                         if b.len() == 0 {
                             b.add_point(c);
                             b.add_point(c);
@@ -138,24 +325,63 @@ impl UserEventHandler for MouseHandler {
                         let _ = self.canvas_tx.send(
                             CanvasMsg::RedrawTmpBarrier);
                     }
-                    else if state.req_point {
+                    else if let Some(id) = state.req_point.take() {
+                        let gp = proj.unproject(&position).unwrap();
+                        let c = from_latlon(gp.lat(), gp.lon());
+                        let _ = self.app_tx.send(AppMsg::SelectPoint(id, c));
+                    }
+                    else if state.add_points || state.shift_held {
                         let gp = proj.unproject(&position).unwrap();
-                        let c = Coord::from_latlon(gp.lat(), gp.lon());
-                        let _ = self.app_tx.send(AppMsg::SelectPoint(c));
-                        state.req_point = false;
+                        let c = from_latlon(gp.lat(), gp.lon());
+                        let _ = self.app_tx.send(AppMsg::AddPoint(c));
+                    }
+                    else {
+                        state.update_index(map.view().resolution());
+                        if let Some(poi) = state.pick_poi(&position) {
+                            let _ = self.app_tx.send(
+                                AppMsg::AddPoi(poi.clone()));
+                        }
+                        else {
+                            state.selected = state.pick_feature(&position);
+                            let _ = self.canvas_tx.send(
+                                CanvasMsg::RedrawSelection);
+                        }
                     }
                 }
 
                 EventPropagation::Stop
             },
+            UserEvent::DoubleClick(MouseButton::Left, mouse_event) => {
+                let Some(position) = map.view()
+                    .screen_to_map(mouse_event.screen_pointer_position) else {
+                    return EventPropagation::Propagate;
+                };
+
+                state.update_index(map.view().resolution());
+
+                // Edit the name of a waypoint. Elsewhere, zoom as usual.
+                match state.pick_feature(&position) {
+                    Some(Selection::Waypoint(i)) => {
+                        let name = state.names[i].clone();
+                        state.edit_name = Some((i, name));
+                        EventPropagation::Stop
+                    },
+                    _ => EventPropagation::Propagate,
+                }
+            },
             UserEvent::PointerMoved(mouse_event) => {
+                state.pointer = map.view()
+                    .screen_to_map(mouse_event.screen_pointer_position)
+                    .and_then(|position| proj.unproject(&position))
+                    .map(|gp| from_latlon(gp.lat(), gp.lon()));
+
                 if let Some(b) = state.tmp_barrier.as_mut() {
                     if b.len() >= 2 {
                         if let Some(position) = map.view()
                             .screen_to_map(mouse_event.screen_pointer_position)
                         {
                             let gp = proj.unproject(&position).unwrap();
-                            let c = Coord::from_latlon(gp.lat(), gp.lon());
+                            let c = from_latlon(gp.lat(), gp.lon());
                             b.update_point(b.len() - 1, c);
                             let _ = self.canvas_tx.send(
                                 CanvasMsg::RedrawTmpBarrier);
@@ -165,18 +391,73 @@ impl UserEventHandler for MouseHandler {
 
                 EventPropagation::Stop
             },
+            UserEvent::DragStarted(MouseButton::Left, mouse_event) => {
+                let Some(position) = map.view()
+                    .screen_to_map(mouse_event.screen_pointer_position) else {
+                    return EventPropagation::Propagate;
+                };
+
+                if state.tmp_barrier.is_some() || state.req_point.is_some()
+                    || state.add_points {
+                    return EventPropagation::Propagate;
+                }
+
+                state.update_index(map.view().resolution());
+
+                // Grabbing the track reshapes the leg. Elsewhere, pan as
+                // usual.
+                if !matches!(state.pick_feature(&position),
+                             Some(Selection::Track)) {
+                    return EventPropagation::Propagate;
+                }
+
+                let gp = proj.unproject(&position).unwrap();
+                let c = from_latlon(gp.lat(), gp.lon());
+                let Some((a, b)) = state.leg_ends(&c) else {
+                    return EventPropagation::Propagate;
+                };
+
+                state.drag = Some((c, a, b));
+                EventPropagation::Stop
+            },
+            UserEvent::Drag(MouseButton::Left, _, mouse_event)
+                if state.drag.is_some() => {
+                state.pointer = map.view()
+                    .screen_to_map(mouse_event.screen_pointer_position)
+                    .and_then(|position| proj.unproject(&position))
+                    .map(|gp| from_latlon(gp.lat(), gp.lon()));
+                let _ = self.canvas_tx.send(CanvasMsg::RedrawSelection);
+
+                EventPropagation::Stop
+            },
+            UserEvent::DragEnded(MouseButton::Left, mouse_event)
+                if state.drag.is_some() => {
+                let (from, _, _) = state.drag.take().unwrap();
+                let to = map.view()
+                    .screen_to_map(mouse_event.screen_pointer_position)
+                    .and_then(|position| proj.unproject(&position))
+                    .map(|gp| from_latlon(gp.lat(), gp.lon()));
+
+                if let Some(to) = to {
+                    let _ = self.app_tx.send(AppMsg::DragTrack(from, to));
+                }
+                let _ = self.canvas_tx.send(CanvasMsg::RedrawSelection);
+
+                EventPropagation::Stop
+            },
             UserEvent::Click(MouseButton::Right, mouse_event) => {
                 if let Some(mut b) = state.tmp_barrier.take() {
                     if let Some(position) = map.view()
                         .screen_to_map(mouse_event.screen_pointer_position) {
                         let gp = proj.unproject(&position).unwrap();
-                        let c = Coord::from_latlon(gp.lat(), gp.lon());
+                        let c = from_latlon(gp.lat(), gp.lon());
                         if b.len() >= 2 {
                             b.update_point(b.len() - 1, c);
                             let _ = self.canvas_tx.send(
                                 CanvasMsg::RedrawTmpBarrier);
                         }
-                        let _ = self.app_tx.send(AppMsg::CreateBarrier(b));
+                        let _ = self.app_tx.send(
+                            AppMsg::CreateBarrier(state.req_barrier, b));
                     }
                 }
 
@@ -187,19 +468,92 @@ impl UserEventHandler for MouseHandler {
     }
 }
 
+// Map tiles from the configured provider, opentopomap by default
+fn topo_layer() -> RasterTileLayer {
+    let provider = RestTileProvider::new(
+        |index| {
+            CONFIG.tile_url
+                .replace("{z}", &index.z.to_string())
+                .replace("{x}", &index.x.to_string())
+                .replace("{y}", &index.y.to_string())
+        },
+        None,
+        false,
+    );
+
+    RasterTileLayerBuilder::new_with_provider(provider)
+    //        .with_file_cache_checked(".tile_cache")
+        .build()
+        .expect("failed to create layer")
+}
+
 pub struct Canvas {
     state: Arc<RwLock<EguiMapState>>,
+    // Map pane beside the main map, showing a track for comparison. It
+    // follows the view of the main map.
+    compare: EguiMapState,
+    compare_track: Arc<RwLock<FeatureLayer<Point2, Contour<Point2>,
+                                           LineSymbol,
+                                           CartesianSpace2d>>>,
+    compare_label: Option<String>,
+    // Viewport last sent to the app
+    viewport: Option<Viewport>,
+    // Resolution and center of the main map when the comparison pane was
+    // last synchronized
+    compare_view: Option<(f64, Option<(f64, f64)>)>,
     features_state: Arc<RwLock<FeaturesState>>,
     rx: CanvasReceiver,
+    app_tx: AppSender,
     waypoints: Arc<RwLock<FeatureLayer<GeoPoint2d, Waypoint, WaypointSymbol,
                                        GeoSpace2d>>>,
+    pois: Arc<RwLock<FeatureLayer<GeoPoint2d, PoiFeature, PoiSymbol,
+                                  GeoSpace2d>>>,
+    markers: Arc<RwLock<FeatureLayer<GeoPoint2d, Marker, MarkerSymbol,
+                                     GeoSpace2d>>>,
+    marker_ids: HashMap<MarkerKind, Vec<FeatureId>>,
     areas: Arc<RwLock<FeatureLayer<Point2, Contour<Point2>,
-                                   SimpleContourSymbol, CartesianSpace2d>>>,
-    tracks: Arc<RwLock<FeatureLayer<Point2, Contour<Point2>,
-                                    SimpleContourSymbol, CartesianSpace2d>>>,
+                                   LineSymbol, CartesianSpace2d>>>,
+    corridor: Arc<RwLock<FeatureLayer<Point2, Contour<Point2>,
+                                      LineSymbol,
+                                      CartesianSpace2d>>>,
+    gates: Arc<RwLock<FeatureLayer<Point2, Contour<Point2>,
+                                   LineSymbol, CartesianSpace2d>>>,
+    pin_areas: Arc<RwLock<FeatureLayer<Point2, Contour<Point2>,
+                                       LineSymbol,
+                                       CartesianSpace2d>>>,
+    tracks: Arc<RwLock<FeatureLayer<Point2, TrackLeg, TrackSymbol,
+                                    CartesianSpace2d>>>,
+    night: Arc<RwLock<FeatureLayer<Point2, Contour<Point2>,
+                                   LineSymbol, CartesianSpace2d>>>,
+    heatmap: Arc<RwLock<FeatureLayer<GeoPoint2d, HeatCell, HeatmapSymbol,
+                                     GeoSpace2d>>>,
+    debug_nodes: Arc<RwLock<FeatureLayer<Point2, Point2, CirclePointSymbol,
+                                         CartesianSpace2d>>>,
+    debug_explored: Arc<RwLock<FeatureLayer<Point2, Point2,
+                                            CirclePointSymbol,
+                                            CartesianSpace2d>>>,
+    selection: Arc<RwLock<FeatureLayer<Point2, Contour<Point2>,
+                                       LineSymbol,
+                                       CartesianSpace2d>>>,
+    symbology: Arc<RwLock<Symbology>>,
     tmp_barrier_id: Option<FeatureId>,
+    // Track features, one for each leg
+    track_legs: Vec<FeatureId>,
+    // Declination of the bearings in the status bar, and whether they are
+    // magnetic
+    north: (f32, bool),
+    // Position from the GPS receiver
+    gps: Option<Coord>,
+    // End of the alarm flash
+    flash_until: Option<Instant>,
     covering_length: Option<f32>,
     covering_width: Option<f32>,
+    search_text: String,
+    search_result: Option<Poi>,
+    search_failed: bool,
+    cost_plot: CostPlot,
+    // Console for commands, in GUI-only mode
+    console: Option<Console>,
 }
 
 impl Canvas {
@@ -217,37 +571,57 @@ impl Canvas {
             .clone()
             .expect("failed to get wgpu context");
 
-        // Get tiles from the opentopomap provider
-        let provider = RestTileProvider::new(
-            |index| {
-                format!(
-                    // "https://tile.openstreetmap.org/{}/{}/{}.png",
-                    "https://tile.opentopomap.org/{}/{}/{}.png",
-                    index.z, index.x, index.y
-                )
-            },
-            None,
-            false,
-        );
-
-        let raster_layer = RasterTileLayerBuilder::new_with_provider(provider)
-        //        .with_file_cache_checked(".tile_cache")
-            .build()
-            .expect("failed to create layer");
-
-        let (lat, lon) = Coord::from("N6969971.14E182124.64").latlon();
+        let (lat, lon) = to_latlon(&Coord::from("N6969971.14E182124.64"));
 
         // Build the map
         let mut map = MapBuilder::default()
             .with_latlon(lat, lon)
             .with_resolution(30.0)
-            .with_layer(raster_layer)
+            .with_layer(topo_layer())
+            .build();
+
+        // Symbols of the layers, set from the params
+        let symbology = Arc::new(RwLock::new(Symbology::default()));
+
+        // Build the comparison map, with a layer for its track
+        let mut compare_map = MapBuilder::default()
+            .with_latlon(lat, lon)
+            .with_resolution(30.0)
+            .with_layer(topo_layer())
             .build();
 
+        let compare_track_layer = Arc::new(RwLock::new(FeatureLayer::new(
+            vec![],
+            LineSymbol::new(symbology.clone(), LineLayer::Compare),
+            Crs::EPSG3857
+        )));
+        compare_map.layers_mut().push(compare_track_layer.clone());
+
+        let mut compare_state = EguiMapState::new(
+            compare_map, ctx.clone(), render_state.clone());
+        compare_state.hide_attributions();
+
+        // Add a layer for the points of interest. It is empty until pois are
+        // toggled on.
+        let poi_layer = Arc::new(RwLock::new(FeatureLayer::new(
+            vec![],
+            PoiSymbol::new(symbology.clone()),
+            Crs::WGS84
+        )));
+        map.layers_mut().push(poi_layer.clone());
+
+        // Add a layer for markers of various kinds
+        let markers_layer = Arc::new(RwLock::new(FeatureLayer::new(
+            vec![],
+            MarkerSymbol::new(symbology.clone()),
+            Crs::WGS84
+        )));
+        map.layers_mut().push(markers_layer.clone());
+
         // Add a layer for the waypoints
         let wp_layer = Arc::new(RwLock::new(FeatureLayer::new(
             vec![],
-            WaypointSymbol::new(),
+            WaypointSymbol::new(symbology.clone()),
             Crs::WGS84
         )));
         map.layers_mut().push(wp_layer.clone());
@@ -255,19 +629,83 @@ impl Canvas {
         // Add a layer for the covering areas
         let areas_layer = Arc::new(RwLock::new(FeatureLayer::new(
             vec![],
-            SimpleContourSymbol::new(Color::RED, 1.5),
+            LineSymbol::new(symbology.clone(), LineLayer::Areas),
             Crs::EPSG3857
         )));
         map.layers_mut().push(areas_layer.clone());
 
+        // Add a layer for the corridor polygon
+        let corridor_layer = Arc::new(RwLock::new(FeatureLayer::new(
+            vec![],
+            LineSymbol::new(symbology.clone(), LineLayer::Corridor),
+            Crs::EPSG3857
+        )));
+        map.layers_mut().push(corridor_layer.clone());
+
+        // Add a layer for the gates
+        let gates_layer = Arc::new(RwLock::new(FeatureLayer::new(
+            vec![],
+            LineSymbol::new(symbology.clone(), LineLayer::Gates),
+            Crs::EPSG3857
+        )));
+        map.layers_mut().push(gates_layer.clone());
+
+        // Add a layer for the areas where path points are pinned
+        let pin_areas_layer = Arc::new(RwLock::new(FeatureLayer::new(
+            vec![],
+            LineSymbol::new(symbology.clone(), LineLayer::PinAreas),
+            Crs::EPSG3857
+        )));
+        map.layers_mut().push(pin_areas_layer.clone());
+
+        // Add a layer coloring the area of the first pass by the time to
+        // reach it. It lies below the tracks.
+        let heatmap_layer = Arc::new(RwLock::new(FeatureLayer::new(
+            vec![],
+            HeatmapSymbol::new(),
+            Crs::WGS84
+        )));
+        map.layers_mut().push(heatmap_layer.clone());
+
         // Add a layer for the tracks. We'll add content to it later
         let tracks_layer = Arc::new(RwLock::new(FeatureLayer::new(
             vec![],
-            SimpleContourSymbol::new(Color::RED, 3.0),
+            TrackSymbol::new(symbology.clone()),
             Crs::EPSG3857
         )));
         map.layers_mut().push(tracks_layer.clone());
 
+        // Add a layer for the parts of the tracks walked after dark
+        let night_layer = Arc::new(RwLock::new(FeatureLayer::new(
+            vec![],
+            LineSymbol::new(symbology.clone(), LineLayer::Night),
+            Crs::EPSG3857
+        )));
+        map.layers_mut().push(night_layer.clone());
+
+        // Add layers for debugging the graph search
+        let debug_nodes_layer = Arc::new(RwLock::new(FeatureLayer::new(
+            vec![],
+            CirclePointSymbol::new(Color::rgba(120, 120, 120, 255), 2.0),
+            Crs::EPSG3857
+        )));
+        map.layers_mut().push(debug_nodes_layer.clone());
+
+        let debug_explored_layer = Arc::new(RwLock::new(FeatureLayer::new(
+            vec![],
+            CirclePointSymbol::new(Color::rgba(230, 140, 0, 255), 2.0),
+            Crs::EPSG3857
+        )));
+        map.layers_mut().push(debug_explored_layer.clone());
+
+        // Add a layer highlighting the selected barrier or track
+        let selection_layer = Arc::new(RwLock::new(FeatureLayer::new(
+            vec![],
+            LineSymbol::new(symbology.clone(), LineLayer::Selection),
+            Crs::EPSG3857
+        )));
+        map.layers_mut().push(selection_layer.clone());
+
         let map_state = Arc::new(RwLock::new(
             EguiMapState::new(map, ctx, render_state)));
 
@@ -276,14 +714,41 @@ impl Canvas {
 
         let ret = Self {
             state: map_state.clone(),
+            compare: compare_state,
+            compare_track: compare_track_layer,
+            compare_label: None,
+            compare_view: None,
+            viewport: None,
             features_state: features_state.clone(),
             rx: canvas_rx,
+            app_tx: app_tx.clone(),
             waypoints: wp_layer,
+            pois: poi_layer,
+            markers: markers_layer,
+            marker_ids: HashMap::new(),
             areas: areas_layer,
+            corridor: corridor_layer,
+            gates: gates_layer,
+            pin_areas: pin_areas_layer,
             tracks: tracks_layer,
+            night: night_layer,
+            heatmap: heatmap_layer,
+            debug_nodes: debug_nodes_layer,
+            debug_explored: debug_explored_layer,
+            selection: selection_layer,
+            symbology: symbology,
             covering_length: None,
             covering_width: None,
             tmp_barrier_id: None,
+            track_legs: vec![],
+            north: (0.0, false),
+            gps: None,
+            flash_until: None,
+            search_text: "".to_string(),
+            search_result: None,
+            search_failed: false,
+            cost_plot: CostPlot::new(),
+            console: CONFIG.gui_only.then(|| Console::new(COMMAND_LIST)),
         };
 
         // Create a mouse handler for the app
@@ -297,8 +762,36 @@ impl Canvas {
         return ret;
     }
 
-    fn set_waypoints(&mut self, points: Vec<Coord>) {
+    fn set_waypoints(&mut self, points: Vec<Coord>, names: Vec<String>,
+                     types: Vec<String>) {
+        let mut state = self.features_state.write();
+        state.points = points;
+        state.names = names;
+        state.types = types;
+        state.index_dirty = true;
+        drop(state);
+
+        self.redraw_waypoints();
+    }
+
+    // Draw all features again with the current symbology
+    fn redraw_symbols(&self) {
+        self.waypoints.write().update_all_features();
+        self.pois.write().update_all_features();
+        self.markers.write().update_all_features();
+        self.tracks.write().update_all_features();
+
+        for layer in [&self.compare_track, &self.areas, &self.corridor,
+                      &self.gates, &self.pin_areas, &self.night,
+                      &self.selection] {
+            layer.write().update_all_features();
+        }
+    }
+
+    fn redraw_waypoints(&self) {
         let mut layer = self.waypoints.write();
+        let state = self.features_state.read();
+        let points = &state.points;
 
         // Remove old features
         let fs = layer.features_mut();
@@ -312,23 +805,63 @@ impl Canvas {
 
         for i in 0..n {
             let p = points[i];
-            let label = if i == 0 {
-                format!("{} (start)", i + 1)
-            }
-            else if i == n - 1 {
-                format!("{} (end)", i + 1)
-            }
-            else {
-                format!("{}", i + 1)
-            };
+            let name = state.names.get(i).map(|s| s.as_str()).unwrap_or("");
+            let label = point_label(name, i, n);
 
-            let (lat, lon) = p.latlon();
-            let wp = Waypoint::new(label, lat, lon);
+            let (lat, lon) = to_latlon(&p);
+            let mut wp = Waypoint::new(label, lat, lon);
+            wp.kind = state.types.get(i).cloned().unwrap_or_default();
+            wp.selected = state.selected == Some(Selection::Waypoint(i));
             let _ = layer.features_mut().add(wp);
         }
 
-        self.features_state.write().points = points;
+        layer.update_all_features();
+    }
+
+    fn redraw_pois(&self) {
+        let mut layer = self.pois.write();
+        let state = self.features_state.read();
+
+        // Remove old features
+        let fs = layer.features_mut();
+        let ids: Vec<FeatureId> = fs.iter().map(|(id, _)| id).collect();
+
+        for id in ids {
+            fs.remove(id);
+        }
+
+        if state.show_pois {
+            for p in &state.pois {
+                let (lat, lon) = to_latlon(&p.coord);
+                let _ = layer.features_mut().add(
+                    PoiFeature::new(p.name.clone(), p.kind, lat, lon));
+            }
+        }
+
+        layer.update_all_features();
+    }
+
+    // Replace all markers of a kind
+    fn set_markers(&mut self, kind: MarkerKind,
+                   markers: Vec<(Coord, String)>) {
+        let mut layer = self.markers.write();
+
+        if let Some(ids) = self.marker_ids.remove(&kind) {
+            let fs = layer.features_mut();
+            for id in ids {
+                fs.remove(id);
+            }
+        }
+
+        let mut ids = vec![];
 
+        for (c, label) in markers {
+            let (lat, lon) = to_latlon(&c);
+            ids.push(layer.features_mut().add(
+                Marker::new(kind, label, lat, lon)));
+        }
+
+        self.marker_ids.insert(kind, ids);
         layer.update_all_features();
     }
 
@@ -362,7 +895,7 @@ impl Canvas {
         let Some(covering_length) = self.covering_length else { return; };
 
         // Determine center of map view
-        let (lat, lon) = Coord::new((e + w)/2.0, (n + s)/2.0).latlon();
+        let (lat, lon) = to_latlon(&Coord::new((e + w)/2.0, (n + s)/2.0));
         // Try to find a reasonable resolution which will encompass the
         // area to be used for the calculation.
         let res = (n - s).max(e - w)*covering_length*0.0030;
@@ -371,6 +904,198 @@ impl Canvas {
         self.state.write().map_mut().set_view(view);
     }
 
+    // Center map view on coordinate, keeping the resolution
+    fn center_on(&self, c: &Coord) {
+        let (lat, lon) = to_latlon(&c);
+        let mut state = self.state.write();
+        let res = state.map_mut().view().resolution();
+
+        let view = MapView::new(&GeoPoint2d::latlon(lat, lon), res);
+        state.map_mut().set_view(view);
+    }
+
+    // Zoom the map view by a factor, keeping the center
+    fn zoom(&self, factor: f64) {
+        let mut state = self.state.write();
+        let view = state.map_mut().view().clone();
+        let res = view.resolution()/factor;
+
+        state.map_mut().set_view(view.with_resolution(res));
+    }
+
+    // Drop the request with the given id, or all requests. Returns the ids
+    // of the requests dropped.
+    fn drop_requests(&mut self, opt_id: Option<u64>) -> Vec<u64> {
+        let mut state = self.features_state.write();
+        let matches = |id: u64| opt_id.is_none_or(|i| i == id);
+        let mut dropped = vec![];
+
+        if let Some(id) = state.req_point {
+            if matches(id) {
+                state.req_point = None;
+                dropped.push(id);
+            }
+        }
+
+        if state.tmp_barrier.is_some() && matches(state.req_barrier) {
+            state.tmp_barrier = None;
+            dropped.push(state.req_barrier);
+
+            if let Some(fid) = self.tmp_barrier_id.take() {
+                let mut layer = self.areas.write();
+                layer.features_mut().remove(fid);
+                layer.update_feature(fid);
+            }
+        }
+
+        dropped
+    }
+
+    // Cancel the requests from the app, telling it so
+    fn cancel_requests(&mut self) {
+        for id in self.drop_requests(None) {
+            let _ = self.app_tx.send(AppMsg::RequestCancelled(id));
+        }
+    }
+
+    // Keyboard shortcuts. These are ignored while typing in a text field.
+    fn handle_keys(&mut self, ctx: &egui::Context) {
+        if ctx.wants_keyboard_input() {
+            return;
+        }
+
+        let (compute, reset, cancel, undo, zoom_in, zoom_out, delete) =
+            ctx.input_mut(|i| (
+                i.consume_key(Modifiers::NONE, Key::C),
+                i.consume_key(Modifiers::NONE, Key::R),
+                i.consume_key(Modifiers::NONE, Key::Escape),
+                i.consume_key(Modifiers::COMMAND, Key::Z),
+                i.key_pressed(Key::Plus) || i.key_pressed(Key::Equals),
+                i.key_pressed(Key::Minus),
+                i.key_pressed(Key::Delete),
+            ));
+
+        if compute {
+            let _ = self.app_tx.send(AppMsg::Command("compute".to_string()));
+        }
+        if reset {
+            self.reset_view();
+        }
+        if cancel {
+            self.cancel_requests();
+        }
+        if undo {
+            let _ = self.app_tx.send(AppMsg::Command("undo".to_string()));
+        }
+        if zoom_in {
+            self.zoom(2.0);
+        }
+        if zoom_out {
+            self.zoom(0.5);
+        }
+        if delete {
+            self.delete_selected();
+        }
+    }
+
+    fn search_place(&mut self) {
+        let found = search(&self.features_state.read().pois,
+                           &self.search_text).cloned();
+
+        if let Some(poi) = &found {
+            self.center_on(&poi.coord);
+        }
+
+        self.search_failed = found.is_none();
+        self.search_result = found;
+    }
+
+    // Show the position of the mouse pointer, and its distance and bearing
+    // from the last waypoint. With a GPS, show the distance from the GPS
+    // position to the track.
+    fn show_status_bar(&self, ctx: &egui::Context) {
+        let state = self.features_state.read();
+        let mut parts = vec![];
+
+        if let Some(gps) = self.gps {
+            if state.track.len() >= 2 {
+                parts.push(format!("GPS {}, {:.0} m off the track", gps,
+                                   state.track.distance_sq(&gps).sqrt()));
+            }
+            else {
+                parts.push(format!("GPS {}", gps));
+            }
+        }
+
+        if let Some(pointer) = state.pointer {
+            parts.push(pointer.to_string());
+
+            if let Some(last) = state.points.last() {
+                let (declination, magnetic) = self.north;
+                parts.push(format!(
+                    "from point {}: {:.2} km, bearing {:.0}° {}",
+                    state.points.len(), (pointer - *last).abs()/1000.0,
+                    bearing(last, &pointer, declination),
+                    if magnetic { "magnetic" } else { "true" }));
+            }
+        }
+
+        if parts.is_empty() {
+            return;
+        }
+
+        egui::TopBottomPanel::bottom("status").show(ctx, |ui| {
+            ui.label(parts.join("    "));
+        });
+    }
+
+    // Flash the window red while an alarm is on
+    fn show_flash(&mut self, ctx: &egui::Context) {
+        let Some(until) = self.flash_until else {
+            return;
+        };
+
+        let now = Instant::now();
+        if now >= until {
+            self.flash_until = None;
+            return;
+        }
+
+        let left = (until - now).as_secs_f32();
+        if (left/FLASH_PERIOD) as usize%2 == 0 {
+            let painter = ctx.layer_painter(egui::LayerId::new(
+                egui::Order::Foreground, egui::Id::new("flash")));
+            painter.rect_filled(ctx.screen_rect(), 0.0,
+                                egui::Color32::from_rgba_unmultiplied(
+                                    255, 0, 0, 80));
+        }
+
+        ctx.request_repaint_after(Duration::from_millis(50));
+    }
+
+    fn show_search_bar(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Search:");
+            let response = ui.text_edit_singleline(&mut self.search_text);
+
+            if response.lost_focus()
+                && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                self.search_place();
+            }
+
+            if let Some(poi) = &self.search_result {
+                ui.label(poi.to_string());
+
+                if ui.button("Add waypoint").clicked() {
+                    let _ = self.app_tx.send(AppMsg::AddPoi(poi.clone()));
+                }
+            }
+            else if self.search_failed {
+                ui.label("No such place");
+            }
+        });
+    }
+
     fn draw_covering_areas(&self) {
         let state = self.features_state.read();
 
@@ -438,7 +1163,7 @@ impl Canvas {
                 let pe2 = ta*pe1 + tb*pn1 + o.e;
                 let pn2 = tc*pe1 + td*pn1 + o.n;
 
-                let (lat, lon) = Coord::new(pe2, pn2).latlon();
+                let (lat, lon) = to_latlon(&Coord::new(pe2, pn2));
 
                 let geop = GeoPoint2d::latlon(lat, lon);
                 let p = proj.project(&geop).unwrap();
@@ -463,7 +1188,7 @@ impl Canvas {
             let mut points = vec!();
 
             for c in &b.points {
-                let (lat, lon) = c.latlon();
+                let (lat, lon) = to_latlon(&c);
                 let geop = GeoPoint2d::latlon(lat, lon);
                 let p = proj.project(&geop).unwrap();
                 points.push(p);
@@ -476,17 +1201,121 @@ impl Canvas {
         layer.update_all_features();
     }
 
-    fn redraw_covering_areas_and_barriers(&mut self) {
-        // Remove old features
-        {
-            let mut layer = self.areas.write();
+    // Replace the contours of a layer with lines made from the points of
+    // the given barriers.
+    fn replace_contours(layer: &RwLock<FeatureLayer<Point2, Contour<Point2>,
+                                                    LineSymbol,
+                                                    CartesianSpace2d>>,
+                        lines: &[Barrier], closed: bool) {
+        let mut layer = layer.write();
+        let fs = layer.features_mut();
+        let ids: Vec<FeatureId> = fs.iter().map(|(id, _)| id).collect();
 
-            let fs = layer.features_mut();
-            let ids: Vec<FeatureId> = fs.iter().map(|(id, _)| id).collect();
+        for id in ids {
+            fs.remove(id);
+        }
 
-            for id in ids {
-                fs.remove(id);
-            }
+        let proj = Crs::EPSG3857
+            .get_projection::<GeoPoint2d, Point2>()
+            .unwrap();
+
+        for line in lines {
+            let points = line.points.iter()
+                .map(|c| {
+                    let (lat, lon) = to_latlon(&c);
+                    proj.project(&GeoPoint2d::latlon(lat, lon)).unwrap()
+                })
+                .collect();
+
+            let contour = if closed {
+                Contour::closed(points)
+            }
+            else {
+                Contour::open(points)
+            };
+
+            let _ = layer.features_mut().add(contour);
+        }
+
+        layer.update_all_features();
+    }
+
+    fn set_corridor(&self, corridor: Barrier) {
+        let lines = if corridor.len() >= 3 { vec![corridor] } else { vec![] };
+
+        Canvas::replace_contours(&self.corridor, &lines, true);
+    }
+
+    fn set_gates(&self, gates: Vec<Barrier>) {
+        Canvas::replace_contours(&self.gates, &gates, false);
+    }
+
+    fn set_pin_areas(&self, areas: Vec<Barrier>) {
+        Canvas::replace_contours(&self.pin_areas, &areas, true);
+    }
+
+    fn replace_points(layer: &RwLock<FeatureLayer<Point2, Point2,
+                                                  CirclePointSymbol,
+                                                  CartesianSpace2d>>,
+                      points: Vec<Coord>) {
+        let mut layer = layer.write();
+        let fs = layer.features_mut();
+        let ids: Vec<FeatureId> = fs.iter().map(|(id, _)| id).collect();
+
+        for id in ids {
+            fs.remove(id);
+        }
+
+        let proj = Crs::EPSG3857
+            .get_projection::<GeoPoint2d, Point2>()
+            .unwrap();
+
+        for c in points {
+            let (lat, lon) = to_latlon(&c);
+            let p = proj.project(&GeoPoint2d::latlon(lat, lon)).unwrap();
+            let _ = layer.features_mut().add(p);
+        }
+
+        layer.update_all_features();
+    }
+
+    fn set_debug_graph(&self, nodes: Vec<Coord>, explored: Vec<Coord>) {
+        Canvas::replace_points(&self.debug_nodes, nodes);
+        Canvas::replace_points(&self.debug_explored, explored);
+    }
+
+    // Replace the heatmap. The times are scaled by the largest one.
+    fn set_heatmap(&self, cells: Vec<(Coord, f32)>) {
+        let mut layer = self.heatmap.write();
+        let fs = layer.features_mut();
+        let ids: Vec<FeatureId> = fs.iter().map(|(id, _)| id).collect();
+
+        for id in ids {
+            fs.remove(id);
+        }
+
+        let max_time = cells.iter().map(|(_, t)| *t).fold(0.0, f32::max);
+
+        for (c, t) in cells {
+            let (lat, lon) = to_latlon(&c);
+            let level = if max_time > 0.0 { t/max_time } else { 0.0 };
+            let _ = layer.features_mut().add(HeatCell::new(lat, lon, level));
+        }
+
+        layer.update_all_features();
+    }
+
+    fn redraw_covering_areas_and_barriers(&mut self) {
+        // Remove old features
+        {
+            let mut layer = self.areas.write();
+
+            let fs = layer.features_mut();
+            let ids: Vec<FeatureId> = fs.iter().map(|(id, _)| id).collect();
+
+            for id in ids {
+                fs.remove(id);
+            }
 
             self.tmp_barrier_id.take();
         }
@@ -514,7 +1343,7 @@ impl Canvas {
             let mut points = vec!();
 
             for c in &barrier.points {
-                let (lat, lon) = c.latlon();
+                let (lat, lon) = to_latlon(&c);
                 let geop = GeoPoint2d::latlon(lat, lon);
                 let p = proj.project(&geop).unwrap();
                 points.push(p);
@@ -527,8 +1356,27 @@ impl Canvas {
         }
     }
 
-    fn set_track(&self, path: &Path) {
-	let mut points = vec!();
+    // Contour of a section of the track
+    fn track_leg(points: &[Coord]) -> TrackLeg {
+        let proj = Crs::EPSG3857
+            .get_projection::<GeoPoint2d, Point2>()
+            .unwrap();
+
+        TrackLeg::new(points.iter()
+            .map(|c| {
+                let (lat, lon) = to_latlon(c);
+                proj.project(&GeoPoint2d::latlon(lat, lon)).unwrap()
+            })
+            .collect())
+    }
+
+    // Draw the track. Each leg is a feature of its own, so that legs can
+    // be replaced one by one.
+    fn set_track(&mut self, path: &Path) {
+        let mut state = self.features_state.write();
+        state.track = Barrier::from_vec(path.into_iter().cloned().collect());
+        state.index_dirty = true;
+        drop(state);
 
         // Remove old track
         let mut layer = self.tracks.write();
@@ -539,55 +1387,412 @@ impl Canvas {
             fs.remove(id);
         }
 
+        self.track_legs.clear();
+
+        if path.num_legs() == 0 {
+            let points: Vec<Coord> = path.into_iter().cloned().collect();
+            let _ = fs.add(Canvas::track_leg(&points));
+        }
+        else {
+            for i in 0..path.num_legs() {
+                let id = fs.add(Canvas::track_leg(path.leg_points(i)));
+                self.track_legs.push(id);
+            }
+        }
+
+        layer.update_all_features();
+    }
+
+    // Replace the features of some legs of the track, the other legs being
+    // unchanged
+    fn update_track_legs(&mut self, path: &Path, legs: &[usize]) {
+        if self.track_legs.len() != path.num_legs() {
+            self.set_track(path);
+            return;
+        }
+
+        let mut state = self.features_state.write();
+        state.track = Barrier::from_vec(path.into_iter().cloned().collect());
+        state.index_dirty = true;
+        drop(state);
+
+        let mut layer = self.tracks.write();
+
+        for i in legs {
+            let old = self.track_legs[*i];
+            layer.features_mut().remove(old);
+            layer.update_feature(old);
+
+            let id = layer.features_mut()
+                .add(Canvas::track_leg(path.leg_points(*i)));
+            layer.update_feature(id);
+            self.track_legs[*i] = id;
+        }
+    }
+
+    fn set_night_track(&self, sections: Vec<Vec<Coord>>) {
+        let mut layer = self.night.write();
+        let fs = layer.features_mut();
+        let ids: Vec<FeatureId> = fs.iter().map(|(id, _)| id).collect();
+
+        for id in ids {
+            fs.remove(id);
+        }
+
         let proj = Crs::EPSG3857
             .get_projection::<GeoPoint2d, Point2>()
             .unwrap();
 
-        for c in path {
-            let (lat, lon) = c.latlon();
-            let geop = GeoPoint2d::latlon(lat, lon);
-            let p = proj.project(&geop).unwrap();
-	    points.push(p);
-	}
+        for section in sections {
+            let mut points = vec!();
+
+            for c in &section {
+                let (lat, lon) = to_latlon(&c);
+                let geop = GeoPoint2d::latlon(lat, lon);
+                points.push(proj.project(&geop).unwrap());
+            }
 
-	let contour = Contour::open(points);
+            let _ = fs.add(Contour::open(points));
+        }
 
-        let _ = fs.add(contour);
         layer.update_all_features();
     }
 
+    // Forget the selection if the selected kind of feature has changed
+    fn deselect(&mut self, kind: fn(&Selection) -> bool) {
+        let selected = self.features_state.read().selected;
+
+        if selected.as_ref().is_some_and(kind) {
+            self.features_state.write().selected = None;
+            self.redraw_selection();
+        }
+    }
+
+    // Highlight the selected feature. Waypoints change color, barriers and
+    // tracks are drawn over in the selection layer.
+    fn redraw_selection(&self) {
+        self.redraw_waypoints();
+
+        let state = self.features_state.read();
+        let mut lines = match state.selected {
+            Some(Selection::Barrier(i)) => vec![state.barriers[i].clone()],
+            Some(Selection::Track) => vec![state.track.clone()],
+            _ => vec![],
+        };
+
+        // Rubber band from the ends of the dragged leg to the pointer
+        if let (Some((_, a, b)), Some(p)) = (state.drag, state.pointer) {
+            let mut band = Barrier::new();
+            for c in [a, p, b] {
+                band.add_point(c);
+            }
+            lines.push(band);
+        }
+
+        Canvas::replace_contours(&self.selection, &lines, false);
+    }
+
+    // Remove the selected feature
+    fn delete_selected(&mut self) {
+        let Some(selected) = self.features_state.write().selected.take()
+        else {
+            return;
+        };
+
+        let msg = match selected {
+            Selection::Waypoint(i) => AppMsg::RemovePoint(i),
+            Selection::Barrier(i) => AppMsg::RemoveBarrier(i),
+            Selection::Track => AppMsg::RemovePath,
+        };
+
+        let _ = self.app_tx.send(msg);
+        self.redraw_selection();
+    }
+
+    // Small window with the properties of the selected feature
+    fn show_selection(&mut self, ctx: &egui::Context) {
+        let Some(selected) = self.features_state.read().selected else {
+            return;
+        };
+
+        let mut delete = false;
+
+        egui::Window::new("Selected")
+            .resizable(false)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                let state = self.features_state.read();
+
+                match selected {
+                    Selection::Waypoint(i) => {
+                        ui.label(format!("Waypoint {} of {}", i + 1,
+                                         state.points.len()));
+                        if state.names[i] != "" {
+                            ui.label(&state.names[i]);
+                        }
+                        ui.label(state.points[i].to_string());
+                    },
+                    Selection::Barrier(i) => {
+                        let b = &state.barriers[i];
+                        ui.label(format!("Barrier {}", i + 1));
+                        ui.label(format!("{} points, {:.0} m", b.len(),
+                                         line_length(&b.points)));
+                        if b.buffer_m > 0.0 {
+                            ui.label(format!("Buffer {:.0} m", b.buffer_m));
+                        }
+                    },
+                    Selection::Track => {
+                        ui.label("Track");
+                        ui.label(format!(
+                            "{} points, {:.2} km", state.track.len(),
+                            line_length(&state.track.points)/1000.0));
+                    },
+                }
+
+                delete = ui.button("Delete").clicked();
+            });
+
+        if delete {
+            self.delete_selected();
+        }
+    }
+
+    // Small window for editing a waypoint name, opened by double clicking
+    // the waypoint. An empty name gives the waypoint its number back.
+    fn show_name_editor(&mut self, ctx: &egui::Context) {
+        let mut state = self.features_state.write();
+        let Some((i, name)) = state.edit_name.as_mut() else { return; };
+        let (mut done, mut cancel) = (false, false);
+
+        egui::Window::new(format!("Waypoint {}", *i + 1))
+            .resizable(false)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                let response = ui.text_edit_singleline(name);
+                response.request_focus();
+
+                done = response.lost_focus()
+                    && ui.input(|input| input.key_pressed(Key::Enter));
+                cancel = ui.input(|input| input.key_pressed(Key::Escape));
+
+                ui.horizontal(|ui| {
+                    done |= ui.button("OK").clicked();
+                    cancel |= ui.button("Cancel").clicked();
+                });
+            });
+
+        if done {
+            let (i, name) = state.edit_name.take().unwrap();
+            let _ = self.app_tx.send(
+                AppMsg::SetPointName(i, name.trim().to_string()));
+        }
+        else if cancel {
+            state.edit_name = None;
+        }
+    }
+
+    fn set_compare_track(&mut self, opt_track: Option<(Path, String)>) {
+        let Some((path, label)) = opt_track else {
+            self.compare_label = None;
+            return;
+        };
+
+        let line = Barrier::from_vec(path.into_iter().cloned().collect());
+        Canvas::replace_contours(&self.compare_track, &[line], false);
+
+        self.compare_label = Some(label);
+        self.compare_view = None;
+    }
+
+    // Area shown by the main map
+    fn current_viewport(&self) -> Option<Viewport> {
+        let proj = Crs::EPSG3857
+            .get_projection::<GeoPoint2d, Point2>()
+            .unwrap();
+
+        let mut state = self.state.write();
+        let view = state.map_mut().view().clone();
+        let size = view.size();
+
+        let center = view.position()?;
+        let mut corners = vec![];
+
+        for (x, y) in [(0.0, 0.0), (size.width(), 0.0),
+                       (0.0, size.height()), (size.width(), size.height())] {
+            let p = view.screen_to_map(Point2::new(x, y))?;
+            let gp = proj.unproject(&p)?;
+            corners.push(from_latlon(gp.lat(), gp.lon()));
+        }
+
+        let sw = Coord::new(
+            corners.iter().map(|c| c.e).fold(f32::INFINITY, f32::min),
+            corners.iter().map(|c| c.n).fold(f32::INFINITY, f32::min));
+        let ne = Coord::new(
+            corners.iter().map(|c| c.e).fold(f32::NEG_INFINITY, f32::max),
+            corners.iter().map(|c| c.n).fold(f32::NEG_INFINITY, f32::max));
+
+        Some(Viewport {
+            center: from_latlon(center.lat(), center.lon()),
+            sw: sw,
+            ne: ne,
+            // Map resolution is in mercator units. Scale it to meters on the
+            // ground.
+            resolution: (view.resolution()*center.lat().to_radians().cos())
+                as f32,
+        })
+    }
+
+    // Tell the app about the area shown, when it has moved by a tenth of
+    // its size or has been zoomed.
+    fn notify_viewport(&mut self) {
+        let Some(v) = self.current_viewport() else { return; };
+
+        if let Some(last) = &self.viewport {
+            let width = (last.ne.e - last.sw.e).max(1.0);
+            let moved = (v.center - last.center).abs() > width*0.1;
+            let ratio = v.resolution/last.resolution;
+
+            if !moved && ratio > 0.8 && ratio < 1.25 {
+                return;
+            }
+        }
+
+        self.viewport = Some(v);
+        let _ = self.app_tx.send(AppMsg::SetViewport(v));
+    }
+
+    // Give the comparison pane the view of the main map
+    fn sync_compare_view(&mut self) {
+        let view = self.state.write().map_mut().view().clone();
+        let key = (view.resolution(),
+                   view.position().map(|p| (p.lat(), p.lon())));
+
+        if self.compare_view != Some(key) {
+            self.compare.map_mut().set_view(view);
+            self.compare_view = Some(key);
+        }
+    }
+
     fn check_channel(&mut self) -> bool {
         while let Ok(o) = self.rx.try_recv() {
             match o {
                 CanvasMsg::SetPath(path) => {
+                    self.deselect(|s| *s == Selection::Track);
                     self.set_track(&path);
+                    self.set_night_track(vec![]);
+                },
+                CanvasMsg::UpdatePathLegs(path, legs) => {
+                    self.deselect(|s| *s == Selection::Track);
+                    self.update_track_legs(&path, &legs);
+                    self.set_night_track(vec![]);
+                },
+                CanvasMsg::SetNightTrack(sections) => {
+                    self.set_night_track(sections);
+                },
+                CanvasMsg::SetCompareTrack(opt_track) => {
+                    self.set_compare_track(opt_track);
                 },
-                CanvasMsg::SetWaypoints(points) => {
-                    self.set_waypoints(points);
+                CanvasMsg::SetWaypoints(points, names, types) => {
+                    self.deselect(|s| matches!(s, Selection::Waypoint(_)));
+                    self.features_state.write().edit_name = None;
+                    self.set_waypoints(points, names, types);
                     self.redraw_covering_areas_and_barriers();
                 },
                 CanvasMsg::SetBarriers(barriers) => {
-                    self.features_state.write().barriers = barriers;
+                    self.deselect(|s| matches!(s, Selection::Barrier(_)));
+                    let mut state = self.features_state.write();
+                    state.barriers = barriers;
+                    state.index_dirty = true;
+                    drop(state);
                     self.redraw_covering_areas_and_barriers();
                 },
+                CanvasMsg::SetCorridor(corridor) => {
+                    self.set_corridor(corridor);
+                },
+                CanvasMsg::SetGates(gates) => {
+                    self.set_gates(gates);
+                },
+                CanvasMsg::SetPinAreas(areas) => {
+                    self.set_pin_areas(areas);
+                },
                 CanvasMsg::SetCoveringArea(length, width) => {
                     self.covering_length.replace(length);
                     self.covering_width.replace(width);
                     self.redraw_covering_areas_and_barriers();
                 },
-                CanvasMsg::RequestPoint => {
-                    // FIXME: Ensure that point has not already been requested
-                    self.features_state.write().req_point = true;
+                CanvasMsg::SetPois(pois) => {
+                    let mut state = self.features_state.write();
+                    state.pois = pois;
+                    state.index_dirty = true;
+                    drop(state);
+                    self.redraw_pois();
+                },
+                CanvasMsg::SetMarkers(kind, markers) => {
+                    self.set_markers(kind, markers);
                 },
-                CanvasMsg::RequestBarrier => {
-                    // FIXME: Ensure that barrier has not already been requested
-                    self.features_state.write().tmp_barrier
-                        .replace(Barrier::new());
-                    self.tmp_barrier_id.take();
+                CanvasMsg::SetGpsPosition(gps) => {
+                    self.gps = gps;
+                    self.set_markers(MarkerKind::Gps, gps.iter()
+                                     .map(|c| (*c, "GPS".to_string()))
+                                     .collect());
+                },
+                CanvasMsg::SetSymbology(symbology) => {
+                    *self.symbology.write() = symbology;
+                    self.redraw_symbols();
+                },
+                CanvasMsg::FlashAlarm => {
+                    self.flash_until = Some(Instant::now() + FLASH_DURATION);
+                },
+                CanvasMsg::SetDebugGraph(nodes, explored) => {
+                    self.set_debug_graph(nodes, explored);
+                },
+                CanvasMsg::SetHeatmap(cells) => {
+                    self.set_heatmap(cells);
+                },
+                CanvasMsg::SetCostCurve(samples, controls) => {
+                    self.cost_plot.set_samples(samples, controls);
+                },
+                CanvasMsg::ShowPois(show) => {
+                    let mut state = self.features_state.write();
+                    state.show_pois = show;
+                    state.index_dirty = true;
+                    drop(state);
+                    self.redraw_pois();
+                },
+                CanvasMsg::ShowCostCurve => {
+                    self.cost_plot.open = true;
+                },
+                CanvasMsg::ConsoleOutput(line) => {
+                    if let Some(console) = self.console.as_mut() {
+                        console.push_line(line);
+                    }
+                },
+                CanvasMsg::RequestPoint(id) => {
+                    // The app has given up any earlier request
+                    self.drop_requests(None);
+                    self.features_state.write().req_point = Some(id);
+                },
+                CanvasMsg::RequestBarrier(id) => {
+                    self.drop_requests(None);
+                    let mut state = self.features_state.write();
+                    state.tmp_barrier = Some(Barrier::new());
+                    state.req_barrier = id;
+                },
+                CanvasMsg::CancelRequest(id) => {
+                    self.drop_requests(Some(id));
                 },
                 CanvasMsg::RedrawTmpBarrier => {
                     self.redraw_tmp_barrier();
                 },
+                CanvasMsg::RedrawSelection => {
+                    self.redraw_selection();
+                },
+                CanvasMsg::CenterOn(c) => {
+                    self.center_on(&c);
+                },
+                CanvasMsg::SetNorth(declination, magnetic) => {
+                    self.north = (declination, magnetic);
+                },
                 CanvasMsg::ResetView => {
                     self.reset_view();
                 },
@@ -610,24 +1815,82 @@ impl eframe::App for Canvas {
             return;
         }
 
+        self.features_state.write().shift_held =
+            ctx.input(|i| i.modifiers.shift);
+        self.handle_keys(ctx);
+
+        egui::TopBottomPanel::top("toolbar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let mut state = self.features_state.write();
+                ui.toggle_value(&mut state.add_points, "Add waypoints")
+                    .on_hover_text("Click on the map to append waypoints. \
+                                    Shift-click does the same.");
+                drop(state);
+
+                if let Some(console) = self.console.as_mut() {
+                    ui.toggle_value(&mut console.open, "Console");
+                }
+            });
+            self.show_search_bar(ui);
+        });
+
+        self.show_status_bar(ctx);
+
         egui::CentralPanel::default().show(ctx, |ui| {
-            self.state.write().render(ui);
+            let Some(label) = self.compare_label.clone() else {
+                self.state.write().render(ui);
+                return;
+            };
+
+            // Side by side with the comparison pane
+            ui.columns(2, |columns| {
+                columns[0].label("Current");
+                self.state.write().render(&mut columns[0]);
+
+                self.sync_compare_view();
+                columns[1].label(label);
+                self.compare.render(&mut columns[1]);
+            });
         });
+
+        self.show_selection(ctx);
+        self.show_name_editor(ctx);
+        self.show_flash(ctx);
+
+        self.notify_viewport();
+
+        if let Some(curve) = self.cost_plot.show(ctx) {
+            let _ = self.app_tx.send(AppMsg::SetCostCurve(curve));
+        }
+
+        if let Some(line) = self.console.as_mut().and_then(|c| c.show(ctx)) {
+            let _ = self.app_tx.send(AppMsg::ConsoleLine(line));
+        }
+
+        // Output may arrive while nothing else happens in the window
+        if self.console.as_ref().is_some_and(|c| c.open) {
+            ctx.request_repaint_after(CONSOLE_REFRESH);
+        }
     }
 }
 
 struct Waypoint {
     label: String,
+    // Type of waypoint, e.g. "hut", or "" for none
+    kind: String,
     lat: f64,
     lon: f64,
+    selected: bool,
 }
 
 impl Waypoint {
     fn new(label: String, lat: f64, lon: f64) -> Self {
         Self {
             label: label,
+            kind: "".to_string(),
             lat: lat,
             lon: lon,
+            selected: false,
         }
     }
 }
@@ -665,11 +1928,15 @@ impl Geometry for Waypoint {
 
 struct WaypointSymbol {
     style: TextStyle,
+    // Letter on the icons of typed waypoints
+    icon_style: TextStyle,
+    symbology: Arc<RwLock<Symbology>>,
 }
 
 impl WaypointSymbol {
-    fn new() -> Self {
+    fn new(symbology: Arc<RwLock<Symbology>>) -> Self {
         Self {
+            symbology: symbology,
             style: TextStyle {
                 font_family: vec!["Noto Sans".to_string()],
                 font_size: 15.0,
@@ -679,8 +1946,32 @@ impl WaypointSymbol {
                 weight: Default::default(),
                 style: Default::default(),
                 outline_width: Default::default(),
+                outline_color: Color::WHITE,
+            },
+            icon_style: TextStyle {
+                font_family: vec!["Noto Sans".to_string()],
+                font_size: 11.0,
+                font_color: Color::WHITE,
+                horizontal_alignment: Default::default(),
+                vertical_alignment: Default::default(),
+                weight: Default::default(),
+                style: Default::default(),
+                outline_width: Default::default(),
                 outline_color: Default::default(),
-            }
+            },
+        }
+    }
+
+    // Color and letter of the icon of a waypoint type
+    fn icon(kind: &str) -> Option<(Color, &'static str)> {
+        match kind {
+            "start" => Some((Color::rgba(20, 140, 40, 255), "S")),
+            "finish" => Some((Color::rgba(20, 20, 20, 255), "F")),
+            "hut" => Some((Color::rgba(200, 30, 30, 255), "H")),
+            "summit" => Some((Color::rgba(140, 70, 20, 255), "^")),
+            "water" => Some((Color::rgba(30, 90, 220, 255), "W")),
+            "camp" => Some((Color::rgba(230, 140, 0, 255), "C")),
+            _ => None,
         }
     }
 }
@@ -697,19 +1988,529 @@ impl Symbol<Waypoint> for WaypointSymbol {
             return;
         };
 
-        // Draw point
-        bundle.add_point(
-            point,
-            &PointPaint::circle(Color::RED, 8.0),
-            min_resolution,
-        );
+        let s = self.symbology.read();
+        let icon = WaypointSymbol::icon(&feature.kind);
+        let color = if feature.selected {
+            rgb(s.line(LineLayer::Selection).color)
+        }
+        else if let Some((c, _)) = icon {
+            c
+        }
+        else {
+            rgb(s.waypoint_color)
+        };
+        let style = label_style(&self.style, &s);
+
+        // Draw point. Typed waypoints have a larger icon with a letter.
+        if let Some((_, letter)) = icon {
+            bundle.add_point(
+                point,
+                &PointPaint::circle(color, s.waypoint_size*1.8),
+                min_resolution,
+            );
+            bundle.add_label(
+                point,
+                letter,
+                &self.icon_style,
+                Vector2::new(0.0, 0.0),
+                true,
+            );
+        }
+        else {
+            bundle.add_point(
+                point,
+                &PointPaint::circle(color, s.waypoint_size),
+                min_resolution,
+            );
+        }
         // Print caption
         bundle.add_label(
             point,
             &feature.label,
-            &self.style,
+            &style,
             Vector2::new(0.0, 10.0),
             true,
         );
     }
 }
+
+struct PoiFeature {
+    name: String,
+    kind: PoiKind,
+    lat: f64,
+    lon: f64,
+}
+
+impl PoiFeature {
+    fn new(name: String, kind: PoiKind, lat: f64, lon: f64) -> Self {
+        Self {
+            name: name,
+            kind: kind,
+            lat: lat,
+            lon: lon,
+        }
+    }
+}
+
+impl Feature for PoiFeature {
+    type Geom = Self;
+
+    fn geometry(&self) -> &Self::Geom {
+        self
+    }
+}
+
+impl GeoPoint for PoiFeature {
+    type Num = f64;
+
+    fn lat(&self) -> Self::Num {
+        self.lat
+    }
+
+    fn lon(&self) -> Self::Num {
+        self.lon
+    }
+}
+
+impl Geometry for PoiFeature {
+    type Point = GeoPoint2d;
+
+    fn project<P: Projection<InPoint = Self::Point> + ?Sized>(
+        &self,
+        projection: &P,
+    ) -> Option<Geom<P::OutPoint>> {
+        GeoPoint2d::latlon(self.lat, self.lon).project(projection)
+    }
+}
+
+struct PoiSymbol {
+    style: TextStyle,
+    symbology: Arc<RwLock<Symbology>>,
+}
+
+impl PoiSymbol {
+    fn new(symbology: Arc<RwLock<Symbology>>) -> Self {
+        Self {
+            symbology: symbology,
+            style: TextStyle {
+                font_family: vec!["Noto Sans".to_string()],
+                font_size: 12.0,
+                font_color: Color::BLACK,
+                horizontal_alignment: Default::default(),
+                vertical_alignment: Default::default(),
+                weight: Default::default(),
+                style: Default::default(),
+                outline_width: 2.0,
+                outline_color: Color::WHITE,
+            }
+        }
+    }
+
+    fn color(kind: PoiKind) -> Color {
+        match kind {
+            PoiKind::Summit => Color::rgba(140, 70, 20, 255),
+            PoiKind::Hut => Color::rgba(200, 30, 30, 255),
+            PoiKind::LakeOutlet => Color::rgba(30, 90, 220, 255),
+            PoiKind::Spring => Color::rgba(0, 150, 200, 255),
+            PoiKind::Bridge => Color::rgba(60, 60, 60, 255),
+            PoiKind::Ford => Color::rgba(0, 150, 200, 255),
+            PoiKind::Parking => Color::rgba(40, 40, 160, 255),
+            PoiKind::Other => Color::rgba(90, 90, 90, 255),
+        }
+    }
+}
+
+impl Symbol<PoiFeature> for PoiSymbol {
+    fn render<'a> (
+        &self,
+        feature: &PoiFeature,
+        geometry: &'a galileo_types::geometry::Geom<Point3>,
+        min_resolution: f64,
+        bundle: &mut RenderBundle,
+    ) {
+        let Geom::Point(point) = geometry else {
+            return;
+        };
+
+        let s = self.symbology.read();
+        let style = label_style(&self.style, &s);
+
+        // Draw icon with a white rim
+        bundle.add_point(
+            point,
+            &PointPaint::circle(Color::WHITE, s.poi_size + 3.0),
+            min_resolution,
+        );
+        bundle.add_point(
+            point,
+            &PointPaint::circle(PoiSymbol::color(feature.kind), s.poi_size),
+            min_resolution,
+        );
+        // Print caption
+        bundle.add_label(
+            point,
+            &feature.name,
+            &style,
+            Vector2::new(0.0, 8.0),
+            true,
+        );
+    }
+}
+
+fn rgb(c: Rgb) -> Color {
+    Color::rgba(c.0, c.1, c.2, 255)
+}
+
+// Label style with the halo of the symbology
+fn label_style(style: &TextStyle, symbology: &Symbology) -> TextStyle {
+    let mut style = style.clone();
+    style.outline_width = symbology.label_halo;
+    style
+}
+
+// Contour with the color and width of a line layer. The style is read when
+// the contour is drawn, so that features drawn again get the current style.
+struct LineSymbol {
+    symbology: Arc<RwLock<Symbology>>,
+    layer: LineLayer,
+}
+
+impl LineSymbol {
+    fn new(symbology: Arc<RwLock<Symbology>>, layer: LineLayer) -> Self {
+        Self {
+            symbology: symbology,
+            layer: layer,
+        }
+    }
+}
+
+impl Symbol<Contour<Point2>> for LineSymbol {
+    fn render<'a> (
+        &self,
+        feature: &Contour<Point2>,
+        geometry: &'a galileo_types::geometry::Geom<Point3>,
+        min_resolution: f64,
+        bundle: &mut RenderBundle,
+    ) {
+        let style = self.symbology.read().line(self.layer);
+
+        SimpleContourSymbol::new(rgb(style.color), style.width)
+            .render(feature, geometry, min_resolution, bundle);
+    }
+}
+
+// Leg of the track, in map coordinates
+struct TrackLeg {
+    points: Vec<Point2>,
+    contour: Contour<Point2>,
+}
+
+impl TrackLeg {
+    fn new(points: Vec<Point2>) -> Self {
+        Self {
+            contour: Contour::open(points.clone()),
+            points: points,
+        }
+    }
+}
+
+impl Feature for TrackLeg {
+    type Geom = Contour<Point2>;
+
+    fn geometry(&self) -> &Self::Geom {
+        &self.contour
+    }
+}
+
+// Track line with arrowheads along it pointing in the walking direction
+struct TrackSymbol {
+    line: LineSymbol,
+}
+
+impl TrackSymbol {
+    fn new(symbology: Arc<RwLock<Symbology>>) -> Self {
+        Self {
+            line: LineSymbol::new(symbology, LineLayer::Track),
+        }
+    }
+
+    // Arrowheads every ARROW_SPACING pixels, as the two strokes of each
+    // arrowhead joined at the tip. Distances in map units are pixels times
+    // the resolution.
+    fn arrows(points: &[Point2], resolution: f64) -> Vec<[(f64, f64); 3]> {
+        let spacing = ARROW_SPACING*resolution;
+        let size = ARROW_SIZE*resolution;
+        let mut arrows = vec![];
+        // Distance to the next arrowhead. The first one is half the spacing
+        // from the start.
+        let mut next = spacing/2.0;
+
+        for w in points.windows(2) {
+            let (dx, dy) = (w[1].x() - w[0].x(), w[1].y() - w[0].y());
+            let len = (dx*dx + dy*dy).sqrt();
+
+            if len == 0.0 {
+                continue;
+            }
+
+            let (ux, uy) = (dx/len, dy/len);
+            let mut d = next;
+
+            while d <= len {
+                let tip = (w[0].x() + ux*d, w[0].y() + uy*d);
+                let back = (tip.0 - ux*size, tip.1 - uy*size);
+                let (px, py) = (-uy*size*0.6, ux*size*0.6);
+
+                arrows.push([(back.0 + px, back.1 + py), tip,
+                             (back.0 - px, back.1 - py)]);
+                d += spacing;
+            }
+
+            next = d - len;
+        }
+
+        arrows
+    }
+}
+
+impl Symbol<TrackLeg> for TrackSymbol {
+    fn render<'a> (
+        &self,
+        feature: &TrackLeg,
+        geometry: &'a galileo_types::geometry::Geom<Point3>,
+        min_resolution: f64,
+        bundle: &mut RenderBundle,
+    ) {
+        self.line.render(&feature.contour, geometry, min_resolution, bundle);
+
+        let style = self.line.symbology.read().line(LineLayer::Track);
+        let symbol = SimpleContourSymbol::new(rgb(style.color),
+                                              style.width);
+
+        for a in TrackSymbol::arrows(&feature.points, min_resolution) {
+            let arrow = Geom::Contour(Contour::open(
+                a.iter().map(|(x, y)| Point3::new(*x, *y, 0.0)).collect()));
+            symbol.render(&feature.contour, &arrow, min_resolution, bundle);
+        }
+    }
+}
+
+struct Marker {
+    kind: MarkerKind,
+    label: String,
+    lat: f64,
+    lon: f64,
+}
+
+impl Marker {
+    fn new(kind: MarkerKind, label: String, lat: f64, lon: f64) -> Self {
+        Self {
+            kind: kind,
+            label: label,
+            lat: lat,
+            lon: lon,
+        }
+    }
+}
+
+impl Feature for Marker {
+    type Geom = Self;
+
+    fn geometry(&self) -> &Self::Geom {
+        self
+    }
+}
+
+impl GeoPoint for Marker {
+    type Num = f64;
+
+    fn lat(&self) -> Self::Num {
+        self.lat
+    }
+
+    fn lon(&self) -> Self::Num {
+        self.lon
+    }
+}
+
+impl Geometry for Marker {
+    type Point = GeoPoint2d;
+
+    fn project<P: Projection<InPoint = Self::Point> + ?Sized>(
+        &self,
+        projection: &P,
+    ) -> Option<Geom<P::OutPoint>> {
+        GeoPoint2d::latlon(self.lat, self.lon).project(projection)
+    }
+}
+
+struct MarkerSymbol {
+    style: TextStyle,
+    symbology: Arc<RwLock<Symbology>>,
+}
+
+impl MarkerSymbol {
+    fn new(symbology: Arc<RwLock<Symbology>>) -> Self {
+        Self {
+            symbology: symbology,
+            style: TextStyle {
+                font_family: vec!["Noto Sans".to_string()],
+                font_size: 12.0,
+                font_color: Color::BLACK,
+                horizontal_alignment: Default::default(),
+                vertical_alignment: Default::default(),
+                weight: Default::default(),
+                style: Default::default(),
+                outline_width: 2.0,
+                outline_color: Color::WHITE,
+            }
+        }
+    }
+
+    fn color(kind: MarkerKind) -> Color {
+        match kind {
+            MarkerKind::Ford => Color::rgba(0, 150, 200, 255),
+            MarkerKind::Camp => Color::rgba(20, 140, 40, 255),
+            MarkerKind::Pin => Color::rgba(140, 60, 200, 255),
+            MarkerKind::Turnaround => Color::rgba(220, 30, 30, 255),
+            MarkerKind::Exit => Color::rgba(90, 90, 90, 255),
+            MarkerKind::Gps => Color::rgba(0, 90, 255, 255),
+            MarkerKind::Km => Color::rgba(60, 60, 60, 255),
+        }
+    }
+}
+
+impl Symbol<Marker> for MarkerSymbol {
+    fn render<'a> (
+        &self,
+        feature: &Marker,
+        geometry: &'a galileo_types::geometry::Geom<Point3>,
+        min_resolution: f64,
+        bundle: &mut RenderBundle,
+    ) {
+        let Geom::Point(point) = geometry else {
+            return;
+        };
+
+        let s = self.symbology.read();
+        let style = label_style(&self.style, &s);
+
+        // Distance markers are small ticks
+        let size = if feature.kind == MarkerKind::Km {
+            s.marker_size*0.5
+        }
+        else {
+            s.marker_size
+        };
+
+        bundle.add_point(
+            point,
+            &PointPaint::circle(MarkerSymbol::color(feature.kind), size),
+            min_resolution,
+        );
+        bundle.add_label(
+            point,
+            &feature.label,
+            &style,
+            Vector2::new(0.0, 8.0),
+            true,
+        );
+    }
+}
+
+// A node of the first pass graph, with the time to reach it relative to the
+// slowest node reached
+struct HeatCell {
+    lat: f64,
+    lon: f64,
+    level: f32,
+}
+
+impl HeatCell {
+    fn new(lat: f64, lon: f64, level: f32) -> Self {
+        Self {
+            lat: lat,
+            lon: lon,
+            level: level,
+        }
+    }
+}
+
+impl Feature for HeatCell {
+    type Geom = Self;
+
+    fn geometry(&self) -> &Self::Geom {
+        self
+    }
+}
+
+impl GeoPoint for HeatCell {
+    type Num = f64;
+
+    fn lat(&self) -> Self::Num {
+        self.lat
+    }
+
+    fn lon(&self) -> Self::Num {
+        self.lon
+    }
+}
+
+impl Geometry for HeatCell {
+    type Point = GeoPoint2d;
+
+    fn project<P: Projection<InPoint = Self::Point> + ?Sized>(
+        &self,
+        projection: &P,
+    ) -> Option<Geom<P::OutPoint>> {
+        GeoPoint2d::latlon(self.lat, self.lon).project(projection)
+    }
+}
+
+// Semi-transparent dots going from green (near the start) through yellow to
+// red (slowest to reach)
+struct HeatmapSymbol {
+    alpha: u8,
+    size: f32,
+}
+
+impl HeatmapSymbol {
+    fn new() -> Self {
+        Self {
+            alpha: 110,
+            size: 8.0,
+        }
+    }
+
+    fn color(&self, level: f32) -> Color {
+        let level = level.clamp(0.0, 1.0);
+        let (r, g) = if level < 0.5 {
+            (510.0*level, 255.0)
+        }
+        else {
+            (255.0, 510.0*(1.0 - level))
+        };
+
+        Color::rgba(r as u8, g as u8, 0, self.alpha)
+    }
+}
+
+impl Symbol<HeatCell> for HeatmapSymbol {
+    fn render<'a> (
+        &self,
+        feature: &HeatCell,
+        geometry: &'a galileo_types::geometry::Geom<Point3>,
+        min_resolution: f64,
+        bundle: &mut RenderBundle,
+    ) {
+        let Geom::Point(point) = geometry else {
+            return;
+        };
+
+        bundle.add_point(
+            point,
+            &PointPaint::circle(self.color(feature.level), self.size),
+            min_resolution,
+        );
+    }
+}
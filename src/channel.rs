@@ -1,25 +1,110 @@
 use crate::barrier::Barrier;
 use crate::path::Path;
+use crate::poi::Poi;
+use crate::symbology::Symbology;
 
 use crossbeam_channel::{Sender, Receiver, unbounded};
 use hoydedata::Coord;
 
+// Kinds of markers shown on the map. Each kind is replaced as a whole.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MarkerKind {
+    Ford,
+    Camp,
+    Pin,
+    Turnaround,
+    Exit,
+    Gps,
+    // Distance along the track
+    Km,
+}
+
 #[derive(Debug)]
 pub enum CanvasMsg {
-    SetWaypoints(Vec<Coord>),
+    // Waypoints with their names and types
+    SetWaypoints(Vec<Coord>, Vec<String>, Vec<String>),
     SetBarriers(Vec<Barrier>),
+    SetCorridor(Barrier),
+    SetGates(Vec<Barrier>),
+    // Declination of the bearings shown, and whether they are magnetic
+    SetNorth(f32, bool),
+    SetPinAreas(Vec<Barrier>),
     SetPath(Path),
+    // Path where only the given legs have changed
+    UpdatePathLegs(Path, Vec<usize>),
+    SetNightTrack(Vec<Vec<Coord>>),
+    // Track shown in a comparison pane beside the map, with a label. None
+    // closes the pane.
+    SetCompareTrack(Option<(Path, String)>),
     SetCoveringArea(f32, f32),
-    RequestPoint,
-    RequestBarrier,
+    SetPois(Vec<Poi>),
+    SetMarkers(MarkerKind, Vec<(Coord, String)>),
+    // Position from the GPS receiver
+    SetGpsPosition(Option<Coord>),
+    // Colors, line widths and sizes of the map symbols
+    SetSymbology(Symbology),
+    // The GPS position has drifted off the planned route
+    FlashAlarm,
+    // Slope (degrees) and time per meter of the active cost model, and the
+    // slope and speed (km/h) of the editable control points
+    SetCostCurve(Vec<(f32, f32)>, Vec<(f32, f32)>),
+    // Graph nodes and nodes explored by the search, for debugging
+    SetDebugGraph(Vec<Coord>, Vec<Coord>),
+    // Time (s) to reach graph nodes from the start of a leg
+    SetHeatmap(Vec<(Coord, f32)>),
+    ShowPois(bool),
+    ShowCostCurve,
+    // Line of command output shown in the console
+    ConsoleOutput(String),
+    // Requests from the app for a point or a barrier, by request id. A new
+    // request replaces an unanswered one.
+    RequestPoint(u64),
+    RequestBarrier(u64),
+    // The app has given up waiting for the answer to a request
+    CancelRequest(u64),
     RedrawTmpBarrier,
+    RedrawSelection,
+    CenterOn(Coord),
     ResetView,
     Quit,
 }
 
+// Area shown in the map window
+#[derive(Clone, Copy, Debug)]
+pub struct Viewport {
+    pub center: Coord,
+    // South west and north east corners
+    pub sw: Coord,
+    pub ne: Coord,
+    // Meters per pixel
+    pub resolution: f32,
+}
+
 pub enum AppMsg {
-    SelectPoint(Coord),
-    CreateBarrier(Barrier),
+    // Answers to requests from the app, by request id
+    SelectPoint(u64, Coord),
+    CreateBarrier(u64, Barrier),
+    RequestCancelled(u64),
+    AddPoi(Poi),
+    // Waypoint clicked in the map window in add mode
+    AddPoint(Coord),
+    // Track dragged in the map window, from one position to another
+    DragTrack(Coord, Coord),
+    // Waypoint name edited in the map window
+    SetPointName(usize, String),
+    // Features deleted in the map window
+    RemovePoint(usize),
+    RemoveBarrier(usize),
+    RemovePath,
+    // Cost curve edited in the map window
+    SetCostCurve(Vec<(f32, f32)>),
+    // The map view has changed significantly
+    SetViewport(Viewport),
+    // Command without arguments given by a keyboard shortcut in the map
+    // window, e.g. "compute" or "undo"
+    Command(String),
+    // Command line typed in the console, with arguments
+    ConsoleLine(String),
     Quit,
 }
 
@@ -1,15 +1,71 @@
+use crate::crs::DEFAULT_CRS;
+
 use clap::arg;
 use config::{*, ext::*};
 use lazy_static::lazy_static;
+use std::collections::HashMap;
 use std::env;
+use std::fs;
 use serde::{Deserialize, Serialize};
 
+// Settings read from the config file and the environment, in addition to
+// the command line. Environment variables are named STIVALG_<KEY>, e.g.
+// STIVALG_MAPS.
+const SETTINGS: [&str; 12] = ["maps", "crs", "geotiff", "download", "pois",
+                              "roads", "params_fname", "tile_url",
+                              "language", "units", "wmm", "gps"];
+const ENV_PREFIX: &str = "STIVALG_";
+// Separator of the map directories in the maps setting
+const MAP_DIR_SEPARATOR: &str = ":";
+// Default map tiles, with {z}, {x} and {y} replaced by the tile index
+const DEFAULT_TILE_URL: &str =
+    "https://tile.opentopomap.org/{z}/{x}/{y}.png";
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Config {
+    // Map directories, separated by ':', searched in order
     pub maps: String,
+    // Reference system of the map coordinates, e.g. EPSG:25833
+    pub crs: String,
+    // Directory of GeoTIFF DEMs used instead of the hoydedata maps
+    pub geotiff: String,
+    // Url template for downloading elevation tiles on demand
+    pub download: String,
+    // Url template of the background map tiles
+    pub tile_url: String,
+    // Language and units (metric or imperial) of the user interface. Only
+    // English and metric units are implemented so far.
+    pub language: String,
+    pub units: String,
+    // Coefficient file (WMM.COF) of the World Magnetic Model, for finding
+    // the magnetic declination
+    pub wmm: String,
+    // GPS receiver: gpsd, gpsd://host:port or a device giving NMEA
+    // sentences
+    pub gps: String,
     pub headless: bool,
+    // Read commands from a console in the map window instead of the
+    // terminal
+    pub gui_only: bool,
+    // Socket where other programs can send commands
+    pub control_socket: String,
+    // Report the progress of computations as JSON lines on stderr
+    pub json: bool,
     pub params_fname: String,
+    // Params set on the command line (--set key=value), one per line
+    pub overrides: String,
+    // Waypoints given on the command line, separated by ','
+    pub points: String,
+    pub pois: String,
+    // Road ends, parking and trailheads, in the format of the pois
+    pub roads: String,
     pub command: String,
+    // File arguments of the info and export subcommands
+    pub input_fname: String,
+    pub output_fname: String,
+    pub export_format: String,
+    // Second params file of the diff subcommand
+    pub diff_fname: String,
 }
 
 lazy_static! {
@@ -27,6 +83,54 @@ pub const CLAP_STYLING: clap::builder::styling::Styles =
     .invalid(clap_cargo::style::INVALID);
 
 impl Config {
+    // Name of the config file, ~/.config/stivalg/config.toml
+    fn config_fname() -> Option<String> {
+        let home = env::var("HOME").ok()?;
+
+        Some(format!("{}/.config/stivalg/config.toml", home))
+    }
+
+    // Settings in the config file. Errors are reported, and the file is
+    // then ignored.
+    fn read_config_file() -> HashMap<String, String> {
+        let mut settings = HashMap::new();
+        let Some(fname) = Config::config_fname() else {
+            return settings;
+        };
+        let Ok(data) = fs::read_to_string(&fname) else {
+            return settings;
+        };
+
+        let table = match data.parse::<toml::Table>() {
+            Ok(table) => table,
+            Err(e) => {
+                println!("Ignoring {}: {}", fname, e);
+                return settings;
+            },
+        };
+
+        for (key, value) in table {
+            if !SETTINGS.contains(&key.as_str()) {
+                println!("Unknown setting '{}' in {}", key, fname);
+                continue;
+            }
+
+            let value = match value {
+                toml::Value::String(s) => s,
+                // A list of map directories
+                toml::Value::Array(a) => a.iter()
+                    .map(|v| v.as_str().map(|s| s.to_string())
+                         .unwrap_or(v.to_string()))
+                    .collect::<Vec<String>>()
+                    .join(MAP_DIR_SEPARATOR),
+                v => v.to_string(),
+            };
+            settings.insert(key, value);
+        }
+
+        settings
+    }
+
     pub fn new() -> Self {
         // Parse command line
         let clap = clap::Command::new("stivalg")
@@ -35,19 +139,106 @@ impl Config {
             .args([
                 arg!(-p --params <FILE> "Read params from file"),
                 arg!(-H --headless "Don't show map window"),
+                arg!(--"gui-only"
+                     "Type commands in a console in the map window instead \
+                      of the terminal"),
+                arg!(--"control-socket" <PATH>
+                     "Accept commands on a Unix domain socket (host:port \
+                      on Windows)"),
+                arg!(--gps <SOURCE>
+                     "Show the position from a GPS: gpsd, gpsd://host:port \
+                      or an NMEA serial device"),
+                arg!(--json
+                     "Write the progress of computations as JSON lines to \
+                      stderr"),
+                arg!(--set <KEY_VALUE>
+                     "Set a param, overriding the params file \
+                      (key=value, may be repeated)")
+                    .action(clap::ArgAction::Append),
+                arg!(--points <POINTS>
+                     "Waypoints replacing those of the params, as coords \
+                      or place names separated by ','"),
+                arg!(--pois <FILE> "Read points of interest from file"),
+                arg!(--roads <FILE>
+                     "Read road ends and trailheads from file"),
+                arg!(--crs <CRS>
+                     "Reference system of the maps (default EPSG:25833)"),
+                arg!(--geotiff <DIR>
+                     "Use the GeoTIFF elevation rasters in a directory"),
+                arg!(--download <URL>
+                     "Download elevation tiles on demand from a WCS url \
+                      template with {west} {south} {east} {north} {crs} \
+                      {size}"),
             ])
             .subcommand_required(false)
-            .subcommand(clap::command!("compute"));
+            .subcommand(clap::command!("compute"))
+            .subcommand(clap::command!("selftest"))
+            .subcommand(clap::command!("info")
+                        .about("Print statistics of a params or GPX file")
+                        .arg(arg!(<FILE> "Params JSON or GPX track")))
+            .subcommand(clap::command!("export")
+                        .about("Convert a GPX track or the waypoints of a \
+                                params file")
+                        .args([
+                            arg!(<FILE> "Params JSON or GPX track"),
+                            arg!(--to <FORMAT>
+                                 "kml, geojson, csv, fit or gpx"),
+                            arg!(<OUTPUT> "Output file"),
+                        ]))
+            .subcommand(clap::command!("diff")
+                        .about("Compare two params files")
+                        .args([
+                            arg!(<FILE> "Params JSON"),
+                            arg!(<OTHER> "Params JSON to compare with"),
+                        ]));
 
         let matches = clap.get_matches();
-        let opt_params = matches.get_one::<String>("params");
         let mut headless = false;
-        let mut params_fname = "";
 
-        if let Some(params) = opt_params {
-            params_fname = params;
+        // Settings are taken from the defaults, the config file, the
+        // environment and the command line, with the later ones overriding
+        // the earlier ones.
+        let mut settings: HashMap<String, String> = [
+            ("maps", "/media/ekstern/hoydedata"),
+            ("crs", DEFAULT_CRS),
+            ("tile_url", DEFAULT_TILE_URL),
+            ("language", "en"),
+            ("units", "metric"),
+        ].iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+
+        settings.extend(Config::read_config_file());
+
+        for key in SETTINGS {
+            let var = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+            if let Ok(value) = env::var(&var) {
+                settings.insert(key.to_string(), value);
+            }
+        }
+
+        for (arg, key) in [("params", "params_fname"), ("pois", "pois"),
+                           ("roads", "roads"), ("crs", "crs"),
+                           ("geotiff", "geotiff"), ("download", "download"),
+                           ("gps", "gps")] {
+            if let Some(value) = matches.get_one::<String>(arg) {
+                settings.insert(key.to_string(), value.clone());
+            }
         }
 
+        if settings.get("units").is_some_and(|u| u != "metric"
+                                             && u != "imperial") {
+            println!("Invalid units '{}'. Using metric.", settings["units"]);
+            settings.insert("units".to_string(), "metric".to_string());
+        }
+
+        let overrides = matches.get_many::<String>("set")
+            .map(|v| v.cloned().collect::<Vec<String>>().join("\n"))
+            .unwrap_or_default();
+
+        let points = matches.get_one::<String>("points").cloned()
+            .unwrap_or_default();
+        let control_socket = matches.get_one::<String>("control-socket")
+            .cloned().unwrap_or_default();
+
         match matches.get_one::<bool>("headless") {
             Some(h) => {
                 headless = *h;
@@ -56,22 +247,68 @@ impl Config {
         }
 
         let mut command = "";
+        let mut input_fname = "";
+        let mut output_fname = "";
+        let mut export_format = "";
+        let mut diff_fname = "";
 
         match matches.subcommand() {
-            Some((cmd, _)) => {
+            Some((cmd, sub_matches)) => {
                 command = cmd;
                 headless = true;
+
+                if let Some(f) = sub_matches.try_get_one::<String>("FILE")
+                    .ok().flatten() {
+                    input_fname = f;
+                }
+
+                if let Some(f) = sub_matches.try_get_one::<String>("OUTPUT")
+                    .ok().flatten() {
+                    output_fname = f;
+                }
+
+                if let Some(f) = sub_matches.try_get_one::<String>("to")
+                    .ok().flatten() {
+                    export_format = f;
+                }
+
+                if let Some(f) = sub_matches.try_get_one::<String>("OTHER")
+                    .ok().flatten() {
+                    diff_fname = f;
+                }
             },
             None => { },
         }
 
-        // Create config with default settings
+        let setting = |key: &str| settings.get(key).cloned()
+            .unwrap_or_default();
+
+        // Create config with the settings
 	let config = DefaultConfigurationBuilder::new()
             .add_in_memory(&[
-	        ("maps", "/media/ekstern/hoydedata"),
+	        ("maps", &setting("maps")),
+                ("crs", &setting("crs")),
+                ("geotiff", &setting("geotiff")),
+                ("download", &setting("download")),
+                ("tile_url", &setting("tile_url")),
+                ("language", &setting("language")),
+                ("units", &setting("units")),
+                ("wmm", &setting("wmm")),
+                ("gps", &setting("gps")),
                 ("headless", &headless.to_string()),
-                ("params_fname", params_fname),
+                ("json", &matches.get_flag("json").to_string()),
+                ("gui_only", &matches.get_flag("gui-only").to_string()),
+                ("control_socket", &control_socket),
+                ("params_fname", &setting("params_fname")),
+                ("overrides", &overrides),
+                ("points", &points),
+                ("pois", &setting("pois")),
+                ("roads", &setting("roads")),
                 ("command", command),
+                ("input_fname", input_fname),
+                ("output_fname", output_fname),
+                ("export_format", export_format),
+                ("diff_fname", diff_fname),
             ])
             .build()
             .unwrap();
@@ -79,12 +316,46 @@ impl Config {
 	config.reify()
     }
 
+    // The map directories, in search order
+    pub fn map_dirs(&self) -> Vec<String> {
+        self.maps.split(MAP_DIR_SEPARATOR)
+            .filter(|d| !d.is_empty())
+            .map(|d| {
+                let mut md = d.to_string();
+                if !md.ends_with("/") {
+                    md.push('/');
+                }
+
+                md
+            })
+            .collect()
+    }
+
+    // The first map directory. Downloads and the poi database are kept
+    // there.
     pub fn map_dir(&self) -> String {
-	let mut md = self.maps.clone();
-	if !md.ends_with("/") {
-	    md.push('/');
-	}
+        self.map_dirs().first().cloned().unwrap_or("/".to_string())
+    }
 
-	md
+    // Params set on the command line, as (key, value)
+    pub fn overrides(&self) -> Result<Vec<(String, String)>, String> {
+        self.overrides.lines()
+            .map(|l| match l.split_once('=') {
+                Some((k, v)) => Ok((k.trim().to_string(),
+                                    v.trim().to_string())),
+                None => Err(format!("Invalid --set {}. Expected key=value",
+                                    l)),
+            })
+            .collect()
+    }
+
+    // The poi database defaults to a file in the map directory
+    pub fn poi_fname(&self) -> String {
+        if self.pois == "" {
+            format!("{}poi.csv", self.map_dir())
+        }
+        else {
+            self.pois.clone()
+        }
     }
 }
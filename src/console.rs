@@ -0,0 +1,146 @@
+use crate::channel::{CanvasMsg, CanvasSender};
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+
+// Lines of output kept in the console
+const MAX_LINES: usize = 1000;
+// Number of commands suggested while typing
+const MAX_SUGGESTIONS: usize = 8;
+
+// Window for typing commands in the map window, replacing the terminal in
+// GUI-only mode. It shows the output of the commands, and suggests commands
+// matching what has been typed.
+pub struct Console {
+    lines: VecDeque<String>,
+    input: String,
+    commands: Vec<String>,
+    pub open: bool,
+}
+
+impl Console {
+    pub fn new(commands: &[&str]) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            input: "".to_string(),
+            commands: commands.iter().map(|c| c.to_string()).collect(),
+            open: true,
+        }
+    }
+
+    pub fn push_line(&mut self, line: String) {
+        if self.lines.len() >= MAX_LINES {
+            self.lines.pop_front();
+        }
+
+        self.lines.push_back(line);
+    }
+
+    // Commands starting with the words typed so far
+    fn suggestions(&self) -> Vec<&String> {
+        let typed = self.input.trim_start();
+
+        if typed.is_empty() {
+            return vec![];
+        }
+
+        self.commands.iter()
+            .filter(|c| c.starts_with(typed) && c.as_str() != typed)
+            .take(MAX_SUGGESTIONS)
+            .collect()
+    }
+
+    // Show the window. Returns a command line when one has been entered.
+    pub fn show(&mut self, ctx: &egui::Context) -> Option<String> {
+        let mut open = self.open;
+        let mut entered = None;
+        let mut completed = None;
+
+        egui::Window::new("Console")
+            .open(&mut open)
+            .default_width(500.0)
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .stick_to_bottom(true)
+                    .show(ui, |ui| {
+                        for line in &self.lines {
+                            ui.monospace(line);
+                        }
+                    });
+
+                ui.separator();
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut self.input)
+                        .font(egui::TextStyle::Monospace)
+                        .desired_width(f32::INFINITY)
+                        .hint_text("Command, e.g. help"));
+
+                if response.lost_focus()
+                    && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    let line = self.input.trim().to_string();
+                    if line != "" {
+                        entered = Some(line);
+                    }
+                    self.input.clear();
+                    response.request_focus();
+                }
+
+                // Clicking a suggestion puts its command words in the input
+                for s in self.suggestions() {
+                    if ui.small_button(s).clicked() {
+                        completed = Some(s.split(' ')
+                            .take_while(|w| !w.starts_with(['<', '[']))
+                            .collect::<Vec<&str>>()
+                            .join(" ") + " ");
+                    }
+                }
+            });
+
+        if let Some(c) = completed {
+            self.input = c;
+        }
+
+        self.open = open;
+
+        entered
+    }
+}
+
+// Send everything written to stdout to the console as lines, as well as
+// to the original stdout
+pub fn capture_stdout(tx: CanvasSender) -> Result<(), String> {
+    let mut fds = [0; 2];
+
+    // The write end of a pipe replaces stdout
+    let stdout_fd = std::io::stdout().as_raw_fd();
+    let (orig, res) = unsafe {
+        if libc::pipe(fds.as_mut_ptr()) != 0 {
+            return Err("Unable to create pipe for the console".to_string());
+        }
+        let orig = libc::dup(stdout_fd);
+        let res = libc::dup2(fds[1], stdout_fd);
+        libc::close(fds[1]);
+        (orig, res)
+    };
+
+    if orig < 0 || res < 0 {
+        return Err("Unable to redirect stdout to the console".to_string());
+    }
+
+    let reader = BufReader::new(unsafe { File::from_raw_fd(fds[0]) });
+    let mut orig = unsafe { File::from_raw_fd(orig) };
+
+    std::thread::spawn(move || {
+        for line in reader.lines() {
+            let Ok(line) = line else { break; };
+            let _ = writeln!(orig, "{}", line);
+            // Output after the window has closed only goes to stdout
+            let _ = tx.send(CanvasMsg::ConsoleOutput(line));
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,93 @@
+use crate::output;
+
+use std::cell::Cell;
+use std::io::{BufRead, BufReader, Read, Write};
+
+// Remote control of a running instance. Clients connect to a Unix domain
+// socket (a TCP address on Windows) and send command lines, as typed in the
+// terminal. Each command is answered by its output, with each line prefixed
+// by "| ", and a last line "ok" or "error <message>".
+
+thread_local! {
+    // Whether this thread serves a client of the socket
+    static REMOTE: Cell<bool> = const { Cell::new(false) };
+}
+
+// Whether the command being run comes from a client of the socket. There
+// is no terminal to answer questions from such commands.
+pub fn is_remote() -> bool {
+    REMOTE.with(|r| r.get())
+}
+
+// Execute the commands of a client until it disconnects
+fn serve_client<S>(stream: S, execute: &dyn Fn(&str) -> Result<(), String>)
+where for<'a> &'a S: Read + Write {
+    let reader = BufReader::new(&stream);
+    let mut writer = &stream;
+
+    REMOTE.with(|r| r.set(true));
+
+    for line in reader.lines() {
+        let Ok(line) = line else { break; };
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let (res, output) = output::capture(|| execute(line));
+        let mut answer = String::new();
+
+        for l in output {
+            answer.push_str(&format!("| {}\n", l));
+        }
+
+        match res {
+            Ok(()) => answer.push_str("ok\n"),
+            Err(e) => answer.push_str(&format!("error {}\n", e)),
+        }
+
+        if writer.write_all(answer.as_bytes()).is_err() {
+            break;
+        }
+    }
+}
+
+// Listen for clients on a socket, executing their commands. Each client is
+// served by a thread of its own.
+pub fn serve<F>(address: &str, execute: F) -> Result<(), String>
+where F: Fn(&str) -> Result<(), String> + Clone + Send + 'static {
+    #[cfg(unix)]
+    let listener = {
+        use std::os::unix::fs::FileTypeExt;
+
+        // A socket left by an earlier instance is replaced. Other files
+        // are left alone.
+        if let Ok(meta) = std::fs::symlink_metadata(address) {
+            if !meta.file_type().is_socket() {
+                return Err(format!("{} exists and is not a socket",
+                                   address));
+            }
+            let _ = std::fs::remove_file(address);
+        }
+        std::os::unix::net::UnixListener::bind(address)
+    };
+    #[cfg(not(unix))]
+    let listener = std::net::TcpListener::bind(address);
+
+    let listener = listener
+        .map_err(|e| format!("Unable to listen on {}: {}", address, e))?;
+
+    println!("Listening for commands on {}", address);
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue; };
+            let execute = execute.clone();
+
+            std::thread::spawn(move || serve_client(stream, &execute));
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,372 @@
+use crate::elevation::ElevationSource;
+use crate::params::Params;
+use crate::path::Segment;
+use crate::script::CostScript;
+
+use hoydedata::Coord;
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+// Gradients below this (about 3 degrees) are considered flat, i.e. without
+// any aspect.
+const FLAT_GRADIENT: f32 = 0.05;
+
+// Names of the aspect sectors, in the order used by the aspect multipliers
+pub const ASPECT_NAMES: [&str; 8] = [
+    "N", "NE", "E", "SE", "S", "SW", "W", "NW",
+];
+
+// Joule per kilocalorie
+pub const JOULE_PER_KCAL: f32 = 4184.0;
+
+// Names of the built-in cost models
+pub const COST_MODELS: &[&str] = &["default", "naismith", "curve"];
+
+lazy_static! {
+    // Cost models registered by applications using stivalg as a library
+    static ref REGISTERED: RwLock<BTreeMap<String, Arc<dyn CostModel>>> =
+        RwLock::new(BTreeMap::new());
+}
+
+// A model of walking time as a function of the steepness s (the gradient in
+// the walking direction) and the absolute gradient abs (squared) of the
+// terrain. Returns time (seconds) per meter. Other models can be added with
+// register_cost_model.
+pub trait CostModel: Debug + Send + Sync {
+    fn time_by_steepness(&self, s: f32, abs: f32) -> f32;
+}
+
+// The built-in piecewise table of walking times
+#[derive(Debug)]
+pub struct DefaultModel {
+}
+
+impl CostModel for DefaultModel {
+    fn time_by_steepness(&self, s: f32, abs: f32) -> f32 {
+        Segment::time_by_steepness(s, abs)
+    }
+}
+
+// Naismith's rule (5 km/h, plus one hour per 600m of ascent) with Langmuir's
+// corrections for descent: 10 minutes per 300m are subtracted on gentle
+// slopes (5 - 12 degrees) and added on steeper slopes.
+#[derive(Debug)]
+pub struct NaismithModel {
+}
+
+impl CostModel for NaismithModel {
+    fn time_by_steepness(&self, s: f32, _abs: f32) -> f32 {
+        let flat = 3600.0/5000.0;
+
+        if s >= 0.0 {
+            flat + s*3600.0/600.0
+        }
+        else if -s < 5.0_f32.to_radians().tan() {
+            flat
+        }
+        else if -s < 12.0_f32.to_radians().tan() {
+            flat + s*600.0/300.0
+        }
+        else {
+            flat - s*600.0/300.0
+        }
+    }
+}
+
+// User defined curve of walking speed by slope. The time is interpolated
+// linearly between the samples, and kept constant beyond the first and last
+// samples.
+#[derive(Debug)]
+pub struct CurveModel {
+    // Steepness and time per meter, ordered by steepness
+    samples: Vec<(f32, f32)>,
+}
+
+impl CurveModel {
+    // Make model from samples of slope (degrees) and speed (km/h)
+    pub fn new(curve: &[(f32, f32)]) -> Self {
+        let mut samples: Vec<(f32, f32)> = curve.iter()
+            .map(|(slope, speed)| (slope.to_radians().tan(), 3.6/speed))
+            .collect();
+        samples.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        Self {
+            samples: samples,
+        }
+    }
+}
+
+impl CostModel for CurveModel {
+    fn time_by_steepness(&self, s: f32, _abs: f32) -> f32 {
+        let n = self.samples.partition_point(|(ss, _)| *ss < s);
+
+        if n == 0 {
+            return self.samples[0].1;
+        }
+        else if n == self.samples.len() {
+            return self.samples[n - 1].1;
+        }
+
+        let (s1, t1) = self.samples[n - 1];
+        let (s2, t2) = self.samples[n];
+
+        (t2 - t1)*(s - s1)/(s2 - s1) + t1
+    }
+}
+
+// Read a cost curve from a csv file with the columns slope (degrees) and
+// speed (km/h), e.g.:
+//
+//   slope,speed
+//   -20,3.5
+//   0,4.5
+//   20,1.8
+pub fn read_cost_curve(fname: &str) -> Result<Vec<(f32, f32)>, String> {
+    let mut reader = csv::Reader::from_path(fname)
+        .map_err(|e| format!("Unable to open {}: {}", fname, e))?;
+    let mut curve = vec![];
+
+    for result in reader.deserialize() {
+        let (slope, speed): (f32, f32) = result
+            .map_err(|e| format!("Invalid sample in {}: {}", fname, e))?;
+
+        if speed <= 0.0 || slope.abs() >= 90.0 {
+            return Err(format!("Invalid sample in {}: {},{}", fname, slope,
+                               speed));
+        }
+
+        curve.push((slope, speed));
+    }
+
+    if curve.len() < 2 {
+        return Err(format!("Expected at least two samples in {}", fname));
+    }
+
+    Ok(curve)
+}
+
+// Make a cost model selectable by name in the params, like the built-in
+// models. A model registered under the same name earlier is replaced.
+pub fn register_cost_model(name: &str, model: Box<dyn CostModel>)
+                           -> Result<(), String> {
+    if COST_MODELS.contains(&name) {
+        return Err(format!("Cost model '{}' is built in", name));
+    }
+
+    REGISTERED.write().insert(name.to_string(), Arc::from(model));
+
+    Ok(())
+}
+
+// Names of the built-in and the registered cost models
+pub fn cost_model_names() -> Vec<String> {
+    COST_MODELS.iter()
+        .map(|n| n.to_string())
+        .chain(REGISTERED.read().keys().cloned())
+        .collect()
+}
+
+// Look up a cost model by name. The curve model is made from the given
+// samples of slope and speed.
+pub fn cost_model(name: &str, curve: &[(f32, f32)])
+                  -> Result<Arc<dyn CostModel>, String> {
+    match name {
+        "default" => Ok(Arc::new(DefaultModel {})),
+        "naismith" => Ok(Arc::new(NaismithModel {})),
+        "curve" => {
+            if curve.len() < 2 {
+                return Err("No cost curve defined".to_string());
+            }
+
+            Ok(Arc::new(CurveModel::new(curve)))
+        },
+        s => match REGISTERED.read().get(s) {
+            Some(model) => Ok(model.clone()),
+            None => Err(format!("Invalid cost model '{}'. Expected one of \
+                                 {}", s, cost_model_names().join(", "))),
+        },
+    }
+}
+
+// Cost function parameters. This is made from the params when a computation
+// starts, and is used for calculating the time of walking across the terrain.
+#[derive(Clone, Debug)]
+pub struct Cost {
+    model: Arc<dyn CostModel>,
+    // Script replacing the model and the cross slope weight
+    script: Option<Arc<CostScript>>,
+    aspect_multipliers: [f32; 8],
+    // Mass of walker and pack (kg)
+    body_mass: f32,
+    // Recommended water intake (liters per hour)
+    fluid_rate: f32,
+    // Terrain gradients above this can't be walked
+    max_gradient: f32,
+    // Extra time per unit of gradient across the walking direction
+    cross_slope_weight: f32,
+    // Extra time per meter of terrain roughness
+    roughness_weight: f32,
+    // Elevation changes (m) ignored when adding up ascent and descent
+    ascent_threshold: f32,
+    // Time multiplier of the slowest member of the group, and the ratio of
+    // the fastest member's time to it
+    pace: f32,
+    group_spread: f32,
+    // Generation of the params the cost was made from
+    generation: u64,
+}
+
+impl Cost {
+    pub fn from_params(params: &Params) -> Self {
+        let mut aspect_multipliers = [1.0; 8];
+
+        for (i, m) in params.aspect_multipliers.iter().take(8).enumerate() {
+            aspect_multipliers[i] = *m;
+        }
+
+        // The model name is checked when set, fall back to the default
+        // model for invalid names in params files.
+        let model = cost_model(&params.cost_model, &params.cost_curve)
+            .unwrap_or_else(|_| Arc::new(DefaultModel {}));
+
+        let (fastest, slowest) = params.group_paces().unwrap_or((1.0, 1.0));
+
+        // The script is checked when set as well
+        let script = if params.cost_script != "" {
+            CostScript::load(&params.cost_script).ok()
+        }
+        else {
+            None
+        };
+
+        Self {
+            model: model,
+            script: script,
+            aspect_multipliers: aspect_multipliers,
+            body_mass: params.body_mass,
+            fluid_rate: params.fluid_rate,
+            max_gradient: if params.impassable_slope >= 90.0 {
+                f32::INFINITY
+            }
+            else {
+                params.impassable_slope.to_radians().tan()
+            },
+            cross_slope_weight: params.cross_slope_weight,
+            roughness_weight: params.roughness_weight,
+            ascent_threshold: params.ascent_threshold,
+            pace: slowest,
+            group_spread: fastest/slowest,
+            generation: params.generation,
+        }
+    }
+
+    // Generation of the params and of the script the cost was made from.
+    // Costs of the same generation give the same times.
+    pub fn generation(&self) -> (u64, u64) {
+        (self.generation, self.script.as_ref().map_or(0, |s| s.generation))
+    }
+
+    pub fn ascent_threshold(&self) -> f32 {
+        self.ascent_threshold
+    }
+
+    // The fastest member's time for a time of the slowest
+    pub fn fastest_time(&self, time: f32) -> f32 {
+        time*self.group_spread
+    }
+
+    // Whether the group walks at different paces
+    pub fn has_group_spread(&self) -> bool {
+        self.group_spread < 1.0
+    }
+
+    // Aspect (the compass direction a slope faces) in degrees from north,
+    // given the gradient (dx, dy). The slope faces the downhill direction.
+    pub fn aspect(dx: f32, dy: f32) -> f32 {
+        let a = (-dx).atan2(-dy).to_degrees();
+
+        if a < 0.0 { a + 360.0 } else { a }
+    }
+
+    fn aspect_multiplier(&self, dx: f32, dy: f32, abs: f32) -> f32 {
+        if abs < FLAT_GRADIENT*FLAT_GRADIENT {
+            return 1.0;
+        }
+
+        let sector = ((Cost::aspect(dx, dy) + 22.5)/45.0) as usize % 8;
+
+        self.aspect_multipliers[sector]
+    }
+
+    // Whether terrain with gradient (dx, dy) is too steep to be walked
+    pub fn is_impassable(&self, dx: f32, dy: f32) -> bool {
+        (dx*dx + dy*dy).sqrt() > self.max_gradient
+    }
+
+    // Time (seconds) of walking one meter in the direction (de, dn) over
+    // terrain with gradient (dx, dy). Returns None if the terrain is too steep
+    // to be walked.
+    pub fn field_time(&self, de: f32, dn: f32, dx: f32, dy: f32)
+                      -> Option<f32> {
+        if self.is_impassable(dx, dy) {
+            return None;
+        }
+
+        // The models take the squared gradient
+        let abs = dx*dx + dy*dy;
+
+        // den*dxy = de*dx + dn*dy = |den||dxy|*cos(phi)
+        let s = de*dx + dn*dy;
+        // Gradient across the walking direction. Traversing a sidehill is
+        // slow even when the track itself is level.
+        let cross = (de*dy - dn*dx).abs();
+
+        if let Some(script) = &self.script {
+            let t = script.cost(s.atan().to_degrees(),
+                                cross.atan().to_degrees(), "")?;
+            return Some(t*self.aspect_multiplier(dx, dy, abs)*self.pace);
+        }
+
+        Some(self.time_by_steepness(s, abs)
+             *self.aspect_multiplier(dx, dy, abs)
+             *(1.0 + self.cross_slope_weight*cross)
+             *self.pace)
+    }
+
+    // Slowdown of walking over rough terrain at a coordinate. Estimating
+    // the roughness takes several lookups, so it is only done if it has a
+    // weight.
+    pub fn roughness_multiplier(&self, atlas: &dyn ElevationSource,
+                                c: &Coord) -> f32 {
+        if self.roughness_weight == 0.0 {
+            return 1.0;
+        }
+
+        1.0 + self.roughness_weight*atlas.roughness(c).unwrap_or(0.0)
+    }
+
+    // Time (seconds) per meter by the cost model, without any corrections
+    pub fn time_by_steepness(&self, s: f32, abs: f32) -> f32 {
+        self.model.time_by_steepness(s, abs)
+    }
+
+    // Energy (joule) spent walking one meter in the direction (de, dn) over
+    // terrain with gradient (dx, dy). Uses the metabolic cost of walking on
+    // gradients by Minetti et al. (2002), which is valid for gradients
+    // between -0.45 and 0.45.
+    pub fn field_energy(&self, de: f32, dn: f32, dx: f32, dy: f32) -> f32 {
+        let i = (de*dx + dn*dy).clamp(-0.45, 0.45);
+        // J/(kg m)
+        let c = ((((280.5*i - 58.7)*i - 76.8)*i + 51.9)*i + 19.6)*i + 2.5;
+
+        c*self.body_mass
+    }
+
+    // Recommended water intake (liters) for walking the given time (seconds)
+    pub fn fluid(&self, time: f32) -> f32 {
+        time/3600.0*self.fluid_rate
+    }
+}
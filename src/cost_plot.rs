@@ -0,0 +1,209 @@
+use egui::{Align2, Color32, FontId, Pos2, Rect, Sense, Stroke, Vec2};
+
+// Range of slopes (degrees) shown in the plot
+const MIN_SLOPE: f32 = -45.0;
+const MAX_SLOPE: f32 = 45.0;
+const PLOT_SIZE: Vec2 = Vec2::new(400.0, 180.0);
+const MARGIN: f32 = 40.0;
+// Radius of the control points (pixels)
+const CONTROL_RADIUS: f32 = 5.0;
+// Lowest speed (km/h) a control point can be dragged to
+const MIN_SPEED: f32 = 0.05;
+
+// Window plotting the active cost model as horizontal speed and vertical
+// speed by slope. This is the graphical counterpart of 'show cost'. The
+// speed curve of the curve model has control points which can be dragged
+// for calibrating the model.
+pub struct CostPlot {
+    // Slope (degrees) and time (seconds) per meter
+    samples: Vec<(f32, f32)>,
+    // Slope (degrees) and speed (km/h) of the control points
+    controls: Vec<(f32, f32)>,
+    // Index of the control point being dragged, and the speed range of the
+    // plot when the drag started.
+    dragged: Option<(usize, f32, f32)>,
+    pub open: bool,
+}
+
+impl CostPlot {
+    pub fn new() -> Self {
+        Self {
+            samples: vec![],
+            controls: vec![],
+            dragged: None,
+            open: false,
+        }
+    }
+
+    pub fn set_samples(&mut self, samples: Vec<(f32, f32)>,
+                       controls: Vec<(f32, f32)>) {
+        self.samples = samples;
+
+        // Don't move control points away under the mouse
+        if self.dragged.is_none() {
+            self.controls = controls;
+        }
+    }
+
+    // Show the window. Returns the edited curve when a control point has
+    // been dragged to a new position.
+    pub fn show(&mut self, ctx: &egui::Context) -> Option<Vec<(f32, f32)>> {
+        let mut open = self.open;
+        let mut edited = None;
+
+        egui::Window::new("Cost model")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                // Horizontal speed, km/h
+                let dpt: Vec<(f32, f32)> = self.samples.iter()
+                    .map(|(r, c)| (*r, 3.6/c))
+                    .collect();
+                // Vertical speed, m/h
+                let ept: Vec<(f32, f32)> = self.samples.iter()
+                    .map(|(r, c)| (*r, 3600.0*r.to_radians().tan()/c))
+                    .collect();
+
+                ui.label(if self.controls.is_empty() {
+                    "Distance/hour (km). Only the curve model can be edited."
+                }
+                else {
+                    "Distance/hour (km). Drag the points to edit."
+                });
+                edited = self.plot_speed(ui, &dpt);
+                ui.label("Elevation/hour (m)");
+                CostPlot::plot(ui, &ept, Color32::BLUE, None);
+            });
+
+        self.open = open;
+
+        edited
+    }
+
+    // Plot the horizontal speed with draggable control points
+    fn plot_speed(&mut self, ui: &mut egui::Ui, values: &[(f32, f32)])
+                  -> Option<Vec<(f32, f32)>> {
+        let range = self.dragged.map(|(_, vmin, vmax)| (vmin, vmax));
+        let (response, rect, vmin, vmax) = CostPlot::plot(
+            ui, values, Color32::RED, range);
+
+        let to_screen = |r: f32, v: f32| CostPlot::to_screen(
+            rect, vmin, vmax, r, v);
+        let painter = ui.painter_at(response.rect);
+
+        // While dragging, show the edited curve through the control points
+        if self.dragged.is_some() {
+            let points: Vec<Pos2> = self.controls.iter()
+                .map(|(r, v)| to_screen(*r, *v))
+                .collect();
+            painter.add(egui::Shape::dashed_line(
+                &points, Stroke::new(1.0, Color32::RED), 4.0, 4.0));
+        }
+
+        for (i, (r, v)) in self.controls.iter().enumerate() {
+            let active = matches!(self.dragged, Some((j, _, _)) if j == i);
+            painter.circle(to_screen(*r, *v), CONTROL_RADIUS,
+                           if active { Color32::YELLOW } else { Color32::WHITE },
+                           Stroke::new(1.0, Color32::RED));
+        }
+
+        if response.drag_started() {
+            if let Some(pos) = response.interact_pointer_pos() {
+                self.dragged = self.controls.iter()
+                    .position(|(r, v)| (to_screen(*r, *v) - pos).length()
+                              < CONTROL_RADIUS*2.0)
+                    .map(|i| (i, vmin, vmax));
+            }
+        }
+
+        if let Some((i, _, _)) = self.dragged {
+            if let Some(pos) = response.interact_pointer_pos() {
+                // Only the speed can be changed, the slope is kept
+                let v = vmin + (rect.bottom() - pos.y)/rect.height()
+                    *(vmax - vmin);
+                self.controls[i].1 = v.max(MIN_SPEED);
+            }
+
+            if response.drag_stopped() {
+                self.dragged = None;
+                return Some(self.controls.clone());
+            }
+        }
+
+        None
+    }
+
+    fn to_screen(rect: Rect, vmin: f32, vmax: f32, r: f32, v: f32) -> Pos2 {
+        Pos2::new(
+            rect.left() + (r - MIN_SLOPE)/(MAX_SLOPE - MIN_SLOPE)*rect.width(),
+            rect.bottom() - (v - vmin)/(vmax - vmin)*rect.height())
+    }
+
+    // Draw a curve of values by slope. The axes are scaled to the values
+    // unless a fixed range is given. Returns the response, the plot area and
+    // the range of the values axis.
+    fn plot(ui: &mut egui::Ui, values: &[(f32, f32)], color: Color32,
+            range: Option<(f32, f32)>)
+            -> (egui::Response, Rect, f32, f32) {
+        let (response, painter) = ui.allocate_painter(
+            PLOT_SIZE + Vec2::new(MARGIN, MARGIN*0.5), Sense::drag());
+        let rect = Rect::from_min_size(
+            response.rect.min + Vec2::new(MARGIN, 0.0), PLOT_SIZE);
+
+        let visible = values.iter()
+            .filter(|(r, v)| (MIN_SLOPE..=MAX_SLOPE).contains(r)
+                    && v.is_finite());
+
+        let (vmin, vmax) = range.unwrap_or_else(|| {
+            let vmin = visible.clone().map(|(_, v)| *v).fold(0.0, f32::min);
+            let vmax = visible.clone().map(|(_, v)| *v).fold(0.0, f32::max);
+
+            if vmax - vmin < 1e-3 {
+                (vmin - 1.0, vmax + 1.0)
+            }
+            else {
+                (vmin, vmax*1.1)
+            }
+        });
+
+        let to_screen = |r: f32, v: f32| CostPlot::to_screen(
+            rect, vmin, vmax, r, v);
+
+        let axis = Stroke::new(1.0, Color32::GRAY);
+        let grid = Stroke::new(0.5, Color32::DARK_GRAY);
+        let font = FontId::proportional(10.0);
+
+        painter.rect_stroke(rect, 0.0, axis, egui::StrokeKind::Inside);
+
+        // Vertical grid lines every 15 degrees
+        for i in 0..=6 {
+            let r = MIN_SLOPE + (i as f32)*15.0;
+            let x = to_screen(r, vmin).x;
+            painter.line_segment([Pos2::new(x, rect.top()),
+                                  Pos2::new(x, rect.bottom())], grid);
+            painter.text(Pos2::new(x, rect.bottom() + 2.0), Align2::CENTER_TOP,
+                         format!("{}°", r), font.clone(), Color32::GRAY);
+        }
+
+        // Zero line and value range
+        if vmin < 0.0 && vmax > 0.0 {
+            let y = to_screen(MIN_SLOPE, 0.0).y;
+            painter.line_segment([Pos2::new(rect.left(), y),
+                                  Pos2::new(rect.right(), y)], axis);
+        }
+
+        for v in [vmin, vmax] {
+            painter.text(Pos2::new(rect.left() - 4.0, to_screen(0.0, v).y),
+                         Align2::RIGHT_CENTER, format!("{:.1}", v),
+                         font.clone(), Color32::GRAY);
+        }
+
+        let points: Vec<Pos2> = visible
+            .map(|(r, v)| to_screen(*r, *v))
+            .collect();
+
+        painter.add(egui::Shape::line(points, Stroke::new(2.0, color)));
+
+        (response, rect, vmin, vmax)
+    }
+}
@@ -0,0 +1,144 @@
+use hoydedata::Coord;
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+
+// Reference system of the map coordinates. The hoydedata maps of Norway
+// use ETRS89 / UTM zone 33N.
+pub const DEFAULT_CRS: &str = "EPSG:25833";
+
+// GRS80 ellipsoid. WGS84 differs by less than a millimeter.
+const A: f64 = 6378137.0;
+const F: f64 = 1.0/298.257222101;
+// UTM scale factor and false easting
+const K0: f64 = 0.9996;
+const FALSE_EASTING: f64 = 500000.0;
+
+lazy_static! {
+    static ref ZONE: RwLock<u32> = RwLock::new(33);
+}
+
+// Parse a CRS name into a UTM zone. Accepts EPSG:258zz (ETRS89 / UTM),
+// EPSG:326zz (WGS84 / UTM) and UTMzz, all northern hemisphere.
+pub fn parse_crs(name: &str) -> Result<u32, String> {
+    let err = || format!("Expected EPSG:258<zone>, EPSG:326<zone> or \
+                          UTM<zone>, got '{}'", name);
+    let upper = name.to_uppercase();
+
+    let zone_str = if let Some(code) = upper.strip_prefix("EPSG:") {
+        code.strip_prefix("258")
+            .or_else(|| code.strip_prefix("326"))
+            .ok_or_else(err)?
+    }
+    else {
+        upper.strip_prefix("UTM").ok_or_else(err)?
+    };
+
+    let zone: u32 = zone_str.parse().map_err(|_| err())?;
+
+    if zone < 1 || zone > 60 {
+        return Err(err());
+    }
+
+    Ok(zone)
+}
+
+// Set the reference system of the map coordinates
+pub fn set_crs(name: &str) -> Result<(), String> {
+    *ZONE.write() = parse_crs(name)?;
+
+    Ok(())
+}
+
+pub fn crs_name() -> String {
+    format!("EPSG:258{:02}", *ZONE.read())
+}
+
+fn central_meridian(zone: u32) -> f64 {
+    ((zone as f64)*6.0 - 183.0).to_radians()
+}
+
+// Latitude and longitude (degrees) of map coordinates. Inverse transverse
+// Mercator projection, using the series expansion from Snyder (1987).
+pub fn to_latlon(c: &Coord) -> (f64, f64) {
+    let e2 = F*(2.0 - F);
+    let ep2 = e2/(1.0 - e2);
+    let x = c.e as f64 - FALSE_EASTING;
+    let y = c.n as f64;
+
+    let m = y/K0;
+    let mu = m/(A*(1.0 - e2/4.0 - 3.0*e2*e2/64.0 - 5.0*e2*e2*e2/256.0));
+    let e1 = (1.0 - (1.0 - e2).sqrt())/(1.0 + (1.0 - e2).sqrt());
+
+    let phi1 = mu
+        + (3.0*e1/2.0 - 27.0*e1.powi(3)/32.0)*(2.0*mu).sin()
+        + (21.0*e1*e1/16.0 - 55.0*e1.powi(4)/32.0)*(4.0*mu).sin()
+        + (151.0*e1.powi(3)/96.0)*(6.0*mu).sin()
+        + (1097.0*e1.powi(4)/512.0)*(8.0*mu).sin();
+
+    let (sin1, cos1, tan1) = (phi1.sin(), phi1.cos(), phi1.tan());
+    let n1 = A/(1.0 - e2*sin1*sin1).sqrt();
+    let t1 = tan1*tan1;
+    let c1 = ep2*cos1*cos1;
+    let r1 = A*(1.0 - e2)/(1.0 - e2*sin1*sin1).powf(1.5);
+    let d = x/(n1*K0);
+
+    let lat = phi1 - (n1*tan1/r1)*(
+        d*d/2.0
+            - (5.0 + 3.0*t1 + 10.0*c1 - 4.0*c1*c1 - 9.0*ep2)*d.powi(4)/24.0
+            + (61.0 + 90.0*t1 + 298.0*c1 + 45.0*t1*t1 - 252.0*ep2
+               - 3.0*c1*c1)*d.powi(6)/720.0);
+    let lon = central_meridian(*ZONE.read()) + (
+        d - (1.0 + 2.0*t1 + c1)*d.powi(3)/6.0
+            + (5.0 - 2.0*c1 + 28.0*t1 - 3.0*c1*c1 + 8.0*ep2 + 24.0*t1*t1)
+            *d.powi(5)/120.0)/cos1;
+
+    (lat.to_degrees(), lon.to_degrees())
+}
+
+// Map coordinates of a latitude and longitude (degrees). Transverse
+// Mercator projection, using the series expansion from Snyder (1987).
+pub fn from_latlon(lat: f64, lon: f64) -> Coord {
+    let e2 = F*(2.0 - F);
+    let ep2 = e2/(1.0 - e2);
+    let phi = lat.to_radians();
+    let (sin, cos, tan) = (phi.sin(), phi.cos(), phi.tan());
+
+    let n = A/(1.0 - e2*sin*sin).sqrt();
+    let t = tan*tan;
+    let c = ep2*cos*cos;
+    let a = cos*(lon.to_radians() - central_meridian(*ZONE.read()));
+
+    let m = A*((1.0 - e2/4.0 - 3.0*e2*e2/64.0 - 5.0*e2.powi(3)/256.0)*phi
+               - (3.0*e2/8.0 + 3.0*e2*e2/32.0 + 45.0*e2.powi(3)/1024.0)
+               *(2.0*phi).sin()
+               + (15.0*e2*e2/256.0 + 45.0*e2.powi(3)/1024.0)*(4.0*phi).sin()
+               - (35.0*e2.powi(3)/3072.0)*(6.0*phi).sin());
+
+    let x = K0*n*(a + (1.0 - t + c)*a.powi(3)/6.0
+                  + (5.0 - 18.0*t + t*t + 72.0*c - 58.0*ep2)*a.powi(5)/120.0)
+        + FALSE_EASTING;
+    let y = K0*(m + n*tan*(a*a/2.0
+                           + (5.0 - t + 9.0*c + 4.0*c*c)*a.powi(4)/24.0
+                           + (61.0 - 58.0*t + t*t + 600.0*c - 330.0*ep2)
+                           *a.powi(6)/720.0));
+
+    Coord::new(x as f32, y as f32)
+}
+
+// Angle (degrees, clockwise) from grid north to true north at c. True north
+// is found by stepping north along the meridian.
+pub fn grid_convergence(c: &Coord) -> f32 {
+    let (lat, lon) = to_latlon(c);
+    let north = from_latlon(lat + 0.01, lon);
+
+    (north.e - c.e).atan2(north.n - c.n).to_degrees()
+}
+
+// Bearing (degrees clockwise, 0..360) from a to b relative to true north,
+// or to magnetic north with the declination (degrees, east positive)
+pub fn bearing(a: &Coord, b: &Coord, declination: f32) -> f32 {
+    let d = *b - *a;
+    let grid = d.e.atan2(d.n).to_degrees();
+
+    (grid - grid_convergence(a) - declination).rem_euclid(360.0)
+}
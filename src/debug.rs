@@ -0,0 +1,230 @@
+use crate::channel::{CanvasMsg, CanvasSender};
+use crate::crs::to_latlon;
+use crate::graph::Graph;
+use crate::progress::Progress;
+
+use hoydedata::Coord;
+use serde_json::{json, Value};
+use std::fmt::Write;
+use std::fs;
+use std::sync::Arc;
+
+// Maximal number of graph nodes sent to the map window. Larger graphs are
+// thinned out.
+const MAX_DEBUG_NODES: usize = 100000;
+// Graphs larger than this are not written as DOT, since graphviz can't lay
+// them out anyway.
+const MAX_DOT_NODES: usize = 5000;
+
+// An area searched for a path. Pass 1 searches an ellipse around the start
+// and end points of a leg, the later passes search corridors around the path
+// of the previous pass.
+pub struct SearchArea {
+    pub leg: usize,
+    pub pass: usize,
+    pub grid_size: f32,
+    pub polygon: Vec<Coord>,
+}
+
+// Information collected while computing a path, for understanding why the
+// computation came out the way it did.
+pub struct DebugLog {
+    pub search_areas: Vec<SearchArea>,
+    // Show the nodes of each graph, and the nodes explored by the search, in
+    // the map window as the computation goes.
+    pub show_graph: bool,
+    pub show_explored: bool,
+    // Color the area of the first pass by the time to reach it from the
+    // start
+    pub show_heatmap: bool,
+    pub opt_tx: Option<CanvasSender>,
+    // Keep a copy of the last graph searched, for store graph-debug. Off
+    // by default, since the copy doubles the memory of the graph.
+    pub keep_graph: bool,
+    // Nodes and edges (from, to, cost) of the last graph searched
+    pub last_nodes: Vec<Coord>,
+    pub last_edges: Vec<(usize, usize, f32)>,
+    // Progress reported to, and cancelling by, the command line interface
+    pub progress: Arc<Progress>,
+}
+
+impl DebugLog {
+    pub fn new() -> Self {
+        Self {
+            search_areas: vec![],
+            show_graph: false,
+            show_explored: false,
+            show_heatmap: false,
+            opt_tx: None,
+            keep_graph: false,
+            last_nodes: vec![],
+            last_edges: vec![],
+            progress: Arc::new(Progress::new()),
+        }
+    }
+
+    // Prepare a graph for recording the information to be shown
+    pub fn prepare_graph(&self, g: &mut Graph) {
+        if self.show_explored && self.opt_tx.is_some() {
+            g.record_explored();
+        }
+    }
+
+    fn thin_out(points: &[Coord]) -> Vec<Coord> {
+        let step = points.len()/MAX_DEBUG_NODES + 1;
+
+        points.iter().step_by(step).cloned().collect()
+    }
+
+    // Keep the searched graph for dumping if asked to, and send its nodes
+    // to the map window.
+    pub fn graph_searched(&mut self, g: &Graph) {
+        if self.keep_graph {
+            self.last_nodes = g.nodes().clone();
+            self.last_edges = g.edges().clone();
+        }
+
+        let Some(tx) = &self.opt_tx else { return; };
+
+        if !self.show_graph && !self.show_explored {
+            return;
+        }
+
+        let nodes = if self.show_graph {
+            DebugLog::thin_out(g.nodes())
+        }
+        else {
+            vec![]
+        };
+
+        let _ = tx.send(CanvasMsg::SetDebugGraph(
+            nodes, DebugLog::thin_out(g.explored())));
+    }
+
+    // Send the time to reach each node of a first pass graph to the map
+    // window, to be shown as a heatmap
+    pub fn heatmap(&self, g: &Graph) {
+        let Some(tx) = &self.opt_tx else { return; };
+
+        if !self.show_heatmap {
+            return;
+        }
+
+        let cells: Vec<(Coord, f32)> = g.nodes().iter()
+            .zip(g.reach_times())
+            .filter(|(_, t)| t.is_finite())
+            .map(|(c, t)| (*c, *t))
+            .collect();
+        let step = cells.len()/MAX_DEBUG_NODES + 1;
+
+        let _ = tx.send(CanvasMsg::SetHeatmap(
+            cells.into_iter().step_by(step).collect()));
+    }
+
+    // Show the nodes reached from the start in the map window, when no path
+    // was found
+    pub fn no_path(&self, g: &Graph) {
+        let Some(tx) = &self.opt_tx else { return; };
+
+        let _ = tx.send(CanvasMsg::SetDebugGraph(
+            vec![], DebugLog::thin_out(&g.reached_nodes())));
+    }
+
+    // Write the last graph searched as <prefix>nodes.csv (id, e, n) and
+    // <prefix>edges.csv (from, to, cost). Small graphs are also written as
+    // <prefix>graph.dot, with the nodes at their map positions. Returns the
+    // names of the files written.
+    pub fn write_graph(&self, prefix: &str) -> Result<Vec<String>, String> {
+        let write = |fname: &str, content: &str| {
+            fs::write(fname, content)
+                .map_err(|e| format!("Unable to write {}: {}", fname, e))
+        };
+
+        let mut files = vec![];
+
+        let mut nodes = "id,e,n\n".to_string();
+        for (i, c) in self.last_nodes.iter().enumerate() {
+            let _ = writeln!(nodes, "{},{:.1},{:.1}", i, c.e, c.n);
+        }
+        let fname = format!("{}nodes.csv", prefix);
+        write(&fname, &nodes)?;
+        files.push(fname);
+
+        let mut edges = "from,to,cost\n".to_string();
+        for (n1, n2, t) in &self.last_edges {
+            let _ = writeln!(edges, "{},{},{:.3}", n1, n2, t);
+        }
+        let fname = format!("{}edges.csv", prefix);
+        write(&fname, &edges)?;
+        files.push(fname);
+
+        if self.last_nodes.len() <= MAX_DOT_NODES {
+            // Positions relative to the first node, in units of 10 m
+            let o = self.last_nodes.first().cloned()
+                .unwrap_or(Coord::new(0.0, 0.0));
+            let mut dot = "digraph stivalg {\n  node [shape=point];\n"
+                .to_string();
+
+            for (i, c) in self.last_nodes.iter().enumerate() {
+                let _ = writeln!(dot, "  {} [pos=\"{:.1},{:.1}!\"];", i,
+                                 (c.e - o.e)/10.0, (c.n - o.n)/10.0);
+            }
+
+            for (n1, n2, t) in &self.last_edges {
+                let _ = writeln!(dot, "  {} -> {} [label=\"{:.1}\"];",
+                                 n1, n2, t);
+            }
+
+            dot.push_str("}\n");
+
+            let fname = format!("{}graph.dot", prefix);
+            write(&fname, &dot)?;
+            files.push(fname);
+        }
+
+        Ok(files)
+    }
+
+    fn geojson_ring(points: &[Coord]) -> Value {
+        let mut ring: Vec<Value> = points.iter()
+            .map(|c| {
+                let (lat, lon) = to_latlon(&c);
+                json!([lon, lat])
+            })
+            .collect();
+
+        // GeoJSON rings must be closed
+        if let Some(first) = ring.first().cloned() {
+            ring.push(first);
+        }
+
+        Value::Array(ring)
+    }
+
+    // Write the search areas as a GeoJSON feature collection of polygons
+    pub fn write_search_areas(&self, fname: &str) -> Result<(), String> {
+        let features: Vec<Value> = self.search_areas.iter()
+            .map(|a| json!({
+                "type": "Feature",
+                "properties": {
+                    "leg": a.leg + 1,
+                    "pass": a.pass + 1,
+                    "kind": if a.pass == 0 { "ellipse" } else { "corridor" },
+                    "grid_size": a.grid_size,
+                },
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [DebugLog::geojson_ring(&a.polygon)],
+                },
+            }))
+            .collect();
+
+        let collection = json!({
+            "type": "FeatureCollection",
+            "features": features,
+        });
+
+        fs::write(fname, collection.to_string())
+            .map_err(|e| format!("Unable to write {}: {}", fname, e))
+    }
+}
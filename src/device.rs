@@ -0,0 +1,57 @@
+use std::path::{Path as FsPath, PathBuf};
+
+// Directories on Garmin devices mounted as USB mass storage. FIT courses
+// are put in NewFiles, from where the device imports them. Older devices
+// without NewFiles read GPX files from the GPX directory.
+const GARMIN_DIRS: [&str; 2] = ["GARMIN", "Garmin"];
+const DEVICE_XML: &str = "GarminDevice.xml";
+const NEW_FILES: &str = "NewFiles";
+const GPX_DIR: &str = "GPX";
+
+// Garmin directory of a device mounted at mountpoint, if it is one
+fn garmin_dir(mountpoint: &str) -> Option<PathBuf> {
+    GARMIN_DIRS.iter()
+        .map(|d| FsPath::new(mountpoint).join(d))
+        .find(|d| d.join(DEVICE_XML).exists() || d.join(NEW_FILES).is_dir())
+}
+
+// File name made of the letters and digits of a track name
+pub fn file_stem(name: &str) -> String {
+    let stem: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+
+    if stem.is_empty() { "stivalg".to_string() } else { stem }
+}
+
+// Where to write a course on the device, and its format (fit or gpx). Files
+// already on the device aren't overwritten. A number is added to the name
+// instead.
+pub fn device_file(mountpoint: &str, name: &str)
+                   -> Result<(String, &'static str), String> {
+    let Some(garmin) = garmin_dir(mountpoint) else {
+        return Err(format!("No Garmin device found at {}", mountpoint));
+    };
+
+    let (dir, format) = if garmin.join(NEW_FILES).is_dir() {
+        (garmin.join(NEW_FILES), "fit")
+    }
+    else if garmin.join(GPX_DIR).is_dir() {
+        (garmin.join(GPX_DIR), "gpx")
+    }
+    else {
+        return Err(format!("{} has neither {} nor {}", garmin.display(),
+                           NEW_FILES, GPX_DIR));
+    };
+
+    let stem = file_stem(name);
+    let mut fname = dir.join(format!("{}.{}", stem, format));
+    let mut n = 1;
+
+    while fname.exists() {
+        fname = dir.join(format!("{}_{}.{}", stem, n, format));
+        n += 1;
+    }
+
+    Ok((fname.to_string_lossy().to_string(), format))
+}
@@ -0,0 +1,164 @@
+use crate::crs::crs_name;
+use crate::elevation::ElevationSource;
+use crate::geotiff::GeoTiffSource;
+
+use hoydedata::Coord;
+use parking_lot::{Condvar, Mutex, RwLock};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path as FsPath;
+use std::sync::Arc;
+use std::thread;
+
+// Size of the downloaded tiles (meters) and their resolution (pixels)
+const TILE_SIZE: f32 = 2000.0;
+const TILE_PIXELS: usize = 2000;
+
+// State of a tile of the download grid
+#[derive(Clone, Copy, PartialEq)]
+enum TileState {
+    Loading,
+    Loaded,
+    Failed,
+}
+
+// Elevation data downloaded on demand from a WCS or height API endpoint.
+// The url is a template where {west}, {south}, {east} and {north} are
+// replaced by the bounding box of a tile, {crs} by the map CRS and {size}
+// by the width and height in pixels. The endpoint must return GeoTIFF.
+// Tiles are cached as GeoTIFF files, so each tile is only downloaded once.
+pub struct DownloadSource {
+    tiles: Arc<Tiles>,
+}
+
+struct Tiles {
+    url: String,
+    cache_dir: String,
+    source: RwLock<GeoTiffSource>,
+    // Tiles looked for, and those being downloaded. No lock is held while
+    // downloading. Lookups in a tile being downloaded wait for it.
+    state: Mutex<HashMap<(i64, i64), TileState>>,
+    loaded: Condvar,
+}
+
+impl DownloadSource {
+    pub fn new(url: &str, cache_dir: &str) -> Result<Self, String> {
+        fs::create_dir_all(cache_dir)
+            .map_err(|e| format!("Unable to create {}: {}", cache_dir, e))?;
+
+        Ok(Self {
+            tiles: Arc::new(Tiles {
+                url: url.to_string(),
+                cache_dir: cache_dir.to_string(),
+                source: RwLock::new(GeoTiffSource::new()),
+                state: Mutex::new(HashMap::new()),
+                loaded: Condvar::new(),
+            }),
+        })
+    }
+}
+
+impl Tiles {
+    fn key(c: &Coord) -> (i64, i64) {
+        ((c.e/TILE_SIZE).floor() as i64, (c.n/TILE_SIZE).floor() as i64)
+    }
+
+    fn tile_url(&self, ix: i64, iy: i64) -> String {
+        let (w, s) = ((ix as f32)*TILE_SIZE, (iy as f32)*TILE_SIZE);
+
+        self.url
+            .replace("{west}", &w.to_string())
+            .replace("{south}", &s.to_string())
+            .replace("{east}", &(w + TILE_SIZE).to_string())
+            .replace("{north}", &(s + TILE_SIZE).to_string())
+            .replace("{crs}", &crs_name())
+            .replace("{size}", &TILE_PIXELS.to_string())
+    }
+
+    fn download(&self, url: &str, fname: &str) -> Result<(), String> {
+        println!("Downloading elevation tile {}", fname);
+
+        let response = ureq::get(url).call()
+            .map_err(|e| format!("Download of {} failed: {}", url, e))?;
+        let mut data = vec![];
+        response.into_reader().read_to_end(&mut data)
+            .map_err(|e| format!("Download of {} failed: {}", url, e))?;
+
+        // Errors are typically returned as XML
+        if !data.starts_with(b"II*\0") && !data.starts_with(b"MM\0*") {
+            return Err(format!("{} did not return a GeoTIFF", url));
+        }
+
+        fs::write(fname, &data)
+            .map_err(|e| format!("Unable to write {}: {}", fname, e))
+    }
+
+    // Make sure the tile covering c is loaded, downloading it if it isn't
+    // cached. A position on the seam between tiles belongs to the tile to
+    // the north east, which covers its edge.
+    fn ensure_tile(&self, key: (i64, i64)) {
+        let mut state = self.state.lock();
+
+        loop {
+            match state.get(&key) {
+                Some(TileState::Loading) => self.loaded.wait(&mut state),
+                Some(_) => return,
+                None => break,
+            }
+        }
+
+        state.insert(key, TileState::Loading);
+        drop(state);
+
+        let fname = format!("{}/{}_{}.tif", self.cache_dir, key.0, key.1);
+
+        let result = if FsPath::new(&fname).exists() {
+            Ok(())
+        }
+        else {
+            self.download(&self.tile_url(key.0, key.1), &fname)
+        }
+        .and_then(|_| self.source.write().add_tile(&fname));
+
+        if let Err(e) = &result {
+            println!("{}", e);
+        }
+
+        self.state.lock().insert(key, if result.is_ok() {
+            TileState::Loaded
+        }
+        else {
+            TileState::Failed
+        });
+        self.loaded.notify_all();
+    }
+}
+
+impl ElevationSource for DownloadSource {
+    fn lookup(&self, c: &Coord) -> Option<f32> {
+        self.tiles.ensure_tile(Tiles::key(c));
+        self.tiles.source.read().lookup(c)
+    }
+
+    fn lookup_with_gradient(&self, c: &Coord) -> Option<(f32, f32, f32)> {
+        self.tiles.ensure_tile(Tiles::key(c));
+        self.tiles.source.read().lookup_with_gradient(c)
+    }
+
+    // Download the tiles of the area in the background, so that the app
+    // doesn't wait for the network
+    fn prefetch(&self, sw: &Coord, ne: &Coord) {
+        let (x0, y0) = Tiles::key(sw);
+        let (x1, y1) = Tiles::key(ne);
+        let tiles = self.tiles.clone();
+
+        thread::spawn(move || {
+            for x in x0..=x1 {
+                for y in y0..=y1 {
+                    tiles.ensure_tile((x, y));
+                }
+            }
+        });
+    }
+}
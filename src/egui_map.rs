@@ -1,17 +1,22 @@
 use eframe::AppCreator;
-use egui::{Event, Image, ImageSource, Sense, TextureId, Vec2};
+use egui::{Event, Image, ImageSource, Sense, TextureId, TouchPhase, Vec2};
 use egui::load::SizedTexture;
 use egui_wgpu::RenderState;
 use egui_wgpu::wgpu::{FilterMode, TextureView};
 use galileo::{Map, Messenger};
 use galileo::control::{EventProcessor, MouseButton, RawUserEvent,
-                       UserEventHandler};
+                       TouchEvent, UserEventHandler};
 use galileo::galileo_types::cartesian::{Point2, Size};
 use galileo::layer::attribution::Attribution;
 use galileo::render::WgpuRenderer;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 
+// Scroll amount corresponding to doubling the zoom by a pinch gesture on a
+// touchpad
+const PINCH_SCROLL: f64 = 4.0;
+
 pub fn init_with_app(app_creator: AppCreator<'_>) -> eframe::Result {
     use std::time::Duration;
 
@@ -46,6 +51,9 @@ pub struct EguiMapState {
     texture_id: TextureId,
     texture_view: TextureView,
     event_processor: EventProcessor,
+    show_attributions: bool,
+    // Screen positions of the fingers on a touch screen
+    touches: HashMap<u64, Point2>,
 }
 
 impl<'a> EguiMapState {
@@ -92,9 +100,16 @@ impl<'a> EguiMapState {
             texture_id: texture_id,
             texture_view: texture,
             event_processor: EventProcessor::default(),
+            show_attributions: true,
+            touches: HashMap::new(),
         }
     }
 
+    // Leave the attributions to another map shown in the same window
+    pub fn hide_attributions(&mut self) {
+        self.show_attributions = false;
+    }
+
     pub fn add_handler(&mut self, handler: impl UserEventHandler + 'static) {
         self.event_processor.add_handler(handler);
     }
@@ -106,7 +121,7 @@ impl<'a> EguiMapState {
         let (rect, response) = ui.allocate_exact_size(available_size, Sense::click_and_drag());
 
         let attributions = self.collect_attributions();
-        if attributions.is_some() {
+        if attributions.is_some() && self.show_attributions {
             egui::Window::new("Attributions")
                 .collapsible(false)
                 .title_bar(false)
@@ -219,12 +234,77 @@ impl<'a> EguiMapState {
 
     fn process_events(&mut self, events: &[Event], offset: [f32; 2]) {
         for event in events {
-            if let Some(raw_event) = Self::convert_event(event, offset) {
+            if let Event::Touch { id, phase, pos, .. } = event {
+                let position = Point2::new((pos.x + offset[0]) as f64,
+                                           (pos.y + offset[1]) as f64);
+                self.process_touch(id.0, *phase, position);
+            }
+            else if self.touches.len() >= 2
+                && matches!(event, Event::PointerButton { .. }
+                            | Event::PointerMoved(_)) {
+                // egui also reports the first finger as a pointer. It is
+                // ignored while the fingers are handled as touches.
+            }
+            else if let Some(raw_event) = Self::convert_event(event, offset) {
                 self.event_processor.handle(raw_event, &mut self.map);
             }
         }
     }
 
+    // A single finger is handled as a mouse pointer, by the pointer events
+    // egui makes from it. Two or more fingers are passed on as touches, for
+    // pinch zoom and two-finger pan.
+    fn process_touch(&mut self, id: u64, phase: TouchPhase,
+                     position: Point2) {
+        let was_multi = self.touches.len() >= 2;
+
+        match phase {
+            TouchPhase::Start | TouchPhase::Move => {
+                self.touches.insert(id, position);
+            },
+            TouchPhase::End | TouchPhase::Cancel => {
+                self.touches.remove(&id);
+            },
+        }
+
+        let is_multi = self.touches.len() >= 2;
+        let touch = |id: u64, position: Point2| TouchEvent {
+            touch_id: id,
+            screen_pointer_position: position,
+        };
+
+        let mut raw_events = vec![];
+
+        if is_multi && !was_multi {
+            // End the drag of the pointer and start all touches
+            raw_events.push(RawUserEvent::ButtonReleased(MouseButton::Left));
+            for (id, p) in &self.touches {
+                raw_events.push(RawUserEvent::TouchStart(touch(*id, *p)));
+            }
+        }
+        else if is_multi {
+            raw_events.push(match phase {
+                TouchPhase::Start => RawUserEvent::TouchStart(
+                    touch(id, position)),
+                TouchPhase::Move => RawUserEvent::TouchMove(
+                    touch(id, position)),
+                _ => RawUserEvent::TouchEnd(touch(id, position)),
+            });
+        }
+        else if was_multi {
+            // Back to one finger or none. End the remaining touches too.
+            raw_events.push(RawUserEvent::TouchEnd(touch(id, position)));
+            for (id, p) in &self.touches {
+                raw_events.push(RawUserEvent::TouchEnd(touch(*id, *p)));
+            }
+            self.touches.clear();
+        }
+
+        for raw_event in raw_events {
+            self.event_processor.handle(raw_event, &mut self.map);
+        }
+    }
+
     fn convert_event(event: &Event, offset: [f32; 2]) -> Option<RawUserEvent> {
         match event {
             Event::PointerButton {
@@ -258,6 +338,15 @@ impl<'a> EguiMapState {
 
                 Some(RawUserEvent::Scroll(zoom))
             }
+            Event::Zoom(factor) => {
+                // Pinch on a touchpad
+                let zoom = (*factor as f64).log2()*PINCH_SCROLL;
+                if zoom.abs() < 0.0001 {
+                    return None;
+                }
+
+                Some(RawUserEvent::Scroll(zoom))
+            }
 
             _ => None,
         }
@@ -0,0 +1,98 @@
+use hoydedata::{Atlas, Coord};
+
+// Number of lookups along each side of an area being prefetched
+const PREFETCH_GRID: usize = 8;
+// Spacing (meters) of the samples around a point used for estimating the
+// roughness of the terrain
+const ROUGHNESS_STEP: f32 = 2.0;
+
+// Source of elevation data for the path computations. Implemented by the
+// hoydedata atlas and by the synthetic terrains used for testing. The
+// algorithms only use this trait, so other DEM backends can be plugged in.
+pub trait ElevationSource: Send + Sync {
+    // Elevation (meters) at a coordinate
+    fn lookup(&self, c: &Coord) -> Option<f32>;
+    // Elevation and gradient (east, north) at a coordinate
+    fn lookup_with_gradient(&self, c: &Coord) -> Option<(f32, f32, f32)>;
+    // South west and north east corners of the covered area. None if the
+    // extent is unknown or unlimited.
+    fn bounds(&self) -> Option<(Coord, Coord)> {
+        None
+    }
+
+    // Whether a coordinate is within the bounds
+    fn covers(&self, c: &Coord) -> bool {
+        match self.bounds() {
+            Some((sw, ne)) => c.e >= sw.e && c.e <= ne.e
+                && c.n >= sw.n && c.n <= ne.n,
+            None => true,
+        }
+    }
+
+    // Prepare for lookups within an area, e.g. the area shown in the map
+    // window. By default, a grid of lookups makes the source mount or fetch
+    // the data of the area.
+    fn prefetch(&self, sw: &Coord, ne: &Coord) {
+        for i in 0..=PREFETCH_GRID {
+            for j in 0..=PREFETCH_GRID {
+                let (fi, fj) = (i as f32/PREFETCH_GRID as f32,
+                                j as f32/PREFETCH_GRID as f32);
+                let _ = self.lookup(&Coord::new(sw.e + (ne.e - sw.e)*fi,
+                                                sw.n + (ne.n - sw.n)*fj));
+            }
+        }
+    }
+
+    // Roughness (meters) of the terrain at a coordinate. This is the root
+    // mean square deviation of the elevations around it from the plane
+    // given by its elevation and gradient, so that even slopes are smooth
+    // while boulder fields and broken ground are rough.
+    fn roughness(&self, c: &Coord) -> Option<f32> {
+        let (h, dx, dy) = self.lookup_with_gradient(c)?;
+        let mut sum_sq = 0.0;
+        let mut n = 0;
+
+        for i in -1..=1 {
+            for j in -1..=1 {
+                if i == 0 && j == 0 {
+                    continue;
+                }
+
+                let (oe, on) = (i as f32*ROUGHNESS_STEP,
+                                j as f32*ROUGHNESS_STEP);
+                let Some(hk) = self.lookup(&Coord::new(c.e + oe, c.n + on))
+                else {
+                    continue;
+                };
+                let r = hk - (h + dx*oe + dy*on);
+                sum_sq += r*r;
+                n += 1;
+            }
+        }
+
+        (n > 0).then_some((sum_sq/n as f32).sqrt())
+    }
+}
+
+// Intersection of two bounds, where None is unlimited
+pub fn intersect_bounds(a: Option<(Coord, Coord)>, b: Option<(Coord, Coord)>)
+                        -> Option<(Coord, Coord)> {
+    match (a, b) {
+        (Some((sw1, ne1)), Some((sw2, ne2))) => Some((
+            Coord::new(sw1.e.max(sw2.e), sw1.n.max(sw2.n)),
+            Coord::new(ne1.e.min(ne2.e), ne1.n.min(ne2.n)))),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+// The atlas mounts map tiles on demand, so its extent isn't known up front
+impl ElevationSource for Atlas {
+    fn lookup(&self, c: &Coord) -> Option<f32> {
+        Atlas::lookup(self, c).ok()
+    }
+
+    fn lookup_with_gradient(&self, c: &Coord) -> Option<(f32, f32, f32)> {
+        Atlas::lookup_with_gradient(self, c).ok()
+    }
+}
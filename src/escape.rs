@@ -0,0 +1,166 @@
+use crate::barrier::Barrier;
+use crate::cost::Cost;
+use crate::elevation::{ElevationSource, intersect_bounds};
+use crate::graph::Graph;
+use crate::params::Params;
+use crate::path::{Path, Segment};
+use crate::progress::Progress;
+
+use hoydedata::Coord;
+use std::fmt;
+
+// Number of exits, nearest first, to which routes are searched from each
+// point of the track
+const EXIT_CANDIDATES: usize = 3;
+// Radius of the graph searched from a point, relative to half the distance
+// to the farthest exit searched. The graph is a circle centered halfway to
+// that exit, leaving room for detours to each of the exits.
+const ESCAPE_COVERING: f32 = 3.5;
+
+// Fastest way from a point of the track to an exit
+pub struct Escape {
+    // Distance along the track (meters)
+    pub distance: f32,
+    pub coord: Coord,
+    // Name of the exit, and the modeled time (seconds) to reach it. None if
+    // no exit can be reached.
+    pub exit: Option<(String, f32)>,
+}
+
+impl Escape {
+    // Whether reaching an exit takes more than max_time seconds
+    pub fn is_long(&self, max_time: f32) -> bool {
+        self.exit.as_ref().map(|(_, t)| *t > max_time).unwrap_or(true)
+    }
+}
+
+impl fmt::Display for Escape {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(formatter, "{:5.1} km {}", self.distance/1000.0, self.coord)?;
+
+        match &self.exit {
+            Some((name, t)) => write!(formatter, ", {} min to {}",
+                                      (t/60.0).ceil() as usize, name),
+            None => write!(formatter, ", no exit reachable"),
+        }
+    }
+}
+
+// Find the fastest route to an exit from points every interval meters
+// along the path. Routes are searched to the nearest exits only, by one
+// search from each point. Each point is a step of the progress, and the
+// search stops if it is cancelled.
+pub fn find_escapes(path: &Path, atlas: &dyn ElevationSource,
+                    params: &Params, exits: &[(Coord, String)],
+                    interval: f32, progress: &Progress) -> Vec<Escape> {
+    let cost = Cost::from_params(params);
+    let mut search = params.clone();
+    // Escaping is not bound by the constraints of the planned route
+    search.point_info = vec![];
+    search.gates = vec![];
+    search.corridor = Barrier::new();
+    // The graph is a circle around the point and the exits
+    search.covering_length = ESCAPE_COVERING;
+    search.covering_width = ESCAPE_COVERING;
+
+    let len = path.len();
+    let mut distances = vec![];
+    let mut d = 0.0;
+
+    while d < len {
+        distances.push(d);
+        d += interval;
+    }
+    distances.push(len);
+
+    let steps = distances.len();
+    let mut escapes = vec![];
+
+    for (i, d) in distances.into_iter().enumerate() {
+        if progress.is_cancelled() {
+            break;
+        }
+
+        progress.set_phase(format!("Escapes from km {:.1}", d/1000.0), i,
+                           steps);
+
+        let Some(c) = path.position_at_distance(d) else {
+            continue;
+        };
+
+        escapes.push(Escape {
+            distance: d,
+            coord: c,
+            exit: fastest_exit(c, &search, atlas, &cost, exits),
+        });
+    }
+
+    escapes
+}
+
+// The exit reached fastest from c, with the time to reach it. The nearest
+// exits are reached by one search of a graph centered between c and the
+// farthest of them.
+fn fastest_exit(c: Coord, params: &Params, atlas: &dyn ElevationSource,
+                cost: &Cost, exits: &[(Coord, String)])
+                -> Option<(String, f32)> {
+    let mut nearest: Vec<&(Coord, String)> = exits.iter().collect();
+    nearest.sort_by(|a, b| (a.0 - c).abs_sq()
+                    .total_cmp(&(b.0 - c).abs_sq()));
+    nearest.truncate(EXIT_CANDIDATES);
+
+    let (far, _) = nearest.last()?;
+    if *far == c {
+        return nearest.first().map(|(_, name)| (name.clone(), 0.0));
+    }
+
+    let mut g = Graph::new(c, *far, params);
+    g.set_bounds(intersect_bounds(atlas.bounds(), params.bounds));
+    g.build_graph_from_end_points(atlas);
+    // Exits reached later than the farthest one aren't needed, so the
+    // search may stop there
+    g.shortest_path();
+
+    let nodes = g.nodes();
+    let times = g.reach_times();
+
+    nearest.into_iter()
+        .filter_map(|(e, name)| {
+            // The exit is reached from the nearest node
+            let (n, node) = nodes.iter().enumerate()
+                .min_by(|a, b| (*a.1 - *e).abs_sq()
+                        .total_cmp(&(*b.1 - *e).abs_sq()))?;
+            let last = if node == e {
+                0.0
+            }
+            else {
+                Segment::new(*node, *e).time(atlas, cost)?
+            };
+            let t = times.get(n)? + last;
+
+            t.is_finite().then(|| (name.clone(), t))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+// Stretches of the track (start and end distance in meters) where reaching
+// an exit takes more than max_time seconds
+pub fn long_stretches(escapes: &[Escape], max_time: f32) -> Vec<(f32, f32)> {
+    let mut stretches: Vec<(f32, f32)> = vec![];
+    let mut prev_long = false;
+
+    for e in escapes {
+        let long = e.is_long(max_time);
+
+        if long {
+            match stretches.last_mut() {
+                Some(s) if prev_long => s.1 = e.distance,
+                _ => stretches.push((e.distance, e.distance)),
+            }
+        }
+
+        prev_long = long;
+    }
+
+    stretches
+}
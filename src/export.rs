@@ -0,0 +1,334 @@
+use crate::cost::Cost;
+use crate::crs::to_latlon;
+use crate::elevation::ElevationSource;
+use crate::info::{FileKind, atlas_covering, detect_file_kind};
+use crate::params::Params;
+use crate::path::Path;
+use crate::stats::PathStats;
+
+use hoydedata::Coord;
+use serde_json::json;
+use std::fmt::Write;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const EXPORT_FORMATS: [&str; 5] = ["gpx", "kml", "geojson", "csv", "fit"];
+
+// Seconds between the unix epoch and the FIT epoch (1989-12-31T00:00Z)
+const FIT_EPOCH: u64 = 631065600;
+// FIT profile version 21.32
+const FIT_PROFILE_VERSION: u16 = 2132;
+// Walking speed (m/s) used for the timestamps of FIT courses
+const FIT_SPEED: f32 = 1.1;
+
+// Export format given by the extension of a file name
+pub fn format_from_fname(fname: &str) -> Option<&'static str> {
+    let ext = fname.rsplit_once('.')?.1.to_lowercase();
+    let ext = if ext == "json" { "geojson".to_string() } else { ext };
+
+    EXPORT_FORMATS.into_iter().find(|f| *f == ext)
+}
+
+// Points of the path with their elevation, if known, and the distance
+// walked from the start
+fn track_points(path: &Path, atlas: Option<&dyn ElevationSource>)
+                -> Vec<(Coord, Option<f32>, f32)> {
+    let mut points = vec![];
+    let mut distance = 0.0;
+    let mut prev: Option<Coord> = None;
+
+    for c in path {
+        if let Some(p) = prev {
+            distance += (*c - p).abs();
+        }
+
+        let elevation = atlas.and_then(|a| a.lookup(c));
+        points.push((*c, elevation, distance));
+        prev = Some(*c);
+    }
+
+    points
+}
+
+fn write_file(fname: &str, data: &[u8]) -> Result<(), String> {
+    fs::write(fname, data)
+        .map_err(|e| format!("Unable to write {}: {}", fname, e))
+}
+
+fn write_kml(points: &[(Coord, Option<f32>, f32)], fname: &str, name: &str)
+             -> Result<(), String> {
+    let mut coords = String::new();
+
+    for (c, elevation, _) in points {
+        let (lat, lon) = to_latlon(&c);
+        let _ = write!(coords, "{:.7},{:.7},{:.1} ", lon, lat,
+                       elevation.unwrap_or(0.0));
+    }
+
+    let kml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <kml xmlns=\"http://www.opengis.net/kml/2.2\">\n\
+         <Document>\n\
+         <Placemark>\n\
+         <name>{}</name>\n\
+         <LineString>\n\
+         <altitudeMode>clampToGround</altitudeMode>\n\
+         <coordinates>{}</coordinates>\n\
+         </LineString>\n\
+         </Placemark>\n\
+         </Document>\n\
+         </kml>\n", name, coords.trim_end());
+
+    write_file(fname, kml.as_bytes())
+}
+
+fn write_geojson(points: &[(Coord, Option<f32>, f32)], fname: &str,
+                 name: &str, stats: Option<&PathStats>)
+                 -> Result<(), String> {
+    let coords: Vec<serde_json::Value> = points.iter()
+        .map(|(c, elevation, _)| {
+            let (lat, lon) = to_latlon(&c);
+            match elevation {
+                Some(h) => json!([lon, lat, h]),
+                None => json!([lon, lat]),
+            }
+        })
+        .collect();
+
+    let length = points.last().map(|p| p.2).unwrap_or(0.0);
+    let mut feature = json!({
+        "type": "Feature",
+        "properties": {
+            "name": name,
+            "length": length,
+        },
+        "geometry": {
+            "type": "LineString",
+            "coordinates": coords,
+        },
+    });
+
+    if let Some(stats) = stats {
+        feature["properties"]["stats"] = json!(stats);
+    }
+
+    write_file(fname, feature.to_string().as_bytes())
+}
+
+// Write the points as CSV. The slope histogram, if given, is written to
+// <name>_slopes.csv beside it.
+fn write_csv(points: &[(Coord, Option<f32>, f32)], fname: &str,
+             stats: Option<&PathStats>) -> Result<(), String> {
+    let mut csv = "index,e,n,lat,lon,distance,elevation\n".to_string();
+
+    for (i, (c, elevation, distance)) in points.iter().enumerate() {
+        let (lat, lon) = to_latlon(&c);
+        let _ = writeln!(csv, "{},{:.1},{:.1},{:.7},{:.7},{:.1},{}", i, c.e,
+                         c.n, lat, lon, distance,
+                         elevation.map(|h| format!("{:.1}", h))
+                         .unwrap_or_default());
+    }
+
+    write_file(fname, csv.as_bytes())?;
+
+    if let Some(stats) = stats {
+        let stem = fname.strip_suffix(".csv").unwrap_or(fname);
+        write_file(&format!("{}_slopes.csv", stem),
+                   stats.slopes.to_csv().as_bytes())?;
+    }
+
+    Ok(())
+}
+
+// FIT checksum (CRC-16 as specified by the FIT protocol)
+fn fit_crc(data: &[u8]) -> u16 {
+    const TABLE: [u16; 16] = [
+        0x0000, 0xcc01, 0xd801, 0x1400, 0xf001, 0x3c00, 0x2800, 0xe401,
+        0xa001, 0x6c00, 0x7800, 0xb401, 0x5000, 0x9c01, 0x8801, 0x4400,
+    ];
+    let mut crc: u16 = 0;
+
+    for b in data {
+        let tmp = TABLE[(crc & 0xf) as usize];
+        crc = ((crc >> 4) & 0x0fff) ^ tmp ^ TABLE[(b & 0xf) as usize];
+        let tmp = TABLE[(crc & 0xf) as usize];
+        crc = ((crc >> 4) & 0x0fff) ^ tmp ^ TABLE[((b >> 4) & 0xf) as usize];
+    }
+
+    crc
+}
+
+// Degrees to FIT semicircles
+fn semicircles(deg: f64) -> i32 {
+    (deg*(2147483648.0/180.0)) as i32
+}
+
+// Definition message for a local message type. Fields are given as (field
+// number, size, base type).
+fn fit_definition(out: &mut Vec<u8>, local: u8, global: u16,
+                  fields: &[(u8, u8, u8)]) {
+    out.push(0x40 | local);
+    // Reserved, little endian
+    out.extend([0, 0]);
+    out.extend(global.to_le_bytes());
+    out.push(fields.len() as u8);
+
+    for (num, size, base_type) in fields {
+        out.extend([*num, *size, *base_type]);
+    }
+}
+
+// Write the path as a FIT course, the format used by Garmin devices
+fn write_fit(points: &[(Coord, Option<f32>, f32)], fname: &str, name: &str)
+             -> Result<(), String> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let created = now.saturating_sub(FIT_EPOCH) as u32;
+    let time_at = |d: f32| created + (d/FIT_SPEED) as u32;
+
+    let (first, last) = (points[0], points[points.len() - 1]);
+    let (lat0, lon0) = to_latlon(&first.0);
+    let (lat1, lon1) = to_latlon(&last.0);
+
+    let mut data: Vec<u8> = vec![];
+
+    // file_id: type course, manufacturer development, time created
+    fit_definition(&mut data, 0, 0, &[(0, 1, 0x00), (1, 2, 0x84),
+                                      (4, 4, 0x86)]);
+    data.push(0);
+    data.push(6);
+    data.extend(255u16.to_le_bytes());
+    data.extend(created.to_le_bytes());
+
+    // course: name
+    let mut course_name = [0u8; 16];
+    for (i, b) in name.bytes().take(15).enumerate() {
+        course_name[i] = b;
+    }
+    fit_definition(&mut data, 1, 31, &[(5, 16, 0x07)]);
+    data.push(1);
+    data.extend(course_name);
+
+    // lap: timestamp, start time, start and end position, total distance
+    fit_definition(&mut data, 2, 19, &[(253, 4, 0x86), (2, 4, 0x86),
+                                       (3, 4, 0x85), (4, 4, 0x85),
+                                       (5, 4, 0x85), (6, 4, 0x85),
+                                       (9, 4, 0x86)]);
+    data.push(2);
+    data.extend(time_at(last.2).to_le_bytes());
+    data.extend(created.to_le_bytes());
+    data.extend(semicircles(lat0).to_le_bytes());
+    data.extend(semicircles(lon0).to_le_bytes());
+    data.extend(semicircles(lat1).to_le_bytes());
+    data.extend(semicircles(lon1).to_le_bytes());
+    data.extend(((last.2*100.0) as u32).to_le_bytes());
+
+    // event: timestamp, event timer, event type start (0) or stop all (4)
+    fit_definition(&mut data, 3, 21, &[(253, 4, 0x86), (0, 1, 0x00),
+                                       (1, 1, 0x00)]);
+    data.push(3);
+    data.extend(created.to_le_bytes());
+    data.extend([0, 0]);
+
+    // record: timestamp, position, altitude, distance
+    fit_definition(&mut data, 4, 20, &[(253, 4, 0x86), (0, 4, 0x85),
+                                       (1, 4, 0x85), (2, 2, 0x84),
+                                       (5, 4, 0x86)]);
+
+    for (c, elevation, distance) in points {
+        let (lat, lon) = to_latlon(&c);
+        // Altitude has scale 5 and offset 500. 0xffff is invalid.
+        let altitude = elevation
+            .map(|h| ((h + 500.0)*5.0).clamp(0.0, 65534.0) as u16)
+            .unwrap_or(0xffff);
+
+        data.push(4);
+        data.extend(time_at(*distance).to_le_bytes());
+        data.extend(semicircles(lat).to_le_bytes());
+        data.extend(semicircles(lon).to_le_bytes());
+        data.extend(altitude.to_le_bytes());
+        data.extend(((distance*100.0) as u32).to_le_bytes());
+    }
+
+    data.push(3);
+    data.extend(time_at(last.2).to_le_bytes());
+    data.extend([0, 4]);
+
+    // File header, data and checksum
+    let mut fit: Vec<u8> = vec![14, 0x20];
+    fit.extend(FIT_PROFILE_VERSION.to_le_bytes());
+    fit.extend((data.len() as u32).to_le_bytes());
+    fit.extend(b".FIT");
+    let header_crc = fit_crc(&fit);
+    fit.extend(header_crc.to_le_bytes());
+    fit.extend(data);
+    let crc = fit_crc(&fit);
+    fit.extend(crc.to_le_bytes());
+
+    write_file(fname, &fit)
+}
+
+// Write a path in one of the export formats. Elevations are included when
+// an elevation source is given. The labelled waypoints, with their GPX
+// symbols, are only written to GPX.
+pub fn export_path(path: &Path, fname: &str, format: &str, name: &str,
+                   waypoints: &[(Coord, String, String)],
+                   atlas: Option<&dyn ElevationSource>,
+                   stats: Option<&PathStats>) -> Result<(), String> {
+    let points = track_points(path, atlas);
+
+    if points.is_empty() {
+        return Err("Path has no points".to_string());
+    }
+
+    match format {
+        "gpx" => {
+            let Some(a) = atlas else {
+                return Err("GPX export needs the maps".to_string());
+            };
+            path.write_gpx(fname, name, waypoints, a, stats);
+            Ok(())
+        },
+        "kml" => write_kml(&points, fname, name),
+        "geojson" => write_geojson(&points, fname, name, stats),
+        "csv" => write_csv(&points, fname, stats),
+        "fit" => write_fit(&points, fname, name),
+        _ => Err(format!("Unknown format '{}'. Expected one of {}", format,
+                         EXPORT_FORMATS.join(", "))),
+    }
+}
+
+// Convert a GPX track, or the waypoints of a params file, to another format
+pub fn export_file(input: &str, format: &str, output: &str)
+                   -> Result<(), String> {
+    let (path, name, waypoints, params) = match detect_file_kind(input)? {
+        FileKind::Gpx => (Path::read_gpx(input)?, "stivalg".to_string(),
+                          vec![], Params::from_config()),
+        FileKind::Params => {
+            let params = Params::from_file(input)?;
+            let mut path = Path::new();
+
+            for c in &params.points {
+                path.push(*c);
+            }
+
+            let name = params.expand_template(&params.track_name,
+                                              path.len());
+            let waypoints = params.export_waypoints(&path);
+            (path, name, waypoints, params)
+        },
+    };
+
+    let points: Vec<Coord> = path.into_iter().cloned().collect();
+    let opt_atlas = atlas_covering(&points);
+    let atlas = opt_atlas.as_ref().map(|a| a as &dyn ElevationSource);
+    let stats = atlas.map(|a| path.stats(&params.points, a,
+                                         &Cost::from_params(&params)));
+
+    export_path(&path, output, format, &name, &waypoints, atlas,
+                stats.as_ref())?;
+    println!("Wrote {} points to {}", points.len(), output);
+
+    Ok(())
+}
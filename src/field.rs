@@ -8,7 +8,7 @@ pub const FIELD_SIZE: f32 = 1.0;
 pub const E_ORIGO: f32 = 0.0;
 pub const N_ORIGO: f32 = 0.0;
 
-#[derive(Copy, Debug, Clone, PartialEq)]
+#[derive(Copy, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Field {
     pub x: u32,
     pub y: u32,
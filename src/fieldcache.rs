@@ -0,0 +1,107 @@
+use crate::elevation::ElevationSource;
+use crate::field::Field;
+
+use hoydedata::Coord;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+
+// Share of max_memory_mb given to the cache. The first pass graph gets less.
+pub const MEMORY_SHARE: f32 = 0.125;
+// Estimated memory (bytes) per field remembered by both maps, including the
+// overhead of the hash maps
+const BYTES_PER_FIELD: f32 = 56.0;
+// Number of parts of each map, locked separately, so that lookups from
+// several threads rarely wait for each other
+const SHARDS: usize = 64;
+
+// Elevation source remembering the gradient and roughness looked up at each
+// field. The passes of a path search and the optimization after them walk
+// many of the same fields, and the samples of one pass serve the next.
+// Only lookups at the corner of a field, as done when walking segments, are
+// remembered. Other coordinates are passed on to the source.
+pub struct FieldCache<'a> {
+    source: &'a dyn ElevationSource,
+    gradients: Vec<RwLock<HashMap<Field, Option<(f32, f32, f32)>>>>,
+    roughness: Vec<RwLock<HashMap<Field, Option<f32>>>>,
+    // Largest number of fields remembered by each shard
+    max_fields: usize,
+}
+
+impl<'a> FieldCache<'a> {
+    // Cache of a source, using its share of the memory budget
+    // (max_memory_mb, 0 is no limit)
+    pub fn new(source: &'a dyn ElevationSource, max_memory_mb: f32) -> Self {
+        let max_fields = if max_memory_mb > 0.0 {
+            (max_memory_mb*MEMORY_SHARE*1024.0*1024.0/BYTES_PER_FIELD)
+                as usize/SHARDS
+        }
+        else {
+            usize::MAX
+        };
+
+        Self {
+            source: source,
+            gradients: (0..SHARDS).map(|_| RwLock::new(HashMap::new()))
+                .collect(),
+            roughness: (0..SHARDS).map(|_| RwLock::new(HashMap::new()))
+                .collect(),
+            max_fields: max_fields,
+        }
+    }
+
+    // The field with its corner at c, if any
+    fn field_at(c: &Coord) -> Option<Field> {
+        let f = Field::from(*c);
+        let corner: Coord = f.into();
+
+        (corner == *c).then_some(f)
+    }
+
+    // Shard of a field. Neighbouring fields are in different shards.
+    fn shard(f: &Field) -> usize {
+        (f.x as usize).wrapping_add((f.y as usize).wrapping_mul(31)) % SHARDS
+    }
+
+    fn cached<T: Copy>(&self, shards: &[RwLock<HashMap<Field, T>>],
+                       c: &Coord, lookup: impl Fn() -> T) -> T {
+        let Some(f) = FieldCache::field_at(c) else {
+            return lookup();
+        };
+        let shard = &shards[FieldCache::shard(&f)];
+
+        if let Some(v) = shard.read().get(&f) {
+            return *v;
+        }
+
+        let v = lookup();
+        let mut map = shard.write();
+        if map.len() < self.max_fields {
+            map.insert(f, v);
+        }
+
+        v
+    }
+}
+
+impl ElevationSource for FieldCache<'_> {
+    fn lookup(&self, c: &Coord) -> Option<f32> {
+        self.source.lookup(c)
+    }
+
+    fn lookup_with_gradient(&self, c: &Coord) -> Option<(f32, f32, f32)> {
+        self.cached(&self.gradients, c,
+                    || self.source.lookup_with_gradient(c))
+    }
+
+    fn bounds(&self) -> Option<(Coord, Coord)> {
+        self.source.bounds()
+    }
+
+    fn prefetch(&self, sw: &Coord, ne: &Coord) {
+        self.source.prefetch(sw, ne);
+    }
+
+    fn roughness(&self, c: &Coord) -> Option<f32> {
+        self.cached(&self.roughness, c, || self.source.roughness(c))
+    }
+}
@@ -0,0 +1,304 @@
+use crate::elevation::ElevationSource;
+
+use hoydedata::Coord;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::tags::Tag;
+
+// GeoTIFF tags
+const MODEL_PIXEL_SCALE: u16 = 33550;
+const MODEL_TIEPOINT: u16 = 33922;
+const GDAL_NODATA: u16 = 42113;
+// Number of decoded rasters kept in memory
+const MAX_CACHED_TILES: usize = 16;
+// Size (meters) of the cells of the index finding the tiles at a position
+const INDEX_CELL: f32 = 1000.0;
+
+// A GeoTIFF file, with the position of its raster. The raster is decoded
+// when it is first needed.
+pub struct Tile {
+    pub fname: String,
+    width: usize,
+    height: usize,
+    // Map coordinate of the upper left corner, and pixel size (meters)
+    west: f32,
+    north: f32,
+    scale_e: f32,
+    scale_n: f32,
+    nodata: Option<f32>,
+    // When the raster was last used, by the clock of the source
+    used: AtomicU64,
+}
+
+impl Tile {
+    pub fn open(fname: &str) -> Result<Self, String> {
+        let err = |e: tiff::TiffError| format!("{}: {}", fname, e);
+        let file = File::open(fname)
+            .map_err(|e| format!("Unable to open {}: {}", fname, e))?;
+        let mut decoder = Decoder::new(BufReader::new(file)).map_err(err)?;
+
+        let (width, height) = decoder.dimensions().map_err(err)?;
+        let scale = decoder.get_tag_f64_vec(Tag::Unknown(MODEL_PIXEL_SCALE))
+            .map_err(err)?;
+        let tiepoint = decoder.get_tag_f64_vec(Tag::Unknown(MODEL_TIEPOINT))
+            .map_err(err)?;
+
+        if scale.len() < 2 || tiepoint.len() < 6 {
+            return Err(format!("{} is not georeferenced", fname));
+        }
+
+        // The tiepoint ties raster position (i, j) to map position (x, y)
+        let west = tiepoint[3] - tiepoint[0]*scale[0];
+        let north = tiepoint[4] + tiepoint[1]*scale[1];
+
+        let nodata = decoder.get_tag_ascii_string(Tag::Unknown(GDAL_NODATA))
+            .ok()
+            .and_then(|s| s.trim().trim_end_matches('\0').parse().ok());
+
+        Ok(Self {
+            fname: fname.to_string(),
+            width: width as usize,
+            height: height as usize,
+            west: west as f32,
+            north: north as f32,
+            scale_e: scale[0] as f32,
+            scale_n: scale[1] as f32,
+            nodata: nodata,
+            used: AtomicU64::new(0),
+        })
+    }
+
+    // South west and north east corners
+    pub fn bounds(&self) -> (Coord, Coord) {
+        (Coord::new(self.west,
+                    self.north - (self.height as f32)*self.scale_n),
+         Coord::new(self.west + (self.width as f32)*self.scale_e,
+                    self.north))
+    }
+
+    fn read_raster(&self) -> Result<Vec<f32>, String> {
+        let err = |e: tiff::TiffError| format!("{}: {}", self.fname, e);
+        let file = File::open(&self.fname)
+            .map_err(|e| format!("Unable to open {}: {}", self.fname, e))?;
+        let mut decoder = Decoder::new(BufReader::new(file)).map_err(err)?;
+
+        let raster = match decoder.read_image().map_err(err)? {
+            DecodingResult::F32(v) => v,
+            DecodingResult::F64(v) => v.into_iter().map(|h| h as f32)
+                .collect(),
+            DecodingResult::I16(v) => v.into_iter().map(|h| h as f32)
+                .collect(),
+            DecodingResult::U16(v) => v.into_iter().map(|h| h as f32)
+                .collect(),
+            DecodingResult::I32(v) => v.into_iter().map(|h| h as f32)
+                .collect(),
+            _ => {
+                return Err(format!("{}: unsupported sample format",
+                                   self.fname));
+            },
+        };
+
+        if raster.len() < self.width*self.height {
+            return Err(format!("{}: only single band rasters are supported",
+                               self.fname));
+        }
+
+        Ok(raster)
+    }
+}
+
+// Elevation data from a directory of GeoTIFF DEMs, e.g. the 1 m or 10 m
+// DTMs from Kartverket, or SRTM/Copernicus tiles reprojected to the map
+// CRS. The rasters are decoded on demand and the most recently used ones
+// are kept in memory.
+pub struct GeoTiffSource {
+    tiles: Vec<Tile>,
+    // Tiles overlapping each cell of INDEX_CELL meters
+    index: HashMap<(i64, i64), Vec<usize>>,
+    // Decoded rasters by tile index. Lookups only take the read lock,
+    // so that parallel lookups don't wait for each other.
+    rasters: RwLock<HashMap<usize, Arc<Vec<f32>>>>,
+    // Counter ordering the uses of the rasters
+    clock: AtomicU64,
+}
+
+impl GeoTiffSource {
+    pub fn new() -> Self {
+        Self {
+            tiles: vec![],
+            index: HashMap::new(),
+            rasters: RwLock::new(HashMap::new()),
+            clock: AtomicU64::new(0),
+        }
+    }
+
+    // Index all .tif/.tiff files in a directory
+    pub fn open_dir(dir: &str) -> Result<Self, String> {
+        let mut source = GeoTiffSource::new();
+        let entries = fs::read_dir(dir)
+            .map_err(|e| format!("Unable to read {}: {}", dir, e))?;
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_tiff = path.extension()
+                .map(|e| e.eq_ignore_ascii_case("tif")
+                     || e.eq_ignore_ascii_case("tiff"))
+                .unwrap_or(false);
+
+            if is_tiff {
+                source.add_tile(&path.to_string_lossy())?;
+            }
+        }
+
+        if source.tiles.is_empty() {
+            return Err(format!("No GeoTIFF files in {}", dir));
+        }
+
+        println!("Found {} GeoTIFF tiles in {}", source.tiles.len(), dir);
+
+        Ok(source)
+    }
+
+    pub fn add_tile(&mut self, fname: &str) -> Result<(), String> {
+        let tile = Tile::open(fname)?;
+        let (sw, ne) = tile.bounds();
+        let (x0, y0) = GeoTiffSource::cell(&sw);
+        let (x1, y1) = GeoTiffSource::cell(&ne);
+
+        for x in x0..=x1 {
+            for y in y0..=y1 {
+                self.index.entry((x, y)).or_default().push(self.tiles.len());
+            }
+        }
+
+        self.tiles.push(tile);
+
+        Ok(())
+    }
+
+    fn cell(c: &Coord) -> (i64, i64) {
+        ((c.e/INDEX_CELL).floor() as i64, (c.n/INDEX_CELL).floor() as i64)
+    }
+
+    fn raster(&self, i: usize) -> Option<Arc<Vec<f32>>> {
+        let now = self.clock.fetch_add(1, Ordering::Relaxed);
+        self.tiles[i].used.store(now, Ordering::Relaxed);
+
+        if let Some(r) = self.rasters.read().get(&i) {
+            return Some(r.clone());
+        }
+
+        // Decode without holding the lock. Two threads may decode the same
+        // raster, and the last one is kept.
+        let raster = match self.tiles[i].read_raster() {
+            Ok(r) => Arc::new(r),
+            Err(e) => {
+                println!("{}", e);
+                return None;
+            },
+        };
+
+        let mut rasters = self.rasters.write();
+
+        if rasters.len() >= MAX_CACHED_TILES {
+            let oldest = rasters.keys()
+                .copied()
+                .min_by_key(|j| self.tiles[*j].used.load(Ordering::Relaxed));
+            if let Some(j) = oldest {
+                rasters.remove(&j);
+            }
+        }
+
+        rasters.insert(i, raster.clone());
+
+        Some(raster)
+    }
+
+    // Tile containing c, with the position of c in its raster (pixels from
+    // the center of the upper left pixel). The raster covers half a pixel
+    // beyond the centers of the outer pixels, up to the seam with the
+    // neighbouring tile. A tile with c between the pixel centers is
+    // preferred. Otherwise, the position is clamped to the outer pixels.
+    fn locate(&self, c: &Coord) -> Option<(usize, f32, f32)> {
+        let candidates = self.index.get(&GeoTiffSource::cell(c))?;
+        let mut clamped = None;
+
+        for i in candidates {
+            let t = &self.tiles[*i];
+            let x = (c.e - t.west)/t.scale_e - 0.5;
+            let y = (t.north - c.n)/t.scale_n - 0.5;
+            let (xmax, ymax) = ((t.width - 1) as f32, (t.height - 1) as f32);
+
+            if x >= 0.0 && y >= 0.0 && x <= xmax && y <= ymax {
+                return Some((*i, x, y));
+            }
+
+            if clamped.is_none() && x >= -0.5 && y >= -0.5
+                && x <= xmax + 0.5 && y <= ymax + 0.5 {
+                clamped = Some((*i, x.clamp(0.0, xmax), y.clamp(0.0, ymax)));
+            }
+        }
+
+        clamped
+    }
+
+    // Bilinear interpolation of the elevation, with its gradient (east,
+    // north)
+    fn interpolate(&self, c: &Coord) -> Option<(f32, f32, f32)> {
+        let (i, x, y) = self.locate(c)?;
+        let t = &self.tiles[i];
+        let raster = self.raster(i)?;
+
+        let (x0, y0) = ((x as usize).min(t.width - 2),
+                        (y as usize).min(t.height - 2));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        let at = |col: usize, row: usize| raster[row*t.width + col];
+
+        let (h00, h10) = (at(x0, y0), at(x0 + 1, y0));
+        let (h01, h11) = (at(x0, y0 + 1), at(x0 + 1, y0 + 1));
+
+        if let Some(nd) = t.nodata {
+            if [h00, h10, h01, h11].contains(&nd) {
+                return None;
+            }
+        }
+
+        let h = h00*(1.0 - fx)*(1.0 - fy) + h10*fx*(1.0 - fy)
+            + h01*(1.0 - fx)*fy + h11*fx*fy;
+        let dx = ((h10 - h00)*(1.0 - fy) + (h11 - h01)*fy)/t.scale_e;
+        // Rows go southwards
+        let dy = -((h01 - h00)*(1.0 - fx) + (h11 - h10)*fx)/t.scale_n;
+
+        Some((h, dx, dy))
+    }
+}
+
+impl ElevationSource for GeoTiffSource {
+    fn lookup(&self, c: &Coord) -> Option<f32> {
+        self.interpolate(c).map(|(h, _, _)| h)
+    }
+
+    fn lookup_with_gradient(&self, c: &Coord) -> Option<(f32, f32, f32)> {
+        self.interpolate(c)
+    }
+
+    fn bounds(&self) -> Option<(Coord, Coord)> {
+        let mut bounds: Option<(Coord, Coord)> = None;
+
+        for t in &self.tiles {
+            let (sw, ne) = t.bounds();
+            bounds = Some(match bounds {
+                Some((s, n)) => (Coord::new(s.e.min(sw.e), s.n.min(sw.n)),
+                                 Coord::new(n.e.max(ne.e), n.n.max(ne.n))),
+                None => (sw, ne),
+            });
+        }
+
+        bounds
+    }
+}
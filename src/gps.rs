@@ -0,0 +1,213 @@
+use crate::barrier::Barrier;
+use crate::channel::{CanvasMsg, CanvasSender};
+use crate::crs::from_latlon;
+
+use hoydedata::Coord;
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+// Default address of gpsd
+const GPSD_ADDRESS: &str = "localhost:2947";
+// Wait before reconnecting after the source has failed
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+// Position from a GPS receiver, read in a thread of its own. The source is
+// gpsd ("gpsd" or "gpsd://host:port"), or a serial device or file giving
+// NMEA sentences. Serial devices must be set to the right speed
+// beforehand, e.g. with stty.
+pub struct Gps {
+    source: String,
+    // Last position, and when it was received
+    fix: Mutex<Option<(Coord, Instant)>>,
+    // Planned route, and the largest distance (meters) from it before the
+    // deviation alarm goes off
+    route: Mutex<Option<(Barrier, f32)>>,
+    deviating: AtomicBool,
+}
+
+impl Gps {
+    // Start reading positions. Each position is shown on the map, if there
+    // is a map window.
+    pub fn start(source: &str, opt_tx: Option<CanvasSender>) -> Arc<Self> {
+        let gps = Arc::new(Self {
+            source: source.to_string(),
+            fix: Mutex::new(None),
+            route: Mutex::new(None),
+            deviating: AtomicBool::new(false),
+        });
+
+        // Only the first of repeated failures is told, e.g. when gpsd isn't
+        // running. Failing again after positions have been received is a
+        // new failure.
+        let g = gps.clone();
+        std::thread::spawn(move || {
+            let mut failing = false;
+            let mut last_fix = None;
+
+            loop {
+                if let Err(e) = g.read(&opt_tx) {
+                    let fix = g.fix.lock().map(|(_, t)| t);
+                    if fix != last_fix {
+                        failing = false;
+                        last_fix = fix;
+                    }
+
+                    if !failing {
+                        println!("GPS: {}. Retrying every {} s.", e,
+                                 RECONNECT_DELAY.as_secs());
+                        failing = true;
+                    }
+                }
+                std::thread::sleep(RECONNECT_DELAY);
+            }
+        });
+
+        gps
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    // Last position and its age
+    pub fn fix(&self) -> Option<(Coord, Duration)> {
+        self.fix.lock().map(|(c, t)| (c, t.elapsed()))
+    }
+
+    // Set the route watched by the deviation alarm. No route or a distance
+    // of 0 turns the alarm off.
+    pub fn set_route(&self, route: Option<Barrier>, max_distance: f32) {
+        let route = route.filter(|r| r.len() >= 2 && max_distance > 0.0);
+
+        *self.route.lock() = route.map(|r| (r, max_distance));
+        self.deviating.store(false, Ordering::SeqCst);
+    }
+
+    // Warn when the position drifts too far from the route, and when it is
+    // back
+    fn check_deviation(&self, c: Coord, opt_tx: &Option<CanvasSender>) {
+        let route = self.route.lock();
+        let Some((track, max_distance)) = route.as_ref() else {
+            return;
+        };
+
+        let d = track.distance_sq(&c).sqrt();
+
+        if d > *max_distance {
+            if !self.deviating.swap(true, Ordering::SeqCst) {
+                // Terminal bell
+                println!("\x07{:.0} m off the planned route", d);
+
+                if let Some(tx) = opt_tx {
+                    let _ = tx.send(CanvasMsg::FlashAlarm);
+                }
+            }
+        }
+        else if self.deviating.swap(false, Ordering::SeqCst) {
+            println!("Back on the planned route");
+        }
+    }
+
+    // Read positions until the source fails or ends
+    fn read(&self, opt_tx: &Option<CanvasSender>) -> Result<(), String> {
+        let gpsd = if self.source == "gpsd" {
+            Some(GPSD_ADDRESS)
+        }
+        else {
+            self.source.strip_prefix("gpsd://")
+        };
+
+        let reader: Box<dyn Read> = if let Some(address) = gpsd {
+            let mut stream = TcpStream::connect(address)
+                .map_err(|e| format!("Unable to connect to gpsd at {}: {}",
+                                     address, e))?;
+            stream.write_all(b"?WATCH={\"enable\":true,\"json\":true};\n")
+                .map_err(|e| e.to_string())?;
+            Box::new(stream)
+        }
+        else {
+            Box::new(File::open(&self.source)
+                     .map_err(|e| format!("Unable to open {}: {}",
+                                          self.source, e))?)
+        };
+
+        for line in BufReader::new(reader).lines() {
+            let line = line.map_err(|e| e.to_string())?;
+            let pos = if gpsd.is_some() {
+                parse_gpsd(&line)
+            }
+            else {
+                parse_nmea(&line)
+            };
+
+            let Some((lat, lon)) = pos else {
+                continue;
+            };
+
+            let c = from_latlon(lat, lon);
+            self.fix.lock().replace((c, Instant::now()));
+
+            if let Some(tx) = opt_tx {
+                let _ = tx.send(CanvasMsg::SetGpsPosition(Some(c)));
+            }
+
+            self.check_deviation(c, opt_tx);
+        }
+
+        Err(format!("{} ended", self.source))
+    }
+}
+
+// Latitude and longitude of a gpsd TPV report with a fix
+fn parse_gpsd(line: &str) -> Option<(f64, f64)> {
+    let v: Value = serde_json::from_str(line).ok()?;
+
+    if v["class"] != "TPV" || v["mode"].as_u64().unwrap_or(0) < 2 {
+        return None;
+    }
+
+    Some((v["lat"].as_f64()?, v["lon"].as_f64()?))
+}
+
+// Latitude and longitude of an NMEA GGA or RMC sentence with a fix
+fn parse_nmea(line: &str) -> Option<(f64, f64)> {
+    let sentence = line.trim().strip_prefix('$')?;
+    let sentence = sentence.split('*').next()?;
+    let fields: Vec<&str> = sentence.split(',').collect();
+    let kind = fields[0].get(2..)?;
+
+    let (lat, lon) = match kind {
+        // Fix quality 0 is no fix
+        "GGA" if fields.len() > 6 && fields[6] != "0" => {
+            ((fields[2], fields[3]), (fields[4], fields[5]))
+        },
+        // Status V is no fix
+        "RMC" if fields.len() > 6 && fields[2] == "A" => {
+            ((fields[3], fields[4]), (fields[5], fields[6]))
+        },
+        _ => return None,
+    };
+
+    Some((nmea_degrees(lat.0, lat.1, 2)?, nmea_degrees(lon.0, lon.1, 3)?))
+}
+
+// Degrees of an NMEA angle, (d)ddmm.mmmm with a hemisphere letter. The
+// degrees have the given number of digits.
+fn nmea_degrees(value: &str, hemisphere: &str, digits: usize)
+                -> Option<f64> {
+    let deg: f64 = value.get(..digits)?.parse().ok()?;
+    let min: f64 = value.get(digits..)?.parse().ok()?;
+    let d = deg + min/60.0;
+
+    match hemisphere {
+        "N" | "E" => Some(d),
+        "S" | "W" => Some(-d),
+        _ => None,
+    }
+}
@@ -1,10 +1,46 @@
 use crate::barrier::Barrier;
+use crate::cost::Cost;
+use crate::elevation::ElevationSource;
+use crate::fieldcache;
+use crate::landmark::{self, LandmarkBounds, Landmarks, Lattice};
 use crate::params::Params;
 use crate::path::{Segment, Path};
 
-use hoydedata::{Atlas, Coord};
+use hoydedata::Coord;
 use std::cmp::max;
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+// Row spacing of a hexagonal grid, relative to the grid size
+pub const HEX_ROW: f32 = 0.8660254;
+// Maximal number of path points replaced by one shortcut
+const MAX_SHORTCUT: usize = 50;
+// Estimated memory (bytes) per graph node, not counting the edges: the node
+// coordinate, grid map entry and search state
+const BYTES_PER_NODE: f32 = 100.0;
+// Estimated memory (bytes) per edge: the edge and its adjacency list entry
+const BYTES_PER_EDGE: f32 = 40.0;
+
+// Reason why an edge can't be walked
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Blocked {
+    Barrier(usize),
+    Corridor,
+    Steep,
+    NoData,
+}
+
+impl fmt::Display for Blocked {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Blocked::Barrier(i) => write!(formatter, "barrier {}", i + 1),
+            Blocked::Corridor => write!(formatter, "the corridor"),
+            Blocked::Steep => write!(formatter, "steep terrain"),
+            Blocked::NoData => write!(formatter, "missing elevation data"),
+        }
+    }
+}
 
 pub struct Graph {
     a: Coord,
@@ -15,12 +51,50 @@ pub struct Graph {
     major: f32,
     gs_pass1: f32,
     gs_pass2: f32,
+    path_width_pass2: f32,
     g_pass1: usize,
     g_pass2: usize,
+    // Grid topology and orientation (cos, sin) of the first pass grid
+    hex: bool,
+    orientation: (f32, f32),
+    // Connect knight move neighbours in the first pass square grid
+    knight_moves: bool,
     barriers: Vec<Barrier>,
+    // Lines which must be crossed, in order
+    gates: Vec<Barrier>,
+    // Paths may end anywhere within this distance of the end point
+    end_radius: f32,
+    // Closed polygon which nodes and edges must stay inside
+    corridor: Option<Barrier>,
+    // Area covered by the elevation data (south west, north east)
+    bounds: Option<(Coord, Coord)>,
+    fords: Vec<Coord>,
+    ford_radius: f32,
+    avoid: Option<Barrier>,
+    avoid_distance: f32,
+    avoid_factor: f32,
+    cost: Cost,
     cmap: HashMap<usize, usize>,
+    // Nodes explored by the last shortest path search, if recorded
+    explored: Option<Vec<Coord>>,
     v: usize,
     edges: Vec<(usize, usize, f32)>,
+    // Edges which couldn't be walked, and why
+    blocked: Vec<(usize, usize, Blocked)>,
+    // Whether each node is on terrain too steep to walk, by the gradient
+    // at the node. In the first pass, nodes are checked once, and no edges
+    // are evaluated into cliff bands.
+    steep: Vec<Option<bool>>,
+    prune_steep: bool,
+    // Time from the start to each node by the last search
+    reach_times: Vec<f32>,
+    // Time from the start to the end node by the last search
+    end_time: f32,
+    // Lower bounds of the time to the end node, guiding the search
+    landmarks: Option<Arc<LandmarkBounds>>,
+    // Lattice node at the center of the grid, if the grid is on the lattice
+    lattice_o: Option<(i64, i64)>,
+    max_memory_mb: f32,
     nodes: Vec<Coord>,
 }
 
@@ -39,27 +113,236 @@ impl Graph {
         let f1 = (a - o)*(f/major) + o;
         let f2 = (b - o)*(f/major) + o;
 
-        // Grid width
-        let g_pass1 = ((major/params.grid_size_pass1) as usize)*2 + 1;
-        let g_pass2 = ((major/params.grid_size_pass2) as usize)*2 + 1;
+        let passes = params.passes();
+        let mut gs_pass1 = passes[0].0;
+        let (gs_pass2, path_width_pass2) = passes[passes.len() - 1];
 
-        Self {
+        let hex = params.grid_topology == "hex";
+        let angle = params.grid_orientation.to_radians();
+
+        // The field cache and the landmarks get their shares of the memory
+        // budget
+        let share = if params.landmarks > 0 {
+            fieldcache::MEMORY_SHARE + landmark::MEMORY_SHARE
+        }
+        else {
+            fieldcache::MEMORY_SHARE
+        };
+        let budget = params.max_memory_mb*(1.0 - share);
+
+        // Coarsen the first pass grid if the graph would exceed the memory
+        // budget. Nodes are placed within the ellipse.
+        if budget > 0.0 {
+            let degree = if hex { 6.0 }
+                else if params.connectivity == 16 { 16.0 }
+                else { 8.0 };
+            let node_area = gs_pass1*gs_pass1*if hex { HEX_ROW } else { 1.0 };
+            let nodes = std::f32::consts::PI*major*minor/node_area;
+            let mb = nodes*(BYTES_PER_NODE + degree*BYTES_PER_EDGE)
+                /(1024.0*1024.0);
+
+            if mb > budget {
+                let gs = gs_pass1*(mb/budget).sqrt();
+                println!("First pass graph would need {:.0} MB, more than \
+                          max_memory_mb. Using grid size {:.1} instead of \
+                          {}.", mb, gs, gs_pass1);
+                gs_pass1 = gs;
+            }
+        }
+
+        // Grid width. Rows of a hexagonal grid are closer, so more of them
+        // are needed for covering the area. The grid is centered at the
+        // lattice node nearest to the center, and extends a couple of
+        // nodes more to cover the area.
+        let g_pass1 = if hex {
+            ((major/(gs_pass1*HEX_ROW)) as usize)*2 + 5
+        }
+        else {
+            ((major/gs_pass1) as usize)*2 + 5
+        };
+
+        let lattice = Lattice {
+            gs: gs_pass1,
+            hex: hex,
+            knight_moves: params.connectivity == 16,
+            orientation: (angle.cos(), angle.sin()),
+        };
+        let (ox, oy) = lattice.units(&o);
+        let mut oy = oy.round() as i64;
+        // Rows of the grid must be shifted like those of the lattice
+        if hex && (oy - ((g_pass1 - 1)/2) as i64) % 2 != 0 {
+            oy += 1;
+        }
+        let ox = (ox - lattice.shift(oy)).round() as i64;
+
+        let mut graph = Self {
             a: a,
             b: b,
             o: o,
             f1: f1,
             f2: f2,
             major: major,
-            gs_pass1: params.grid_size_pass1,
-            gs_pass2: params.grid_size_pass2,
+            gs_pass1: gs_pass1,
+            gs_pass2: 0.0,
+            path_width_pass2: 0.0,
             g_pass1: g_pass1,
-            g_pass2: g_pass2,
+            g_pass2: 0,
+            hex: hex,
+            orientation: (angle.cos(), angle.sin()),
+            knight_moves: params.connectivity == 16,
             barriers: params.barriers.clone(),
+            gates: vec![],
+            end_radius: 0.0,
+            corridor: if params.corridor.len() >= 3 {
+                Some(params.corridor.closed())
+            }
+            else {
+                None
+            },
+            bounds: None,
+            fords: params.fords.clone(),
+            ford_radius: params.ford_radius,
+            avoid: None,
+            avoid_distance: params.return_distance,
+            avoid_factor: params.return_penalty,
+            cost: Cost::from_params(params),
             cmap: HashMap::new(),
+            explored: None,
             v: 0,
             edges: vec!(),
+            blocked: vec![],
+            steep: vec![],
+            prune_steep: false,
+            reach_times: vec![],
+            end_time: f32::INFINITY,
+            landmarks: None,
+            lattice_o: Some((ox, oy)),
+            max_memory_mb: params.max_memory_mb,
             nodes: vec!(),
+        };
+
+        graph.set_refinement(gs_pass2, path_width_pass2);
+
+        graph
+    }
+
+    // Keep nodes within the area covered by the elevation data
+    pub fn set_bounds(&mut self, bounds: Option<(Coord, Coord)>) {
+        self.bounds = bounds;
+    }
+
+    // Set grid size and corridor half width of a refinement pass. By default
+    // these are taken from the last pass in the params.
+    pub fn set_refinement(&mut self, gs: f32, width: f32) {
+        self.gs_pass2 = gs;
+        self.path_width_pass2 = width;
+        // The grid must also cover the corridor around the previous path
+        self.g_pass2 = (((self.major + width)/gs) as usize)*2 + 3;
+    }
+
+    // Set the gates which paths through the graph must cross, in order
+    pub fn set_gates(&mut self, gates: Vec<Barrier>) {
+        self.gates = gates;
+    }
+
+    // Let paths end at any node within the radius of the end point
+    pub fn set_end_radius(&mut self, radius: f32) {
+        self.end_radius = radius;
+    }
+
+    // Penalize edges near an earlier part of the route, e.g. the outbound leg
+    // of an out-and-back trip.
+    pub fn avoid_path(&mut self, path: &Path) {
+        // Distances are measured against a simplified path
+        let mut avoid = Barrier::new();
+        for c in path {
+            if avoid.len() == 0 || (*c - avoid.points[avoid.len() - 1]).abs()
+                > self.avoid_distance*0.5 {
+                avoid.add_point(*c);
+            }
+        }
+
+        if avoid.len() >= 2 {
+            self.avoid.replace(avoid);
+        }
+    }
+
+    // Cost multiplier for an edge
+    fn penalty(&self, c1: &Coord, c2: &Coord) -> f32 {
+        if let Some(avoid) = &self.avoid {
+            let d = self.avoid_distance;
+            if avoid.distance_sq(&((*c1 + *c2)*0.5)) < d*d {
+                return self.avoid_factor;
+            }
+        }
+
+        1.0
+    }
+
+    // Outline of the ellipse covered by the first pass graph
+    pub fn ellipse(&self) -> Vec<Coord> {
+        let f = (self.f2 - self.f1)*0.5;
+        let fabs = f.abs();
+        let minor = (self.major*self.major - fabs*fabs).sqrt();
+        // Unit vectors along the major and minor axes
+        let (ue, un) = if fabs > 0.0 {
+            (f.e/fabs, f.n/fabs)
+        }
+        else {
+            (1.0, 0.0)
+        };
+
+        (0..50).map(|j| {
+            let a = 2.0*std::f32::consts::PI*(j as f32)/50.0;
+            let (x, y) = (self.major*a.cos(), minor*a.sin());
+            Coord::new(self.o.e + x*ue - y*un, self.o.n + x*un + y*ue)
+        }).collect()
+    }
+
+    // Approximate outline of the corridor searched around a path, made by
+    // offsetting the path to each side.
+    pub fn corridor_outline(&self, path: &Path) -> Vec<Coord> {
+        let points: Vec<Coord> = path.into_iter().cloned().collect();
+        let w = self.path_width_pass2;
+        let mut left = vec![];
+        let mut right = vec![];
+
+        for i in 0..points.len() {
+            let a = points[if i > 0 { i - 1 } else { i }];
+            let b = points[if i + 1 < points.len() { i + 1 } else { i }];
+            let d = b - a;
+            let dabs = d.abs();
+            if dabs == 0.0 {
+                continue;
+            }
+
+            // Normal to the path direction
+            let normal = Coord::new(-d.n/dabs, d.e/dabs)*w;
+            left.push(points[i] + normal);
+            right.push(points[i] - normal);
         }
+
+        right.reverse();
+        left.append(&mut right);
+
+        left
+    }
+
+    pub fn nodes(&self) -> &Vec<Coord> {
+        &self.nodes
+    }
+
+    pub fn edges(&self) -> &Vec<(usize, usize, f32)> {
+        &self.edges
+    }
+
+    // Record the nodes explored by the following shortest path searches
+    pub fn record_explored(&mut self) {
+        self.explored.replace(vec![]);
+    }
+
+    pub fn explored(&self) -> &[Coord] {
+        self.explored.as_deref().unwrap_or(&[])
     }
 
     pub fn num_nodes(&self) -> usize {
@@ -75,14 +358,45 @@ impl Graph {
         return self.cmap.contains_key(&hash_key);
     }
 
+    // Lattice of the first pass grid
+    fn lattice(&self) -> Lattice {
+        Lattice {
+            gs: self.gs_pass1,
+            hex: self.hex,
+            knight_moves: self.knight_moves,
+            orientation: self.orientation,
+        }
+    }
+
+    // Coordinate of a grid point. In a hexagonal grid, every other row is
+    // shifted half a grid size. The grid is rotated around the center.
+    fn grid_to_coord(&self, gs: f32, g: usize, x: usize, y: usize) -> Coord {
+        if let Some((ox, oy)) = self.lattice_o {
+            let half = ((g - 1)/2) as i64;
+            return self.lattice().coord(x as i64 - half + ox,
+                                        y as i64 - half + oy);
+        }
+
+        let half = ((g - 1)/2) as f32;
+        let (de, dn) = if self.hex {
+            (((x as f32) + 0.5*((y % 2) as f32) - half)*gs,
+             ((y as f32) - half)*gs*HEX_ROW)
+        }
+        else {
+            (((x as f32) - half)*gs, ((y as f32) - half)*gs)
+        };
+
+        let (cos, sin) = self.orientation;
+
+        Coord::new(self.o.e + de*cos - dn*sin, self.o.n + de*sin + dn*cos)
+    }
+
     // Get a coordinate based on grid coordinates. The coordinate is returned
     // together with its vertex number.
     fn insert_node_from_grid_units(&mut self, gs: f32, g: usize, x: usize,
                                    y: usize, check_area: bool)
                                    -> Option<(Coord, usize)> {
-        let e = (x as f32)*gs + self.o.e - (((g - 1)/2) as f32)*gs;
-        let n = (y as f32)*gs + self.o.n - (((g - 1)/2) as f32)*gs;
-        let c = Coord::new(e, n);
+        let c = self.grid_to_coord(gs, g, x, y);
 
         if check_area {
             // Coordinates must be within the area of an ellipse with focal
@@ -93,6 +407,18 @@ impl Graph {
             }
         }
 
+        if let Some(corridor) = &self.corridor {
+            if !corridor.contains(&c) {
+                return None;
+            }
+        }
+
+        if let Some((sw, ne)) = self.bounds {
+            if c.e < sw.e || c.e > ne.e || c.n < sw.n || c.n > ne.n {
+                return None;
+            }
+        }
+
         // Use cantors pairing function for the hash key
         let hash_key = (x + y) * (x + y + 1) / 2 + x;
         if let Some(v) = self.cmap.get(&hash_key) {
@@ -114,57 +440,209 @@ impl Graph {
         let n = (c.clone(), v);
         self.nodes.push(c);
         self.v += 1;
+
+        // The end points are connected however steep they are
+        self.steep.resize(v + 1, None);
+        self.steep[v] = Some(false);
+
         return n;
     }
 
+    // Whether node v at c is on terrain too steep to walk. Only checked
+    // when pruning, by one gradient lookup per node.
+    fn is_steep(&mut self, c: &Coord, v: usize,
+                atlas: &dyn ElevationSource) -> bool {
+        if !self.prune_steep {
+            return false;
+        }
+
+        if self.steep.len() <= v {
+            self.steep.resize(v + 1, None);
+        }
+
+        *self.steep[v].get_or_insert_with(|| {
+            atlas.lookup_with_gradient(c)
+                .is_some_and(|(_, dx, dy)| self.cost.is_impassable(dx, dy))
+        })
+    }
+
+    fn is_ford(&self, c: &Coord) -> bool {
+        let r = self.ford_radius;
+
+        self.fords.iter().any(|f| (*f - *c).abs_sq() < r*r)
+    }
+
+    // Barrier or corridor crossed by the segment between c1 and c2. This is
+    // the same in both walking directions.
+    fn crossing(&self, c1: &Coord, c2: &Coord) -> Option<Blocked> {
+        for (i, b) in self.barriers.iter().enumerate() {
+            // Barriers can only be crossed at fords
+            if b.crossing_points(c1, c2).iter().any(|x| !self.is_ford(x)) {
+                return Some(Blocked::Barrier(i));
+            }
+
+            // Keep the buffer distance, except when crossing at a ford
+            if b.is_within_buffer(c1, c2) && !self.is_ford(c1)
+                && !self.is_ford(c2) {
+                return Some(Blocked::Barrier(i));
+            }
+        }
+
+        if let Some(corridor) = &self.corridor {
+            if corridor.is_crossing(c1, c2) {
+                return Some(Blocked::Corridor);
+            }
+        }
+
+        None
+    }
+
+    // Time of walking from c1 to c2, not checking for crossings
+    fn walk_time(&self, c1: Coord, c2: Coord, atlas: &dyn ElevationSource)
+                 -> Result<f32, Blocked> {
+        match Segment::new(c1, c2).time(atlas, &self.cost) {
+            Some(t) => Ok(t*self.penalty(&c1, &c2)),
+            None if atlas.lookup(&c1).is_none()
+                || atlas.lookup(&c2).is_none() => Err(Blocked::NoData),
+            None => Err(Blocked::Steep),
+        }
+    }
+
+    // Time of walking from c1 to c2, including any penalty. Fails if the
+    // terrain is too steep, or a barrier is crossed outside the fords.
+    fn edge_time(&self, c1: Coord, c2: Coord, atlas: &dyn ElevationSource)
+                 -> Result<f32, Blocked> {
+        match self.crossing(&c1, &c2) {
+            Some(b) => Err(b),
+            None => self.walk_time(c1, c2, atlas),
+        }
+    }
+
     fn connect(&mut self, opt_c1: Option<(Coord, usize)>,
-               opt_c2: Option<(Coord, usize)>, atlas: &Atlas) {
+               opt_c2: Option<(Coord, usize)>,
+               atlas: &dyn ElevationSource) {
         if let Some((c1, cn1)) = opt_c1 {
             if let Some((c2, cn2)) = opt_c2 {
-                for b in &self.barriers {
-                    if b.is_crossing(&c1, &c2) {
-                        return;
-                    }
+                if self.is_steep(&c1, cn1, atlas)
+                    || self.is_steep(&c2, cn2, atlas) {
+                    self.blocked.push((cn1, cn2, Blocked::Steep));
+                    self.blocked.push((cn2, cn1, Blocked::Steep));
+                    return;
+                }
+
+                if let Some(b) = self.crossing(&c1, &c2) {
+                    self.blocked.push((cn1, cn2, b));
+                    self.blocked.push((cn2, cn1, b));
+                    return;
                 }
 
-                if let Some(time1) = Segment::new(c1, c2).time(atlas) {
-                    self.edges.push((cn1, cn2, time1));
+                match self.walk_time(c1, c2, atlas) {
+                    Ok(time1) => self.edges.push((cn1, cn2, time1)),
+                    Err(b) => self.blocked.push((cn1, cn2, b)),
+                }
+                match self.walk_time(c2, c1, atlas) {
+                    Ok(time2) => self.edges.push((cn2, cn1, time2)),
+                    Err(b) => self.blocked.push((cn2, cn1, b)),
+                }
+            }
+        }
+    }
+
+    // Replace chains of path points with direct segments, where the direct
+    // segment is walkable and faster. The path from the coarse grid zigzags
+    // along the grid directions, and a straighter path gives a narrower
+    // corridor for the next pass.
+    pub fn prune_shortcuts(&self, path: &Path, atlas: &dyn ElevationSource)
+                           -> Path {
+        let points: Vec<Coord> = path.into_iter().cloned().collect();
+        let mut pruned = Path::new();
+        let mut i = 0;
+
+        pruned.push(points[0]);
+
+        while i < points.len() - 1 {
+            let mut chain = 0.0;
+            let mut next = i + 1;
+            // Gates crossed by the chain, which the shortcut must also cross
+            let mut chain_gates = vec![false; self.gates.len()];
+
+            for j in i + 1..points.len().min(i + MAX_SHORTCUT + 1) {
+                match self.edge_time(points[j - 1], points[j], atlas) {
+                    Ok(t) => { chain += t; },
+                    Err(_) => { break; },
+                }
+
+                for (k, g) in self.gates.iter().enumerate() {
+                    if g.is_crossing(&points[j - 1], &points[j]) {
+                        chain_gates[k] = true;
+                    }
                 }
-                if let Some(time2) = Segment::new(c2, c1).time(atlas) {
-                    self.edges.push((cn2, cn1, time2));
+
+                let gates_kept = self.gates.iter().zip(&chain_gates)
+                    .all(|(g, crossed)| !crossed
+                         || g.is_crossing(&points[i], &points[j]));
+
+                if j > i + 1 && gates_kept {
+                    if let Ok(t) = self.edge_time(points[i], points[j],
+                                                  atlas) {
+                        if t <= chain {
+                            next = j;
+                        }
+                    }
                 }
             }
+
+            pruned.push(points[next]);
+            i = next;
         }
+
+        pruned
     }
 
     // Dijkstra's algorithm for finding the shortest path from first to
-    // last node.
+    // last node. If there are gates, the graph is copied into one layer for
+    // each gate crossed so far. Edges crossing the next gate lead to the
+    // next layer, and the path must end in the last layer.
     pub fn shortest_path(&mut self) -> Option<Path> {
+        let nv = self.num_nodes();
+        let layers = self.gates.len() + 1;
+
         // Build graph of lists of nodes and adjacent nodes.
         let start = 0;
-        let end = self.v - 1;
+        let end = self.v - 1 + (layers - 1)*nv;
         let mut times: Vec<f32> = vec!();
-        let mut adj: Vec<[(usize, f32); 10]> = vec!();
-        let mut adj_count: Vec<usize> = vec!();
+        let mut adj: Vec<Vec<(usize, f32)>> = vec!();
         let mut prev: Vec<Option<usize>> = vec!();
         let mut visited: Vec<bool> = vec!();
 
-        let nn = self.num_nodes();
+        let nn = nv*layers;
 
         for _ in 0..nn {
             times.push(f32::INFINITY);
-            adj.push([(0, f32::INFINITY); 10]);
-            adj_count.push(0);
+            adj.push(vec!());
             prev.push(None);
             visited.push(false);
         }
 
         // Populate adjacency list.
         for (n1, n2, t) in &self.edges {
-            adj[*n1][adj_count[*n1]] = (*n2, *t);
-            adj_count[*n1] += 1;
+            for l in 0..layers {
+                let (c1, c2) = (&self.nodes[*n1], &self.nodes[*n2]);
+                let next = if l < layers - 1
+                    && self.gates[l].is_crossing(c1, c2) { l + 1 } else { l };
+
+                adj[*n1 + l*nv].push((*n2 + next*nv, *t));
+            }
         }
 
+        // Lower bound of the time left to the end node, if landmarks are
+        // used. Otherwise the search is plain Dijkstra.
+        let landmarks = self.landmarks.clone();
+        let bound = |n: usize| match &landmarks {
+            Some(l) => l.bound(n % nv),
+            None => 0.0,
+        };
+
         // We may change this to a priority queue with better performance.
         let mut queue = HashMap::new();
         queue.insert(start, 1);
@@ -175,15 +653,17 @@ impl Graph {
             // Find minimum node in queue
             let mut t_min = f32::INFINITY;
             let mut n_min = 0;
+            let mut f_min = f32::INFINITY;
             for i in queue.keys() {
-                let t = times[*i];
-                if t < t_min {
-                    t_min = t;
+                let f = times[*i] + bound(*i);
+                if f < f_min {
+                    f_min = f;
+                    t_min = times[*i];
                     n_min = *i;
                 }
             }
 
-            if t_min == f32::INFINITY {
+            if f_min == f32::INFINITY {
                 break;
             }
 
@@ -193,8 +673,7 @@ impl Graph {
             }
 
             // Look at each neighbour to the minimum node
-            for ac in 0..adj_count[n_min] {
-                let (n_adj, t_edge) = adj[n_min][ac];
+            for &(n_adj, t_edge) in &adj[n_min] {
                 if !visited[n_adj] {
                     queue.insert(n_adj, 1);
                 }
@@ -206,8 +685,18 @@ impl Graph {
             }
 
             visited[n_min] = true;
+            if let Some(explored) = self.explored.as_mut() {
+                explored.push(self.nodes[n_min % nv]);
+            }
         }
 
+        // Nodes may be reached in several layers. Keep the earliest.
+        self.reach_times = (0..nv)
+            .map(|n| (0..layers).map(|l| times[n + l*nv])
+                 .fold(f32::INFINITY, f32::min))
+            .collect();
+
+        self.end_time = times[end];
         if times[end] == f32::INFINITY {
             return None;
         }
@@ -215,7 +704,7 @@ impl Graph {
         let mut p = end;
         let mut reverse = vec!();
         loop {
-            reverse.push(self.nodes[p]);
+            reverse.push(self.nodes[p % nv]);
             if let Some(prev) = prev[p] {
                 p = prev;
             }
@@ -224,6 +713,13 @@ impl Graph {
             }
         }
 
+        // Leave out the end point if the path ends within its radius. The
+        // last edge is then a free edge to the end point.
+        let r = self.end_radius;
+        if r > 0.0 && reverse.len() > 2 && (reverse[1] - self.b).abs() <= r {
+            reverse.remove(0);
+        }
+
         let mut p = Path::new();
         while let Some(c) = reverse.pop() {
             p.push(c);
@@ -232,16 +728,119 @@ impl Graph {
         return Some(p);
     }
 
+    // Guide the searches by lower bounds from a number of landmarks. The
+    // landmarks are computed for the area around the first pass graph, and
+    // reused by later searches in the same area. 0 turns them off.
+    pub fn use_landmarks(&mut self, count: usize,
+                         atlas: &dyn ElevationSource) {
+        self.landmarks = None;
+
+        if count == 0 || self.num_nodes() == 0 || self.lattice_o.is_none() {
+            return;
+        }
+
+        let lattice = self.lattice();
+        let nodes: Vec<(i64, i64)> = self.nodes.iter()
+            .filter_map(|c| lattice.node_at(c))
+            .collect();
+
+        let Some(landmarks) = Landmarks::for_nodes(
+            &lattice, &nodes, count, atlas, &self.cost,
+            self.max_memory_mb) else { return; };
+
+        // The end node is reached from the nodes around it
+        let end = self.v - 1;
+        let into_end: Vec<usize> = self.edges.iter()
+            .filter(|(_, n2, _)| *n2 == end)
+            .map(|(n1, _, _)| *n1)
+            .collect();
+
+        // Edges near the path avoided may be faster than on the lattice
+        let scale = if self.avoid.is_some() {
+            self.avoid_factor.min(1.0)
+        }
+        else {
+            1.0
+        };
+
+        self.landmarks = LandmarkBounds::new(landmarks, &self.nodes,
+                                             &into_end, scale)
+            .map(Arc::new);
+    }
+
+    // Time from the start to each node by the last search. Nodes which
+    // couldn't be reached have infinite time.
+    pub fn reach_times(&self) -> &[f32] {
+        &self.reach_times
+    }
+
+    // Time from the start to the end node by the last search. Infinite if
+    // it wasn't reached.
+    pub fn end_time(&self) -> f32 {
+        self.end_time
+    }
+
+    // Nodes reached from the start by the last search
+    pub fn reached_nodes(&self) -> Vec<Coord> {
+        self.nodes.iter().zip(&self.reach_times)
+            .filter(|(_, t)| t.is_finite())
+            .map(|(c, _)| *c)
+            .collect()
+    }
+
+    // Why the area reached by the last search couldn't be left: the reasons
+    // the edges leading out of it are blocked, with the number of edges of
+    // each reason, most frequent first.
+    pub fn diagnose(&self) -> Vec<(Blocked, usize)> {
+        let reached = |n: usize| self.reach_times.get(n)
+            .is_some_and(|t| t.is_finite());
+        let mut counts: HashMap<Blocked, usize> = HashMap::new();
+
+        for (n1, n2, b) in &self.blocked {
+            if reached(*n1) && !reached(*n2) {
+                *counts.entry(*b).or_insert(0) += 1;
+            }
+        }
+
+        let mut reasons: Vec<(Blocked, usize)> = counts.into_iter().collect();
+        reasons.sort_by(|a, b| b.1.cmp(&a.1));
+
+        reasons
+    }
+
     fn grid_units_for_node(&self, c: &Coord, gs: f32, g: usize)
                            -> (usize, usize) {
-        let x = ((c.e - self.o.e)/gs + ((g - 1)/2) as f32) as usize;
-        let y = ((c.n - self.o.n)/gs + ((g - 1)/2) as f32) as usize;
+        if let Some((ox, oy)) = self.lattice_o {
+            let half = ((g - 1)/2) as i64;
+            let lattice = self.lattice();
+            let (u, v) = lattice.units(c);
+            let y = v.floor() as i64;
+            let x = (u - lattice.shift(y)).floor() as i64;
+
+            return ((x - ox + half) as usize, (y - oy + half) as usize);
+        }
+
+        let half = ((g - 1)/2) as f32;
+        let (cos, sin) = self.orientation;
+        let (e, n) = (c.e - self.o.e, c.n - self.o.n);
+        // Rotate back to grid orientation
+        let (de, dn) = (e*cos + n*sin, -e*sin + n*cos);
+
+        if self.hex {
+            let y = (dn/(gs*HEX_ROW) + half) as usize;
+            let x = (de/gs + half - 0.5*((y % 2) as f32)) as usize;
+
+            return (x, y);
+        }
+
+        let x = (de/gs + half) as usize;
+        let y = (dn/gs + half) as usize;
 
         return (x, y);
     }
 
     fn connect_end_node(&mut self, c: Option<(Coord, usize)>, gs: f32,
-                        g: usize, atlas: &Atlas) {
+                        g: usize, atlas: &dyn ElevationSource) {
         if let Some((c1, _)) = c {
             let (x, y) = self.grid_units_for_node(&c1, gs, g);
             let s1 = self.insert_node_from_grid_units(gs, g, x, y, false);
@@ -256,7 +855,27 @@ impl Graph {
         }
     }
 
-    pub fn add_pass2_node(&mut self, x: usize, y: usize, atlas: &Atlas) {
+    // Connect all nodes within the end radius to the end node, with no cost
+    fn connect_end_radius(&mut self, b: Option<(Coord, usize)>) {
+        let r = self.end_radius;
+        let Some((cb, nb)) = b else { return; };
+
+        if r <= 0.0 {
+            return;
+        }
+
+        let near: Vec<usize> = self.nodes.iter().enumerate()
+            .filter(|(n, c)| *n != nb && (**c - cb).abs_sq() <= r*r)
+            .map(|(n, _)| n)
+            .collect();
+
+        for n in near {
+            self.edges.push((n, nb, 0.0));
+        }
+    }
+
+    pub fn add_pass2_node(&mut self, x: usize, y: usize,
+                          atlas: &dyn ElevationSource) {
         // Return if point has already been added
         if self.node_exists(x, y) {
             return;
@@ -285,15 +904,62 @@ impl Graph {
             self.gs_pass1, self.g_pass1, x, y, true);
     }
 
+    // Connect a node to the neighbours one knight move away in the rows
+    // above. Together with the 8 nearest neighbours, this gives each node 16
+    // neighbours with finer angular resolution.
+    fn connect_knight_moves(&mut self, x: usize, y: usize,
+                            atlas: &dyn ElevationSource) {
+        let c1 = self.add_pass1_node(x, y);
+
+        for (xn, yn) in [(Some(x + 2), y + 1), (Some(x + 1), y + 2),
+                         (x.checked_sub(1), y + 2),
+                         (x.checked_sub(2), y + 1)] {
+            if let Some(xn) = xn {
+                let cn = self.add_pass1_node(xn, yn);
+                self.connect(c1, cn, atlas);
+            }
+        }
+    }
+
+    // Connect a node of a hexagonal grid to its neighbours to the east and in
+    // the row above.
+    fn connect_hex_node(&mut self, x: usize, y: usize,
+                        atlas: &dyn ElevationSource) {
+        let c1 = self.add_pass1_node(x, y);
+        let c2 = self.add_pass1_node(x + 1, y);
+        self.connect(c1, c2, atlas);
+
+        // Odd rows are shifted to the east
+        let (xl, xr) = if y % 2 == 0 {
+            (x.checked_sub(1), Some(x))
+        }
+        else {
+            (Some(x), Some(x + 1))
+        };
+
+        for xn in [xl, xr].into_iter().flatten() {
+            let cn = self.add_pass1_node(xn, y + 1);
+            self.connect(c1, cn, atlas);
+        }
+    }
+
     // Build finely grained a graph for the area around a given path. The area
-    // is determined by dragging a square along the path.
-    pub fn build_graph_from_path(&mut self, path: &Path, atlas: &Atlas) {
+    // is determined by dragging a square along the path. The square extends
+    // path_width_pass2 to each side of the path.
+    pub fn build_graph_from_path(&mut self, path: &Path,
+                                 atlas: &dyn ElevationSource) {
         // Finely grained grid size
         let gs = self.gs_pass2;
         // Number of grid points within area diameter
         let g = self.g_pass2;
         // Square size in grid units
-        let ss = (self.gs_pass2/gs) as usize;
+        let ss = max(((2.0*self.path_width_pass2)/gs) as usize, 1);
+
+        // The refinement grid is always square and north aligned, and
+        // centered at the center of the area
+        self.hex = false;
+        self.orientation = (1.0, 0.0);
+        self.lattice_o = None;
 
         // Create start node
         let a = Some(self.insert_node_from_coord(self.a));
@@ -346,19 +1012,29 @@ impl Graph {
         // Create end node and connect it to graph
         let b = Some(self.insert_node_from_coord(self.b));
         self.connect_end_node(b, gs, g, atlas);
+        self.connect_end_radius(b);
     }
 
     // Build a coarsely grained graph from the area defined by an ellipse
     // overlapping the start and end points.
-    pub fn build_graph_from_end_points(&mut self, atlas: &Atlas) {
+    pub fn build_graph_from_end_points(&mut self,
+                                       atlas: &dyn ElevationSource) {
         let g = self.g_pass1;
 
+        // Leave out the edges into nodes too steep to walk
+        self.prune_steep = true;
+
         // Create start node
         let a = Some(self.insert_node_from_coord(self.a));
 
         // Create intermediate candidate nodes
         for x in 0..g {
             for y in 0..g {
+                if self.hex {
+                    self.connect_hex_node(x, y, atlas);
+                    continue;
+                }
+
                 let c1 = self.add_pass1_node(x, y);
                 let c2 = self.add_pass1_node(x + 1, y);
                 let c3 = self.add_pass1_node(x, y + 1);
@@ -368,6 +1044,10 @@ impl Graph {
                 self.connect(c1, c3, atlas);
                 self.connect(c1, c4, atlas);
                 self.connect(c2, c3, atlas);
+
+                if self.knight_moves {
+                    self.connect_knight_moves(x, y, atlas);
+                }
             }
         }
 
@@ -377,5 +1057,6 @@ impl Graph {
         // Create end node and connect it to graph
         let b = Some(self.insert_node_from_coord(self.b));
         self.connect_end_node(b, self.gs_pass1, g, atlas);
+        self.connect_end_radius(b);
     }
 }
@@ -0,0 +1,121 @@
+use crate::cost::Cost;
+use crate::elevation::ElevationSource;
+use crate::params::Params;
+use crate::path::Path;
+
+use hoydedata::{Atlas, Coord};
+use std::fs;
+
+#[derive(Debug, PartialEq)]
+pub enum FileKind {
+    Params,
+    Gpx,
+}
+
+// Detect the kind of a file by its extension, or by its content if the
+// extension is unknown
+pub fn detect_file_kind(fname: &str) -> Result<FileKind, String> {
+    let lower = fname.to_lowercase();
+
+    if lower.ends_with(".json") {
+        return Ok(FileKind::Params);
+    }
+    else if lower.ends_with(".gpx") {
+        return Ok(FileKind::Gpx);
+    }
+
+    let data = fs::read_to_string(fname)
+        .map_err(|e| format!("Unable to read {}: {}", fname, e))?;
+    let data = data.trim_start();
+
+    if data.starts_with('{') {
+        Ok(FileKind::Params)
+    }
+    else if data.starts_with("<?xml") || data.starts_with("<gpx") {
+        Ok(FileKind::Gpx)
+    }
+    else {
+        Err(format!("{} is neither a params file nor a GPX track", fname))
+    }
+}
+
+// Open the atlas if the maps cover all the points
+pub fn atlas_covering(points: &[Coord]) -> Option<Atlas> {
+    let atlas = Atlas::new(1.0, None).ok()?;
+
+    if points.iter().all(|c| ElevationSource::lookup(&atlas, c).is_some()) {
+        Some(atlas)
+    }
+    else {
+        None
+    }
+}
+
+fn print_params_info(fname: &str) -> Result<(), String> {
+    let params = Params::from_file(fname)?;
+
+    println!("Params file {}", fname);
+    println!("Waypoints: {}", params.points.len());
+    println!("Barriers: {}", params.barriers.len());
+    println!("Gates: {}", params.gates.len());
+    println!("Fords: {}", params.fords.len());
+
+    if params.corridor.len() > 0 {
+        println!("Corridor: {} points", params.corridor.len());
+    }
+
+    if params.points.len() >= 2 {
+        let distance: f32 = params.points.windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .sum();
+        println!("Straight line distance: {:.0}m", distance);
+    }
+
+    println!("Passes: {}", params.passes().iter()
+             .map(|(gs, w)| format!("{}:{}", gs, w))
+             .collect::<Vec<String>>()
+             .join(","));
+    println!("Cost model: {}", &params.cost_model);
+
+    if params.start_time != "" {
+        println!("Start time: {}", &params.start_time);
+    }
+
+    Ok(())
+}
+
+fn print_gpx_info(fname: &str) -> Result<(), String> {
+    let path = Path::read_gpx(fname)?;
+    let points: Vec<Coord> = path.into_iter().cloned().collect();
+
+    if points.is_empty() {
+        return Err(format!("{} has no track points", fname));
+    }
+
+    println!("GPX track {}", fname);
+    println!("Points: {}", points.len());
+
+    // Time, elevation and energy need the maps
+    match atlas_covering(&points) {
+        Some(atlas) => {
+            let cost = Cost::from_params(&Params::from_config());
+            println!("Path: {}", path);
+            path.stats(&[], &atlas, &cost).print_summary();
+        },
+        None => {
+            println!("Path: {}", path);
+            println!("Length: {}m", path.len());
+            println!("No maps covering the track, time is not estimated");
+        },
+    }
+
+    Ok(())
+}
+
+// Print key statistics of a params file or a GPX track
+pub fn print_file_info(fname: &str) -> Result<(), String> {
+    match detect_file_kind(fname)? {
+        FileKind::Params => print_params_info(fname),
+        FileKind::Gpx => print_gpx_info(fname),
+    }
+}
@@ -0,0 +1,409 @@
+use crate::cost::Cost;
+use crate::elevation::ElevationSource;
+use crate::graph::HEX_ROW;
+use crate::path::Segment;
+
+use hoydedata::Coord;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use rayon::prelude::*;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+
+// Side (meters) of the cells making up the areas landmarks are computed for
+const AREA_CELL: f64 = 10000.0;
+// Number of areas whose landmarks are kept for later searches
+const MAX_CACHED: usize = 8;
+// Share of max_memory_mb given to the landmarks kept. The first pass graph
+// gets the rest.
+pub const MEMORY_SHARE: f32 = 0.25;
+// Number of edges checked against the cost model and the elevation data
+// before landmarks are reused
+const PROBES: usize = 16;
+
+lazy_static! {
+    // Landmarks of the areas searched, least recently used first
+    static ref CACHE: Mutex<Vec<Arc<Landmarks>>> = Mutex::new(vec![]);
+}
+
+// Grid of first pass graphs, fixed in the plane, so that the graphs of
+// nearby searches share nodes. Node (x, y) is x grid sizes east and y rows
+// north of the origin, in the orientation of the grid. Odd rows of a
+// hexagonal grid are shifted half a grid size east.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lattice {
+    pub gs: f32,
+    pub hex: bool,
+    pub knight_moves: bool,
+    // Cosine and sine of the grid orientation
+    pub orientation: (f32, f32),
+}
+
+impl Lattice {
+    // Row spacing (meters)
+    fn row(&self) -> f64 {
+        if self.hex { (self.gs*HEX_ROW) as f64 } else { self.gs as f64 }
+    }
+
+    // Coordinate x grid sizes and y rows from the origin
+    pub fn point(&self, x: f64, y: f64) -> Coord {
+        let (cos, sin) = (self.orientation.0 as f64,
+                          self.orientation.1 as f64);
+        let (de, dn) = (x*self.gs as f64, y*self.row());
+
+        Coord::new((de*cos - dn*sin) as f32, (de*sin + dn*cos) as f32)
+    }
+
+    // Grid sizes and rows from the origin to c
+    pub fn units(&self, c: &Coord) -> (f64, f64) {
+        let (cos, sin) = (self.orientation.0 as f64,
+                          self.orientation.1 as f64);
+        let (e, n) = (c.e as f64, c.n as f64);
+
+        ((e*cos + n*sin)/self.gs as f64, (-e*sin + n*cos)/self.row())
+    }
+
+    // Shift of the nodes of row y, in grid sizes
+    pub fn shift(&self, y: i64) -> f64 {
+        if self.hex { 0.5*(y.rem_euclid(2) as f64) } else { 0.0 }
+    }
+
+    // Coordinate of node (x, y)
+    pub fn coord(&self, x: i64, y: i64) -> Coord {
+        self.point(x as f64 + self.shift(y), y as f64)
+    }
+
+    // The node at c, if any
+    pub fn node_at(&self, c: &Coord) -> Option<(i64, i64)> {
+        let (u, v) = self.units(c);
+        let y = v.round() as i64;
+        let x = (u - self.shift(y)).round() as i64;
+
+        (*c == self.coord(x, y)).then_some((x, y))
+    }
+
+    // Neighbours of node (x, y) to the east and in the rows above. Each
+    // edge of a graph on the lattice is between a node and one of these.
+    fn neighbours(&self, x: i64, y: i64) -> Vec<(i64, i64)> {
+        if self.hex {
+            // Odd rows are shifted to the east
+            let xl = if y.rem_euclid(2) == 0 { x - 1 } else { x };
+            return vec![(x + 1, y), (xl, y + 1), (xl + 1, y + 1)];
+        }
+
+        let mut n = vec![(x + 1, y), (x, y + 1), (x + 1, y + 1),
+                         (x - 1, y + 1)];
+        if self.knight_moves {
+            n.extend([(x + 2, y + 1), (x + 1, y + 2), (x - 1, y + 2),
+                      (x - 2, y + 1)]);
+        }
+
+        n
+    }
+}
+
+// Lower bounds of the time between nodes of a lattice (ALT). The time from
+// each of a few landmark nodes to every node of an area, and back, is
+// computed once over all the edges of the lattice in the area. By the
+// triangle inequality, the time from v to t is at least d(v, L) - d(t, L)
+// and d(L, t) - d(L, v) for every landmark L. A graph within the area has a
+// subset of the edges, which take at least as long, so the bounds hold for
+// the graph as well. They guide the search towards the end node, so that
+// fewer nodes are explored.
+pub struct Landmarks {
+    lattice: Lattice,
+    count: usize,
+    // South west node of the area, and the number of columns and rows
+    x0: i64,
+    y0: i64,
+    w: usize,
+    h: usize,
+    // Edges and their times when computed, for telling if the cost model or
+    // the elevation data has changed
+    probes: Vec<(Coord, Coord, Option<f32>)>,
+    from: Vec<Vec<f32>>,
+    to: Vec<Vec<f32>>,
+}
+
+impl Landmarks {
+    // Landmarks of the area around a graph with nodes on the lattice. The
+    // area is made of whole cells, so that nearby searches get the same
+    // area, and the landmarks are reused if they have been computed for it
+    // before. None if they would need more than their share of the memory
+    // budget (max_memory_mb, 0 is no limit).
+    pub fn for_nodes(lattice: &Lattice, nodes: &[(i64, i64)], count: usize,
+                     atlas: &dyn ElevationSource, cost: &Cost,
+                     max_memory_mb: f32) -> Option<Arc<Landmarks>> {
+        let (gs, row) = (lattice.gs as f64, lattice.row());
+        let cell = |x: i64, unit: f64| (x as f64*unit/AREA_CELL).floor();
+        let (cx0, cx1) = nodes.iter()
+            .map(|(x, _)| cell(*x, gs))
+            .fold((f64::INFINITY, f64::NEG_INFINITY),
+                  |(a, b), c| (a.min(c), b.max(c)));
+        let (cy0, cy1) = nodes.iter()
+            .map(|(_, y)| cell(*y, row))
+            .fold((f64::INFINITY, f64::NEG_INFINITY),
+                  |(a, b), c| (a.min(c), b.max(c)));
+
+        if cx0 > cx1 {
+            return None;
+        }
+
+        let x0 = (cx0*AREA_CELL/gs).floor() as i64;
+        let x1 = ((cx1 + 1.0)*AREA_CELL/gs).ceil() as i64;
+        let y0 = (cy0*AREA_CELL/row).floor() as i64;
+        let y1 = ((cy1 + 1.0)*AREA_CELL/row).ceil() as i64;
+
+        let mut landmarks = Self {
+            lattice: *lattice,
+            count: count,
+            x0: x0,
+            y0: y0,
+            w: (x1 - x0 + 1) as usize,
+            h: (y1 - y0 + 1) as usize,
+            probes: vec![],
+            from: vec![],
+            to: vec![],
+        };
+        landmarks.probe(atlas, cost);
+
+        let mut cache = CACHE.lock();
+
+        if let Some(i) = cache.iter().position(|l| l.is_same(&landmarks)) {
+            let l = cache.remove(i);
+            cache.push(l.clone());
+            return Some(l);
+        }
+
+        let mb = landmarks.memory_mb(count);
+        let limit = if max_memory_mb > 0.0 {
+            max_memory_mb*MEMORY_SHARE
+        }
+        else {
+            f32::INFINITY
+        };
+
+        if mb > limit {
+            println!("Landmarks would need {:.0} MB, more than their share \
+                      of max_memory_mb. Searching without landmarks.", mb);
+            return None;
+        }
+
+        // Make room for the new landmarks
+        while cache.len() >= MAX_CACHED || cache.iter()
+            .map(|l| l.memory_mb(l.count))
+            .sum::<f32>() + mb > limit {
+            cache.remove(0);
+        }
+        drop(cache);
+
+        println!("Computing {} landmarks for {:.0} km²...", count,
+                 (landmarks.w*landmarks.h) as f64*gs*row/1.0e6);
+        let start = nodes.iter().find_map(|(x, y)| landmarks.index(*x, *y))
+            .unwrap_or(0);
+        landmarks.compute(start, atlas, cost);
+
+        let l = Arc::new(landmarks);
+        CACHE.lock().push(l.clone());
+
+        Some(l)
+    }
+
+    // Memory (MB) used by the given number of landmarks
+    fn memory_mb(&self, count: usize) -> f32 {
+        (2*count*self.w*self.h*std::mem::size_of::<f32>()) as f32
+            /(1024.0*1024.0)
+    }
+
+    // Whether the landmarks are for the same lattice, area, cost model and
+    // elevation data
+    fn is_same(&self, other: &Landmarks) -> bool {
+        self.lattice == other.lattice && self.count == other.count
+            && (self.x0, self.y0, self.w, self.h)
+            == (other.x0, other.y0, other.w, other.h)
+            && self.probes == other.probes
+    }
+
+    // Index of node (x, y) in the area, if within it
+    fn index(&self, x: i64, y: i64) -> Option<usize> {
+        let (dx, dy) = (x - self.x0, y - self.y0);
+
+        (dx >= 0 && dy >= 0 && (dx as usize) < self.w
+         && (dy as usize) < self.h)
+            .then(|| (dy as usize)*self.w + dx as usize)
+    }
+
+    // Node of an index in the area
+    fn node(&self, i: usize) -> (i64, i64) {
+        (self.x0 + (i % self.w) as i64, self.y0 + (i/self.w) as i64)
+    }
+
+    // Record the times of edges spread over the area
+    fn probe(&mut self, atlas: &dyn ElevationSource, cost: &Cost) {
+        let n = self.w*self.h;
+
+        self.probes = (0..PROBES).map(|i| {
+            let (x, y) = self.node(i*n/PROBES);
+            let (xn, yn) = self.lattice.neighbours(x, y)[0];
+            let (c1, c2) = (self.lattice.coord(x, y),
+                            self.lattice.coord(xn, yn));
+            (c1, c2, Segment::new(c1, c2).time(atlas, cost))
+        }).collect();
+    }
+
+    // Compute the times to and from landmarks over the edges of the area
+    fn compute(&mut self, start: usize, atlas: &dyn ElevationSource,
+               cost: &Cost) {
+        let nv = self.w*self.h;
+        let lattice = self.lattice;
+
+        let edges: Vec<(usize, usize, f32)> = (0..nv)
+            .into_par_iter()
+            .flat_map(|i| {
+                let (x, y) = self.node(i);
+                let c1 = lattice.coord(x, y);
+                let mut edges = vec![];
+
+                for (xn, yn) in lattice.neighbours(x, y) {
+                    let Some(j) = self.index(xn, yn) else { continue; };
+                    let c2 = lattice.coord(xn, yn);
+
+                    if let Some(t) = Segment::new(c1, c2).time(atlas, cost) {
+                        edges.push((i, j, t));
+                    }
+                    if let Some(t) = Segment::new(c2, c1).time(atlas, cost) {
+                        edges.push((j, i, t));
+                    }
+                }
+
+                edges
+            })
+            .collect();
+
+        let mut forward = vec![vec![]; nv];
+        let mut backward = vec![vec![]; nv];
+
+        for (n1, n2, t) in edges {
+            forward[n1].push((n2, t));
+            backward[n2].push((n1, t));
+        }
+
+        // Pick landmarks far apart: each new landmark is the node farthest
+        // from the ones already picked, starting from a node of the graph.
+        let mut nearest = Landmarks::times(&forward, start);
+
+        for _ in 0..self.count {
+            let Some(l) = nearest.iter().enumerate()
+                .filter(|(_, t)| t.is_finite())
+                .max_by(|(_, t1), (_, t2)| t1.total_cmp(t2))
+                .map(|(n, _)| n) else { break; };
+
+            let from = Landmarks::times(&forward, l);
+
+            for (d, t) in nearest.iter_mut().zip(&from) {
+                *d = d.min(*t);
+            }
+
+            self.to.push(Landmarks::times(&backward, l));
+            self.from.push(from);
+        }
+    }
+
+    // Time from a node to every other node over the adjacency lists
+    fn times(adj: &[Vec<(usize, f32)>], start: usize) -> Vec<f32> {
+        let mut times = vec![f32::INFINITY; adj.len()];
+        // Times are never negative, so their bits sort like the times
+        let mut queue = BinaryHeap::new();
+
+        times[start] = 0.0;
+        queue.push(Reverse((0u32, start)));
+
+        while let Some(Reverse((bits, n))) = queue.pop() {
+            let t = f32::from_bits(bits);
+
+            if t > times[n] {
+                continue;
+            }
+
+            for &(n_adj, t_edge) in &adj[n] {
+                let t_new = t + t_edge;
+                if t_new < times[n_adj] {
+                    times[n_adj] = t_new;
+                    queue.push(Reverse((t_new.to_bits(), n_adj)));
+                }
+            }
+        }
+
+        times
+    }
+}
+
+// Lower bounds from the landmarks of the time from each node of a graph to
+// its end node
+pub struct LandmarkBounds {
+    landmarks: Arc<Landmarks>,
+    // Index in the area of each node, if on the lattice
+    nodes: Vec<Option<usize>>,
+    // For each landmark, the longest time to it and the shortest time from
+    // it among the nodes with edges into the end node
+    end: Vec<(f32, f32)>,
+    // Factor on the bounds, below 1 if edges of the graph may be faster
+    // than those of the lattice
+    scale: f32,
+}
+
+impl LandmarkBounds {
+    // Bounds for a graph with the given nodes. The end node is reached by
+    // edges from the nodes of the end set, which must be on the lattice.
+    pub fn new(landmarks: Arc<Landmarks>, nodes: &[Coord], end: &[usize],
+               scale: f32) -> Option<Self> {
+        let nodes: Vec<Option<usize>> = nodes.iter()
+            .map(|c| landmarks.lattice.node_at(c)
+                 .and_then(|(x, y)| landmarks.index(x, y)))
+            .collect();
+        let end: Vec<usize> = end.iter()
+            .map(|n| nodes[*n])
+            .collect::<Option<Vec<usize>>>()?;
+
+        if end.is_empty() {
+            return None;
+        }
+
+        let end = landmarks.from.iter().zip(&landmarks.to)
+            .map(|(from, to)| {
+                (end.iter().map(|n| to[*n]).fold(0.0, f32::max),
+                 end.iter().map(|n| from[*n]).fold(f32::INFINITY, f32::min))
+            })
+            .collect();
+
+        Some(Self {
+            landmarks: landmarks,
+            nodes: nodes,
+            end: end,
+            scale: scale,
+        })
+    }
+
+    // Lower bound of the time from node v to the end node
+    pub fn bound(&self, v: usize) -> f32 {
+        let Some(i) = self.nodes[v] else { return 0.0; };
+        let mut bound: f32 = 0.0;
+
+        for ((from, to), (to_end, from_end)) in self.landmarks.from.iter()
+            .zip(&self.landmarks.to)
+            .zip(&self.end) {
+            let b1 = to[i] - to_end;
+            let b2 = from_end - from[i];
+
+            if b1.is_finite() {
+                bound = bound.max(b1);
+            }
+            if b2.is_finite() {
+                bound = bound.max(b2);
+            }
+        }
+
+        bound*self.scale
+    }
+}
@@ -1,16 +1,75 @@
+// Output of the crate goes through the output module, which can capture
+// the output of a command on the thread running it
+macro_rules! println {
+    () => {
+        $crate::output::write(format_args!(""), true)
+    };
+    ($($arg:tt)*) => {
+        $crate::output::write(format_args!($($arg)*), true)
+    };
+}
+
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::output::write(format_args!($($arg)*), false)
+    };
+}
+
 mod app;
 mod barrier;
+mod camp;
 mod channel;
 mod canvas;
 mod config;
+mod console;
+mod control;
+mod cost;
+mod cost_plot;
+mod crs;
+mod debug;
+mod device;
+mod download;
+mod elevation;
+mod escape;
+mod export;
 mod field;
+mod fieldcache;
+mod geotiff;
+mod gps;
 mod graph;
+mod info;
+mod landmark;
+mod magnetic;
+mod mapdirs;
+mod mapsheet;
+mod output;
 mod params;
 mod path;
+mod pick;
+mod poi;
+mod progress;
+mod report;
+mod routecard;
+mod schedule;
+mod script;
+mod selftest;
+mod starts;
+mod stats;
+mod symbology;
+mod terrain;
+mod upload;
 mod egui_map;
 
 pub use crate::app::{App, run_cmdui};
 pub use crate::canvas::init_with_canvas;
 pub use crate::config::CONFIG;
+pub use crate::cost::{Cost, CostModel, register_cost_model};
+pub use crate::crs::set_crs;
+pub use crate::elevation::ElevationSource;
+pub use crate::export::{export_file, format_from_fname};
+pub use crate::info::print_file_info;
 pub use crate::params::Params;
 pub use crate::path::Path;
+pub use crate::selftest::run_selftest;
+pub use crate::stats::PathStats;
+pub use crate::terrain::{SyntheticTerrain, TERRAINS};
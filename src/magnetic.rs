@@ -0,0 +1,214 @@
+use crate::config::CONFIG;
+
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::fs;
+use std::sync::Arc;
+
+// WGS84 ellipsoid (km), and the reference radius of the geomagnetic model
+const WGS84_A: f64 = 6378.137;
+const WGS84_F: f64 = 1.0/298.257223563;
+const EARTH_RADIUS: f64 = 6371.2;
+
+lazy_static! {
+    // Model read from the wmm setting, read when first needed
+    static ref MODEL: Mutex<Option<Arc<MagneticModel>>> = Mutex::new(None);
+}
+
+// World Magnetic Model, read from the coefficient file (WMM.COF) published
+// by NOAA. Gauss coefficients (nT) at the epoch, and their yearly change.
+pub struct MagneticModel {
+    name: String,
+    epoch: f64,
+    degree: usize,
+    // Indexed by [n][m]
+    g: Vec<Vec<f64>>,
+    h: Vec<Vec<f64>>,
+    dg: Vec<Vec<f64>>,
+    dh: Vec<Vec<f64>>,
+}
+
+impl MagneticModel {
+    // Parse a coefficient file. The first line has the epoch and the model
+    // name, the following lines n, m, g, h, dg/dt and dh/dt. The file ends
+    // with a line of nines.
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut lines = content.lines();
+        let header: Vec<&str> = lines.next()
+            .ok_or("Empty coefficient file".to_string())?
+            .split_whitespace().collect();
+        let epoch = header.first()
+            .and_then(|e| e.parse::<f64>().ok())
+            .ok_or("No epoch in coefficient file".to_string())?;
+        let name = header.get(1).unwrap_or(&"WMM").to_string();
+
+        let mut terms = vec![];
+
+        for line in lines {
+            if line.trim_start().starts_with("9999") {
+                break;
+            }
+
+            let v: Vec<&str> = line.split_whitespace().collect();
+            if v.len() < 6 {
+                continue;
+            }
+
+            let err = || format!("Invalid coefficient line '{}'", line);
+            let n: usize = v[0].parse().map_err(|_| err())?;
+            let m: usize = v[1].parse().map_err(|_| err())?;
+            let mut c = [0.0; 4];
+            for (k, ck) in c.iter_mut().enumerate() {
+                *ck = v[k + 2].parse().map_err(|_| err())?;
+            }
+
+            if m > n || n == 0 {
+                return Err(err());
+            }
+
+            terms.push((n, m, c));
+        }
+
+        let degree = terms.iter().map(|t| t.0).max()
+            .ok_or("No coefficients in coefficient file".to_string())?;
+        let zeros = vec![vec![0.0; degree + 1]; degree + 1];
+        let (mut g, mut h) = (zeros.clone(), zeros.clone());
+        let (mut dg, mut dh) = (zeros.clone(), zeros);
+
+        for (n, m, c) in terms {
+            g[n][m] = c[0];
+            h[n][m] = c[1];
+            dg[n][m] = c[2];
+            dh[n][m] = c[3];
+        }
+
+        Ok(Self {
+            name: name,
+            epoch: epoch,
+            degree: degree,
+            g: g,
+            h: h,
+            dg: dg,
+            dh: dh,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Declination (degrees, east positive) at a geodetic latitude and
+    // longitude (degrees) and height (km) above the ellipsoid, at a decimal
+    // year
+    pub fn declination(&self, lat: f64, lon: f64, height: f64, year: f64)
+                       -> f64 {
+        let (x, y) = self.field(lat, lon, height, year);
+
+        y.atan2(x).to_degrees()
+    }
+
+    // North and east components of the field. The spherical harmonic
+    // synthesis follows the WMM technical report.
+    fn field(&self, lat: f64, lon: f64, height: f64, year: f64)
+             -> (f64, f64) {
+        let dt = year - self.epoch;
+        let (phi, lambda) = (lat.to_radians(), lon.to_radians());
+
+        // Geocentric latitude and radius
+        let e2 = WGS84_F*(2.0 - WGS84_F);
+        let rc = WGS84_A/(1.0 - e2*phi.sin().powi(2)).sqrt();
+        let p = (rc + height)*phi.cos();
+        let z = (rc*(1.0 - e2) + height)*phi.sin();
+        let r = (p*p + z*z).sqrt();
+        let phi_c = (z/r).asin();
+
+        // Schmidt semi-normalized associated Legendre functions of
+        // sin(phi_c), and their derivatives by phi_c
+        let n_max = self.degree;
+        let (s, c) = (phi_c.sin(), phi_c.cos());
+        let mut pnm = vec![vec![0.0; n_max + 1]; n_max + 1];
+        let mut dpnm = vec![vec![0.0; n_max + 1]; n_max + 1];
+        pnm[0][0] = 1.0;
+
+        for n in 1..=n_max {
+            for m in 0..=n {
+                if n == m {
+                    let k = if n == 1 { 1.0 }
+                        else { ((2*n - 1) as f64/(2*n) as f64).sqrt() };
+                    pnm[n][n] = k*c*pnm[n - 1][n - 1];
+                    dpnm[n][n] = k*(c*dpnm[n - 1][n - 1]
+                                    - s*pnm[n - 1][n - 1]);
+                }
+                else {
+                    let nf = n as f64;
+                    let mf = m as f64;
+                    let k1 = (2.0*nf - 1.0)/(nf*nf - mf*mf).sqrt();
+                    let k2 = if n >= 2 {
+                        (((nf - 1.0).powi(2) - mf*mf)/(nf*nf - mf*mf)).sqrt()
+                    }
+                    else {
+                        0.0
+                    };
+                    let (p2, dp2) = if n >= 2 {
+                        (pnm[n - 2][m], dpnm[n - 2][m])
+                    }
+                    else {
+                        (0.0, 0.0)
+                    };
+                    pnm[n][m] = k1*s*pnm[n - 1][m] - k2*p2;
+                    dpnm[n][m] = k1*(s*dpnm[n - 1][m] + c*pnm[n - 1][m])
+                        - k2*dp2;
+                }
+            }
+        }
+
+        // Field components in geocentric coordinates
+        let (mut x, mut y, mut zc) = (0.0, 0.0, 0.0);
+
+        for n in 1..=n_max {
+            let ratio = (EARTH_RADIUS/r).powi(n as i32 + 2);
+
+            for m in 0..=n {
+                let g = self.g[n][m] + dt*self.dg[n][m];
+                let h = self.h[n][m] + dt*self.dh[n][m];
+                let (sin_ml, cos_ml) = ((m as f64)*lambda).sin_cos();
+                let gh = g*cos_ml + h*sin_ml;
+
+                x -= ratio*gh*dpnm[n][m];
+                zc -= ratio*(n as f64 + 1.0)*gh*pnm[n][m];
+
+                if c.abs() > 1e-10 {
+                    y += ratio*(m as f64)*(g*sin_ml - h*cos_ml)*pnm[n][m]/c;
+                }
+            }
+        }
+
+        // Rotate to the geodetic frame. The east component is unchanged.
+        let psi = phi_c - phi;
+        let north = x*psi.cos() - zc*psi.sin();
+
+        (north, y)
+    }
+}
+
+// The model of the coefficient file given by the wmm setting
+pub fn magnetic_model() -> Result<Arc<MagneticModel>, String> {
+    if CONFIG.wmm == "" {
+        return Err("No magnetic model. Set wmm to the path of a WMM.COF \
+                    coefficient file.".to_string());
+    }
+
+    let mut model = MODEL.lock();
+
+    if let Some(m) = model.as_ref() {
+        return Ok(m.clone());
+    }
+
+    let content = fs::read_to_string(&CONFIG.wmm)
+        .map_err(|e| format!("Unable to read {}: {}", &CONFIG.wmm, e))?;
+    let m = Arc::new(MagneticModel::parse(&content)
+                     .map_err(|e| format!("{}: {}", &CONFIG.wmm, e))?);
+    model.replace(m.clone());
+
+    Ok(m)
+}
@@ -0,0 +1,130 @@
+use crate::elevation::ElevationSource;
+
+use crossbeam_channel::unbounded;
+use hoydedata::{set_map_dir, Atlas, Coord, MsgReceiver, MsgSender};
+use lazy_static::lazy_static;
+use parking_lot::{Mutex, RwLock, RwLockWriteGuard};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+// Number of atlas messages remembered for 'show maps'
+const MAX_MOUNTED: usize = 200;
+
+lazy_static! {
+    // The map directory set in hoydedata. The atlases read it whenever they
+    // mount a tile, so it is held for reading during each call to an atlas,
+    // and only changed when no atlas is in use.
+    static ref MAP_DIR: RwLock<String> = RwLock::new(String::new());
+}
+
+// Run f with the map directory of hoydedata set to dir. Calls for the same
+// directory run in parallel.
+fn with_map_dir<T>(dir: &str, f: impl FnOnce() -> T) -> T {
+    {
+        let current = MAP_DIR.read();
+        if *current == dir {
+            return f();
+        }
+    }
+
+    let mut current = MAP_DIR.write();
+    if *current != dir {
+        set_map_dir(dir);
+        *current = dir.to_string();
+    }
+    let _current = RwLockWriteGuard::downgrade(current);
+
+    f()
+}
+
+// Elevation data from a list of map directories, e.g. a local cache in
+// front of a slow network share. The hoydedata atlas reads one directory,
+// so there is an atlas for each of them, used with its directory set.
+// Lookups are tried in the order of the directories, so the first directory
+// having a tile is used.
+pub struct MapDirs {
+    atlases: Vec<(String, Atlas)>,
+    // The last messages of the atlases (tiles mounted etc.) by directory
+    // index
+    mounted: Arc<Mutex<VecDeque<(usize, String)>>>,
+}
+
+impl MapDirs {
+    // Open the atlas of each directory. Messages of the atlases are
+    // forwarded to mtx. Directories which can't be opened are skipped.
+    pub fn open(dirs: &[String], mtx: MsgSender) -> Result<Self, String> {
+        let mounted = Arc::new(Mutex::new(VecDeque::new()));
+        let mut atlases = vec![];
+
+        for dir in dirs {
+            let (dtx, drx): (MsgSender, MsgReceiver) = unbounded();
+            let (i, mtx, mounted) = (atlases.len(), mtx.clone(),
+                                     mounted.clone());
+
+            match with_map_dir(dir, || Atlas::new(1.0, Some(dtx))) {
+                Ok(atlas) => {
+                    atlases.push((dir.clone(), atlas));
+                },
+                Err(e) => {
+                    println!("Skipping map directory {}: {:?}", dir, e);
+                    continue;
+                },
+            }
+
+            std::thread::spawn(move || {
+                while let Ok(msg) = drx.recv() {
+                    let mut mounted = mounted.lock();
+                    if mounted.len() >= MAX_MOUNTED {
+                        mounted.pop_front();
+                    }
+                    mounted.push_back((i, msg.to_string()));
+                    drop(mounted);
+
+                    let _ = mtx.send(msg);
+                }
+            });
+        }
+
+        // The first directory is the default for atlases opened elsewhere
+        if let Some(dir) = dirs.first() {
+            with_map_dir(dir, || ());
+        }
+
+        if atlases.is_empty() {
+            return Err(format!("No usable map directory in {}",
+                               dirs.join(", ")));
+        }
+
+        Ok(Self {
+            atlases: atlases,
+            mounted: mounted,
+        })
+    }
+
+    // Print the directories in search order, with the last tiles mounted
+    // from each of them
+    pub fn print(&self) {
+        let mounted = self.mounted.lock();
+
+        for (i, (dir, _)) in self.atlases.iter().enumerate() {
+            println!("{}. {}", i + 1, dir);
+
+            for (_, msg) in mounted.iter().filter(|(j, _)| *j == i) {
+                println!("     {}", msg);
+            }
+        }
+    }
+}
+
+impl ElevationSource for MapDirs {
+    fn lookup(&self, c: &Coord) -> Option<f32> {
+        self.atlases.iter()
+            .find_map(|(dir, a)| with_map_dir(dir, || a.lookup(c).ok()))
+    }
+
+    fn lookup_with_gradient(&self, c: &Coord) -> Option<(f32, f32, f32)> {
+        self.atlases.iter()
+            .find_map(|(dir, a)| with_map_dir(
+                dir, || a.lookup_with_gradient(c).ok()))
+    }
+}
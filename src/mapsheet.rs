@@ -0,0 +1,531 @@
+use crate::config::CONFIG;
+use crate::crs::{from_latlon, grid_convergence, to_latlon};
+use crate::params::Params;
+use crate::path::Path;
+
+use hoydedata::Coord;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fmt::Write;
+use std::fs;
+use std::io::Read;
+
+// A4 portrait page and its margins (points)
+const PAGE_WIDTH: f32 = 595.28;
+const PAGE_HEIGHT: f32 = 841.89;
+const MARGIN: f32 = 28.35;
+// Height of the legend below the map (points)
+const LEGEND: f32 = 48.0;
+// Spacing of the UTM grid (meters)
+const GRID_SPACING: f32 = 1000.0;
+// Ground (meters) kept around the path when laying out the sheets, and the
+// sampling interval for finding the sheets the path passes through
+const SHEET_MARGIN: f32 = 200.0;
+const SHEET_STEP: f32 = 50.0;
+// Basemap tiles are cached as <TILE_CACHE>/<z>/<x>/<y>.png
+const TILE_CACHE: &str = ".tile_cache";
+const MAX_ZOOM: u32 = 17;
+// Resolution (meters per pixel) of zoom level 0 at the equator
+const ZOOM0_RESOLUTION: f64 = 156543.03;
+// Scale of the map sheets when none is given, 1:25 000
+pub const DEFAULT_SCALE: f32 = 25000.0;
+
+// Minimal PDF writer. Objects are numbered from 1 in the order they are
+// added, and written with a cross reference table at the end.
+struct Pdf {
+    objects: Vec<Vec<u8>>,
+}
+
+impl Pdf {
+    fn new() -> Self {
+        Self {
+            objects: vec![],
+        }
+    }
+
+    // Reserve a number for an object written later, e.g. one which refers
+    // to objects not yet added
+    fn reserve(&mut self) -> usize {
+        self.objects.push(vec![]);
+        self.objects.len()
+    }
+
+    fn set(&mut self, id: usize, content: String) {
+        self.objects[id - 1] = content.into_bytes();
+    }
+
+    fn add(&mut self, content: String) -> usize {
+        let id = self.reserve();
+        self.set(id, content);
+        id
+    }
+
+    fn add_stream(&mut self, dict: &str, data: &[u8]) -> usize {
+        let id = self.reserve();
+        let mut obj = format!("<< {} /Length {} >>\nstream\n", dict,
+                              data.len()).into_bytes();
+        obj.extend(data);
+        obj.extend(b"\nendstream");
+        self.objects[id - 1] = obj;
+        id
+    }
+
+    fn to_bytes(&self, root: usize) -> Vec<u8> {
+        let mut out = b"%PDF-1.4\n".to_vec();
+        let mut offsets = vec![];
+
+        for (i, obj) in self.objects.iter().enumerate() {
+            offsets.push(out.len());
+            out.extend(format!("{} 0 obj\n", i + 1).as_bytes());
+            out.extend(obj);
+            out.extend(b"\nendobj\n");
+        }
+
+        let xref = out.len();
+        let mut table = format!("xref\n0 {}\n0000000000 65535 f \n",
+                                self.objects.len() + 1);
+
+        for o in offsets {
+            let _ = writeln!(table, "{:010} 00000 n ", o);
+        }
+
+        let _ = writeln!(table, "trailer\n<< /Size {} /Root {} 0 R >>\n\
+                                 startxref\n{}\n%%EOF",
+                         self.objects.len() + 1, root, xref);
+        out.extend(table.as_bytes());
+        out
+    }
+}
+
+// Text as a PDF hex string in WinAnsi encoding. Characters outside Latin-1
+// are replaced by '?'.
+fn pdf_text(s: &str) -> String {
+    let hex: String = s.chars()
+        .map(|c| format!("{:02x}", if (c as u32) < 256 { c as u32 }
+                                   else { '?' as u32 }))
+        .collect();
+
+    format!("<{}>", hex)
+}
+
+// PDF image of a PNG basemap tile: the image dictionary and the compressed
+// data, which is used as is with the PNG predictors. Only non-interlaced
+// 8 bit gray and RGB images and palette images are supported.
+fn png_image(png: &[u8]) -> Option<(String, Vec<u8>)> {
+    if !png.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return None;
+    }
+
+    let be32 = |b: &[u8]| u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+    let mut pos = 8;
+    let (mut width, mut height, mut depth, mut color) = (0, 0, 0, 0);
+    let mut palette = vec![];
+    let mut data = vec![];
+
+    while pos + 8 <= png.len() {
+        let len = be32(&png[pos..]) as usize;
+        let body = png.get(pos + 8..pos + 8 + len)?;
+
+        match &png[pos + 4..pos + 8] {
+            b"IHDR" if len >= 13 => {
+                width = be32(body);
+                height = be32(&body[4..]);
+                depth = body[8];
+                color = body[9];
+
+                if body[12] != 0 {
+                    return None;
+                }
+            },
+            b"PLTE" => { palette = body.to_vec(); },
+            b"IDAT" => { data.extend(body); },
+            _ => {},
+        }
+
+        pos += len + 12;
+    }
+
+    let (space, colors) = match color {
+        0 if depth == 8 => ("/DeviceGray".to_string(), 1),
+        2 if depth == 8 => ("/DeviceRGB".to_string(), 3),
+        3 if palette.len() >= 3 => {
+            let hex: String = palette.iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            (format!("[/Indexed /DeviceRGB {} <{}>]", palette.len()/3 - 1,
+                     hex), 1)
+        },
+        _ => { return None; },
+    };
+
+    Some((format!("/Type /XObject /Subtype /Image /Width {} /Height {} \
+                   /ColorSpace {} /BitsPerComponent {} /Filter /FlateDecode \
+                   /DecodeParms << /Predictor 15 /Colors {} \
+                   /BitsPerComponent {} /Columns {} >>", width, height,
+                  space, depth, colors, depth, width), data))
+}
+
+// Read a basemap tile from the cache, downloading it if it isn't there
+fn fetch_tile(z: u32, x: i64, y: i64) -> Result<Vec<u8>, String> {
+    let dir = format!("{}/{}/{}", TILE_CACHE, z, x);
+    let fname = format!("{}/{}.png", dir, y);
+
+    if let Ok(data) = fs::read(&fname) {
+        return Ok(data);
+    }
+
+    let url = CONFIG.tile_url.replace("{z}", &z.to_string())
+        .replace("{x}", &x.to_string())
+        .replace("{y}", &y.to_string());
+    let response = ureq::get(&url).set("User-Agent", "stivalg").call()
+        .map_err(|e| format!("Download of {} failed: {}", url, e))?;
+    let mut data = vec![];
+    response.into_reader().read_to_end(&mut data)
+        .map_err(|e| format!("Download of {} failed: {}", url, e))?;
+
+    // The cache is only an optimization
+    if fs::create_dir_all(&dir).is_ok() {
+        let _ = fs::write(&fname, &data);
+    }
+
+    Ok(data)
+}
+
+// Latitude and longitude of the north west corner of a tile
+fn tile_corner(z: u32, x: i64, y: i64) -> (f64, f64) {
+    let n = (1u64 << z) as f64;
+    let lon = x as f64/n*360.0 - 180.0;
+    let lat = (PI*(1.0 - 2.0*y as f64/n)).sinh().atan().to_degrees();
+
+    (lat, lon)
+}
+
+// Tile containing a latitude and longitude
+fn tile_index(z: u32, lat: f64, lon: f64) -> (i64, i64) {
+    let n = (1u64 << z) as f64;
+    let x = (lon + 180.0)/360.0*n;
+    let phi = lat.to_radians();
+    let y = (1.0 - (phi.tan() + 1.0/phi.cos()).ln()/PI)/2.0*n;
+
+    (x.floor() as i64, y.floor() as i64)
+}
+
+// Sheet of the map, given by the south west corner of the area it shows
+struct Sheet {
+    w: f32,
+    s: f32,
+}
+
+// Layout of the sheets at a scale
+struct Layout {
+    // Ground meters per point on paper
+    mpp: f32,
+    map_width: f32,
+    map_height: f32,
+}
+
+impl Layout {
+    fn new(scale: f32) -> Self {
+        let mpp = 25.4/72.0*scale/1000.0;
+
+        Self {
+            mpp: mpp,
+            map_width: (PAGE_WIDTH - 2.0*MARGIN)*mpp,
+            map_height: (PAGE_HEIGHT - 2.0*MARGIN - LEGEND)*mpp,
+        }
+    }
+
+    // Sheets covering the path, in the order the path passes through them
+    fn sheets(&self, points: &[Coord]) -> Vec<Sheet> {
+        let (mut w, mut e, mut s, mut n) = (
+            f32::INFINITY, f32::NEG_INFINITY,
+            f32::INFINITY, f32::NEG_INFINITY);
+
+        for c in points {
+            w = w.min(c.e - SHEET_MARGIN);
+            e = e.max(c.e + SHEET_MARGIN);
+            s = s.min(c.n - SHEET_MARGIN);
+            n = n.max(c.n + SHEET_MARGIN);
+        }
+
+        // Center the grid of sheets on the path
+        let nx = ((e - w)/self.map_width).ceil().max(1.0);
+        let ny = ((n - s)/self.map_height).ceil().max(1.0);
+        let w0 = (w + e - nx*self.map_width)/2.0;
+        let s0 = (s + n - ny*self.map_height)/2.0;
+
+        let mut order: Vec<(i64, i64)> = vec![];
+        let mut visit = |c: Coord| {
+            let key = (((c.e - w0)/self.map_width).floor() as i64,
+                       ((c.n - s0)/self.map_height).floor() as i64);
+            if !order.contains(&key) {
+                order.push(key);
+            }
+        };
+
+        for p in points.windows(2) {
+            let len = (p[1] - p[0]).abs();
+            let steps = (len/SHEET_STEP).ceil().max(1.0) as usize;
+
+            for k in 0..steps {
+                visit(p[0] + (p[1] - p[0])*(k as f32/steps as f32));
+            }
+        }
+
+        if let Some(c) = points.last() {
+            visit(*c);
+        }
+
+        order.iter()
+            .map(|(i, j)| Sheet {
+                w: w0 + *i as f32*self.map_width,
+                s: s0 + *j as f32*self.map_height,
+            })
+            .collect()
+    }
+
+    // Zoom level of the basemap tiles giving about two pixels per point
+    fn zoom(&self, lat: f64) -> u32 {
+        let z = (ZOOM0_RESOLUTION*lat.to_radians().cos()
+                 /(self.mpp as f64/2.0)).log2().round();
+
+        z.clamp(0.0, MAX_ZOOM as f64) as u32
+    }
+}
+
+// Write PDF map sheets of a path at a scale (e.g. 25000 for 1:25 000) on
+// A4 pages, with the basemap, a UTM grid, a scale bar and the directions of
+// true and magnetic north. Returns the number of sheets.
+pub fn write_mapsheets(fname: &str, path: &Path, params: &Params,
+                       scale: f32) -> Result<usize, String> {
+    let points: Vec<Coord> = path.into_iter().cloned().collect();
+
+    if points.len() < 2 {
+        return Err("Path has no points".to_string());
+    }
+
+    if scale <= 0.0 {
+        return Err(format!("Invalid scale {}", scale));
+    }
+
+    let layout = Layout::new(scale);
+    let sheets = layout.sheets(&points);
+    let waypoints = params.labelled_points();
+    let name = params.expand_template(&params.track_name, path.len());
+
+    let mut pdf = Pdf::new();
+    let pages_id = pdf.reserve();
+    let font_id = pdf.add("<< /Type /Font /Subtype /Type1 /BaseFont \
+                           /Helvetica /Encoding /WinAnsiEncoding >>"
+                          .to_string());
+    let mut tile_ids: HashMap<(u32, i64, i64), Option<usize>> =
+        HashMap::new();
+    let mut failed = 0;
+    let mut page_ids = vec![];
+
+    for (k, sheet) in sheets.iter().enumerate() {
+        println!("Rendering sheet {} of {}", k + 1, sheets.len());
+
+        let (x0, y0) = (MARGIN, MARGIN + LEGEND);
+        let (mw, mh) = (PAGE_WIDTH - 2.0*MARGIN,
+                        PAGE_HEIGHT - 2.0*MARGIN - LEGEND);
+        let to_page = |c: &Coord| (x0 + (c.e - sheet.w)/layout.mpp,
+                                   y0 + (c.n - sheet.s)/layout.mpp);
+        let mut content = String::new();
+        let mut images = String::new();
+
+        // Basemap tiles, clipped to the map frame. Each tile is placed by
+        // mapping three of its corners to the page, which is accurate
+        // enough over the size of a tile.
+        let _ = writeln!(content, "q {:.2} {:.2} {:.2} {:.2} re W n",
+                         x0, y0, mw, mh);
+
+        let center = Coord::new(sheet.w + layout.map_width/2.0,
+                                sheet.s + layout.map_height/2.0);
+        let (clat, _) = to_latlon(&center);
+        let z = layout.zoom(clat);
+        let corners = [
+            Coord::new(sheet.w, sheet.s),
+            Coord::new(sheet.w + layout.map_width, sheet.s),
+            Coord::new(sheet.w, sheet.s + layout.map_height),
+            Coord::new(sheet.w + layout.map_width,
+                       sheet.s + layout.map_height),
+        ];
+        let tiles: Vec<(i64, i64)> = corners.iter()
+            .map(|c| {
+                let (lat, lon) = to_latlon(c);
+                tile_index(z, lat, lon)
+            })
+            .collect();
+        let (tx0, tx1) = (tiles.iter().map(|t| t.0).min().unwrap_or(0),
+                          tiles.iter().map(|t| t.0).max().unwrap_or(0));
+        let (ty0, ty1) = (tiles.iter().map(|t| t.1).min().unwrap_or(0),
+                          tiles.iter().map(|t| t.1).max().unwrap_or(0));
+
+        for tx in tx0..=tx1 {
+            for ty in ty0..=ty1 {
+                let id = *tile_ids.entry((z, tx, ty)).or_insert_with(|| {
+                    let image = fetch_tile(z, tx, ty).ok()
+                        .and_then(|png| png_image(&png));
+                    match image {
+                        Some((dict, data)) => Some(pdf.add_stream(&dict,
+                                                                  &data)),
+                        None => { failed += 1; None },
+                    }
+                });
+
+                let Some(id) = id else { continue; };
+
+                let corner = |x: i64, y: i64| {
+                    let (lat, lon) = tile_corner(z, x, y);
+                    to_page(&from_latlon(lat, lon))
+                };
+                let sw = corner(tx, ty + 1);
+                let se = corner(tx + 1, ty + 1);
+                let nw = corner(tx, ty);
+
+                let _ = writeln!(content, "q {:.3} {:.3} {:.3} {:.3} {:.3} \
+                                           {:.3} cm /T{} Do Q",
+                                 se.0 - sw.0, se.1 - sw.1, nw.0 - sw.0,
+                                 nw.1 - sw.1, sw.0, sw.1, id);
+                let _ = write!(images, " /T{} {} 0 R", id, id);
+            }
+        }
+
+        // UTM grid
+        content.push_str("0 0 0.55 RG 0.4 w\n");
+        let mut labels = String::new();
+        let first_e = (sheet.w/GRID_SPACING).ceil()*GRID_SPACING;
+        let first_n = (sheet.s/GRID_SPACING).ceil()*GRID_SPACING;
+        let mut e = first_e;
+
+        while e < sheet.w + layout.map_width {
+            let x = x0 + (e - sheet.w)/layout.mpp;
+            let _ = writeln!(content, "{:.2} {:.2} m {:.2} {:.2} l S",
+                             x, y0, x, y0 + mh);
+            let _ = writeln!(labels, "BT /F1 6 Tf {:.2} {:.2} Td {} Tj ET",
+                             x - 6.0, y0 + mh + 3.0,
+                             pdf_text(&format!("{}", (e/1000.0) as i64)));
+            e += GRID_SPACING;
+        }
+
+        let mut n = first_n;
+
+        while n < sheet.s + layout.map_height {
+            let y = y0 + (n - sheet.s)/layout.mpp;
+            let _ = writeln!(content, "{:.2} {:.2} m {:.2} {:.2} l S",
+                             x0, y, x0 + mw, y);
+            let _ = writeln!(labels, "BT /F1 6 Tf {:.2} {:.2} Td {} Tj ET",
+                             x0 + mw + 2.0, y - 2.0,
+                             pdf_text(&format!("{}", (n/1000.0) as i64)));
+            n += GRID_SPACING;
+        }
+
+        // The path and the waypoints
+        content.push_str("0.85 0.05 0.05 RG 2 w 1 J 1 j\n");
+        for (i, c) in points.iter().enumerate() {
+            let (x, y) = to_page(c);
+            let op = if i == 0 { "m" } else { "l" };
+            let _ = writeln!(content, "{:.2} {:.2} {}", x, y, op);
+        }
+        content.push_str("S\n0 0 0 RG 1 1 1 rg 1 w\n");
+
+        for (c, label) in &waypoints {
+            let (x, y) = to_page(c);
+            let _ = writeln!(content, "{:.2} {:.2} 6 6 re B", x - 3.0,
+                             y - 3.0);
+            let _ = writeln!(content, "0 0 0 rg BT /F1 8 Tf {:.2} {:.2} Td \
+                                       {} Tj ET 1 1 1 rg", x + 5.0, y + 4.0,
+                             pdf_text(label));
+        }
+
+        content.push_str("Q\n");
+
+        // Frame, grid labels and legend
+        let _ = writeln!(content, "0 0 0 RG 0.8 w {:.2} {:.2} {:.2} {:.2} re \
+                                   S", x0, y0, mw, mh);
+        content.push_str("0 0 0 rg\n");
+        content.push_str(&labels);
+
+        let _ = writeln!(content, "BT /F1 10 Tf {:.2} {:.2} Td {} Tj ET",
+                         x0, MARGIN + 32.0,
+                         pdf_text(&format!("{}  -  sheet {} of {}  -  \
+                                            1:{}", name,
+                                           k + 1, sheets.len(),
+                                           scale as u64)));
+
+        // Scale bar of 1, 2 or 5 times a power of ten meters, about a
+        // quarter of the map width
+        let target = layout.map_width/4.0;
+        let magnitude = 10f32.powf(target.log10().floor());
+        let bar = [5.0, 2.0, 1.0].iter().map(|m| m*magnitude)
+            .find(|b| *b <= target).unwrap_or(magnitude);
+        let bar_pt = bar/layout.mpp;
+        let bar_label = if bar >= 1000.0 {
+            format!("{} km", bar/1000.0)
+        }
+        else {
+            format!("{} m", bar)
+        };
+        let _ = writeln!(content, "2 w {:.2} {:.2} m {:.2} {:.2} l S",
+                         x0, MARGIN + 14.0, x0 + bar_pt, MARGIN + 14.0);
+        let _ = writeln!(content, "BT /F1 8 Tf {:.2} {:.2} Td {} Tj ET",
+                         x0, MARGIN + 2.0, pdf_text("0"));
+        let _ = writeln!(content, "BT /F1 8 Tf {:.2} {:.2} Td {} Tj ET",
+                         x0 + bar_pt - 10.0, MARGIN + 2.0,
+                         pdf_text(&bar_label));
+
+        // Directions of grid, true and magnetic north at the center of the
+        // sheet
+        let convergence = grid_convergence(&center);
+        let magnetic = convergence + params.declination;
+        let (ax, ay) = (x0 + mw - 120.0, MARGIN + 6.0);
+        let arrow = |angle: f32| {
+            let a = angle.to_radians();
+            (ax + 30.0*a.sin(), ay + 30.0*a.cos())
+        };
+
+        for (angle, label) in [(0.0, "GN"), (convergence, "TN"),
+                               (magnetic, "MN")] {
+            let (x, y) = arrow(angle);
+            let _ = writeln!(content, "0.8 w {:.2} {:.2} m {:.2} {:.2} l S",
+                             ax, ay, x, y);
+            let _ = writeln!(content, "BT /F1 6 Tf {:.2} {:.2} Td {} Tj ET",
+                             x - 4.0, y + 2.0, pdf_text(label));
+        }
+
+        let _ = writeln!(content, "BT /F1 7 Tf {:.2} {:.2} Td {} Tj ET",
+                         ax + 20.0, MARGIN + 20.0,
+                         pdf_text(&format!("True north {:.1}° from grid",
+                                           convergence)));
+        let _ = writeln!(content, "BT /F1 7 Tf {:.2} {:.2} Td {} Tj ET",
+                         ax + 20.0, MARGIN + 10.0,
+                         pdf_text(&format!("Declination {:.1}°",
+                                           params.declination)));
+
+        let content_id = pdf.add_stream("", content.as_bytes());
+        page_ids.push(pdf.add(format!(
+            "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] \
+             /Contents {} 0 R /Resources << /Font << /F1 {} 0 R >> \
+             /XObject <<{} >> >> >>", pages_id, PAGE_WIDTH, PAGE_HEIGHT,
+            content_id, font_id, images)));
+    }
+
+    let kids: Vec<String> = page_ids.iter()
+        .map(|id| format!("{} 0 R", id))
+        .collect();
+    pdf.set(pages_id, format!("<< /Type /Pages /Kids [{}] /Count {} >>",
+                              kids.join(" "), page_ids.len()));
+    let root = pdf.add(format!("<< /Type /Catalog /Pages {} 0 R >>",
+                               pages_id));
+
+    if failed > 0 {
+        println!("{} basemap tiles couldn't be downloaded or read",
+                 failed);
+    }
+
+    fs::write(fname, pdf.to_bytes(root))
+        .map_err(|e| format!("Unable to write {}: {}", fname, e))?;
+
+    Ok(sheets.len())
+}
@@ -0,0 +1,54 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::io::Write;
+
+// Output of the crate. The print! and println! macros of the crate write
+// through here, so that the output of a command can be captured on the
+// thread running it, e.g. for answering a client of the control socket.
+// Output from other threads isn't captured.
+
+thread_local! {
+    // Lines captured on this thread, and the line being written
+    static CAPTURED: RefCell<Option<(Vec<String>, String)>> =
+        const { RefCell::new(None) };
+}
+
+pub fn write(args: fmt::Arguments, newline: bool) {
+    let mut text = args.to_string();
+    if newline {
+        text.push('\n');
+    }
+
+    CAPTURED.with(|c| {
+        if let Some((lines, partial)) = c.borrow_mut().as_mut() {
+            partial.push_str(&text);
+
+            while let Some(i) = partial.find('\n') {
+                lines.push(partial[..i].to_string());
+                partial.drain(..=i);
+            }
+        }
+    });
+
+    let mut stdout = std::io::stdout().lock();
+    let _ = stdout.write_all(text.as_bytes());
+    if !newline {
+        let _ = stdout.flush();
+    }
+}
+
+// Run f with the output written on this thread captured. The output is
+// passed on to stdout as well.
+pub fn capture<R>(f: impl FnOnce() -> R) -> (R, Vec<String>) {
+    CAPTURED.with(|c| *c.borrow_mut() = Some((vec![], String::new())));
+
+    let res = f();
+
+    let (mut lines, partial) = CAPTURED.with(|c| c.borrow_mut().take())
+        .unwrap_or_default();
+    if !partial.is_empty() {
+        lines.push(partial);
+    }
+
+    (res, lines)
+}
@@ -1,23 +1,234 @@
 use crate::barrier::Barrier;
+use crate::cost::{ASPECT_NAMES, cost_model, read_cost_curve};
+use crate::crs::to_latlon;
+use crate::device::file_stem;
+use crate::magnetic::magnetic_model;
+use crate::path::Path;
+use crate::schedule::{parse_date_time, parse_target};
+use crate::script::CostScript;
+use crate::symbology::Symbology;
 
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
 use hoydedata::Coord;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
 use std::fs;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Last generation given to params or cost scripts
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// A generation not given out before
+pub fn next_generation() -> u64 {
+    GENERATION.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+// Label of waypoint i of n. Unnamed waypoints are numbered, with the first
+// and last marked as start and end.
+pub fn point_label(name: &str, i: usize, n: usize) -> String {
+    if name != "" {
+        name.to_string()
+    }
+    else if i == 0 {
+        format!("{} (start)", i + 1)
+    }
+    else if i == n - 1 {
+        format!("{} (end)", i + 1)
+    }
+    else {
+        format!("{}", i + 1)
+    }
+}
 
 fn default_grid_size_pass1() -> f32 { 25.0 }
 fn default_grid_size_pass2() -> f32 { 1.0 }
 fn default_covering_length() -> f32 { 1.1 }
 fn default_covering_width() -> f32 { 1.1 }
-fn default_path_width_pass2() -> f32 { 1000.0 }
+fn default_path_width_pass2() -> f32 { 20.0 }
+// path_width_pass2 of params files saved before it set the width of the
+// second pass corridor
+const LEGACY_PATH_WIDTH_PASS2: f32 = 1000.0;
+fn default_grid_topology() -> String { "square-8".to_string() }
+fn default_connectivity() -> usize { 8 }
 fn default_track_name() -> String { "Stivalg".to_string() }
+fn default_aspect_multipliers() -> Vec<f32> { vec![1.0; 8] }
+fn default_utc_offset() -> f32 { 1.0 }
+fn default_darkness_factor() -> f32 { 1.5 }
+fn default_ford_radius() -> f32 { 25.0 }
+fn default_camp_max_slope() -> f32 { 5.0 }
+fn default_camp_corridor() -> f32 { 300.0 }
+fn default_camp_water_distance() -> f32 { 300.0 }
+fn default_escape_max_time() -> f32 { 120.0 }
+fn default_deviation_alarm() -> f32 { 100.0 }
+fn default_km_markers() -> f32 { 1.0 }
+fn default_export_km_markers() -> f32 { 0.0 }
+fn default_access_distance() -> f32 { 1000.0 }
+fn default_return_penalty() -> f32 { 1.0 }
+fn default_return_distance() -> f32 { 200.0 }
+fn default_cost_model() -> String { "default".to_string() }
+fn default_body_mass() -> f32 { 80.0 }
+fn default_fluid_rate() -> f32 { 0.5 }
+fn default_max_memory_mb() -> f32 { 2048.0 }
+fn default_impassable_slope() -> f32 { 45.0 }
+fn default_cross_slope_weight() -> f32 { 0.0 }
+fn default_optimize_split() -> f32 { 20.0 }
+fn default_optimize_join() -> f32 { 10.0 }
+fn default_optimize_probe() -> f32 { 40.0 }
+fn default_optimize_range() -> f32 { 0.2 }
+fn default_optimize_convergence() -> f32 { 1e-8 }
+fn default_optimize_method() -> String { "adaptive".to_string() }
+fn default_north() -> String { "magnetic".to_string() }
+fn default_symbology() -> String { "default".to_string() }
+
+// Distance (m) within which a path point is at a pinned point
+const PIN_TOLERANCE: f32 = 0.5;
+// Waypoint types, with their symbol in GPX files
+pub const POINT_TYPES: &[(&str, &str)] = &[
+    ("start", "Flag, Green"),
+    ("finish", "Flag, Red"),
+    ("hut", "Lodge"),
+    ("summit", "Summit"),
+    ("water", "Drinking Water"),
+    ("camp", "Campground"),
+];
+// Slowdown per kg of pack weight
+const PACK_SLOWDOWN: f32 = 0.01;
+
+// Seasonal presets. Each preset sets a group of parameters suited for the
+// conditions of the season.
+pub const SEASONS: &[(&str, &[(&str, &str)])] = &[
+    ("bare-ground", &[
+        ("cost_model", "default"),
+        ("impassable_slope", "45"),
+        ("aspect_multipliers", "1,1,1,1,1,1,1,1"),
+        ("darkness_factor", "1.5"),
+    ]),
+    // North facing slopes stay wet and slippery
+    ("wet-autumn", &[
+        ("cost_model", "default"),
+        ("impassable_slope", "38"),
+        ("aspect_multipliers", "1.15,1.1,1,0.95,0.9,0.95,1,1.1"),
+        ("darkness_factor", "2.0"),
+    ]),
+    // Short days, walking by headlamp is slow. Deep snow evens out the
+    // terrain, but steep slopes are avalanche prone.
+    ("mid-winter", &[
+        ("cost_model", "naismith"),
+        ("impassable_slope", "30"),
+        ("aspect_multipliers", "1,1,1,1,1,1,1,1"),
+        ("darkness_factor", "1.8"),
+    ]),
+    // Sun facing slopes have firm snow early in the day
+    ("spring-crust", &[
+        ("cost_model", "default"),
+        ("impassable_slope", "35"),
+        ("aspect_multipliers", "1.2,1.1,1,0.9,0.8,0.9,1,1.1"),
+        ("darkness_factor", "1.3"),
+    ]),
+];
+
+// Attributes of a waypoint
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PointInfo {
+    // Name shown on the map and written to the GPX file. Unnamed points are
+    // numbered.
+    #[serde(default)]
+    pub name: String,
+    // Target arrival time HH:MM, optionally with days after the start day
+    // added, e.g. 09:15+1
+    #[serde(default)]
+    pub target: String,
+    // The route may pass anywhere within this distance (meters) of the
+    // waypoint. The radius of the first waypoint is not used.
+    #[serde(default)]
+    pub radius: f32,
+    // One of POINT_TYPES, shown by the icon of the waypoint
+    #[serde(default, rename = "type")]
+    pub kind: String,
+}
+
+// Member of a hiking group. The fitness is the walking speed relative to
+// the cost model, e.g. 0.8 for a hiker walking at 80 % of the modeled
+// speed. The pack weight (kg) slows the hiker down further.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Hiker {
+    pub name: String,
+    pub fitness: f32,
+    pub pack: f32,
+}
 
-#[derive(Deserialize, Serialize)]
+impl Hiker {
+    pub fn parse(name: &str, fitness: &str, pack: &str)
+                 -> Result<Self, String> {
+        let fitness = Params::parse_float(fitness)?;
+        let pack = Params::parse_float(pack)?;
+
+        if fitness <= 0.0 || pack < 0.0 {
+            return Err("Expected a positive fitness and pack weight"
+                       .to_string());
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            fitness: fitness,
+            pack: pack,
+        })
+    }
+
+    // Multiplier of the modeled walking time
+    pub fn pace(&self) -> f32 {
+        (1.0 + PACK_SLOWDOWN*self.pack)/self.fitness
+    }
+}
+
+impl fmt::Display for Hiker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, fitness {}, pack {} kg", self.name, self.fitness,
+               self.pack)
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Params {
     pub points: Vec<Coord>,
+    // Attributes of each waypoint, in the same order as the points
+    #[serde(default)]
+    pub point_info: Vec<PointInfo>,
     #[serde(default)]
     pub barriers: Vec<Barrier>,
+    // Lines which the route must cross, e.g. at a bridge or a col
+    #[serde(default)]
+    pub gates: Vec<Barrier>,
+    // Polygon which the route must stay inside. Empty if the route is not
+    // constrained.
+    #[serde(default = "Barrier::new")]
+    pub corridor: Barrier,
+    // Points where barriers (e.g. rivers) may be crossed
+    #[serde(default)]
+    pub fords: Vec<Coord>,
+    #[serde(default = "default_ford_radius")]
+    pub ford_radius: f32,
+    // Path points which optimization and recomputation of legs keep in
+    // place, and polygons whose path points are all kept in place
+    #[serde(default)]
+    pub pins: Vec<Coord>,
+    #[serde(default)]
+    pub pin_areas: Vec<Barrier>,
+    // Places to escape to, e.g. roads and trailheads. Parking places among
+    // the points of interest are exits too.
+    #[serde(default)]
+    pub exits: Vec<Coord>,
+    // Members of the group. Times are estimated for the slowest.
+    #[serde(default)]
+    pub group: Vec<Hiker>,
     #[serde(default = "default_grid_size_pass1")]
     pub grid_size_pass1: f32,
     #[serde(default = "default_grid_size_pass2")]
@@ -26,39 +237,258 @@ pub struct Params {
     pub covering_length: f32,
     #[serde(default = "default_covering_width")]
     pub covering_width: f32,
+    // Half width (meters) of the corridor around the first pass path, where
+    // the second pass graph is built
     #[serde(default = "default_path_width_pass2")]
     pub path_width_pass2: f32,
+    // Grid size and corridor half width of each pass. The first pass covers
+    // the whole area between the waypoints, so its width is not used. If
+    // empty, two passes are made using the pass 1 and pass 2 params.
+    #[serde(default)]
+    pub passes: Vec<(f32, f32)>,
+    // Topology (square-8 or hex) and orientation (degrees counterclockwise
+    // from east) of the first pass grid
+    #[serde(default = "default_grid_topology")]
+    pub grid_topology: String,
+    #[serde(default)]
+    pub grid_orientation: f32,
+    // Number of neighbours (8 or 16) of each node in the first pass square
+    // grid
+    #[serde(default = "default_connectivity")]
+    pub connectivity: usize,
     #[serde(default)]
     pub params_fname: String,
+    // The output file name and the track name may have placeholders, see
+    // expand_template
     #[serde(default)]
     pub output_fname: String,
     #[serde(default = "default_track_name")]
     pub track_name: String,
+    // Name of the model of walking time by steepness
+    #[serde(default = "default_cost_model")]
+    pub cost_model: String,
+    // Samples of slope (degrees) and speed (km/h) for the curve cost model
+    #[serde(default)]
+    pub cost_curve: Vec<(f32, f32)>,
+    // Rhai script with a cost function replacing the cost model, see
+    // CostScript
+    #[serde(default)]
+    pub cost_script: String,
+    // Cost multipliers for slopes facing N, NE, E, SE, S, SW, W and NW
+    #[serde(default = "default_aspect_multipliers")]
+    pub aspect_multipliers: Vec<f32>,
+    // Start of trip as local date and time YYYY-MM-DDTHH:MM
+    #[serde(default)]
+    pub start_time: String,
+    // Local time offset from UTC (hours)
+    #[serde(default = "default_utc_offset")]
+    pub utc_offset: f32,
+    // Slowdown of walking after dark
+    #[serde(default = "default_darkness_factor")]
+    pub darkness_factor: f32,
+    // Time to be back at the start by, HH:MM with optional days after the
+    // start day, e.g. the last bus
+    #[serde(default)]
+    pub deadline: String,
+    // Name of the last applied seasonal preset
+    #[serde(default)]
+    pub season: String,
+    // Maximal slope (degrees) of a camp site
+    #[serde(default = "default_camp_max_slope")]
+    pub camp_max_slope: f32,
+    // Maximal distance from track to camp site
+    #[serde(default = "default_camp_corridor")]
+    pub camp_corridor: f32,
+    // Maximal distance from camp site to water
+    #[serde(default = "default_camp_water_distance")]
+    pub camp_water_distance: f32,
+    // Longest acceptable time (minutes) from the track to an exit
+    #[serde(default = "default_escape_max_time")]
+    pub escape_max_time: f32,
+    // Distance (meters) from the planned route at which the GPS position
+    // sets off an alarm. 0 turns the alarm off.
+    #[serde(default = "default_deviation_alarm")]
+    pub deviation_alarm: f32,
+    // Interval (km) of the distance markers along the track on the map. 0
+    // turns them off.
+    #[serde(default = "default_km_markers")]
+    pub km_markers: f32,
+    // Interval (km) of the distance markers written as GPX waypoints. 0
+    // turns them off.
+    #[serde(default = "default_export_km_markers")]
+    pub export_km_markers: f32,
+    // Walking distance (meters, with ascent counted as extra distance)
+    // from the start and end points to road access beyond which a warning
+    // is given. 0 turns the check off.
+    #[serde(default = "default_access_distance")]
+    pub access_distance: f32,
+    // Cost multiplier for walking near the earlier legs of the route. A
+    // value above 1 makes out-and-back trips return by a different route.
+    #[serde(default = "default_return_penalty")]
+    pub return_penalty: f32,
+    #[serde(default = "default_return_distance")]
+    pub return_distance: f32,
+    // Mass of walker and pack (kg), used for the energy estimate
+    #[serde(default = "default_body_mass")]
+    pub body_mass: f32,
+    // Recommended water intake (liters per hour)
+    #[serde(default = "default_fluid_rate")]
+    pub fluid_rate: f32,
+    // Memory (MB) a search may use, for the first pass graph, the cached
+    // elevation lookups and the landmarks. If the graph would be larger
+    // than its share, its grid is coarsened. 0 means no limit.
+    #[serde(default = "default_max_memory_mb")]
+    pub max_memory_mb: f32,
+    // Terrain steeper than this (degrees) can't be walked. 90 means no
+    // limit.
+    #[serde(default = "default_impassable_slope")]
+    pub impassable_slope: f32,
+    // Slowdown of walking across a slope, per unit of gradient across the
+    // walking direction. With 0.5, traversing a 35° slope takes 35% longer.
+    // The default 0 leaves the cost as before the weight was added.
+    #[serde(default = "default_cross_slope_weight")]
+    pub cross_slope_weight: f32,
+    // Slowdown of walking over rough terrain, per meter of roughness (the
+    // deviation of the terrain from an even slope). 0 disables the
+    // roughness estimate, which makes computations slower.
+    #[serde(default)]
+    pub roughness_weight: f32,
+    // Number of landmarks giving lower bounds for the first pass search.
+    // They take a while to compute, but are reused by later searches in the
+    // same area, which then go faster. They get a quarter of
+    // max_memory_mb. 0 turns them off.
+    #[serde(default)]
+    pub landmarks: usize,
+    // Elevation changes smaller than this (m) are ignored when adding up
+    // the ascent and descent of a path, so that noise in the elevation data
+    // doesn't inflate them. GPS apps typically ignore a few meters.
+    #[serde(default)]
+    pub ascent_threshold: f32,
+    // Magnetic declination (degrees, east positive) at the route, shown on
+    // the map sheets
+    #[serde(default)]
+    pub declination: f32,
+    // North of the bearings shown, "magnetic" or "true"
+    #[serde(default = "default_north")]
+    pub north: String,
+    // Symbol preset of the map, and settings overriding it as a comma
+    // separated list of key=value
+    #[serde(default = "default_symbology")]
+    pub symbology: String,
+    #[serde(default)]
+    pub symbols: String,
+    // Settings of the final optimization by relaxation. Segments longer
+    // than optimize_split (m) are split and points closer than
+    // optimize_join (m) are joined.
+    #[serde(default = "default_optimize_split")]
+    pub optimize_split: f32,
+    #[serde(default = "default_optimize_join")]
+    pub optimize_join: f32,
+    // Largest sideways probe vector (m), and the initial step between
+    // probes relative to it. There are ten probes on each side.
+    #[serde(default = "default_optimize_probe")]
+    pub optimize_probe: f32,
+    #[serde(default = "default_optimize_range")]
+    pub optimize_range: f32,
+    // Iterations stop when the time improves less than this (seconds)
+    #[serde(default = "default_optimize_convergence")]
+    pub optimize_convergence: f32,
+    // Relaxation with adaptive steps per point, or with fixed probes
+    #[serde(default = "default_optimize_method")]
+    pub optimize_method: String,
+    // South west and north east corners of the area searched, e.g. the area
+    // shown in the map window. Not stored.
+    #[serde(skip)]
+    pub bounds: Option<(Coord, Coord)>,
+    // Changes when the params are changed in the app, so that results
+    // computed from them can tell that they are outdated. Not stored.
+    #[serde(skip, default = "next_generation")]
+    pub generation: u64,
 }
 
 impl Params {
     pub fn from_config() -> Self {
         Self {
             points: vec![],
+            point_info: vec![],
             barriers: vec![],
+            gates: vec![],
+            corridor: Barrier::new(),
+            fords: vec![],
+            pins: vec![],
+            exits: vec![],
+            group: vec![],
+            pin_areas: vec![],
+            ford_radius: default_ford_radius(),
             grid_size_pass1: default_grid_size_pass1(),
             grid_size_pass2: default_grid_size_pass2(),
             covering_length: default_covering_length(),
             covering_width: default_covering_width(),
             path_width_pass2: default_path_width_pass2(),
+            passes: vec![],
+            grid_topology: default_grid_topology(),
+            grid_orientation: 0.0,
+            connectivity: default_connectivity(),
             params_fname: "".to_string(),
             output_fname: "".to_string(),
             track_name: default_track_name(),
+            cost_model: default_cost_model(),
+            cost_curve: vec![],
+            cost_script: "".to_string(),
+            aspect_multipliers: default_aspect_multipliers(),
+            start_time: "".to_string(),
+            utc_offset: default_utc_offset(),
+            darkness_factor: default_darkness_factor(),
+            deadline: "".to_string(),
+            season: "".to_string(),
+            camp_max_slope: default_camp_max_slope(),
+            camp_corridor: default_camp_corridor(),
+            camp_water_distance: default_camp_water_distance(),
+            escape_max_time: default_escape_max_time(),
+            deviation_alarm: default_deviation_alarm(),
+            km_markers: default_km_markers(),
+            export_km_markers: default_export_km_markers(),
+            access_distance: default_access_distance(),
+            return_penalty: default_return_penalty(),
+            return_distance: default_return_distance(),
+            body_mass: default_body_mass(),
+            fluid_rate: default_fluid_rate(),
+            max_memory_mb: default_max_memory_mb(),
+            impassable_slope: default_impassable_slope(),
+            cross_slope_weight: default_cross_slope_weight(),
+            roughness_weight: 0.0,
+            landmarks: 0,
+            ascent_threshold: 0.0,
+            declination: 0.0,
+            north: default_north(),
+            symbology: default_symbology(),
+            symbols: "".to_string(),
+            optimize_split: default_optimize_split(),
+            optimize_join: default_optimize_join(),
+            optimize_probe: default_optimize_probe(),
+            optimize_range: default_optimize_range(),
+            optimize_convergence: default_optimize_convergence(),
+            optimize_method: default_optimize_method(),
+            bounds: None,
+            generation: next_generation(),
         }
     }
 
+    // Give the params a new generation after changing them
+    pub fn touch(&mut self) {
+        self.generation = next_generation();
+    }
+
     pub fn from_file(fname: &str) -> Result<Params, String> {
         let mut data = "".to_string();
         let mut f = File::open(fname).expect("Unable to open file");
         f.read_to_string(&mut data).expect("Unable to read file");
 
         match serde_json::from_str::<Params>(&data) {
-            Ok(params) => {
+            Ok(mut params) => {
+                params.point_info.resize(params.points.len(),
+                                         PointInfo::default());
+                params.upgrade();
                 Ok(params)
             },
             Err(e) => {
@@ -93,14 +523,162 @@ impl Params {
         Ok(())
     }
 
+    // The params as a compact string which can be pasted in a chat: the
+    // JSON compressed and encoded as URL safe base64
+    pub fn to_share(&self) -> String {
+        let data = serde_json::to_string(&self).unwrap();
+        let mut encoder = DeflateEncoder::new(vec![], Compression::best());
+        let _ = encoder.write_all(data.as_bytes());
+        let compressed = encoder.finish().unwrap_or_default();
+
+        URL_SAFE_NO_PAD.encode(compressed)
+    }
+
+    pub fn from_share(share: &str) -> Result<Params, String> {
+        let err = |e: String| format!("Invalid share string: {}", e);
+        let compressed = URL_SAFE_NO_PAD.decode(share.trim())
+            .map_err(|e| err(e.to_string()))?;
+        let mut data = String::new();
+        DeflateDecoder::new(&compressed[..]).read_to_string(&mut data)
+            .map_err(|e| err(e.to_string()))?;
+
+        let mut params = serde_json::from_str::<Params>(&data)
+            .map_err(|e| err(e.to_string()))?;
+        params.point_info.resize(params.points.len(), PointInfo::default());
+        params.upgrade();
+
+        Ok(params)
+    }
+
+    // Bring params saved by older versions up to date. The old value of
+    // path_width_pass2 would now give a second pass graph 2 km wide.
+    fn upgrade(&mut self) {
+        if self.path_width_pass2 == LEGACY_PATH_WIDTH_PASS2 {
+            println!("Note: path_width_pass2 {} from an older params file \
+                      is replaced by the default {}",
+                     self.path_width_pass2, default_path_width_pass2());
+            self.path_width_pass2 = default_path_width_pass2();
+        }
+    }
+
+    // Expand the placeholders of a template for the output file name or the
+    // track name: {date} is the date of start_time, or today if it isn't
+    // set, {start} and {end} the names of the first and last waypoint, and
+    // {length_km} the length of the path.
+    pub fn expand_template(&self, template: &str, length: f32) -> String {
+        self.expand_with(template, length, |name| name.to_string())
+    }
+
+    // Expand a template for a file name. The letters and digits of the
+    // waypoint names are kept, so that a name can't add directories or
+    // characters not allowed in file names.
+    pub fn expand_fname(&self, template: &str, length: f32) -> String {
+        self.expand_with(template, length, file_stem)
+    }
+
+    fn expand_with(&self, template: &str, length: f32,
+                   clean: impl Fn(&str) -> String) -> String {
+        let name = |i: Option<usize>, default: &str| {
+            i.and_then(|i| self.point_info.get(i))
+                .filter(|info| info.name != "")
+                .map(|info| clean(&info.name))
+                .unwrap_or(default.to_string())
+        };
+
+        let date = match self.start_time.split_once('T') {
+            Some((d, _)) => d.to_string(),
+            None => Params::today(),
+        };
+
+        template.replace("{date}", &date)
+            .replace("{start}", &name(Some(0), "start"))
+            .replace("{end}", &name(self.points.len().checked_sub(1), "end"))
+            .replace("{length_km}", &format!("{:.1}", length/1000.0))
+    }
+
+    // Declination of the bearings shown: the magnetic declination, or zero
+    // for bearings from true north
+    pub fn north_declination(&self) -> f32 {
+        if self.north == "true" { 0.0 } else { self.declination }
+    }
+
+    // Magnetic declination (degrees, east positive) at the middle of the
+    // waypoints, on the date of start_time or today, by the magnetic model
+    fn model_declination(&self) -> Result<f32, String> {
+        if self.points.is_empty() {
+            return Err("No waypoints".to_string());
+        }
+
+        let model = magnetic_model()?;
+        let (mut lat, mut lon) = (0.0, 0.0);
+
+        for p in &self.points {
+            let (la, lo) = to_latlon(p);
+            lat += la;
+            lon += lo;
+        }
+
+        let n = self.points.len() as f64;
+        let date = match self.start_time.split_once('T') {
+            Some((d, _)) => d.to_string(),
+            None => Params::today(),
+        };
+        let ymd: Vec<f64> = date.split('-')
+            .filter_map(|v| v.parse().ok())
+            .collect();
+
+        if ymd.len() != 3 {
+            return Err(format!("Invalid date {}", date));
+        }
+
+        // Precise enough for the yearly change of the field
+        let year = ymd[0] + ((ymd[1] - 1.0)*30.44 + ymd[2] - 1.0)/365.25;
+        let declination = model.declination(lat/n, lon/n, 0.0, year);
+
+        println!("Declination {:.1}° by {}", declination, model.name());
+
+        Ok((declination*10.0).round() as f32/10.0)
+    }
+
+    // Today's date (UTC) as YYYY-MM-DD
+    fn today() -> String {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs()).unwrap_or(0);
+        // Civil date from days since the epoch (Howard Hinnant's algorithm)
+        let z = (secs/86400) as i64 + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era*146097;
+        let yoe = (doe - doe/1460 + doe/36524 - doe/146096)/365;
+        let doy = doe - (365*yoe + yoe/4 - yoe/100);
+        let mp = (5*doy + 2)/153;
+        let day = doy - (153*mp + 2)/5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era*400 + if month <= 2 { 1 } else { 0 };
+
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+
     pub fn print_params(&self) {
         if self.points.is_empty() {
             println!("No waypoints");
         }
         else {
             println!("Waypoints:");
-            for p in &self.points {
-                println!("  {}", p);
+            for (p, info) in self.points.iter().zip(&self.point_info) {
+                let mut line = format!("  {}", p);
+                if info.name != "" {
+                    line.push_str(&format!(" name \"{}\"", info.name));
+                }
+                if info.target != "" {
+                    line.push_str(&format!(" target {}", info.target));
+                }
+                if info.radius > 0.0 {
+                    line.push_str(&format!(" radius {}", info.radius));
+                }
+                if info.kind != "" {
+                    line.push_str(&format!(" type {}", info.kind));
+                }
+                println!("{}", line);
             }
         }
         if self.barriers.is_empty() {
@@ -112,15 +690,506 @@ impl Params {
                 println!("  {}", b);
             }
         }
+        if !self.gates.is_empty() {
+            println!("Gates:");
+            for g in &self.gates {
+                println!("  {}", g);
+            }
+        }
+        if self.corridor.len() > 0 {
+            println!("Corridor:");
+            println!("  {}", self.corridor);
+        }
+        if !self.fords.is_empty() {
+            println!("Fords:");
+            for f in &self.fords {
+                println!("  {}", f);
+            }
+        }
+        if !self.pins.is_empty() {
+            println!("Pinned points:");
+            for p in &self.pins {
+                println!("  {}", p);
+            }
+        }
+        if !self.pin_areas.is_empty() {
+            println!("Pinned areas:");
+            for a in &self.pin_areas {
+                println!("  {}", a);
+            }
+        }
+        if !self.exits.is_empty() {
+            println!("Exits:");
+            for e in &self.exits {
+                println!("  {}", e);
+            }
+        }
+        if !self.group.is_empty() {
+            println!("Group:");
+            for h in &self.group {
+                println!("  {}", h);
+            }
+        }
 
         println!("grid_size_pass1:  {}", self.grid_size_pass1);
         println!("grid_size_pass2:  {}", self.grid_size_pass2);
         println!("covering_length:  {}", self.covering_length);
         println!("covering_width:   {}", self.covering_width);
         println!("path_width_pass2: {}", self.path_width_pass2);
+        if !self.passes.is_empty() {
+            println!("passes:           {}", self.passes.iter()
+                     .map(|(gs, w)| format!("{}:{}", gs, w))
+                     .collect::<Vec<String>>()
+                     .join(","));
+        }
+        println!("grid_topology:    {}", &self.grid_topology);
+        println!("grid_orientation: {}", self.grid_orientation);
+        println!("connectivity:     {}", self.connectivity);
+        println!("ford_radius:      {}", self.ford_radius);
         println!("params_name:      {}", &self.params_fname);
         println!("output_fname:     {}", &self.output_fname);
         println!("track_name:       {}", &self.track_name);
+        println!("cost_model:       {}", &self.cost_model);
+        if !self.cost_curve.is_empty() {
+            println!("cost_curve:       {}", self.cost_curve.iter()
+                     .map(|(slope, speed)| format!("{}°={}km/h", slope, speed))
+                     .collect::<Vec<String>>()
+                     .join(" "));
+        }
+        println!("cost_script:      {}", &self.cost_script);
+        println!("aspect_multipliers: {}", ASPECT_NAMES.iter()
+                 .zip(&self.aspect_multipliers)
+                 .map(|(a, m)| format!("{}={}", a, m))
+                 .collect::<Vec<String>>()
+                 .join(" "));
+        println!("start_time:       {}", &self.start_time);
+        println!("utc_offset:       {}", self.utc_offset);
+        println!("darkness_factor:  {}", self.darkness_factor);
+        println!("deadline:         {}", &self.deadline);
+        println!("season:           {}", &self.season);
+        println!("camp_max_slope:   {}", self.camp_max_slope);
+        println!("camp_corridor:    {}", self.camp_corridor);
+        println!("camp_water_distance: {}", self.camp_water_distance);
+        println!("escape_max_time:  {}", self.escape_max_time);
+        println!("deviation_alarm:  {}", self.deviation_alarm);
+        println!("km_markers:       {}", self.km_markers);
+        println!("export_km_markers: {}", self.export_km_markers);
+        println!("access_distance:  {}", self.access_distance);
+        println!("return_penalty:   {}", self.return_penalty);
+        println!("return_distance:  {}", self.return_distance);
+        println!("body_mass:        {}", self.body_mass);
+        println!("fluid_rate:       {}", self.fluid_rate);
+        println!("max_memory_mb:    {}", self.max_memory_mb);
+        println!("impassable_slope: {}", self.impassable_slope);
+        println!("cross_slope_weight: {}", self.cross_slope_weight);
+        println!("roughness_weight: {}", self.roughness_weight);
+        println!("landmarks:        {}", self.landmarks);
+        println!("ascent_threshold: {}", self.ascent_threshold);
+        println!("declination:      {}", self.declination);
+        println!("north:            {}", &self.north);
+        println!("symbology:        {}", &self.symbology);
+        println!("symbols:          {}", &self.symbols);
+        println!("optimize_split:   {}", self.optimize_split);
+        println!("optimize_join:    {}", self.optimize_join);
+        println!("optimize_probe:   {}", self.optimize_probe);
+        println!("optimize_range:   {}", self.optimize_range);
+        println!("optimize_convergence: {}", self.optimize_convergence);
+        println!("optimize_method:  {}", &self.optimize_method);
+    }
+
+    // Differences between two lists, compared by their display form.
+    // Returns lines '<name> <n>: <a> -> <b>', with '-' for a missing item.
+    fn diff_list<T: fmt::Display>(name: &str, a: &[T], b: &[T])
+                                  -> Vec<String> {
+        let mut lines = vec![];
+
+        for i in 0..a.len().max(b.len()) {
+            let sa = a.get(i).map(|x| x.to_string());
+            let sb = b.get(i).map(|x| x.to_string());
+
+            if sa != sb {
+                lines.push(format!("{} {}: {} -> {}", name, i + 1,
+                                   sa.as_deref().unwrap_or("-"),
+                                   sb.as_deref().unwrap_or("-")));
+            }
+        }
+
+        lines
+    }
+
+    // Differences between these params and another set of params. The
+    // waypoints, barriers, gates, fords, pins, exits, group and corridor are
+    // compared item by item, the other settings by value. The file name is
+    // not compared.
+    pub fn diff(&self, other: &Params) -> Vec<String> {
+        let mut lines = vec![];
+
+        let points = |p: &Params| -> Vec<String> {
+            p.points.iter().zip(&p.point_info)
+                .map(|(c, info)| {
+                    let mut s = c.to_string();
+                    if info.name != "" {
+                        s.push_str(&format!(" name \"{}\"", info.name));
+                    }
+                    if info.target != "" {
+                        s.push_str(&format!(" target {}", info.target));
+                    }
+                    if info.radius > 0.0 {
+                        s.push_str(&format!(" radius {}", info.radius));
+                    }
+                    if info.kind != "" {
+                        s.push_str(&format!(" type {}", info.kind));
+                    }
+                    s
+                })
+                .collect()
+        };
+
+        lines.append(&mut Params::diff_list("point", &points(self),
+                                            &points(other)));
+        lines.append(&mut Params::diff_list("barrier", &self.barriers,
+                                            &other.barriers));
+        lines.append(&mut Params::diff_list("gate", &self.gates,
+                                            &other.gates));
+        lines.append(&mut Params::diff_list("ford", &self.fords,
+                                            &other.fords));
+        lines.append(&mut Params::diff_list("pin", &self.pins,
+                                            &other.pins));
+        lines.append(&mut Params::diff_list("pin area", &self.pin_areas,
+                                            &other.pin_areas));
+        lines.append(&mut Params::diff_list("exit", &self.exits,
+                                            &other.exits));
+        lines.append(&mut Params::diff_list("hiker", &self.group,
+                                            &other.group));
+
+        if self.corridor.to_string() != other.corridor.to_string() {
+            lines.push(format!("corridor: {} -> {}", self.corridor,
+                               other.corridor));
+        }
+
+        // Remaining settings
+        let skip = ["points", "point_info", "barriers", "gates", "fords",
+                    "pins", "pin_areas", "exits", "group", "corridor",
+                    "params_fname"];
+        let (Ok(Value::Object(a)), Ok(Value::Object(b))) = (
+            serde_json::to_value(self), serde_json::to_value(other)) else {
+            return lines;
+        };
+
+        for (key, va) in &a {
+            if skip.contains(&key.as_str()) {
+                continue;
+            }
+
+            let vb = b.get(key).unwrap_or(&Value::Null);
+            if va != vb {
+                lines.push(format!("{}: {} -> {}", key, va, vb));
+            }
+        }
+
+        lines
+    }
+
+    pub fn print_diff(&self, other: &Params) {
+        let lines = self.diff(other);
+
+        if lines.is_empty() {
+            println!("No differences");
+        }
+
+        for l in lines {
+            println!("{}", l);
+        }
+    }
+
+    // Whether a path point is pinned, i.e. it is one of the pinned points
+    // or it is inside a pinned area
+    pub fn is_pinned(&self, c: &Coord) -> bool {
+        self.pins.iter().any(|p| (*p - *c).abs() < PIN_TOLERANCE)
+            || self.pin_areas.iter().any(|a| a.closed().contains(c))
+    }
+
+    // Legs whose covering area (the ellipse searched between the waypoints)
+    // is crossed or touched by a line
+    pub fn covering_legs(&self, line: &Barrier) -> Vec<usize> {
+        let mut legs = vec![];
+
+        for i in 0..self.points.len().saturating_sub(1) {
+            let (a, b) = (self.points[i], self.points[i + 1]);
+            let o = (a + b)*0.5;
+            let r = (a - o).abs();
+
+            if r == 0.0 {
+                continue;
+            }
+
+            // Map the line into coordinates where the ellipse is the unit
+            // circle
+            let (ce, cn) = ((b.e - a.e)/(2.0*r), (b.n - a.n)/(2.0*r));
+            let major = r*self.covering_length;
+            let minor = r*self.covering_width;
+            let to_unit = |c: &Coord| {
+                let (de, dn) = (c.e - o.e, c.n - o.n);
+                ((de*ce + dn*cn)/major, (dn*ce - de*cn)/minor)
+            };
+            let q: Vec<(f32, f32)> = line.points.iter().map(to_unit).collect();
+
+            let inside = |(u, v): (f32, f32)| u*u + v*v <= 1.0;
+            let crossing = q.windows(2).any(|w| {
+                let ((u1, v1), (u2, v2)) = (w[0], w[1]);
+                let (du, dv) = (u2 - u1, v2 - v1);
+                let len_sq = du*du + dv*dv;
+                // Point of the segment nearest to the center
+                let t = if len_sq > 0.0 {
+                    (-(u1*du + v1*dv)/len_sq).clamp(0.0, 1.0)
+                }
+                else {
+                    0.0
+                };
+
+                inside((u1 + t*du, v1 + t*dv))
+            });
+
+            if crossing || q.iter().any(|p| inside(*p)) {
+                legs.push(i);
+            }
+        }
+
+        legs
+    }
+
+    // Gates to be crossed on each leg, in the order they are met. Each gate
+    // belongs to the leg passing nearest to its midpoint.
+    pub fn leg_gates(&self) -> Vec<Vec<Barrier>> {
+        let legs = self.points.len().saturating_sub(1);
+        let mut gates: Vec<Vec<(f32, Barrier)>> = vec![vec![]; legs];
+
+        if legs == 0 {
+            return vec![];
+        }
+
+        for g in &self.gates {
+            let nearest = self.gate_leg(g);
+            // Order by distance from the start of the leg
+            let mid = (g.points[0] + g.points[g.len() - 1])*0.5;
+            let along = (mid - self.points[nearest]).abs();
+            gates[nearest].push((along, g.clone()));
+        }
+
+        gates.into_iter()
+            .map(|mut l| {
+                l.sort_by(|a, b| a.0.total_cmp(&b.0));
+                l.into_iter().map(|(_, g)| g).collect()
+            })
+            .collect()
+    }
+
+    // The leg passing nearest to the midpoint of a gate
+    fn gate_leg(&self, g: &Barrier) -> usize {
+        let mid = (g.points[0] + g.points[g.len() - 1])*0.5;
+        let mut dsq_min = f32::INFINITY;
+        let mut nearest = 0;
+
+        for i in 0..self.points.len().saturating_sub(1) {
+            let leg = Barrier::from_vec(
+                vec![self.points[i], self.points[i + 1]]);
+            let dsq = leg.distance_sq(&mid);
+            if dsq < dsq_min {
+                dsq_min = dsq;
+                nearest = i;
+            }
+        }
+
+        nearest
+    }
+
+    // Start the route at c instead of waypoint n. The waypoints before n,
+    // and the gates of their legs, are removed.
+    pub fn start_from(&mut self, n: usize, c: Coord) {
+        let passed: Vec<bool> = self.gates.iter()
+            .map(|g| self.gate_leg(g) < n)
+            .collect();
+        let mut keep = passed.into_iter().map(|p| !p);
+        self.gates.retain(|_| keep.next().unwrap_or(true));
+
+        self.points.drain(..n);
+        self.point_info.drain(..n);
+        self.points[0] = c;
+        self.point_info[0] = PointInfo::default();
+    }
+
+    // Symbols of the map. Invalid settings in params files are ignored.
+    pub fn symbology(&self) -> Symbology {
+        Symbology::new(&self.symbology, &self.symbols).unwrap_or_default()
+    }
+
+    // Waypoints written to GPX files, with their GPX symbol: the labelled
+    // waypoints and the distance markers along the path, if enabled by
+    // export_km_markers
+    pub fn export_waypoints(&self, path: &Path)
+                            -> Vec<(Coord, String, String)> {
+        let symbol = |kind: &str| POINT_TYPES.iter()
+            .find(|(t, _)| *t == kind)
+            .map(|(_, sym)| sym.to_string())
+            .unwrap_or_default();
+
+        let mut waypoints: Vec<(Coord, String, String)> = self
+            .labelled_points().into_iter()
+            .zip(&self.point_info)
+            .map(|((c, label), info)| (c, label, symbol(&info.kind)))
+            .collect();
+        waypoints.extend(path.distance_markers(self.export_km_markers*1000.0)
+                         .into_iter()
+                         .map(|(c, label)| (c, label, "".to_string())));
+        waypoints
+    }
+
+    // Pace multipliers of the fastest and slowest member of the group
+    pub fn group_paces(&self) -> Option<(f32, f32)> {
+        let paces = self.group.iter().map(|h| h.pace());
+        let fastest = paces.clone().reduce(f32::min)?;
+        let slowest = paces.reduce(f32::max)?;
+
+        Some((fastest, slowest))
+    }
+
+    // Grid size and corridor half width of each pass
+    pub fn passes(&self) -> Vec<(f32, f32)> {
+        if self.passes.is_empty() {
+            vec![(self.grid_size_pass1, 0.0),
+                 (self.grid_size_pass2, self.path_width_pass2)]
+        }
+        else {
+            self.passes.clone()
+        }
+    }
+
+    // Parse a comma separated list of passes <grid size>:<width>, e.g.
+    // '50,5:30,1:10'. The width may be left out for the first pass.
+    fn parse_passes(value: &str) -> Result<Vec<(f32, f32)>, String> {
+        if value == "default" {
+            return Ok(vec![]);
+        }
+
+        let mut passes = vec![];
+
+        for (i, p) in value.split(',').enumerate() {
+            let (gs, w) = match p.split_once(':') {
+                Some((gs, w)) => (Params::parse_float(gs)?,
+                                  Params::parse_float(w)?),
+                None if i == 0 => (Params::parse_float(p)?, 0.0),
+                None => {
+                    return Err(format!("Expected <grid size>:<width>, got \
+                                        '{}'", p));
+                },
+            };
+
+            if gs <= 0.0 {
+                return Err(format!("Invalid grid size '{}'", gs));
+            }
+
+            passes.push((gs, w));
+        }
+
+        Ok(passes)
+    }
+
+    // Insert a waypoint at position n, with empty attributes
+    pub fn insert_point(&mut self, n: usize, c: Coord) {
+        self.points.insert(n, c);
+        self.point_info.insert(n, PointInfo::default());
+    }
+
+    pub fn clear_points(&mut self) {
+        self.points.clear();
+        self.point_info.clear();
+    }
+
+    pub fn push_point(&mut self, c: Coord) {
+        self.insert_point(self.points.len(), c);
+    }
+
+    // Waypoints with their labels
+    pub fn labelled_points(&self) -> Vec<(Coord, String)> {
+        let n = self.points.len();
+
+        self.points.iter().zip(&self.point_info).enumerate()
+            .map(|(i, (c, info))| (*c, point_label(&info.name, i, n)))
+            .collect()
+    }
+
+    pub fn point_names(&self) -> Vec<String> {
+        self.point_info.iter().map(|info| info.name.clone()).collect()
+    }
+
+    pub fn point_types(&self) -> Vec<String> {
+        self.point_info.iter().map(|info| info.kind.clone()).collect()
+    }
+
+    pub fn remove_point(&mut self, n: usize) {
+        self.points.remove(n);
+        self.point_info.remove(n);
+    }
+
+    // Set an attribute of waypoint n
+    pub fn set_point(&mut self, n: usize, attr: &str, value: &str)
+                     -> Result<(), String> {
+        let info = &mut self.point_info[n];
+
+        match attr {
+            "name" => {
+                info.name = if value == "none" { "" } else { value }
+                    .to_string();
+            },
+            "target" => {
+                if value == "none" {
+                    info.target = "".to_string();
+                }
+                else {
+                    parse_target(value)?;
+                    info.target = value.to_string();
+                }
+            },
+            "radius" => {
+                info.radius = Params::parse_float(value)?;
+            },
+            "type" => {
+                if value == "none" {
+                    info.kind = "".to_string();
+                }
+                else if POINT_TYPES.iter().any(|(t, _)| *t == value) {
+                    info.kind = value.to_string();
+                }
+                else {
+                    return Err(format!(
+                        "Unknown type '{}'. Expected one of {}", value,
+                        POINT_TYPES.iter().map(|(t, _)| *t)
+                            .collect::<Vec<&str>>().join(", ")));
+                }
+            },
+            s => {
+                return Err(format!("Invalid point attribute '{}'", s));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Set an attribute of barrier n
+    pub fn set_barrier(&mut self, n: usize, attr: &str, value: &str)
+                       -> Result<(), String> {
+        let barrier = &mut self.barriers[n];
+
+        match attr {
+            "buffer" => {
+                barrier.buffer_m = Params::parse_float(value)?;
+            },
+            s => {
+                return Err(format!("Invalid barrier attribute '{}'", s));
+            }
+        }
+
+        Ok(())
     }
 
     fn parse_float(value: &str) -> Result<f32, String> {
@@ -132,6 +1201,38 @@ impl Params {
         }
     }
 
+    // Parse a comma separated list of floats
+    fn parse_float_list(value: &str, len: usize) -> Result<Vec<f32>, String> {
+        let list = value.split(',')
+            .map(|v| Params::parse_float(v.trim()))
+            .collect::<Result<Vec<f32>, String>>()?;
+
+        if list.len() != len {
+            return Err(format!("Expected {} comma separated values", len));
+        }
+
+        Ok(list)
+    }
+
+    fn set_season(&mut self, name: &str) -> Result<(), String> {
+        let Some((_, preset)) = SEASONS.iter().find(|(s, _)| *s == name)
+        else {
+            return Err(format!("Invalid season '{}'. Expected one of {}",
+                               name, SEASONS.iter()
+                               .map(|(s, _)| *s)
+                               .collect::<Vec<&str>>()
+                               .join(", ")));
+        };
+
+        for (param, value) in preset.iter() {
+            self.set(param, value)?;
+        }
+
+        self.season = name.to_string();
+
+        Ok(())
+    }
+
     pub fn set(&mut self, param: &str, value: &str) -> Result<(), String> {
         match param {
             "grid_size_pass1" => {
@@ -149,6 +1250,32 @@ impl Params {
             "path_width_pass2" => {
                 self.path_width_pass2 = Params::parse_float(value)?;
             },
+            "passes" => {
+                self.passes = Params::parse_passes(value)?;
+            },
+            "grid_topology" => {
+                if value != "square-8" && value != "hex" {
+                    return Err(format!("Invalid grid topology '{}'. \
+                                        Expected square-8 or hex", value));
+                }
+                self.grid_topology = value.to_string();
+            },
+            "grid_orientation" => {
+                self.grid_orientation = Params::parse_float(value)?;
+            },
+            "connectivity" => {
+                match value {
+                    "8" => { self.connectivity = 8; },
+                    "16" => { self.connectivity = 16; },
+                    s => {
+                        return Err(format!("Invalid connectivity '{}'. \
+                                            Expected 8 or 16", s));
+                    },
+                }
+            },
+            "ford_radius" => {
+                self.ford_radius = Params::parse_float(value)?;
+            },
             /*
             "params_fname" => {
                 self.params_fname = value.to_string()
@@ -160,6 +1287,154 @@ impl Params {
             "track_name" => {
                 self.track_name = value.to_string()
             },
+            "cost_model" => {
+                cost_model(value, &self.cost_curve)?;
+                self.cost_model = value.to_string();
+            },
+            "cost_curve" => {
+                // Read curve from file and select the curve model
+                self.cost_curve = read_cost_curve(value)?;
+                self.cost_model = "curve".to_string();
+            },
+            "cost_script" => {
+                if value == "none" {
+                    self.cost_script = "".to_string();
+                }
+                else {
+                    CostScript::load(value)?;
+                    self.cost_script = value.to_string();
+                }
+            },
+            "aspect_multipliers" => {
+                self.aspect_multipliers = Params::parse_float_list(value, 8)?;
+            },
+            "start_time" => {
+                parse_date_time(value)?;
+                self.start_time = value.to_string();
+            },
+            "utc_offset" => {
+                self.utc_offset = Params::parse_float(value)?;
+            },
+            "darkness_factor" => {
+                self.darkness_factor = Params::parse_float(value)?;
+            },
+            "deadline" => {
+                if value == "none" {
+                    self.deadline = "".to_string();
+                }
+                else {
+                    parse_target(value)?;
+                    self.deadline = value.to_string();
+                }
+            },
+            "season" => {
+                self.set_season(value)?;
+            },
+            "camp_max_slope" => {
+                self.camp_max_slope = Params::parse_float(value)?;
+            },
+            "camp_corridor" => {
+                self.camp_corridor = Params::parse_float(value)?;
+            },
+            "camp_water_distance" => {
+                self.camp_water_distance = Params::parse_float(value)?;
+            },
+            "escape_max_time" => {
+                self.escape_max_time = Params::parse_float(value)?;
+            },
+            "deviation_alarm" => {
+                self.deviation_alarm = Params::parse_float(value)?;
+            },
+            "km_markers" => {
+                self.km_markers = Params::parse_float(value)?;
+            },
+            "export_km_markers" => {
+                self.export_km_markers = Params::parse_float(value)?;
+            },
+            "access_distance" => {
+                self.access_distance = Params::parse_float(value)?;
+            },
+            "return_penalty" => {
+                self.return_penalty = Params::parse_float(value)?;
+            },
+            "return_distance" => {
+                self.return_distance = Params::parse_float(value)?;
+            },
+            "body_mass" => {
+                self.body_mass = Params::parse_float(value)?;
+            },
+            "fluid_rate" => {
+                self.fluid_rate = Params::parse_float(value)?;
+            },
+            "max_memory_mb" => {
+                self.max_memory_mb = Params::parse_float(value)?;
+            },
+            "impassable_slope" => {
+                let slope = Params::parse_float(value)?;
+                if slope <= 0.0 || slope > 90.0 {
+                    return Err("Expected slope in range 0..90".to_string());
+                }
+                self.impassable_slope = slope;
+            },
+            "cross_slope_weight" => {
+                self.cross_slope_weight = Params::parse_float(value)?;
+            },
+            "roughness_weight" => {
+                self.roughness_weight = Params::parse_float(value)?;
+            },
+            "declination" => {
+                self.declination = if value == "auto" {
+                    self.model_declination()?
+                }
+                else {
+                    Params::parse_float(value)?
+                };
+            },
+            "north" => {
+                if value != "magnetic" && value != "true" {
+                    return Err(format!("Expected magnetic or true, got '{}'",
+                                       value));
+                }
+                self.north = value.to_string();
+            },
+            "symbology" => {
+                Symbology::new(value, &self.symbols)?;
+                self.symbology = value.to_string();
+            },
+            "symbols" => {
+                let value = if value == "none" { "" } else { value };
+                Symbology::new(&self.symbology, value)?;
+                self.symbols = value.to_string();
+            },
+            "ascent_threshold" => {
+                self.ascent_threshold = Params::parse_float(value)?;
+            },
+            "landmarks" => {
+                self.landmarks = value.parse::<usize>()
+                    .map_err(|_| format!("Invalid value '{}'", value))?;
+            },
+            "optimize_split" => {
+                self.optimize_split = Params::parse_float(value)?;
+            },
+            "optimize_join" => {
+                self.optimize_join = Params::parse_float(value)?;
+            },
+            "optimize_probe" => {
+                self.optimize_probe = Params::parse_float(value)?;
+            },
+            "optimize_range" => {
+                self.optimize_range = Params::parse_float(value)?;
+            },
+            "optimize_convergence" => {
+                self.optimize_convergence = Params::parse_float(value)?;
+            },
+            "optimize_method" => {
+                if value != "adaptive" && value != "probe" {
+                    return Err(format!("Invalid optimize method '{}'. \
+                                        Expected adaptive or probe", value));
+                }
+                self.optimize_method = value.to_string();
+            },
             s => {
                 return Err(format!("Invalid parameter '{}'", s));
             }
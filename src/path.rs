@@ -1,15 +1,39 @@
+use crate::barrier::Barrier;
+use crate::cost::Cost;
+use crate::crs::{bearing, from_latlon, to_latlon};
+use crate::debug::{DebugLog, SearchArea};
+use crate::elevation::{ElevationSource, intersect_bounds};
 use crate::field::Field;
+use crate::fieldcache::FieldCache;
 use crate::graph::Graph;
 use crate::params::Params;
+use crate::stats::PathStats;
 
 use core::slice::Iter;
 use geo_types::Point;
 use gpx::{Gpx, GpxVersion, Metadata, Track, TrackSegment, Waypoint};
-use hoydedata::{Atlas, Coord};
+use hoydedata::Coord;
+use parking_lot::Mutex;
+use rayon::prelude::*;
 use std::fmt;
+use std::ops::Range;
 use std::{fs::File, io::BufWriter};
 use std::io::BufReader;
 
+// Distance (meters) within which the end of a leg is taken to be at a point
+const LEG_TOLERANCE: f32 = 1.0;
+// Adaptive relaxation: factors for the step of a point after a move which
+// improved the time and after one which didn't, and the part of the last
+// move carried over to the next.
+const STEP_GROW: f32 = 1.5;
+const STEP_SHRINK: f32 = 0.5;
+const MOMENTUM: f32 = 0.5;
+// Smallest sampling interval (meters) of elevation profiles
+const MIN_PROFILE_STEP: f32 = 0.1;
+// Sampling interval (meters) for adding up ascent and descent when small
+// elevation changes are filtered out
+const ASCENT_STEP: f32 = 2.0;
+
 #[derive(Clone)]
 pub struct Segment {
     pub a: Coord,
@@ -68,7 +92,8 @@ impl Segment {
 
     // Calculate cost of walking the segment. Input is an atlas of height
     // maps. Output is a cost value.
-    pub fn time(&self, atlas: &Atlas) -> Option<f32> {
+    pub fn time(&self, atlas: &dyn ElevationSource, cost: &Cost)
+                -> Option<f32> {
         let mut time = 0.0;
 
         let (be, bn, ae, an) = (self.b.e, self.b.n, self.a.e, self.a.n);
@@ -77,23 +102,84 @@ impl Segment {
         let dn = (bn - an)/r;
 
         for (f, l) in self.fields() {
-            let (_, dx, dy) = atlas.lookup_with_gradient(&f.into()).unwrap();
-            // If absolute gradient is too high (45 degrees), return None
-            let abs = dx*dx + dy*dy;
-            if abs > 1.0 {
-                return None;
-            }
-
-            let s = de*dx + dn*dy;
-            // den*dxy = de*dx + dn*dy = |den||dxy|*cos(phi)
-            time += l*Segment::time_by_steepness(s, abs);
+            let c = f.into();
+            let (_, dx, dy) = atlas.lookup_with_gradient(&c)?;
+            time += l*cost.field_time(de, dn, dx, dy)?
+                *cost.roughness_multiplier(atlas, &c);
         }
 
         return Some(time);
     }
 
+    // Elevation profile of the segment, sampled every step meters and at
+    // the end point. Yields (distance, elevation, slope) with the slope in
+    // degrees along the segment, positive uphill. The slope is taken from
+    // the gradient of the field the sample is in.
+    pub fn profile<'a>(&self, atlas: &'a dyn ElevationSource, step: f32)
+                       -> impl Iterator<Item = (f32, f32, f32)> + 'a {
+        let (a, b) = (self.a, self.b);
+        let len = self.len();
+        let step = step.max(MIN_PROFILE_STEP);
+        let (de, dn) = if len > 0.0 {
+            ((b.e - a.e)/len, (b.n - a.n)/len)
+        }
+        else {
+            (0.0, 0.0)
+        };
+        let sample = move |f: Field, d: f32| {
+            let c = if len > 0.0 { a + (b - a)*(d/len) } else { a };
+            let (_, dx, dy) = atlas.lookup_with_gradient(&f.into())?;
+            let h = atlas.lookup(&c)?;
+            Some((d, h, (dx*de + dy*dn).atan().to_degrees()))
+        };
+
+        // Distance to the start of the current field and to the next sample
+        let mut start = 0.0;
+        let mut next = 0.0;
+
+        self.fields()
+            .flat_map(move |(f, l)| {
+                let end = start + l;
+                let mut samples = vec![];
+
+                while next < end {
+                    samples.extend(sample(f, next));
+                    next += step;
+                }
+
+                start = end;
+                samples
+            })
+            .chain(sample(Field::from(b), len))
+    }
+
+    // Calculate energy (joule) spent walking the segment
+    pub fn energy(&self, atlas: &dyn ElevationSource, cost: &Cost) -> f32 {
+        let mut energy = 0.0;
+
+        let (be, bn, ae, an) = (self.b.e, self.b.n, self.a.e, self.a.n);
+        let r = ((be - ae)*(be - ae) + (bn - an)*(bn - an)).sqrt();
+
+        if r == 0.0 {
+            return 0.0;
+        }
+
+        let de = (be - ae)/r;
+        let dn = (bn - an)/r;
+
+        for (f, l) in self.fields() {
+            let Some((_, dx, dy)) = atlas.lookup_with_gradient(&f.into())
+            else {
+                continue;
+            };
+            energy += l*cost.field_energy(de, dn, dx, dy);
+        }
+
+        return energy;
+    }
+
     // Calculate uphill height meters along the segment
-    pub fn height(&self, atlas: &Atlas) -> f32 {
+    pub fn height(&self, atlas: &dyn ElevationSource) -> f32 {
         let mut height = 0.0;
 
         let (be, bn, ae, an) = (self.b.e, self.b.n, self.a.e, self.a.n);
@@ -107,7 +193,10 @@ impl Segment {
         let dn = (bn - an)/r;
 
         for (f, l) in self.fields() {
-            let (_, dx, dy) = atlas.lookup_with_gradient(&f.into()).unwrap();
+            let Some((_, dx, dy)) = atlas.lookup_with_gradient(&f.into())
+            else {
+                continue;
+            };
             let s = de*dx + dn*dy;
             height += if s < 0.0 { 0.0 } else { s*l };
         }
@@ -165,71 +254,597 @@ impl Iterator for SegmentIterator {
     }
 }
 
+// Smallest distance (meters) between two f32 coordinates near c. With UTM
+// northings around 7000 km, this is half a meter.
+fn coord_resolution(c: &Coord) -> f32 {
+    let m = c.e.abs().max(c.n.abs());
+
+    f32::from_bits(m.to_bits() + 1) - m
+}
+
+// Length (m), ascent (m), descent (m) and time (s) of a part of a path
+pub struct Stretch {
+    pub length: f32,
+    pub ascent: f32,
+    pub descent: f32,
+    pub time: f32,
+}
+
+// Statistics of a path, with what they were computed for: the waypoints,
+// the generation of the cost and the elevation source
+#[derive(Debug, Default)]
+struct StatsCache(Mutex<Option<(StatsKey, PathStats)>>);
+
+type StatsKey = (Vec<Coord>, (u64, u64), usize);
+
+impl Clone for StatsCache {
+    fn clone(&self) -> Self {
+        StatsCache(Mutex::new(self.0.lock().clone()))
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Path {
+    // The points are hoydedata's f32 coordinates, with a resolution of half
+    // a meter at UTM northings. This doesn't add up along the path, as the
+    // sums over the segments are accumulated in f64.
     points: Vec<Coord>,
+    // Index of the first point of each leg, when computed from waypoints.
+    // The last point of a leg is the first point of the next.
+    legs: Vec<usize>,
+    // Distance (m) and modeled time (s) from the start to each point. Empty
+    // if not computed, and cleared when the points change.
+    cumulative: Vec<(f32, f32)>,
+    // The last statistics. Walking every segment against the atlas is slow
+    // on long routes, so they are kept until the points change.
+    stats_cache: StatsCache,
 }
 
 impl Path {
     pub fn new() -> Self {
         Self {
             points: vec!(),
+            legs: vec![],
+            cumulative: vec![],
+            stats_cache: StatsCache::default(),
         }
     }
 
     // Create path from a vector of points. First, use graph shortest path, i
     // order to establish a start path. Then optimize the path using iterative
     // relaxation.
-    pub fn from_points(params: &Params, atlas: &Atlas) -> Option<Self> {
+    pub fn from_points(params: &Params, atlas: &dyn ElevationSource)
+                       -> Option<Self> {
+        Path::from_points_with_debug(params, atlas, &mut DebugLog::new(),
+                                     None)
+    }
+
+    // Same as from_points, collecting information about the computation in
+    // the debug log. The pinned points of an old path with the same number
+    // of legs are kept.
+    pub fn from_points_with_debug(params: &Params, atlas: &dyn ElevationSource,
+                                  debug: &mut DebugLog, old: Option<&Path>)
+                                  -> Option<Self> {
         let points = &params.points;
         let len = points.len();
 
         assert!(len >= 2);
         let mut path = Path::new();
+        let old = old.filter(|o| o.legs.len() + 1 == len
+                             && o.points.iter().any(|c| params.is_pinned(c)));
+
+        // Start of the next leg. This is the end of the previous leg, which
+        // may differ from the waypoint if it has a radius.
+        let mut start = points[0];
 
         for i in 0..len - 1 {
-            // Find a start path using a shortest path algorithm over a graph
-            // of points in the area between the start and end points.
-            let mut g = Graph::new(points[i], points[i + 1], params);
-            println!("Building first pass graph...");
-            g.build_graph_from_end_points(atlas);
-            println!("First pass graph: {} nodes, {} edges", g.num_nodes(),
-                     g.num_edges());
-            println!("Finding shortest path...");
+            let mut p = match old {
+                Some(o) => {
+                    let (a, b) = o.leg_range(i);
+                    Path::find_pinned_leg(params, atlas, debug, i, start,
+                                          &o.points[a..=b], &path)?
+                },
+                None => Path::find_leg(params, atlas, debug, i, start,
+                                       &path)?,
+            };
+            start = p.points[p.points.len() - 1];
+            path.legs.push(path.points.len().saturating_sub(1));
+            path.append(&mut p);
+        }
+
+        path.update_cumulative(atlas, &Cost::from_params(params));
+        return Some(path);
+    }
+
+    // Find the path of leg i from start to within the radius of the next
+    // waypoint. The path walked before the leg is avoided if there is a
+    // return penalty.
+    pub fn find_leg(params: &Params, atlas: &dyn ElevationSource,
+                debug: &mut DebugLog, i: usize, start: Coord, walked: &Path)
+                -> Option<Path> {
+        let end = params.points[i + 1];
+        let radius = params.point_info.get(i + 1)
+            .map(|info| info.radius)
+            .unwrap_or(0.0);
+        let gates = params.leg_gates()[i].clone();
+
+        Path::find_section(params, atlas, debug, i, (start, end, radius),
+                           gates, walked)
+    }
 
-            if let Some(p) = g.shortest_path() {
-                println!("First pass path: {} points, {}m", p.points.len(),
-                         p.len());
-                let mut g2 = Graph::new(points[i], points[i + 1], params);
-                println!("Building second pass graph...");
-                g2.build_graph_from_path(&p, atlas);
-                println!("Second pass graph: {} nodes, {} edges",
-                         g2.num_nodes(), g2.num_edges());
-                println!("Finding shortest path...");
-
-                if let Some(mut p2) = g2.shortest_path() {
-                    println!("Second pass path: {} points, {}m",
-                             p2.points.len(), p2.len());
-                    println!("Local optimization...");
-                    p2.optimize(atlas);
-                    println!("Final path: {} points, {}m", p2.points.len(),
-                             p2.len());
-                    path.append(&mut p2);
+    // Find the path of a section of leg i, from start to within a radius of
+    // end, crossing the gates in order
+    fn find_section(params: &Params, atlas: &dyn ElevationSource,
+                    debug: &mut DebugLog, i: usize,
+                    (start, end, radius): (Coord, Coord, f32),
+                    gates: Vec<Barrier>, walked: &Path) -> Option<Path> {
+        // The passes and the optimization share the field lookups
+        let cache = FieldCache::new(atlas, params.max_memory_mb);
+        let atlas: &dyn ElevationSource = &cache;
+        let cost = Cost::from_params(params);
+        let passes = params.passes();
+        let bounds = intersect_bounds(atlas.bounds(), params.bounds);
+        let avoid = params.return_penalty > 1.0 && i > 0;
+        // Progress is counted in graph passes and optimizations
+        let legs = params.points.len() - 1;
+        let steps = legs*(passes.len() + 1);
+        let step = i*(passes.len() + 1);
+        let phase = |what: &str| format!("Leg {} of {}: {}", i + 1, legs,
+                                         what);
+
+        if debug.progress.is_cancelled() {
+            return None;
+        }
+        debug.progress.set_phase(phase("first pass"), step, steps);
+        debug.progress.set_best_time(None);
+
+        // Find a start path using a shortest path algorithm over a graph
+        // of points in the area between the start and end points.
+        let first_graph = |params: &Params| {
+            let mut g = Graph::new(start, end, params);
+            g.set_bounds(bounds);
+            g.set_gates(gates.clone());
+            g.set_end_radius(radius);
+            if avoid {
+                // Keep away from the legs already walked
+                g.avoid_path(walked);
+            }
+            g
+        };
+        let mut g = first_graph(params);
+        debug.search_areas.push(SearchArea {
+            leg: i,
+            pass: 0,
+            grid_size: passes[0].0,
+            polygon: g.ellipse(),
+        });
+        println!("Building first pass graph...");
+        g.build_graph_from_end_points(atlas);
+        println!("First pass graph: {} nodes, {} edges", g.num_nodes(),
+                 g.num_edges());
+        debug.progress.set_nodes(g.num_nodes());
+        println!("Finding shortest path...");
+
+        g.use_landmarks(params.landmarks, atlas);
+        debug.prepare_graph(&mut g);
+        let opt_p1 = g.shortest_path();
+        debug.progress.set_best_time(opt_p1.as_ref().map(|_| g.end_time()));
+        debug.graph_searched(&g);
+        debug.heatmap(&g);
+
+        let Some(p1) = opt_p1 else {
+            Path::report_no_path(&g, debug, i, 0);
+
+            // Tell if the leg is blocked only by the impassable slope
+            if params.impassable_slope < 90.0 {
+                let mut relaxed = params.clone();
+                relaxed.impassable_slope = 90.0;
+                let mut g = first_graph(&relaxed);
+                g.build_graph_from_end_points(atlas);
+
+                if let Some(p) = g.shortest_path() {
+                    println!("Leg {} is blocked only by slopes steeper than \
+                              impassable_slope ({}°). The path found \
+                              without the limit crosses {:.0}° slopes.",
+                             i + 1, params.impassable_slope,
+                             p.steepest_slope(atlas));
                 }
             }
-            else {
+
+            return None;
+        };
+        println!("First pass path: {} points, {}m", p1.points.len(),
+                 p1.len());
+        let mut p = g.prune_shortcuts(&p1, atlas);
+        println!("Pruned path: {} points, {}m", p.points.len(), p.len());
+
+        // Refine the path by searching graphs of finer grids in a
+        // corridor around the previous path.
+        for (n, (gs, width)) in passes.iter().enumerate().skip(1) {
+            if debug.progress.is_cancelled() {
                 return None;
             }
+            debug.progress.set_phase(phase(&format!("pass {}", n + 1)),
+                                     step + n, steps);
+
+            let mut g2 = Graph::new(start, end, params);
+            g2.set_refinement(*gs, *width);
+            g2.set_bounds(bounds);
+            g2.set_gates(gates.clone());
+            g2.set_end_radius(radius);
+            if avoid {
+                g2.avoid_path(walked);
+            }
+            debug.search_areas.push(SearchArea {
+                leg: i,
+                pass: n,
+                grid_size: *gs,
+                polygon: g2.corridor_outline(&p),
+            });
+            println!("Building pass {} graph...", n + 1);
+            g2.build_graph_from_path(&p, atlas);
+            println!("Pass {} graph: {} nodes, {} edges", n + 1,
+                     g2.num_nodes(), g2.num_edges());
+            debug.progress.set_nodes(g2.num_nodes());
+            println!("Finding shortest path...");
+
+            debug.prepare_graph(&mut g2);
+            let opt_p2 = g2.shortest_path();
+            if opt_p2.is_some() {
+                debug.progress.set_best_time(Some(g2.end_time()));
+            }
+            debug.graph_searched(&g2);
+
+            let Some(p2) = opt_p2 else {
+                Path::report_no_path(&g2, debug, i, n);
+                return None;
+            };
+            println!("Pass {} path: {} points, {}m", n + 1,
+                     p2.points.len(), p2.len());
+            p = p2;
         }
 
-        return Some(path);
+        if debug.progress.is_cancelled() {
+            return None;
+        }
+        debug.progress.set_phase(phase("local optimization"),
+                                 step + passes.len(), steps);
+
+        println!("Local optimization...");
+        p.optimize(atlas, &cost, params);
+        println!("Final path: {} points, {}m", p.points.len(), p.len());
+        Some(p)
+    }
+
+    // Tell why no path was found through the graph of a pass, and show the
+    // area reached from the start
+    fn report_no_path(g: &Graph, debug: &DebugLog, i: usize, pass: usize) {
+        let reached = g.reach_times().iter()
+            .filter(|t| t.is_finite())
+            .count();
+        println!("No path for leg {} in pass {}. {} of {} nodes can be \
+                  reached from the start.", i + 1, pass + 1, reached,
+                 g.num_nodes());
+
+        let reasons = g.diagnose();
+        if reasons.is_empty() {
+            println!("Nothing blocks the way out of the reached area. The \
+                      area searched may be too small.");
+        }
+
+        for (b, n) in reasons {
+            println!("Blocked by {} ({} edges)", b, n);
+        }
+
+        debug.no_path(g);
+    }
+
+    // Number of legs, if the path was computed from waypoints
+    pub fn num_legs(&self) -> usize {
+        self.legs.len()
+    }
+
+    // Indices of the first and last point of leg i
+    fn leg_range(&self, i: usize) -> (usize, usize) {
+        let last = self.legs.get(i + 1).cloned()
+            .unwrap_or(self.points.len() - 1);
+
+        (self.legs[i], last)
+    }
+
+    // Leg containing point k
+    pub fn leg_at(&self, k: usize) -> Option<usize> {
+        self.legs.iter().rposition(|l| *l <= k)
+    }
+
+    // Remove the legs before leg n, so that the path starts with leg n
+    pub fn remove_legs_before(&mut self, n: usize) {
+        let start = self.legs[n];
+
+        self.points.drain(..start);
+        self.legs.drain(..n);
+        for l in &mut self.legs {
+            *l -= start;
+        }
+        self.clear_derived();
+    }
+
+    // Points of leg i, including both ends
+    pub fn leg_points(&self, i: usize) -> &[Coord] {
+        let (a, b) = self.leg_range(i);
+
+        &self.points[a..=b]
+    }
+
+    // Recompute leg n in place, e.g. after moving a waypoint or adding a
+    // barrier. The leg starts at the end of the previous leg. If that is no
+    // longer at the waypoint, the previous legs are recomputed too. Likewise,
+    // the following legs are recomputed until one fits the new end. Returns
+    // the legs recomputed.
+    pub fn recompute_leg(&mut self, n: usize, params: &Params,
+                         atlas: &dyn ElevationSource, debug: &mut DebugLog)
+                         -> Result<Vec<usize>, String> {
+        let points = &params.points;
+
+        if self.legs.len() + 1 != points.len() {
+            return Err("The waypoints have changed since the path was \
+                        computed. Run compute.".to_string());
+        }
+
+        let radius = |k: usize| params.point_info.get(k)
+            .map(|info| info.radius)
+            .unwrap_or(0.0);
+        // Whether point c is at waypoint k
+        let at_waypoint = |c: Coord, k: usize| {
+            (c - points[k]).abs() <= radius(k) + LEG_TOLERANCE
+        };
+
+        let mut first = n;
+        while first > 0
+            && !at_waypoint(self.points[self.legs[first]], first) {
+            first -= 1;
+        }
+
+        let mut recomputed = vec![];
+        let mut i = first;
+
+        while i < self.legs.len() {
+            let (a, b) = self.leg_range(i);
+            let start = if i == 0 { points[0] } else { self.points[a] };
+            let walked = Path::from_vec(self.points[..=a].to_vec());
+
+            let old_leg = self.points[a..=b].to_vec();
+            let leg = Path::find_pinned_leg(params, atlas, debug, i, start,
+                                            &old_leg, &walked)
+                .ok_or(format!("Leg {} cannot be walked", i + 1))?;
+            let old_end = self.points[b];
+            self.replace_leg(i, leg);
+            recomputed.push(i);
+
+            let (_, b) = self.leg_range(i);
+            if i >= n && (self.points[b] - old_end).abs() < LEG_TOLERANCE {
+                break;
+            }
+
+            i += 1;
+        }
+
+        self.update_cumulative(atlas, &Cost::from_params(params));
+        Ok(recomputed)
+    }
+
+    // Find the path of leg i like find_leg, keeping the pinned points of
+    // the old path of the leg. Runs of pinned points are kept as they are,
+    // and the sections between them are searched. The gates are searched
+    // for in the section where the old path crossed them.
+    fn find_pinned_leg(params: &Params, atlas: &dyn ElevationSource,
+                       debug: &mut DebugLog, i: usize, start: Coord,
+                       old: &[Coord], walked: &Path) -> Option<Path> {
+        // Runs of pinned points, leaving out the start
+        let mut runs: Vec<Range<usize>> = vec![];
+
+        for k in 1..old.len() {
+            if !params.is_pinned(&old[k]) {
+                continue;
+            }
+
+            match runs.last_mut() {
+                Some(r) if r.end == k => r.end = k + 1,
+                _ => runs.push(k..k + 1),
+            }
+        }
+
+        if runs.is_empty() {
+            return Path::find_leg(params, atlas, debug, i, start, walked);
+        }
+
+        // Section of each gate, by the old segment crossing it. Gates
+        // crossed within a run are already crossed.
+        let leg_gates = params.leg_gates()[i].clone();
+        let mut gates = vec![vec![]; runs.len() + 1];
+
+        for g in leg_gates {
+            let k = (0..old.len() - 1)
+                .find(|k| g.is_crossing(&old[*k], &old[*k + 1]))
+                .unwrap_or(old.len() - 1);
+            let s = runs.iter().filter(|r| r.start <= k).count();
+
+            if s == 0 || k + 1 >= runs[s - 1].end {
+                gates[s].push(g);
+            }
+        }
+
+        let mut path = Path::from_vec(vec![start]);
+        let mut cur = start;
+
+        for (s, r) in runs.iter().enumerate() {
+            let mut p = Path::find_section(params, atlas, debug, i,
+                                           (cur, old[r.start], 0.0),
+                                           gates[s].clone(), walked)?;
+            path.append(&mut p);
+            path.append(&mut Path::from_vec(old[r.clone()].to_vec()));
+            cur = old[r.end - 1];
+        }
+
+        // The last section, unless the end of the leg is pinned
+        if runs[runs.len() - 1].end < old.len() {
+            let radius = params.point_info.get(i + 1)
+                .map(|info| info.radius)
+                .unwrap_or(0.0);
+            let mut p = Path::find_section(
+                params, atlas, debug, i, (cur, params.points[i + 1], radius),
+                gates[runs.len()].clone(), walked)?;
+            path.append(&mut p);
+        }
+
+        Some(path)
+    }
+
+    // Replace the points of leg i. Legs are joined by their end points.
+    fn replace_leg(&mut self, i: usize, leg: Path) {
+        let (a, b) = self.leg_range(i);
+
+        // The next leg now starts at the new end of this one
+        self.splice(a..b + 1, leg);
+    }
+
+    // Replace the points in a range of indices by the points of another
+    // path. A leg starting at the last point replaced starts at the last
+    // point of the replacement. If a leg starts inside the range, the legs
+    // can't be kept.
+    pub fn splice(&mut self, range: Range<usize>, replacement: Path) {
+        let (start, end) = (range.start, range.end);
+        let new_len = replacement.points.len();
+        let mut lost = false;
+
+        for l in &mut self.legs {
+            if *l <= start {
+                continue;
+            }
+
+            if *l + 1 < end || (*l + 1 == end && new_len == 0) {
+                lost = true;
+            }
+            else {
+                *l = *l + new_len + start - end;
+            }
+        }
+
+        if lost {
+            self.legs.clear();
+        }
+
+        self.points.splice(range, replacement.points);
+        self.clear_derived();
+    }
+
+    // Part of the path from from_km to to_km along it. The ends are
+    // interpolated between the points.
+    pub fn slice(&self, from_km: f32, to_km: f32) -> Result<Path, String> {
+        let (from, to) = (from_km*1000.0, to_km*1000.0);
+
+        if from < 0.0 || from > to || from > self.len() {
+            return Err(format!("Invalid range {}..{} km", from_km, to_km));
+        }
+
+        let mut sub = Path::new();
+        let mut d = 0.0;
+
+        for w in self.points.windows(2) {
+            let l = (w[1] - w[0]).abs();
+            let (d0, d1) = (d, d + l);
+            d = d1;
+
+            if d1 < from || d0 > to {
+                continue;
+            }
+
+            let at = |x: f32| {
+                if l > 0.0 { w[0] + (w[1] - w[0])*((x - d0)/l) } else { w[0] }
+            };
+
+            if sub.points.is_empty() {
+                sub.points.push(at(from.max(d0)));
+            }
+
+            let c = at(to.min(d1));
+            if sub.points.last() != Some(&c) {
+                sub.points.push(c);
+            }
+        }
+
+        Ok(sub)
+    }
+
+    // Split the path at the point on it nearest to c. Both parts include
+    // the split point.
+    pub fn split_at(&self, c: Coord) -> (Path, Path) {
+        let Some(&first) = self.points.first() else {
+            return (Path::new(), Path::new());
+        };
+
+        // Nearest point, and the segment it is on
+        let mut nearest = (f32::INFINITY, 0, first);
+
+        for (i, w) in self.points.windows(2).enumerate() {
+            let (de, dn) = (w[1].e - w[0].e, w[1].n - w[0].n);
+            let len_sq = de*de + dn*dn;
+            let t = if len_sq > 0.0 {
+                (((c.e - w[0].e)*de + (c.n - w[0].n)*dn)/len_sq)
+                    .clamp(0.0, 1.0)
+            }
+            else {
+                0.0
+            };
+            let q = w[0] + (w[1] - w[0])*t;
+            let d = (q - c).abs();
+
+            if d < nearest.0 {
+                nearest = (d, i, q);
+            }
+        }
+
+        let (_, i, q) = nearest;
+        let mut head = Path::from_vec(self.points[..=i].to_vec());
+        if head.points.last() != Some(&q) {
+            head.points.push(q);
+        }
+
+        let mut tail = Path::from_vec(vec![q]);
+        tail.points.extend(self.points[i + 1..].iter()
+                           .skip_while(|p| **p == q));
+
+        (head, tail)
+    }
+
+    fn from_vec(points: Vec<Coord>) -> Self {
+        Self {
+            points: points,
+            legs: vec![],
+            cumulative: vec![],
+            stats_cache: StatsCache::default(),
+        }
+    }
+
+    // Move point k, which must be inside a leg
+    pub fn move_point(&mut self, k: usize, c: Coord) -> Result<(), String> {
+        if k == 0 || k + 1 >= self.points.len() || self.legs.contains(&k) {
+            return Err("The ends of the legs can't be moved".to_string());
+        }
+
+        self.points[k] = c;
+        self.clear_derived();
+        Ok(())
     }
 
     pub fn push(&mut self, c: Coord) {
         self.points.push(c);
+        self.clear_derived();
     }
 
     pub fn append(&mut self, other: &mut Path) {
+        self.clear_derived();
+
         if other.points.len() != 0 {
             if self.points.len() == 0 {
                 self.points = other.points.drain(..).collect();
@@ -242,10 +857,10 @@ impl Path {
         }
     }
 
-    fn tripoint_time(&self, c1: Coord, c2: Coord, c3: Coord, atlas: &Atlas)
-                     -> f32 {
-        if let Some(t1) = Segment::new(c1, c2).time(atlas) {
-            if let Some(t2) = Segment::new(c2, c3).time(atlas) {
+    fn tripoint_time(&self, c1: Coord, c2: Coord, c3: Coord,
+                     atlas: &dyn ElevationSource, cost: &Cost) -> f32 {
+        if let Some(t1) = Segment::new(c1, c2).time(atlas, cost) {
+            if let Some(t2) = Segment::new(c2, c3).time(atlas, cost) {
                 return t1 + t2;
             }
         }
@@ -254,11 +869,44 @@ impl Path {
     }
 
     // Optimize path using iterative relaxation.
-    pub fn optimize(&mut self, atlas: &Atlas) {
+    pub fn optimize(&mut self, atlas: &dyn ElevationSource, cost: &Cost,
+                    params: &Params) {
+        self.optimize_iterations(atlas, cost, params, None);
+    }
+
+    // Optimize an existing path, e.g. one imported from another planner.
+    // The legs of a computed path are optimized one by one, so that the
+    // waypoints stay in place.
+    pub fn reoptimize(&mut self, atlas: &dyn ElevationSource, cost: &Cost,
+                      params: &Params, max_iterations: Option<usize>) {
+        if self.points.len() < 2 {
+            return;
+        }
+
+        if self.legs.is_empty() {
+            self.optimize_iterations(atlas, cost, params, max_iterations);
+            self.update_cumulative(atlas, cost);
+            return;
+        }
+
+        for i in 0..self.legs.len() {
+            let (a, b) = self.leg_range(i);
+            let mut leg = Path::from_vec(self.points[a..=b].to_vec());
+            leg.optimize_iterations(atlas, cost, params, max_iterations);
+            self.replace_leg(i, leg);
+        }
+
+        self.update_cumulative(atlas, cost);
+    }
+
+    // Optimize path using at most the given number of relaxation
+    // iterations, or until it converges.
+    fn optimize_iterations(&mut self, atlas: &dyn ElevationSource,
+                           cost: &Cost, params: &Params,
+                           max_iterations: Option<usize>) {
         println!("Improving path iteratively.");
-        // let de = Coord::new(4.0, 0.0);
-        // let dn = Coord::new(0.0, 4.0);
-        let mut time = self.calculate_time(atlas);
+        self.clear_derived();
+        let mut time = self.calculate_time_f64(atlas, cost);
         println!("Before adjustments: Time {}, points {}", time,
                  self.points.len());
 
@@ -281,25 +929,28 @@ impl Path {
 
             let d = (n - c).abs();
 
-            if d > 20.0 {
+            // Segments between pinned points are kept
+            if d > params.optimize_split
+                && !(params.is_pinned(&c) && params.is_pinned(&n)) {
                 // Long distance. Create intermediate point between this
                 // one and the next.
                 let c2 = (c + n)*0.5;
                 // Check that path exists from current point via
                 // intermediate ptoint to next point.
-                if self.tripoint_time(c, c2, n, atlas).is_finite() {
+                if self.tripoint_time(c, c2, n, atlas, cost).is_finite() {
                     new_points.push(c2);
                     c = c2;
                     continue;
                 }
             }
 
-            if d < 10.0 && i + 1 < len {
+            if d < params.optimize_join && i + 1 < len
+                && !params.is_pinned(&n) {
                 // Short distance.
                 // Check that path exists from current point to the point
                 // beyond the next one. Then skip the next point.
                 if let Some(_) = Segment::new(c, self.points[i + 1])
-                    .time(atlas) {
+                    .time(atlas, cost) {
                     i += 1;
                     continue;
                 }
@@ -314,54 +965,67 @@ impl Path {
 
         self.points = new_points;
 
-        time = self.calculate_time(atlas);
+        time = self.calculate_time_f64(atlas, cost);
 
         println!("After reducing points: Time {}, points {}", time,
                  self.points.len());
 
-        let mut range = 0.2;
+        if params.optimize_method == "probe" {
+            self.relax_probing(atlas, cost, params, time, max_iterations);
+        }
+        else {
+            self.relax_adaptive(atlas, cost, params, time, max_iterations);
+        }
+    }
+
+    // Relax the path by probing at fixed intervals sideways from each
+    // point. The range of the probes is adjusted to the largest move of the
+    // last iteration.
+    fn relax_probing(&mut self, atlas: &dyn ElevationSource, cost: &Cost,
+                     params: &Params, mut time: f64,
+                     max_iterations: Option<usize>) {
+        let probe = params.optimize_probe;
+        let mut range = params.optimize_range;
+        // Points can't be moved less than the coordinate resolution
+        let resolution = self.points.iter()
+            .map(coord_resolution)
+            .fold(0.0, f32::max);
+        let pinned = self.pinned(params);
+        let mut iteration = 0;
 
         loop {
+            if max_iterations.is_some_and(|m| iteration >= m) {
+                println!("Stopped after {} iterations", iteration);
+                break;
+            }
+            iteration += 1;
+
             let len = self.points.len();
             let mut max_j: i32 = 0;
 
-            for i in 1..len - 1 {
-                // Current, previous and next point
-                let c = self.points[i];
-                let p = self.points[i - 1];
-                let n = self.points[i + 1];
-
-                let t0 = self.tripoint_time(p, c, n, atlas);
-                let mut dc = (n - p).rot90();
-
-                if dc.abs() > 40.0 {
-                    dc = dc.normalize()*40.0;
-                }
-
-                let mut tmin = t0;
-                let mut j_used = 10;
-
-                for j in 1..21 {
-                    let cj = c + dc*((j as f32 - 10.0)*range);
-                    let tj = self.tripoint_time(p, cj, n, atlas);
-
-                    if tj < tmin {
-                        self.points[i] = cj;
-                        tmin = tj;
-                        j_used = j;
-                    }
-                }
-
-                if tmin < t0 {
-                    max_j = max_j.max(((j_used as i32) - 10).abs());
+            // A point is moved depending on its neighbours only, so the odd
+            // and the even points are each moved in parallel.
+            for parity in [1, 0] {
+                let moves: Vec<(usize, Coord, i32)> = (1..len - 1)
+                    .into_par_iter()
+                    .filter(|i| i % 2 == parity && !pinned[*i])
+                    .filter_map(|i| {
+                        self.probe_point(i, probe, range, atlas, cost)
+                            .map(|(c, j)| (i, c, j))
+                    })
+                    .collect();
+
+                for (i, c, j) in moves {
+                    self.points[i] = c;
+                    max_j = max_j.max(j);
                 }
             }
 
-            let time2 = self.calculate_time(atlas);
+            let time2 = self.calculate_time_f64(atlas, cost);
 
             println!("After adjustments: Time {}, range {} max_j {}",
                      time2, range, max_j);
-            if time - time2 < 0.1e-7 {
+            if time - time2 < params.optimize_convergence as f64 {
                 break;
             }
 
@@ -373,78 +1037,525 @@ impl Path {
             time = time2;
             // Adjust next range relative to maximal sideways adjustmest
             range = (max_j as f32)*range/5.0;
+
+            if range*probe < resolution {
+                break;
+            }
         }
     }
 
-    pub fn calculate_time(&self, atlas: &Atlas) -> f32 {
-        let mut time = 0.0;
+    // Relax the path by moving each point sideways with a step of its own.
+    // The step grows while the moves improve the time and shrinks when they
+    // don't, and part of the last move is repeated (momentum). Points on a
+    // straight slope keep moving in long steps, while points near their
+    // optimum settle without slowing down the others.
+    fn relax_adaptive(&mut self, atlas: &dyn ElevationSource, cost: &Cost,
+                      params: &Params, mut time: f64,
+                      max_iterations: Option<usize>) {
+        let len = self.points.len();
+        let probe = params.optimize_probe;
+        // Points can't be moved less than the coordinate resolution
+        let resolution = self.points.iter()
+            .map(coord_resolution)
+            .fold(0.0, f32::max);
+        let pinned = self.pinned(params);
+        let mut step = vec![probe*params.optimize_range; len];
+        // Last sideways move of each point, in meters to the left
+        let mut velocity = vec![0.0; len];
+        let mut iteration = 0;
+
+        loop {
+            if max_iterations.is_some_and(|m| iteration >= m) {
+                println!("Stopped after {} iterations", iteration);
+                break;
+            }
+            iteration += 1;
+
+            let mut moved = 0;
+
+            // Odd and even points are moved in parallel, as in
+            // relax_probing.
+            for parity in [1, 0] {
+                let moves: Vec<(usize, Option<(Coord, f32)>)> = (1..len - 1)
+                    .into_par_iter()
+                    .filter(|i| i % 2 == parity && !pinned[*i]
+                            && step[*i] >= resolution)
+                    .map(|i| (i, self.adaptive_move(i, step[i], velocity[i],
+                                                    atlas, cost)))
+                    .collect();
+
+                for (i, opt_move) in moves {
+                    if let Some((c, m)) = opt_move {
+                        self.points[i] = c;
+                        velocity[i] = m;
+                        step[i] = (step[i]*STEP_GROW).min(probe);
+                        moved += 1;
+                    }
+                    else {
+                        velocity[i] = 0.0;
+                        step[i] *= STEP_SHRINK;
+                    }
+                }
+            }
+
+            let time2 = self.calculate_time_f64(atlas, cost);
+            let max_step = step.iter().cloned().fold(0.0, f32::max);
+
+            println!("After adjustments: Time {}, moved {} max step {}",
+                     time2, moved, max_step);
+
+            // Iterations without moves only shrink the steps, so the
+            // improvement is checked only when something moved.
+            if moved > 0 && time - time2 < params.optimize_convergence as f64 {
+                break;
+            }
+
+            if max_step < resolution {
+                break;
+            }
+
+            time = time2;
+        }
+    }
+
+    // Whether each point is pinned, so that optimization doesn't move it
+    fn pinned(&self, params: &Params) -> Vec<bool> {
+        self.points.iter().map(|c| params.is_pinned(c)).collect()
+    }
+
+    // Best of the probes sideways from point i, and its distance (in
+    // probes) from the point. None if no probe improves the time.
+    fn probe_point(&self, i: usize, probe: f32, range: f32,
+                   atlas: &dyn ElevationSource, cost: &Cost)
+                   -> Option<(Coord, i32)> {
+        // Current, previous and next point
+        let c = self.points[i];
+        let p = self.points[i - 1];
+        let n = self.points[i + 1];
+
+        let mut tmin = self.tripoint_time(p, c, n, atlas, cost);
+        let mut dc = (n - p).rot90();
+
+        if dc.abs() > probe {
+            dc = dc.normalize()*probe;
+        }
+
+        let mut best = None;
+
+        for j in 1..21 {
+            let cj = c + dc*((j as f32 - 10.0)*range);
+            let tj = self.tripoint_time(p, cj, n, atlas, cost);
+
+            if tj < tmin {
+                tmin = tj;
+                best = Some((cj, (j - 10).abs()));
+            }
+        }
+
+        best
+    }
+
+    // Move point i sideways by the step, continuing in the direction of its
+    // last move, or else in the other direction. Returns the new point and
+    // the move (meters to the left), or None if neither improves the time.
+    fn adaptive_move(&self, i: usize, step: f32, velocity: f32,
+                     atlas: &dyn ElevationSource, cost: &Cost)
+                     -> Option<(Coord, f32)> {
+        // Current, previous and next point
+        let c = self.points[i];
+        let p = self.points[i - 1];
+        let n = self.points[i + 1];
+        let side = (n - p).rot90();
+
+        if side.abs() == 0.0 {
+            return None;
+        }
+
+        let u = side.normalize();
+        let t0 = self.tripoint_time(p, c, n, atlas, cost);
+        let dir = if velocity < 0.0 { -1.0 } else { 1.0 };
+
+        [MOMENTUM*velocity + dir*step, -dir*step].into_iter()
+            .map(|m| (c + u*m, m))
+            .find(|(cm, _)| self.tripoint_time(p, *cm, n, atlas, cost) < t0)
+    }
+
+    // Sums over the segments are accumulated in f64. On long routes, the
+    // f32 sum would drift as the small segment values are added to a large
+    // total.
+    fn calculate_time_f64(&self, atlas: &dyn ElevationSource, cost: &Cost)
+                          -> f64 {
+        let mut time: f64 = 0.0;
 
         for i in 0..self.points.len() - 1 {
-            if let Some(t) = Segment::new(self.points[i],
-                                          self.points[i + 1]).time(atlas) {
-                time += t;
+            if let Some(t) = Segment::new(self.points[i], self.points[i + 1])
+                .time(atlas, cost) {
+                time += t as f64;
             }
             else {
-                return f32::INFINITY;
+                return f64::INFINITY;
             }
         }
 
         return time;
     }
 
+    pub fn calculate_time(&self, atlas: &dyn ElevationSource, cost: &Cost)
+                          -> f32 {
+        return self.calculate_time_f64(atlas, cost) as f32;
+    }
+
+    // Compute the distance and time from the start to each point. As in
+    // calculate_time, points beyond a segment which can't be walked (too
+    // steep or outside the elevation data) take infinite time.
+    pub fn update_cumulative(&mut self, atlas: &dyn ElevationSource,
+                             cost: &Cost) {
+        let (mut dist, mut time): (f64, f64) = (0.0, 0.0);
+        self.cumulative = vec![(0.0, 0.0)];
+
+        for w in self.points.windows(2) {
+            let s = Segment::new(w[0], w[1]);
+            dist += s.len() as f64;
+            time += s.time(atlas, cost).map_or(f64::INFINITY, |t| t as f64);
+            self.cumulative.push((dist as f32, time as f32));
+        }
+
+        self.cumulative.truncate(self.points.len());
+    }
+
+    // Forget the values derived from the points
+    fn clear_derived(&mut self) {
+        self.cumulative.clear();
+        *self.stats_cache.0.get_mut() = None;
+    }
+
+    fn has_cumulative(&self) -> bool {
+        !self.points.is_empty() && self.cumulative.len() == self.points.len()
+    }
+
+    // Modeled time (s) from the start to point i. None if the point can't
+    // be reached.
+    pub fn time_at_point(&self, i: usize) -> Option<f32> {
+        if !self.has_cumulative() {
+            return None;
+        }
+
+        self.cumulative.get(i).map(|(_, t)| *t).filter(|t| t.is_finite())
+    }
+
+    // Position after walking t seconds from the start
+    pub fn position_at_time(&self, t: f32) -> Option<Coord> {
+        self.interpolate(t, |(_, time)| *time)
+    }
+
+    // Position d meters along the path
+    pub fn position_at_distance(&self, d: f32) -> Option<Coord> {
+        self.interpolate(d, |(dist, _)| *dist)
+    }
+
+    // Points every interval meters along the path, labelled with the
+    // distance in km
+    pub fn distance_markers(&self, interval: f32) -> Vec<(Coord, String)> {
+        let mut markers = vec![];
+
+        if interval <= 0.0 {
+            return markers;
+        }
+
+        let mut walked = 0.0;
+        let mut k = 1;
+
+        for w in self.points.windows(2) {
+            let len = (w[1] - w[0]).abs();
+
+            while len > 0.0 && walked + len >= k as f32*interval {
+                let f = (k as f32*interval - walked)/len;
+                let km = k as f32*interval/1000.0;
+                let label = if (km - km.round()).abs() < 0.001 {
+                    format!("{:.0} km", km)
+                }
+                else {
+                    format!("{:.1} km", km)
+                };
+
+                markers.push((w[0] + (w[1] - w[0])*f, label));
+                k += 1;
+            }
+
+            walked += len;
+        }
+
+        markers
+    }
+
+    // Position where the cumulative value given by key reaches x,
+    // interpolated between the points. None if the cumulative values are
+    // not computed, or x is outside the path.
+    fn interpolate(&self, x: f32, key: impl Fn(&(f32, f32)) -> f32)
+                   -> Option<Coord> {
+        if !self.has_cumulative() || x < 0.0 {
+            return None;
+        }
+
+        // First point beyond x. The first point is at zero, so i > 0.
+        let i = self.cumulative.partition_point(|c| key(c) <= x);
+
+        if i == self.points.len() {
+            let last = self.points.len() - 1;
+            return (x <= key(&self.cumulative[last]))
+                .then_some(self.points[last]);
+        }
+
+        let (x0, x1) = (key(&self.cumulative[i - 1]),
+                        key(&self.cumulative[i]));
+        let f = if x1 > x0 { (x - x0)/(x1 - x0) } else { 0.0 };
+
+        Some(self.points[i - 1] + (self.points[i] - self.points[i - 1])*f)
+    }
+
+    // Steepest terrain (degrees) crossed by the path, whatever the walking
+    // direction
+    pub fn steepest_slope(&self, atlas: &dyn ElevationSource) -> f32 {
+        let mut max_abs: f32 = 0.0;
+
+        for w in self.points.windows(2) {
+            for (f, _) in Segment::new(w[0], w[1]).fields() {
+                if let Some((_, dx, dy)) = atlas.lookup_with_gradient(
+                    &f.into()) {
+                    max_abs = max_abs.max(dx*dx + dy*dy);
+                }
+            }
+        }
+
+        max_abs.sqrt().atan().to_degrees()
+    }
+
     pub fn len(&self) -> f32 {
-        let mut l = 0.0;
+        let mut l: f64 = 0.0;
 
         for i in 0..self.points.len() - 1 {
-            l += Segment::new(self.points[i], self.points[i + 1]).len();
+            l += Segment::new(self.points[i], self.points[i + 1]).len() as f64;
+        }
+
+        return l as f32;
+    }
+
+    // Calculate energy (joule) spent walking the points from index i to j
+    pub fn energy(&self, i: usize, j: usize, atlas: &dyn ElevationSource,
+                  cost: &Cost) -> f32 {
+        let mut energy: f64 = 0.0;
+
+        for k in i..j {
+            energy += Segment::new(self.points[k], self.points[k + 1])
+                .energy(atlas, cost) as f64;
         }
 
-        return l;
+        return energy as f32;
     }
 
-    pub fn elevation(&self, atlas: &Atlas) -> f32 {
-        let mut h = 0.0;
+    // Total ascent. Elevation changes smaller than threshold (m) are
+    // ignored, so that noise in the elevation data doesn't add up.
+    pub fn elevation(&self, atlas: &dyn ElevationSource, threshold: f32)
+                     -> f32 {
+        if threshold > 0.0 {
+            return self.climb(atlas, threshold).0;
+        }
+
+        let mut h: f64 = 0.0;
 
         // Calculate the accumulated relative elevation along the track. Downhill parts
         // are not counted.
         for i in 0..self.points.len() - 1 {
-            h += Segment::new(self.points[i], self.points[i + 1]).height(atlas);
+            h += Segment::new(self.points[i], self.points[i + 1])
+                .height(atlas) as f64;
         }
 
-        return h;
+        return h as f32;
     }
 
-    pub fn descent(&self, atlas: &Atlas) -> f32 {
-        let mut h = 0.0;
+    pub fn descent(&self, atlas: &dyn ElevationSource, threshold: f32)
+                   -> f32 {
+        if threshold > 0.0 {
+            return self.climb(atlas, threshold).1;
+        }
+
+        let mut h: f64 = 0.0;
 
         // Descent is calculated in the same way as height, but in the oposite direction.
         for i in 0..self.points.len() - 1 {
-            h += Segment::new(self.points[i + 1], self.points[i]).height(atlas);
+            h += Segment::new(self.points[i + 1], self.points[i])
+                .height(atlas) as f64;
         }
 
-        return h;
+        return h as f32;
     }
 
-    pub fn read_gpx(fname: &str) -> Self {
-	let file = File::open(fname).unwrap();
-	let reader = BufReader::new(file);
+    // Ascent and descent from the profile with elevation changes smaller
+    // than threshold (m) filtered out
+    fn climb(&self, atlas: &dyn ElevationSource, threshold: f32)
+             -> (f32, f32) {
+        let profile = self.profile(atlas, ASCENT_STEP, threshold);
+        let mut ascent = 0.0;
+        let mut descent = 0.0;
 
-	let mut points = vec!();
+        for w in profile.windows(2) {
+            let dh = w[1].1 - w[0].1;
 
-	let gpx: Gpx = gpx::read(reader).unwrap();
-	// Assume first track in file is the one to use.
-	let track: &Track = &gpx.tracks[0];
+            if dh > 0.0 {
+                ascent += dh;
+            }
+            else {
+                descent -= dh;
+            }
+        }
 
-	for wp in &track.segments[0].points {
-	    points.push(Coord::from_latlon(wp.point().y(), wp.point().x()));
-	}
+        (ascent, descent)
+    }
 
-        Self {
-            points: points,
+    // Elevation profile of the path, sampled every step meters along each
+    // segment. Returns (distance, elevation, slope) as Segment::profile.
+    // With a threshold (m) above 0, the elevation is only changed when it
+    // has moved more than the threshold from the last value reported
+    // (hysteresis), filtering out small oscillations.
+    pub fn profile(&self, atlas: &dyn ElevationSource, step: f32,
+                   threshold: f32) -> Vec<(f32, f32, f32)> {
+        let mut profile: Vec<(f32, f32, f32)> = vec![];
+        let mut offset = 0.0;
+
+        for w in self.points.windows(2) {
+            let s = Segment::new(w[0], w[1]);
+
+            for (d, h, slope) in s.profile(atlas, step) {
+                // The first sample repeats the end of the last segment
+                if d == 0.0 && !profile.is_empty() {
+                    continue;
+                }
+
+                let h = match profile.last() {
+                    Some(&(_, held, _)) if (h - held).abs() < threshold => {
+                        held
+                    },
+                    _ => h,
+                };
+
+                profile.push((offset + d, h, slope));
+            }
+
+            offset += s.len();
+        }
+
+        profile
+    }
+
+    // Bearing (degrees, 0..360) from point i to point j, relative to true
+    // north, or magnetic north with the declination (east positive)
+    pub fn bearing(&self, i: usize, j: usize, declination: f32) -> f32 {
+        bearing(&self.points[i], &self.points[j], declination)
+    }
+
+    // Indices of the points where the path from point i to j turns, such
+    // that the straight lines between them are within tolerance (meters)
+    // of the path. Includes i and j.
+    pub fn turning_points(&self, i: usize, j: usize, tolerance: f32)
+                          -> Vec<usize> {
+        let mut keep = vec![i, j];
+        let mut stack = vec![(i, j)];
+
+        // Split at the point farthest from the line until all are close
+        while let Some((a, b)) = stack.pop() {
+            let line = Barrier::from_vec(vec![self.points[a],
+                                              self.points[b]]);
+            let farthest = (a + 1..b)
+                .map(|k| (k, line.distance_sq(&self.points[k])))
+                .max_by(|x, y| x.1.total_cmp(&y.1));
+
+            if let Some((k, dsq)) = farthest {
+                if dsq > tolerance*tolerance {
+                    keep.push(k);
+                    stack.push((a, k));
+                    stack.push((k, b));
+                }
+            }
+        }
+
+        keep.sort();
+        keep.dedup();
+        keep
+    }
+
+    // Length, ascent, descent and modeled time of the path from point i to
+    // point j
+    pub fn stretch(&self, i: usize, j: usize, atlas: &dyn ElevationSource,
+                   cost: &Cost) -> Stretch {
+        let part = Path::from_vec(self.points[i..=j].to_vec());
+        let threshold = cost.ascent_threshold();
+
+        Stretch {
+            length: part.len(),
+            ascent: part.elevation(atlas, threshold),
+            descent: part.descent(atlas, threshold),
+            time: part.calculate_time(atlas, cost),
         }
     }
 
-    pub fn write_gpx(&self, fname: &str, name: &str, atlas: &Atlas) {
+    // Index of the track point at each waypoint. The track is searched
+    // forward from the previous waypoint, so that waypoints visited more than
+    // once (e.g. on out-and-back trips) are found in order.
+    pub fn waypoint_indices(&self, waypoints: &[Coord]) -> Vec<usize> {
+        let mut indices = vec![];
+        let mut start = 0;
+
+        for w in waypoints {
+            let mut dsq_min = f32::INFINITY;
+            let mut nearest = start;
+
+            for i in start..self.points.len() {
+                let dsq = (self.points[i] - *w).abs_sq();
+                if dsq < dsq_min {
+                    dsq_min = dsq;
+                    nearest = i;
+                }
+                if dsq == 0.0 {
+                    break;
+                }
+            }
+
+            indices.push(nearest);
+            start = nearest;
+        }
+
+        indices
+    }
+
+    // Read the first segment of the first track in a GPX file
+    pub fn read_gpx(fname: &str) -> Result<Self, String> {
+        let file = File::open(fname)
+            .map_err(|e| format!("Unable to open {}: {}", fname, e))?;
+        let reader = BufReader::new(file);
+
+        let gpx: Gpx = gpx::read(reader)
+            .map_err(|e| format!("Unable to read {}: {}", fname, e))?;
+        // Assume first track in file is the one to use.
+        let Some(track) = gpx.tracks.first() else {
+            return Err(format!("{} has no tracks", fname));
+        };
+        let Some(segment) = track.segments.first() else {
+            return Err(format!("{} has no track segments", fname));
+        };
+
+        let points = segment.points.iter()
+            .map(|wp| from_latlon(wp.point().y(), wp.point().x()))
+            .collect();
+
+        Ok(Path::from_vec(points))
+    }
+
+    // Write the path as a GPX track. The labelled waypoints are written as
+    // GPX waypoints.
+    pub fn write_gpx(&self, fname: &str, name: &str,
+                     waypoints: &[(Coord, String, String)],
+                     atlas: &dyn ElevationSource,
+                     stats: Option<&PathStats>) {
         let track_segment = TrackSegment {
             points: vec![]
         };
@@ -463,7 +1574,7 @@ impl Path {
             creator: None,
             metadata: Some(Metadata {
                 name: Some(name.to_string()),
-                description: None,
+                description: stats.map(|s| s.summary_line()),
                 author: None,
                 links: vec![],
                 time: None,
@@ -488,34 +1599,46 @@ impl Path {
             let (lat, long) = wsg84_utm_to_lat_lon(
             p.e as f64, p.n as f64, 33, 'W').unwrap();
 	     */
-	    let (lat, long) = p.latlon();
+	    let (lat, long) = to_latlon(&p);
             let mut wp = Waypoint::new(Point::new(long, lat));
-            wp.elevation = Some(atlas.lookup(&p).unwrap().into());
+            wp.elevation = atlas.lookup(&p).map(|h| h.into());
             gpx.tracks[0].segments[0].points.push(wp);
         }
 
+        for (c, label, symbol) in waypoints {
+            let (lat, long) = to_latlon(&c);
+            let mut wp = Waypoint::new(Point::new(long, lat));
+            wp.name = Some(label.clone());
+            if symbol != "" {
+                wp.symbol = Some(symbol.clone());
+            }
+            wp.elevation = atlas.lookup(&c).map(|h| h.into());
+            gpx.waypoints.push(wp);
+        }
+
         // Write to file
         gpx::write(&gpx, buf).unwrap();
     }
 
-    pub fn print_summary(&self, atlas: &Atlas) {
-        println!("Path: {}", self);
-        println!("Length: {}m", self.len());
-        let time = self.calculate_time(atlas) as usize;
-        match time {
-            t if t >= 3600 => {
-                println!("Time: {} hr {} min {} sec",
-                         t/3600, (t%3600)/60, t%60);
-            },
-            t if t >= 60 => {
-                println!("Time: {} min {} sec", t/60, t%60);
-            },
-            t => {
-                println!("Time: {} sec", t);
-            },
-        }
-        println!("Total elevation: {}m", self.elevation(&atlas));
-        println!("Total descent: {}m", self.descent(&atlas));
+    // Length, time, heights and per-leg breakdown between the waypoints.
+    // Asking again with the same waypoints, params and elevation source
+    // gives the cached result.
+    pub fn stats(&self, waypoints: &[Coord], atlas: &dyn ElevationSource,
+                 cost: &Cost) -> PathStats {
+        let source = atlas as *const dyn ElevationSource as *const ()
+            as usize;
+        let key = (waypoints.to_vec(), cost.generation(), source);
+        let mut cache = self.stats_cache.0.lock();
+
+        if let Some((k, stats)) = cache.as_ref() {
+            if *k == key {
+                return stats.clone();
+            }
+        }
+
+        let stats = PathStats::new(self, waypoints, atlas, cost);
+        *cache = Some((key, stats.clone()));
+        stats
     }
 }
 
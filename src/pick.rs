@@ -0,0 +1,149 @@
+use galileo_types::cartesian::Point2;
+use std::collections::HashMap;
+
+// Feature which can be picked on the map, by its position in the list of
+// features of its kind.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pickable {
+    Poi(usize),
+    Waypoint(usize),
+    Barrier(usize),
+    Track,
+}
+
+// A point, or a line segment from a to b, in map coordinates
+struct Shape {
+    item: Pickable,
+    a: (f64, f64),
+    b: (f64, f64),
+}
+
+impl Shape {
+    fn distance_sq(&self, p: (f64, f64)) -> f64 {
+        let (dx, dy) = (self.b.0 - self.a.0, self.b.1 - self.a.1);
+        let len_sq = dx*dx + dy*dy;
+        let t = if len_sq > 0.0 {
+            (((p.0 - self.a.0)*dx + (p.1 - self.a.1)*dy)/len_sq).clamp(0.0, 1.0)
+        }
+        else {
+            0.0
+        };
+
+        let (ex, ey) = (self.a.0 + t*dx - p.0, self.a.1 + t*dy - p.1);
+        ex*ex + ey*ey
+    }
+}
+
+// Spatial index for picking features on the map. The features are kept in
+// map (mercator) coordinates and bucketed in a grid with cells of the pick
+// radius. Since the radius is given in pixels, the grid is rebuilt when the
+// resolution of the map view changes.
+pub struct PickIndex {
+    shapes: Vec<Shape>,
+    // Cell size (map units) of the grid, zero if it must be rebuilt
+    cell: f64,
+    cells: HashMap<(i64, i64), Vec<usize>>,
+}
+
+impl PickIndex {
+    pub fn new() -> Self {
+        Self {
+            shapes: vec![],
+            cell: 0.0,
+            cells: HashMap::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.shapes.clear();
+        self.cells.clear();
+        self.cell = 0.0;
+    }
+
+    pub fn add_point(&mut self, item: Pickable, p: &Point2) {
+        self.shapes.push(Shape {
+            item: item,
+            a: (p.x(), p.y()),
+            b: (p.x(), p.y()),
+        });
+        self.cell = 0.0;
+    }
+
+    pub fn add_line(&mut self, item: Pickable, points: &[Point2]) {
+        for w in points.windows(2) {
+            self.shapes.push(Shape {
+                item: item,
+                a: (w[0].x(), w[0].y()),
+                b: (w[1].x(), w[1].y()),
+            });
+        }
+        self.cell = 0.0;
+    }
+
+    fn cell_of(&self, p: (f64, f64)) -> (i64, i64) {
+        ((p.0/self.cell).floor() as i64, (p.1/self.cell).floor() as i64)
+    }
+
+    // Rebuild the grid for a pick radius (map units) if it has changed
+    pub fn refresh(&mut self, radius: f64) {
+        if radius <= 0.0 || self.cell == radius {
+            return;
+        }
+
+        self.cell = radius;
+        self.cells.clear();
+
+        for (i, s) in self.shapes.iter().enumerate() {
+            // Put segments in the cells along them. Cells cut only at a
+            // corner are found by searching the neighbouring cells.
+            let (dx, dy) = (s.b.0 - s.a.0, s.b.1 - s.a.1);
+            let steps = ((dx*dx + dy*dy).sqrt()/radius).ceil() as usize;
+            let mut last = None;
+
+            for k in 0..=steps {
+                let t = if steps == 0 { 0.0 } else { k as f64/steps as f64 };
+                let c = self.cell_of((s.a.0 + t*dx, s.a.1 + t*dy));
+
+                if last != Some(c) {
+                    self.cells.entry(c).or_insert(vec![]).push(i);
+                    last = Some(c);
+                }
+            }
+        }
+    }
+
+    // Find the feature nearest to p within the pick radius, among those
+    // accepted by the filter.
+    pub fn pick(&self, p: &Point2, filter: impl Fn(&Pickable) -> bool)
+                -> Option<Pickable> {
+        if self.cell <= 0.0 {
+            return None;
+        }
+
+        let p = (p.x(), p.y());
+        let (cx, cy) = self.cell_of(p);
+        let mut dsq_min = self.cell*self.cell;
+        let mut picked = None;
+
+        for x in cx - 1..=cx + 1 {
+            for y in cy - 1..=cy + 1 {
+                let Some(ids) = self.cells.get(&(x, y)) else { continue; };
+
+                for i in ids {
+                    let s = &self.shapes[*i];
+                    if !filter(&s.item) {
+                        continue;
+                    }
+
+                    let dsq = s.distance_sq(p);
+                    if dsq < dsq_min {
+                        dsq_min = dsq;
+                        picked = Some(s.item);
+                    }
+                }
+            }
+        }
+
+        picked
+    }
+}
@@ -0,0 +1,144 @@
+use hoydedata::Coord;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PoiKind {
+    Summit,
+    Hut,
+    LakeOutlet,
+    Spring,
+    Bridge,
+    Ford,
+    Parking,
+    #[serde(other)]
+    Other,
+}
+
+impl fmt::Display for PoiKind {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            PoiKind::Summit => "summit",
+            PoiKind::Hut => "hut",
+            PoiKind::LakeOutlet => "lake outlet",
+            PoiKind::Spring => "spring",
+            PoiKind::Bridge => "bridge",
+            PoiKind::Ford => "ford",
+            PoiKind::Parking => "parking",
+            PoiKind::Other => "other",
+        };
+
+        write!(formatter, "{}", s)
+    }
+}
+
+impl PoiKind {
+    // Whether drinking water can be found at the point
+    pub fn is_water(&self) -> bool {
+        matches!(self, PoiKind::LakeOutlet | PoiKind::Spring)
+    }
+
+    // Whether a river can be crossed at the point
+    pub fn is_crossing(&self) -> bool {
+        matches!(self, PoiKind::Bridge | PoiKind::Ford)
+    }
+}
+
+// A named point of interest
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Poi {
+    pub name: String,
+    pub kind: PoiKind,
+    pub coord: Coord,
+}
+
+impl fmt::Display for Poi {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(formatter, "{} ({}) {}", self.name, self.kind, self.coord)
+    }
+}
+
+// Gazetteer of named places. The database is read from a csv file with the
+// columns name, kind and coord, e.g.:
+//
+//   name,kind,coord
+//   Store Skagastølstind,summit,N6814070E437460
+//   Turtagrø,hut,N6812440E431020
+#[derive(Clone, Debug)]
+pub struct PoiDb {
+    pois: Vec<Poi>,
+}
+
+impl PoiDb {
+    pub fn new() -> Self {
+        Self {
+            pois: vec![],
+        }
+    }
+
+    pub fn from_file(fname: &str) -> Result<Self, String> {
+        let mut reader = csv::Reader::from_path(fname)
+            .map_err(|e| format!("Unable to open {}: {}", fname, e))?;
+        let mut pois = vec![];
+
+        for result in reader.deserialize() {
+            let poi: Poi = result
+                .map_err(|e| format!("Invalid poi in {}: {}", fname, e))?;
+            pois.push(poi);
+        }
+
+        Ok(Self {
+            pois: pois,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.pois.len()
+    }
+
+    pub fn pois(&self) -> &Vec<Poi> {
+        &self.pois
+    }
+
+    // Find a place by name. Names are compared case insensitively.
+    pub fn find(&self, name: &str) -> Option<&Poi> {
+        let lname = name.to_lowercase();
+
+        self.pois.iter().find(|p| p.name.to_lowercase() == lname)
+    }
+
+    pub fn search(&self, name: &str) -> Option<&Poi> {
+        search(&self.pois, name)
+    }
+
+    // Find the place nearest to the given coordinate
+    pub fn nearest(&self, c: &Coord) -> Option<&Poi> {
+        let mut dsq_min = f32::INFINITY;
+        let mut nearest = None;
+
+        for p in &self.pois {
+            let dsq = (p.coord - *c).abs_sq();
+            if dsq < dsq_min {
+                dsq_min = dsq;
+                nearest = Some(p);
+            }
+        }
+
+        nearest
+    }
+}
+
+// Search for a place by name. An exact (case insensitive) match is preferred,
+// otherwise the first place containing the name is returned.
+pub fn search<'a>(pois: &'a [Poi], name: &str) -> Option<&'a Poi> {
+    let lname = name.trim().to_lowercase();
+
+    if lname == "" {
+        return None;
+    }
+
+    pois.iter().find(|p| p.name.to_lowercase() == lname)
+        .or_else(|| pois.iter().find(|p| p.name.to_lowercase()
+                                     .contains(&lname)))
+}
@@ -0,0 +1,65 @@
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+// Progress of a path computation, shared between the thread computing and
+// the command line interface. The computation reports its phase as a number
+// of steps done out of a total, and stops at the next step if cancelled.
+pub struct Progress {
+    // Description of the current phase, steps done and total steps
+    state: Mutex<(String, usize, usize)>,
+    // Nodes of the graph being searched, and the time (s) of the best path
+    // found so far for the current leg
+    nodes: AtomicUsize,
+    best_time: Mutex<Option<f32>>,
+    cancelled: AtomicBool,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(("Starting".to_string(), 0, 0)),
+            nodes: AtomicUsize::new(0),
+            best_time: Mutex::new(None),
+            cancelled: AtomicBool::new(false),
+        }
+    }
+
+    pub fn set_phase(&self, phase: String, step: usize, steps: usize) {
+        *self.state.lock() = (phase, step, steps);
+    }
+
+    pub fn phase(&self) -> (String, usize, usize) {
+        self.state.lock().clone()
+    }
+
+    pub fn set_nodes(&self, nodes: usize) {
+        self.nodes.store(nodes, Ordering::SeqCst);
+    }
+
+    pub fn nodes(&self) -> usize {
+        self.nodes.load(Ordering::SeqCst)
+    }
+
+    pub fn set_best_time(&self, time: Option<f32>) {
+        *self.best_time.lock() = time;
+    }
+
+    pub fn best_time(&self) -> Option<f32> {
+        *self.best_time.lock()
+    }
+
+    // Percent of the steps done
+    pub fn percent(&self) -> usize {
+        let (_, step, steps) = self.phase();
+
+        if steps > 0 { 100*step/steps } else { 0 }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,444 @@
+use crate::cost::Cost;
+use crate::elevation::ElevationSource;
+use crate::params::Params;
+use crate::path::Path;
+use crate::schedule::{Schedule, format_clock};
+
+use hoydedata::Coord;
+use std::fmt::Write;
+use std::fs;
+
+// Size (pixels) of the map sketch and the elevation profile
+const MAP_SIZE: f32 = 600.0;
+const PROFILE_WIDTH: f32 = 800.0;
+const PROFILE_HEIGHT: f32 = 200.0;
+// Margin (pixels) around the drawings
+const MARGIN: f32 = 30.0;
+// Number of samples of the elevation profile, and the smallest sampling
+// interval (meters)
+const PROFILE_SAMPLES: f32 = 500.0;
+const PROFILE_STEP: f32 = 5.0;
+// Slopes (degrees) steeper than this are listed as hazards
+const STEEP_SLOPE: f32 = 30.0;
+
+pub const REPORT_FORMATS: [&str; 2] = ["html", "md"];
+
+// Trip report with the parts shared by the HTML and Markdown versions
+struct Report {
+    title: String,
+    summary: Vec<(String, String)>,
+    map: String,
+    profile: String,
+    legs: Vec<Vec<String>>,
+    hazards: Vec<String>,
+    schedule: Vec<Vec<String>>,
+    sun: Option<String>,
+}
+
+// Write a report of a path, to be shared with people who don't run
+// stivalg. The format is given by the extension, .html or .md. HTML reports
+// are self-contained. Markdown reports refer to the map and elevation
+// profile as SVG files written beside them.
+pub fn write_report(fname: &str, path: &Path, params: &Params,
+                    atlas: &dyn ElevationSource) -> Result<(), String> {
+    let ext = fname.rsplit_once('.').map(|(_, e)| e.to_lowercase())
+        .unwrap_or_default();
+    let format = if ext == "htm" { "html" } else { ext.as_str() };
+
+    if !REPORT_FORMATS.contains(&format) {
+        return Err(format!("Unknown report format '{}'. Expected one of {}",
+                           ext, REPORT_FORMATS.join(", ")));
+    }
+
+    if path.len() == 0.0 {
+        return Err("Path has no length".to_string());
+    }
+
+    let report = Report::new(path, params, atlas);
+
+    if format == "html" {
+        write_file(fname, &report.to_html())
+    }
+    else {
+        let stem = fname.rsplit_once('.').map(|(s, _)| s).unwrap_or(fname);
+        let map_fname = format!("{}_map.svg", stem);
+        let profile_fname = format!("{}_profile.svg", stem);
+
+        write_file(&map_fname, &report.map)?;
+        write_file(&profile_fname, &report.profile)?;
+        write_file(fname, &report.to_markdown(&map_fname, &profile_fname))
+    }
+}
+
+fn write_file(fname: &str, content: &str) -> Result<(), String> {
+    fs::write(fname, content)
+        .map_err(|e| format!("Unable to write {}: {}", fname, e))
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Distance walked from the start to each point of a path
+fn distances(points: &[Coord]) -> Vec<f32> {
+    let mut d = 0.0;
+    let mut distances = vec![0.0];
+
+    for w in points.windows(2) {
+        d += (w[1] - w[0]).abs();
+        distances.push(d);
+    }
+
+    distances
+}
+
+impl Report {
+    fn new(path: &Path, params: &Params, atlas: &dyn ElevationSource)
+           -> Self {
+        let cost = Cost::from_params(params);
+        let stats = path.stats(&params.points, atlas, &cost);
+        let points: Vec<Coord> = path.into_iter().cloned().collect();
+        let distances = distances(&points);
+        let waypoints = params.labelled_points();
+        let indices = path.waypoint_indices(&params.points);
+        let schedule = if params.start_time != "" {
+            Schedule::new(path, atlas, &cost, params).ok()
+        }
+        else {
+            None
+        };
+
+        let summary = vec![
+            ("Length".to_string(), format!("{:.1} km",
+                                           stats.length/1000.0)),
+            ("Time".to_string(), stats.format_time()),
+            ("Ascent".to_string(), format!("{:.0} m", stats.ascent)),
+            ("Descent".to_string(), format!("{:.0} m", stats.descent)),
+            ("Max slope".to_string(), format!("{:.0}°", stats.max_slope)),
+            ("Energy".to_string(), format!("{:.0} kcal", stats.energy)),
+            ("Water".to_string(), format!("{:.1} l", stats.water)),
+        ];
+
+        let mut legs = vec![
+            ["Leg", "From", "To", "Length (km)", "Time (min)",
+             "Energy (kcal)", "Water (l)"]
+                .iter().map(|s| s.to_string()).collect::<Vec<String>>()
+        ];
+
+        for (l, leg) in stats.legs.iter().enumerate() {
+            legs.push(vec![
+                format!("{}", l + 1),
+                waypoints.get(l).map(|w| w.1.clone()).unwrap_or_default(),
+                waypoints.get(l + 1).map(|w| w.1.clone())
+                    .unwrap_or_default(),
+                format!("{:.1}", leg.length/1000.0),
+                format!("{:.0}", leg.time/60.0),
+                format!("{:.0}", leg.energy),
+                format!("{:.1}", leg.water),
+            ]);
+        }
+
+        let mut schedule_rows: Vec<Vec<String>> = vec![];
+        let mut sun = None;
+
+        if let Some(s) = &schedule {
+            schedule_rows.push(["Point", "Arrival", "Target"]
+                .iter().map(|s| s.to_string()).collect());
+
+            for (i, w) in waypoints.iter().enumerate() {
+                let Some(k) = indices.get(i) else { continue; };
+                let target = params.point_info.get(i)
+                    .map(|info| info.target.clone()).unwrap_or_default();
+                schedule_rows.push(vec![w.1.clone(),
+                                        format_clock(s.times[*k]),
+                                        target]);
+            }
+
+            sun = Some(format!("Sunrise {}, sunset {}",
+                               format_clock(s.sunrise),
+                               format_clock(s.sunset)));
+        }
+
+        Self {
+            title: params.expand_template(&params.track_name, path.len()),
+            summary: summary,
+            map: Report::map_svg(&points, &waypoints, params),
+            profile: Report::profile_svg(path, params, atlas),
+            legs: legs,
+            hazards: Report::hazards(path, &distances, params, atlas,
+                                     schedule.as_ref()),
+            schedule: schedule_rows,
+            sun: sun,
+        }
+    }
+
+    // Fords crossed, steep sections and sections walked in darkness, with
+    // their position along the path
+    fn hazards(path: &Path, distances: &[f32], params: &Params,
+               atlas: &dyn ElevationSource, schedule: Option<&Schedule>)
+               -> Vec<String> {
+        let points: Vec<Coord> = path.into_iter().cloned().collect();
+        let mut hazards = vec![];
+
+        for f in &params.fords {
+            let (i, dsq) = points.iter().enumerate()
+                .map(|(i, c)| (i, (*c - *f).abs_sq()))
+                .fold((0, f32::INFINITY),
+                      |a, b| if b.1 < a.1 { b } else { a });
+            let r = params.ford_radius.max(PROFILE_STEP);
+
+            if dsq <= r*r {
+                hazards.push(format!("Ford at km {:.1}",
+                                     distances[i]/1000.0));
+            }
+        }
+
+        // Runs of profile samples steeper than STEEP_SLOPE
+        let mut steep: Option<(f32, f32, f32)> = None;
+        let profile = path.profile(atlas, PROFILE_STEP, 0.0);
+
+        for (d, _, slope) in profile.iter()
+            .chain(std::iter::once(&(f32::INFINITY, 0.0, 0.0))) {
+            if slope.abs() > STEEP_SLOPE {
+                let (from, _, max) = steep.unwrap_or((*d, *d, 0.0));
+                steep = Some((from, *d, max.max(slope.abs())));
+            }
+            else if let Some((from, to, max)) = steep.take() {
+                hazards.push(format!("Steep terrain, up to {:.0}°, from km \
+                                      {:.1} to {:.1}", max, from/1000.0,
+                                     to/1000.0));
+            }
+        }
+
+        if let Some(s) = schedule {
+            let mut from = None;
+
+            for (i, dark) in s.dark.iter().chain(&[false]).enumerate() {
+                if *dark && from.is_none() {
+                    from = Some(distances[i]);
+                }
+                else if !*dark {
+                    if let Some(d) = from.take() {
+                        hazards.push(format!("Walking in darkness from km \
+                                              {:.1} to {:.1}", d/1000.0,
+                                             distances[i]/1000.0));
+                    }
+                }
+            }
+        }
+
+        hazards
+    }
+
+    // Sketch of the path and the waypoints, with north up and a scale bar
+    fn map_svg(points: &[Coord], waypoints: &[(Coord, String)],
+               params: &Params) -> String {
+        let all = points.iter().chain(waypoints.iter().map(|w| &w.0));
+        let (mut w, mut e, mut s, mut n) = (
+            f32::INFINITY, f32::NEG_INFINITY,
+            f32::INFINITY, f32::NEG_INFINITY);
+
+        for c in all {
+            w = w.min(c.e);
+            e = e.max(c.e);
+            s = s.min(c.n);
+            n = n.max(c.n);
+        }
+
+        let scale = (MAP_SIZE - 2.0*MARGIN)/(e - w).max(n - s).max(1.0);
+        let width = (e - w)*scale + 2.0*MARGIN;
+        let height = (n - s)*scale + 2.0*MARGIN;
+        let xy = |c: &Coord| (MARGIN + (c.e - w)*scale,
+                              height - MARGIN - (c.n - s)*scale);
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" \
+             height=\"{:.0}\" font-family=\"sans-serif\" font-size=\"12\">\n\
+             <rect width=\"100%\" height=\"100%\" fill=\"#f4f1e8\"/>\n",
+            width, height);
+
+        let line: Vec<String> = points.iter()
+            .map(|c| { let (x, y) = xy(c); format!("{:.1},{:.1}", x, y) })
+            .collect();
+        let _ = writeln!(svg, "<polyline points=\"{}\" fill=\"none\" \
+                               stroke=\"#d01010\" stroke-width=\"2\"/>",
+                         line.join(" "));
+
+        for f in &params.fords {
+            let (x, y) = xy(f);
+            let _ = writeln!(svg, "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"4\" \
+                                   fill=\"#0096c8\"/>", x, y);
+        }
+
+        for (c, label) in waypoints {
+            let (x, y) = xy(c);
+            let _ = writeln!(svg, "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"5\" \
+                                   fill=\"white\" stroke=\"black\"/>\n\
+                                   <text x=\"{:.1}\" y=\"{:.1}\">{}</text>",
+                             x, y, x + 8.0, y - 8.0, escape(label));
+        }
+
+        // Scale bar of 1, 2 or 5 times a power of ten meters, about a
+        // quarter of the width
+        let target = (width - 2.0*MARGIN)/scale/4.0;
+        let magnitude = 10f32.powf(target.max(1.0).log10().floor());
+        let bar = [5.0, 2.0, 1.0].iter().map(|m| m*magnitude)
+            .find(|b| *b <= target).unwrap_or(magnitude);
+        let label = if bar >= 1000.0 {
+            format!("{} km", bar/1000.0)
+        }
+        else {
+            format!("{} m", bar)
+        };
+        let _ = writeln!(svg, "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" \
+                               y2=\"{:.1}\" stroke=\"black\" \
+                               stroke-width=\"3\"/>\n\
+                               <text x=\"{:.1}\" y=\"{:.1}\">{}</text>\n\
+                               <text x=\"{:.1}\" y=\"20\">N ↑</text>\n\
+                               </svg>",
+                         MARGIN, height - 10.0, MARGIN + bar*scale,
+                         height - 10.0, MARGIN + bar*scale + 6.0,
+                         height - 6.0, label, width - MARGIN - 20.0);
+
+        svg
+    }
+
+    // Elevation along the path
+    fn profile_svg(path: &Path, params: &Params,
+                   atlas: &dyn ElevationSource) -> String {
+        let step = (path.len()/PROFILE_SAMPLES).max(PROFILE_STEP);
+        let profile = path.profile(atlas, step, params.ascent_threshold);
+        let length = profile.last().map(|p| p.0).unwrap_or(0.0).max(1.0);
+        let low = profile.iter().map(|p| p.1).fold(f32::INFINITY, f32::min);
+        let high = profile.iter().map(|p| p.1)
+            .fold(f32::NEG_INFINITY, f32::max);
+        let range = (high - low).max(1.0);
+        let (w, h) = (PROFILE_WIDTH - 2.0*MARGIN,
+                      PROFILE_HEIGHT - 2.0*MARGIN);
+
+        let line: Vec<String> = profile.iter()
+            .map(|(d, e, _)| format!("{:.1},{:.1}", MARGIN + d/length*w,
+                                     MARGIN + (high - e)/range*h))
+            .collect();
+
+        format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" \
+                 height=\"{:.0}\" font-family=\"sans-serif\" \
+                 font-size=\"12\">\n\
+                 <rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n\
+                 <polyline points=\"{}\" fill=\"none\" stroke=\"#206020\" \
+                 stroke-width=\"2\"/>\n\
+                 <text x=\"2\" y=\"{:.0}\">{:.0} m</text>\n\
+                 <text x=\"2\" y=\"{:.0}\">{:.0} m</text>\n\
+                 <text x=\"{:.0}\" y=\"{:.0}\">0 km</text>\n\
+                 <text x=\"{:.0}\" y=\"{:.0}\">{:.1} km</text>\n\
+                 </svg>\n",
+                PROFILE_WIDTH, PROFILE_HEIGHT, line.join(" "),
+                MARGIN - 4.0, high, MARGIN + h, low, MARGIN,
+                PROFILE_HEIGHT - 6.0, MARGIN + w - 40.0,
+                PROFILE_HEIGHT - 6.0, length/1000.0)
+    }
+
+    fn html_table(rows: &[Vec<String>]) -> String {
+        let mut table = "<table>\n".to_string();
+
+        for (i, row) in rows.iter().enumerate() {
+            let tag = if i == 0 { "th" } else { "td" };
+            let cells: Vec<String> = row.iter()
+                .map(|c| format!("<{}>{}</{}>", tag, escape(c), tag))
+                .collect();
+            let _ = writeln!(table, "<tr>{}</tr>", cells.join(""));
+        }
+
+        table.push_str("</table>\n");
+        table
+    }
+
+    fn md_table(rows: &[Vec<String>]) -> String {
+        let mut table = String::new();
+
+        for (i, row) in rows.iter().enumerate() {
+            let _ = writeln!(table, "| {} |", row.join(" | "));
+
+            if i == 0 {
+                let _ = writeln!(table, "|{}", "---|".repeat(row.len()));
+            }
+        }
+
+        table
+    }
+
+    fn to_html(&self) -> String {
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>{}</title>\n<style>\n\
+             body {{ font-family: sans-serif; max-width: 860px; \
+             margin: auto; }}\n\
+             table {{ border-collapse: collapse; margin-bottom: 1em; }}\n\
+             th, td {{ border: 1px solid #999; padding: 2px 8px; }}\n\
+             </style>\n</head>\n<body>\n<h1>{}</h1>\n",
+            escape(&self.title), escape(&self.title));
+
+        html.push_str("<table>\n");
+        for (k, v) in &self.summary {
+            let _ = writeln!(html, "<tr><th>{}</th><td>{}</td></tr>",
+                             escape(k), escape(v));
+        }
+        html.push_str("</table>\n");
+
+        let _ = writeln!(html, "<h2>Map</h2>\n{}<h2>Elevation profile</h2>\
+                                \n{}<h2>Legs</h2>\n{}<h2>Hazards</h2>",
+                         self.map, self.profile,
+                         Report::html_table(&self.legs));
+
+        if self.hazards.is_empty() {
+            html.push_str("<p>None found.</p>\n");
+        }
+        else {
+            html.push_str("<ul>\n");
+            for h in &self.hazards {
+                let _ = writeln!(html, "<li>{}</li>", escape(h));
+            }
+            html.push_str("</ul>\n");
+        }
+
+        if let Some(sun) = &self.sun {
+            let _ = write!(html, "<h2>Schedule</h2>\n<p>{}</p>\n{}",
+                           escape(sun), Report::html_table(&self.schedule));
+        }
+
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+
+    fn to_markdown(&self, map_fname: &str, profile_fname: &str) -> String {
+        // The images are referred to relative to the report
+        let base = |f: &str| f.rsplit('/').next().unwrap_or(f).to_string();
+        let mut md = format!("# {}\n\n", self.title);
+
+        for (k, v) in &self.summary {
+            let _ = writeln!(md, "- **{}:** {}", k, v);
+        }
+
+        let _ = write!(md, "\n## Map\n\n![Map]({})\n\n\
+                            ## Elevation profile\n\n![Elevation profile]({})\
+                            \n\n## Legs\n\n{}\n## Hazards\n\n",
+                       base(map_fname), base(profile_fname),
+                       Report::md_table(&self.legs));
+
+        if self.hazards.is_empty() {
+            md.push_str("None found.\n");
+        }
+
+        for h in &self.hazards {
+            let _ = writeln!(md, "- {}", h);
+        }
+
+        if let Some(sun) = &self.sun {
+            let _ = write!(md, "\n## Schedule\n\n{}\n\n{}", sun,
+                           Report::md_table(&self.schedule));
+        }
+
+        md
+    }
+}
@@ -0,0 +1,123 @@
+use crate::cost::Cost;
+use crate::elevation::ElevationSource;
+use crate::params::Params;
+use crate::path::Path;
+use crate::schedule::{Schedule, format_clock};
+
+use std::fmt::Write;
+use std::fs;
+
+// Largest distance (meters) between the path and the straight lines of the
+// route card
+const CARD_TOLERANCE: f32 = 50.0;
+
+// Time as H:MM
+fn format_duration(t: f32) -> String {
+    let minutes = (t/60.0).round() as usize;
+
+    format!("{}:{:02}", minutes/60, minutes%60)
+}
+
+// Write a route card for navigating the path by map and compass. Each leg
+// between the waypoints is split into straight stretches, listed with their
+// bearing, distance, ascent, descent and time. The file is plain
+// text for printing.
+pub fn write_routecard(fname: &str, path: &Path, params: &Params,
+                       atlas: &dyn ElevationSource) -> Result<(), String> {
+    if path.len() == 0.0 {
+        return Err("Path has no length".to_string());
+    }
+
+    let cost = Cost::from_params(params);
+    let points: Vec<_> = path.into_iter().cloned().collect();
+    let waypoints = params.labelled_points();
+    let mut indices = path.waypoint_indices(&params.points);
+    let schedule = if params.start_time != "" {
+        Schedule::new(path, atlas, &cost, params).ok()
+    }
+    else {
+        None
+    };
+
+    // A path without waypoints is one leg
+    if indices.len() < 2 {
+        indices = vec![0, points.len() - 1];
+    }
+
+    let mut card = String::new();
+    let _ = writeln!(card, "Route card: {}",
+                     params.expand_template(&params.track_name, path.len()));
+    if params.north == "true" {
+        let _ = writeln!(card, "Bearings are true");
+    }
+    else {
+        let _ = writeln!(card, "Bearings are magnetic, declination {:.1}°{}",
+                         params.declination.abs(),
+                         if params.declination < 0.0 { " W" } else { " E" });
+    }
+    if let Some(s) = &schedule {
+        let _ = writeln!(card, "Start: {}", format_clock(s.times[0]));
+    }
+
+    let header = format!("{:>4}  {:<20} {:>7} {:>9} {:>7} {:>8} {:>6}{}",
+                         "#", "To", "Bearing", "Dist (m)", "Asc (m)",
+                         "Desc (m)", "Time",
+                         if schedule.is_some() { "    ETA" } else { "" });
+    let mut total = (0.0, 0.0, 0.0, 0.0);
+    let mut row = 0;
+
+    for l in 0..indices.len() - 1 {
+        let (i, j) = (indices[l], indices[l + 1]);
+        // Waypoint k at point p
+        let label = |k: usize, p: usize| waypoints.get(k)
+            .map(|w| w.1.clone())
+            .unwrap_or_else(|| points[p].to_string());
+
+        let _ = writeln!(card, "\nLeg {}: {} - {}", l + 1, label(l, i),
+                         label(l + 1, j));
+        let _ = writeln!(card, "{}", header);
+
+        let turns = path.turning_points(i, j, CARD_TOLERANCE);
+        let mut leg = (0.0, 0.0, 0.0, 0.0);
+
+        for w in turns.windows(2) {
+            let (a, b) = (w[0], w[1]);
+            let s = path.stretch(a, b, atlas, &cost);
+            let to = if b == j { label(l + 1, j) }
+                     else { points[b].to_string() };
+            let bearing = path.bearing(a, b, params.north_declination())
+                .round() as u32%360;
+            row += 1;
+
+            let _ = write!(card, "{:>4}  {:<20} {:>6}° {:>9.0} {:>7.0} \
+                                  {:>8.0} {:>6}", row, to, bearing,
+                           s.length, s.ascent, s.descent,
+                           format_duration(s.time));
+            if let Some(sch) = &schedule {
+                let _ = write!(card, " {:>6}", format_clock(sch.times[b]));
+            }
+            card.push('\n');
+
+            leg.0 += s.length;
+            leg.1 += s.ascent;
+            leg.2 += s.descent;
+            leg.3 += s.time;
+        }
+
+        let _ = writeln!(card, "{:>4}  {:<20} {:>7} {:>9.0} {:>7.0} {:>8.0} \
+                                {:>6}", "", "Leg total", "", leg.0, leg.1,
+                         leg.2, format_duration(leg.3));
+
+        total.0 += leg.0;
+        total.1 += leg.1;
+        total.2 += leg.2;
+        total.3 += leg.3;
+    }
+
+    let _ = writeln!(card, "\nTotal: {:.1} km, {:.0} m ascent, {:.0} m \
+                            descent, {}", total.0/1000.0, total.1, total.2,
+                     format_duration(total.3));
+
+    fs::write(fname, card)
+        .map_err(|e| format!("Unable to write {}: {}", fname, e))
+}
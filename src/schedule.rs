@@ -0,0 +1,301 @@
+use crate::cost::Cost;
+use crate::crs::to_latlon;
+use crate::elevation::ElevationSource;
+use crate::params::Params;
+use crate::path::{Path, Segment};
+
+use hoydedata::Coord;
+use std::f32::consts::PI;
+
+const DAY: f32 = 86400.0;
+
+// Parse a local date and time on the form YYYY-MM-DDTHH:MM. Returns day of
+// year (1..366) and seconds since midnight.
+pub fn parse_date_time(value: &str) -> Result<(u32, f32), String> {
+    let err = || format!("Expected date and time YYYY-MM-DDTHH:MM, got '{}'",
+                         value);
+
+    let (date, time) = value.split_once('T').ok_or_else(err)?;
+    let d: Vec<u32> = date.split('-')
+        .map(|p| p.parse().map_err(|_| err()))
+        .collect::<Result<Vec<u32>, String>>()?;
+
+    if d.len() != 3 || d[1] < 1 || d[1] > 12 || d[2] < 1 || d[2] > 31 {
+        return Err(err());
+    }
+
+    let clock = parse_clock(time).map_err(|_| err())?;
+
+    let leap = (d[0] % 4 == 0 && d[0] % 100 != 0) || d[0] % 400 == 0;
+    let month_days = [31, if leap { 29 } else { 28 }, 31, 30, 31, 30,
+                      31, 31, 30, 31, 30, 31];
+    let doy = month_days[..(d[1] - 1) as usize].iter().sum::<u32>() + d[2];
+
+    Ok((doy, clock))
+}
+
+// Parse a clock time HH:MM into seconds since midnight
+pub fn parse_clock(value: &str) -> Result<f32, String> {
+    let err = || format!("Expected time HH:MM, got '{}'", value);
+
+    let (h, m) = value.split_once(':').ok_or_else(err)?;
+    let h: u32 = h.parse().map_err(|_| err())?;
+    let m: u32 = m.parse().map_err(|_| err())?;
+
+    if h > 23 || m > 59 {
+        return Err(err());
+    }
+
+    Ok((h*3600 + m*60) as f32)
+}
+
+// Parse a target time HH:MM, optionally followed by the number of days after
+// the start day, e.g. 09:15+1. Returns seconds since midnight of the start
+// day.
+pub fn parse_target(value: &str) -> Result<f32, String> {
+    let err = || format!("Expected time HH:MM or HH:MM+<days>, got '{}'",
+                         value);
+
+    let (clock, days) = match value.split_once('+') {
+        Some((c, d)) => (c, d.parse::<u32>().map_err(|_| err())?),
+        None => (value, 0),
+    };
+
+    Ok(parse_clock(clock).map_err(|_| err())? + (days as f32)*DAY)
+}
+
+// Format seconds since midnight as HH:MM. Times beyond midnight are shown
+// with the number of days added, e.g. 07:30+1.
+pub fn format_clock(t: f32) -> String {
+    let days = (t/DAY).floor();
+    let secs = (t - days*DAY) as u32;
+    let hm = format!("{:02}:{:02}", secs/3600, (secs%3600)/60);
+
+    if days >= 1.0 {
+        format!("{}+{}", hm, days as u32)
+    }
+    else {
+        hm
+    }
+}
+
+// Local sunrise and sunset (seconds since midnight) for the given day of
+// year and position. Uses the NOAA approximation of the solar position. In
+// midnight sun the sun rises at 00:00 and sets at 24:00, in polar night it
+// rises and sets at noon.
+pub fn sun_times(doy: u32, lat: f64, lon: f64, utc_offset: f32)
+                 -> (f32, f32) {
+    let g = 2.0*PI/365.0*(doy as f32 - 1.0);
+    let eqtime = 229.18*(0.000075 + 0.001868*g.cos() - 0.032077*g.sin()
+                         - 0.014615*(2.0*g).cos() - 0.040849*(2.0*g).sin());
+    let decl = 0.006918 - 0.399912*g.cos() + 0.070257*g.sin()
+        - 0.006758*(2.0*g).cos() + 0.000907*(2.0*g).sin()
+        - 0.002697*(3.0*g).cos() + 0.00148*(3.0*g).sin();
+
+    let lat = (lat as f32).to_radians();
+    let cos_ha = 90.833_f32.to_radians().cos()/(lat.cos()*decl.cos())
+        - lat.tan()*decl.tan();
+
+    if cos_ha > 1.0 {
+        return (DAY/2.0, DAY/2.0);
+    }
+    else if cos_ha < -1.0 {
+        return (0.0, DAY);
+    }
+
+    let ha = cos_ha.acos().to_degrees();
+    let lon = lon as f32;
+    // Solar noon in minutes UTC
+    let noon = 720.0 - 4.0*lon - eqtime + utc_offset*60.0;
+
+    ((noon - 4.0*ha)*60.0, (noon + 4.0*ha)*60.0)
+}
+
+// Time schedule for walking a path, starting at a given date and time. Parts
+// of the path walked in darkness take longer.
+pub struct Schedule {
+    // Clock time (seconds since midnight of the start day) at each point
+    pub times: Vec<f32>,
+    // Whether segment i (between point i and i + 1) is walked in darkness
+    pub dark: Vec<bool>,
+    pub sunrise: f32,
+    pub sunset: f32,
+}
+
+impl Schedule {
+    pub fn new(path: &Path, atlas: &dyn ElevationSource, cost: &Cost,
+               params: &Params) -> Result<Self, String> {
+        let (doy, start) = parse_date_time(&params.start_time)?;
+        let points: Vec<Coord> = path.into_iter().cloned().collect();
+
+        let (lat, lon) = to_latlon(&points[0]);
+        let (sunrise, sunset) = sun_times(doy, lat, lon, params.utc_offset);
+
+        let mut times = vec![start];
+        let mut dark = vec![];
+        let mut t = start;
+
+        for i in 0..points.len() - 1 {
+            let mut dt = Segment::new(points[i], points[i + 1])
+                .time(atlas, cost).unwrap_or(f32::INFINITY);
+            let clock = t % DAY;
+            let is_dark = clock < sunrise || clock >= sunset;
+
+            if is_dark {
+                dt *= params.darkness_factor;
+            }
+
+            t += dt;
+            times.push(t);
+            dark.push(is_dark);
+        }
+
+        Ok(Self {
+            times: times,
+            dark: dark,
+            sunrise: sunrise,
+            sunset: sunset,
+        })
+    }
+
+    // Total time walked in darkness (seconds)
+    pub fn dark_time(&self) -> f32 {
+        let mut t = 0.0;
+
+        for i in 0..self.dark.len() {
+            if self.dark[i] {
+                t += self.times[i + 1] - self.times[i];
+            }
+        }
+
+        t
+    }
+
+    // Continuous sections of the path walked in darkness
+    pub fn dark_sections(&self, path: &Path) -> Vec<Vec<Coord>> {
+        let points: Vec<Coord> = path.into_iter().cloned().collect();
+        let mut sections = vec![];
+        let mut current: Vec<Coord> = vec![];
+
+        for i in 0..self.dark.len() {
+            if self.dark[i] {
+                if current.is_empty() {
+                    current.push(points[i]);
+                }
+                current.push(points[i + 1]);
+            }
+            else if !current.is_empty() {
+                sections.push(std::mem::take(&mut current));
+            }
+        }
+
+        if !current.is_empty() {
+            sections.push(current);
+        }
+
+        sections
+    }
+
+    // The last point of the path from which the start is reached by the
+    // deadline (seconds since midnight of the start day), when turning
+    // around and walking the path back. Returns the index of the point and
+    // the time back at the start, or None if the deadline is before the
+    // start.
+    pub fn point_of_no_return(&self, path: &Path, atlas: &dyn ElevationSource,
+                              cost: &Cost, params: &Params, deadline: f32)
+                              -> Option<(usize, f32)> {
+        let points: Vec<Coord> = path.into_iter().cloned().collect();
+        // Time of walking each segment backwards
+        let back: Vec<f32> = (0..points.len() - 1)
+            .map(|i| Segment::new(points[i + 1], points[i])
+                 .time(atlas, cost).unwrap_or(f32::INFINITY))
+            .collect();
+
+        // Time back at the start when turning around at point i
+        let return_time = |i: usize| {
+            let mut t = self.times[i];
+
+            for k in (0..i).rev() {
+                let clock = t % DAY;
+                let is_dark = clock < self.sunrise || clock >= self.sunset;
+
+                t += if is_dark { back[k]*params.darkness_factor }
+                     else { back[k] };
+            }
+
+            t
+        };
+
+        if return_time(0) > deadline {
+            return None;
+        }
+
+        // Turning around later brings you back later, so the point can be
+        // found by bisection
+        let (mut lo, mut hi) = (0, points.len());
+
+        while hi - lo > 1 {
+            let mid = (lo + hi)/2;
+
+            if return_time(mid) <= deadline {
+                lo = mid;
+            }
+            else {
+                hi = mid;
+            }
+        }
+
+        Some((lo, return_time(lo)))
+    }
+
+    // Compare the arrival times at the waypoints with their target times.
+    // For each leg arriving too late, print the pace factor (planned leg time
+    // divided by available leg time) needed for reaching the target.
+    pub fn print_targets(&self, path: &Path, params: &Params) {
+        let indices = path.waypoint_indices(&params.points);
+
+        for (i, info) in params.point_info.iter().enumerate() {
+            if info.target == "" || i == 0 {
+                continue;
+            }
+
+            let Ok(target) = parse_target(&info.target) else {
+                continue;
+            };
+
+            let eta = self.times[indices[i]];
+            let leg_start = self.times[indices[i - 1]];
+
+            if eta <= target {
+                println!("Point {}: arrival {}, target {}, {} min to spare",
+                         i + 1, format_clock(eta), &info.target,
+                         ((target - eta)/60.0) as usize);
+            }
+            else if target <= leg_start {
+                println!("Point {}: arrival {}, target {} is before leg \
+                          start {}", i + 1, format_clock(eta), &info.target,
+                         format_clock(leg_start));
+            }
+            else {
+                println!("Point {}: arrival {}, target {} missed by {} min. \
+                          Required pace factor {:.2}", i + 1,
+                         format_clock(eta), &info.target,
+                         ((eta - target)/60.0).ceil() as usize,
+                         (eta - leg_start)/(target - leg_start));
+            }
+        }
+    }
+
+    pub fn print_summary(&self) {
+        println!("Start: {}, arrival: {}", format_clock(self.times[0]),
+                 format_clock(self.times[self.times.len() - 1]));
+        println!("Sunrise: {}, sunset: {}", format_clock(self.sunrise),
+                 format_clock(self.sunset));
+
+        let dark_time = self.dark_time();
+        if dark_time > 0.0 {
+            println!("Warning: {} min of the track is walked in darkness",
+                     (dark_time/60.0) as usize);
+        }
+    }
+}
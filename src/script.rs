@@ -0,0 +1,115 @@
+use crate::params::next_generation;
+
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+lazy_static! {
+    // Compiled scripts by file name, with the time the file was modified
+    static ref SCRIPTS: Mutex<HashMap<String, (SystemTime, Arc<CostScript>)>>
+        = Mutex::new(HashMap::new());
+}
+
+// Cost function written in Rhai, for experimenting with cost models without
+// recompiling. The script defines
+//
+//   fn cost(slope, cross_slope, landcover) { ... }
+//
+// returning the time (seconds) per meter of walking with the slope
+// (degrees, positive uphill) along the walking direction and the slope
+// across it. A negative time makes the terrain impassable. There is no land
+// cover data yet, so landcover is always "".
+pub struct CostScript {
+    fname: String,
+    // Changes each time a file is compiled
+    pub generation: u64,
+    #[cfg(feature = "scripting")]
+    engine: rhai::Engine,
+    #[cfg(feature = "scripting")]
+    ast: rhai::AST,
+}
+
+impl fmt::Debug for CostScript {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CostScript({})", self.fname)
+    }
+}
+
+impl CostScript {
+    // The compiled script of a file. It is compiled again only if the file
+    // has changed.
+    pub fn load(fname: &str) -> Result<Arc<CostScript>, String> {
+        let modified = fs::metadata(fname)
+            .and_then(|m| m.modified())
+            .map_err(|e| format!("Unable to read {}: {}", fname, e))?;
+
+        if let Some((t, script)) = SCRIPTS.lock().get(fname) {
+            if *t == modified {
+                return Ok(script.clone());
+            }
+        }
+
+        let script = Arc::new(CostScript::compile(fname)?);
+        // Check that cost can be called before the script is used
+        script.call(0.0, 0.0, "")?;
+
+        SCRIPTS.lock().insert(fname.to_string(), (modified, script.clone()));
+
+        Ok(script)
+    }
+
+    #[cfg(feature = "scripting")]
+    fn compile(fname: &str) -> Result<Self, String> {
+        let engine = rhai::Engine::new();
+        let ast = engine.compile_file(fname.into())
+            .map_err(|e| format!("Error in {}: {}", fname, e))?;
+
+        Ok(Self {
+            fname: fname.to_string(),
+            generation: next_generation(),
+            engine: engine,
+            ast: ast,
+        })
+    }
+
+    #[cfg(not(feature = "scripting"))]
+    fn compile(_fname: &str) -> Result<Self, String> {
+        Err("Cost scripts need stivalg built with the scripting feature"
+            .to_string())
+    }
+
+    #[cfg(feature = "scripting")]
+    fn call(&self, slope: f32, cross: f32, landcover: &str)
+            -> Result<f32, String> {
+        let v: rhai::Dynamic = self.engine
+            .call_fn(&mut rhai::Scope::new(), &self.ast, "cost",
+                     (slope as rhai::FLOAT, cross as rhai::FLOAT,
+                      landcover.to_string()))
+            .map_err(|e| format!("Error in {}: {}", self.fname, e))?;
+
+        v.as_float()
+            .or(v.as_int().map(|i| i as rhai::FLOAT))
+            .map(|t| t as f32)
+            .map_err(|t| format!("cost in {} returned {}, expected a number",
+                                 self.fname, t))
+    }
+
+    #[cfg(not(feature = "scripting"))]
+    fn call(&self, _slope: f32, _cross: f32, _landcover: &str)
+            -> Result<f32, String> {
+        Err("Cost scripts need stivalg built with the scripting feature"
+            .to_string())
+    }
+
+    // Time (seconds) per meter, or None if the terrain is impassable.
+    // Failing calls are treated as impassable terrain.
+    pub fn cost(&self, slope: f32, cross: f32, landcover: &str)
+                -> Option<f32> {
+        self.call(slope, cross, landcover).ok()
+            .filter(|t| t.is_finite() && *t >= 0.0)
+    }
+}
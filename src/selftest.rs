@@ -0,0 +1,191 @@
+use crate::barrier::Barrier;
+use crate::elevation::ElevationSource;
+use crate::params::Params;
+use crate::path::Path;
+use crate::terrain::SyntheticTerrain;
+
+use hoydedata::{Atlas, Coord};
+use std::env;
+
+// Start and end points of the test routes, 800 m apart
+const TEST_START: (f32, f32) = (100000.0, 6900000.0);
+const TEST_END: (f32, f32) = (100800.0, 6900000.0);
+// A straight route may be this much longer than the distance
+const MAX_DETOUR: f32 = 1.02;
+// Tolerance of positions read back from GPX (meters)
+const GPX_TOLERANCE: f32 = 1.0;
+
+fn test_params(terrain: Option<&SyntheticTerrain>) -> Params {
+    let mut params = Params::from_config();
+    params.push_point(Coord::new(TEST_START.0, TEST_START.1));
+    params.push_point(Coord::new(TEST_END.0, TEST_END.1));
+
+    if let Some(t) = terrain {
+        params.barriers = t.barriers();
+    }
+
+    params
+}
+
+fn synthetic(name: &str, seed: u64) -> SyntheticTerrain {
+    let origin = (Coord::new(TEST_START.0, TEST_START.1)
+                  + Coord::new(TEST_END.0, TEST_END.1))*0.5;
+
+    SyntheticTerrain::new(name, origin, seed).unwrap()
+}
+
+fn compute(params: &Params, atlas: &dyn ElevationSource)
+           -> Result<Path, String> {
+    Path::from_points(params, atlas)
+        .ok_or_else(|| "No path found".to_string())
+}
+
+// The path must start and end at the waypoints
+fn check_end_points(path: &Path, params: &Params) -> Result<(), String> {
+    let points: Vec<Coord> = path.into_iter().cloned().collect();
+    let (first, last) = (points[0], points[points.len() - 1]);
+
+    if (first - params.points[0]).abs() > 0.0
+        || (last - params.points[1]).abs() > 0.0 {
+        return Err(format!("Path runs from {} to {}", first, last));
+    }
+
+    Ok(())
+}
+
+// On flat ground, the fastest route is a straight line
+fn check_plane() -> Result<(), String> {
+    let terrain = synthetic("plane", 0);
+    let params = test_params(Some(&terrain));
+    let path = compute(&params, &terrain)?;
+    check_end_points(&path, &params)?;
+
+    let distance = (params.points[1] - params.points[0]).abs();
+
+    if path.len() > distance*MAX_DETOUR {
+        return Err(format!("Path is {:.0}m, straight line is {:.0}m",
+                           path.len(), distance));
+    }
+
+    Ok(())
+}
+
+// The river along the valley floor can only be crossed at the bridge
+fn check_barrier() -> Result<(), String> {
+    let terrain = synthetic("valley", 0);
+    let params = test_params(Some(&terrain));
+    let path = compute(&params, &terrain)?;
+    check_end_points(&path, &params)?;
+
+    let points: Vec<Coord> = path.into_iter().cloned().collect();
+    let barriers: Vec<&Barrier> = params.barriers.iter().collect();
+
+    for i in 0..points.len() - 1 {
+        if barriers.iter().any(|b| b.is_crossing(&points[i], &points[i + 1])) {
+            return Err(format!("Path crosses the river at {}", points[i]));
+        }
+    }
+
+    Ok(())
+}
+
+// The same terrain and seed must give the same path
+fn check_deterministic() -> Result<(), String> {
+    let terrain = synthetic("fractal", 42);
+    let params = test_params(Some(&terrain));
+    let p1 = compute(&params, &terrain)?;
+    let p2 = compute(&params, &terrain)?;
+
+    let pts1: Vec<Coord> = p1.into_iter().cloned().collect();
+    let pts2: Vec<Coord> = p2.into_iter().cloned().collect();
+
+    if pts1.len() != pts2.len()
+        || pts1.iter().zip(pts2.iter())
+            .any(|(c1, c2)| (*c1 - *c2).abs() > 0.0) {
+        return Err("Repeated computations gave different paths".to_string());
+    }
+
+    Ok(())
+}
+
+// A path written to GPX and read back must keep its points
+fn check_gpx() -> Result<(), String> {
+    let terrain = synthetic("ridge", 0);
+    let params = test_params(Some(&terrain));
+    let path = compute(&params, &terrain)?;
+
+    let fname = env::temp_dir().join("stivalg-selftest.gpx");
+    let fname = fname.to_str().unwrap();
+    path.write_gpx(fname, "selftest", &[], &terrain, None);
+    let read = Path::read_gpx(fname);
+    let _ = std::fs::remove_file(fname);
+    let read = read?;
+
+    let pts1: Vec<Coord> = path.into_iter().cloned().collect();
+    let pts2: Vec<Coord> = read.into_iter().cloned().collect();
+
+    if pts1.len() != pts2.len() {
+        return Err(format!("Wrote {} points, read {}", pts1.len(),
+                           pts2.len()));
+    }
+
+    for (c1, c2) in pts1.iter().zip(pts2.iter()) {
+        if (*c1 - *c2).abs() > GPX_TOLERANCE {
+            return Err(format!("Wrote {}, read {}", c1, c2));
+        }
+    }
+
+    Ok(())
+}
+
+// The mockup atlas from hoydedata must be usable for computing a path
+fn check_mockup_atlas() -> Result<(), String> {
+    let atlas = Atlas::new_mockup();
+    let params = test_params(None);
+    let path = compute(&params, &atlas)?;
+
+    check_end_points(&path, &params)
+}
+
+// The map directory must contain a usable atlas
+fn check_map_dir() -> Result<(), String> {
+    Atlas::new(1.0, None)
+        .map(|_| ())
+        .map_err(|e| format!("Unable to open maps: {:?}", e))
+}
+
+// Run all checks, printing the result of each. Returns true if all checks
+// passed.
+pub fn run_selftest() -> bool {
+    let checks: [(&str, fn() -> Result<(), String>); 6] = [
+        ("straight path on flat terrain", check_plane),
+        ("river barrier respected", check_barrier),
+        ("deterministic fractal terrain", check_deterministic),
+        ("GPX round trip", check_gpx),
+        ("path on mockup atlas", check_mockup_atlas),
+        ("map directory", check_map_dir),
+    ];
+
+    let mut failed = 0;
+
+    for (name, check) in checks {
+        match check() {
+            Ok(()) => {
+                println!("PASS {}", name);
+            },
+            Err(e) => {
+                println!("FAIL {}: {}", name, e);
+                failed += 1;
+            },
+        }
+    }
+
+    if failed == 0 {
+        println!("All {} checks passed", checks.len());
+    }
+    else {
+        println!("{} of {} checks failed", failed, checks.len());
+    }
+
+    failed == 0
+}
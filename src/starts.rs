@@ -0,0 +1,109 @@
+use crate::cost::Cost;
+use crate::debug::DebugLog;
+use crate::elevation::ElevationSource;
+use crate::params::{Params, PointInfo};
+use crate::path::Path;
+use crate::poi::Poi;
+
+use hoydedata::Coord;
+use std::fmt;
+
+// Route from a candidate start through the rest of the waypoints
+pub struct StartOption {
+    pub name: String,
+    pub coord: Coord,
+    // Modeled time (seconds), length and ascent (meters) of the route. None
+    // if it can't be walked.
+    pub route: Option<(f32, f32, f32)>,
+}
+
+impl fmt::Display for StartOption {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(formatter, "{} {}", self.name, self.coord)?;
+
+        match self.route {
+            Some((t, len, ascent)) => {
+                let min = (t/60.0).ceil() as usize;
+                write!(formatter, ": {} hr {} min, {:.1} km, {:.0} m ascent",
+                       min/60, min%60, len/1000.0, ascent)
+            },
+            None => write!(formatter, ": no route"),
+        }
+    }
+}
+
+// The legs after the first, from the second waypoint on. They are the same
+// whichever start is chosen, except that they don't avoid the first leg
+// when there is a return penalty.
+fn later_legs(params: &Params, atlas: &dyn ElevationSource) -> Option<Path> {
+    let mut debug = DebugLog::new();
+    let mut path = Path::new();
+    let mut start = params.points[1];
+
+    for i in 1..params.points.len() - 1 {
+        let mut p = Path::find_leg(params, atlas, &mut debug, i, start,
+                                   &path)?;
+        start = *(&p).into_iter().last()?;
+        path.append(&mut p);
+    }
+
+    Some(path)
+}
+
+// Compute the route from each candidate start to the rest of the waypoints.
+// Only the first leg is searched for each candidate. The candidates are
+// ranked by time, fastest first.
+pub fn evaluate_starts(params: &Params, atlas: &dyn ElevationSource,
+                       candidates: &[Poi]) -> Vec<StartOption> {
+    let cost = Cost::from_params(params);
+    let mut options = vec![];
+
+    println!("Finding the route from the second waypoint...");
+    let rest = later_legs(params, atlas);
+
+    for (i, poi) in candidates.iter().enumerate() {
+        println!("Evaluating start {} of {}: {}", i + 1, candidates.len(),
+                 poi.name);
+
+        let mut search = params.clone();
+        search.points[0] = poi.coord;
+        if let Some(info) = search.point_info.first_mut() {
+            *info = PointInfo::default();
+        }
+
+        let route = rest.as_ref()
+            .and_then(|rest| {
+                let mut p = Path::find_leg(&search, atlas,
+                                           &mut DebugLog::new(), 0,
+                                           poi.coord, &Path::new())?;
+                // The first leg may end within the radius of the second
+                // waypoint. The later legs start at the waypoint.
+                let junction = (&rest).into_iter().next();
+                if junction.is_some() && (&p).into_iter().last() != junction {
+                    p.push(params.points[1]);
+                }
+                p.append(&mut rest.clone());
+                Some(p)
+            })
+            .map(|p| {
+                (p.calculate_time(atlas, &cost), p.len(),
+                 p.elevation(atlas, cost.ascent_threshold()))
+            });
+
+        options.push(StartOption {
+            name: poi.name.clone(),
+            coord: poi.coord,
+            route: route,
+        });
+    }
+
+    // Unreachable starts last
+    options.sort_by(|a, b| {
+        let time = |o: &StartOption| o.route
+            .map(|(t, _, _)| t)
+            .unwrap_or(f32::INFINITY);
+        time(a).total_cmp(&time(b))
+    });
+
+    options
+}
@@ -0,0 +1,279 @@
+use crate::cost::{Cost, JOULE_PER_KCAL};
+use crate::elevation::ElevationSource;
+use crate::path::{Path, Segment};
+
+use hoydedata::Coord;
+use serde::Serialize;
+
+// Sampling interval (meters) for finding the maximal slope and the slope
+// histogram
+const SLOPE_STEP: f32 = 5.0;
+// Width (degrees) of the slope classes of the histogram
+const SLOPE_CLASS: f32 = 5.0;
+// Slopes (degrees) below this count as level
+const LEVEL_SLOPE: f32 = 2.0;
+
+// Distance (m) and time (s) spent in a slope class or direction
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SlopeBin {
+    pub distance: f32,
+    pub time: f32,
+}
+
+impl SlopeBin {
+    fn add(&mut self, distance: f32, time: f32) {
+        self.distance += distance;
+        self.time += time;
+    }
+}
+
+// Distance and time by slope class (0-5°, 5-10°, ...) regardless of
+// direction, and by direction
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SlopeHistogram {
+    pub classes: Vec<SlopeBin>,
+    pub ascending: SlopeBin,
+    pub descending: SlopeBin,
+    pub level: SlopeBin,
+}
+
+impl SlopeHistogram {
+    fn new(points: &[Coord], atlas: &dyn ElevationSource, cost: &Cost)
+           -> Self {
+        let mut histogram = SlopeHistogram::default();
+
+        for w in points.windows(2) {
+            let s = Segment::new(w[0], w[1]);
+            let len = s.len();
+
+            if len == 0.0 {
+                continue;
+            }
+
+            // The time of the segment is shared out by distance. Segments
+            // which can't be walked have no time, and are left out.
+            let Some(t) = s.time(atlas, cost) else {
+                continue;
+            };
+            let speed = t/len;
+            let samples: Vec<(f32, f32, f32)> = s.profile(atlas, SLOPE_STEP)
+                .collect();
+
+            for p in samples.windows(2) {
+                let (d, slope) = (p[1].0 - p[0].0, p[0].2);
+                histogram.add(slope, d, d*speed);
+            }
+        }
+
+        histogram
+    }
+
+    fn add(&mut self, slope: f32, distance: f32, time: f32) {
+        let class = (slope.abs()/SLOPE_CLASS) as usize;
+
+        if self.classes.len() <= class {
+            self.classes.resize(class + 1, SlopeBin::default());
+        }
+
+        self.classes[class].add(distance, time);
+
+        if slope.abs() < LEVEL_SLOPE {
+            self.level.add(distance, time);
+        }
+        else if slope > 0.0 {
+            self.ascending.add(distance, time);
+        }
+        else {
+            self.descending.add(distance, time);
+        }
+    }
+
+    pub fn print(&self) {
+        println!("Slope (°)     Distance (m)    Time (min)");
+
+        for (i, c) in self.classes.iter().enumerate() {
+            let from = i as f32*SLOPE_CLASS;
+            println!("{:3.0} - {:3.0}    {:12.0}    {:10.1}", from,
+                     from + SLOPE_CLASS, c.distance, c.time/60.0);
+        }
+
+        println!();
+        println!("Direction     Distance (m)    Time (min)");
+
+        for (name, b) in [("Ascending", &self.ascending),
+                          ("Descending", &self.descending),
+                          ("Level", &self.level)] {
+            println!("{:10}    {:12.0}    {:10.1}", name, b.distance,
+                     b.time/60.0);
+        }
+    }
+
+    // Rows of class (lower bound in degrees, or direction), distance (m)
+    // and time (s)
+    pub fn to_csv(&self) -> String {
+        let mut csv = "class,distance,time\n".to_string();
+
+        for (i, c) in self.classes.iter().enumerate() {
+            csv.push_str(&format!("{:.0},{:.1},{:.1}\n",
+                                  i as f32*SLOPE_CLASS, c.distance, c.time));
+        }
+
+        for (name, b) in [("ascending", &self.ascending),
+                          ("descending", &self.descending),
+                          ("level", &self.level)] {
+            csv.push_str(&format!("{},{:.1},{:.1}\n", name, b.distance,
+                                  b.time));
+        }
+
+        csv
+    }
+}
+
+// Length (m), time (s), energy (kcal) and water intake (l) of a leg
+#[derive(Clone, Debug, Serialize)]
+pub struct LegStats {
+    pub length: f32,
+    pub time: f32,
+    // Time of the fastest member of a group walking at different paces
+    pub fastest_time: Option<f32>,
+    pub energy: f32,
+    pub water: f32,
+}
+
+// Summary of a path. Lengths and heights are in meters, time in seconds,
+// energy in kcal, water in liters and the slope in degrees.
+#[derive(Clone, Debug, Serialize)]
+pub struct PathStats {
+    pub length: f32,
+    pub time: f32,
+    pub fastest_time: Option<f32>,
+    pub ascent: f32,
+    pub descent: f32,
+    pub energy: f32,
+    pub water: f32,
+    pub max_slope: f32,
+    pub slopes: SlopeHistogram,
+    // Legs between the waypoints
+    pub legs: Vec<LegStats>,
+}
+
+impl PathStats {
+    pub fn new(path: &Path, waypoints: &[Coord], atlas: &dyn ElevationSource,
+               cost: &Cost) -> Self {
+        let points: Vec<Coord> = path.into_iter().cloned().collect();
+        let time = path.calculate_time(atlas, cost);
+        let energy = if points.is_empty() {
+            0.0
+        }
+        else {
+            path.energy(0, points.len() - 1, atlas, cost)/JOULE_PER_KCAL
+        };
+
+        let max_slope = path.profile(atlas, SLOPE_STEP, 0.0).iter()
+            .map(|(_, _, slope)| slope.abs())
+            .fold(0.0, f32::max);
+
+        // Time of the fastest member of the group
+        let fastest = |t: f32| cost.has_group_spread()
+            .then(|| cost.fastest_time(t));
+
+        let indices = path.waypoint_indices(waypoints);
+        let mut legs = vec![];
+
+        for l in 0..indices.len().saturating_sub(1) {
+            let (i, j) = (indices[l], indices[l + 1]);
+            let mut length = 0.0;
+            let mut time = 0.0;
+
+            for k in i..j {
+                let s = Segment::new(points[k], points[k + 1]);
+                length += s.len();
+                time += s.time(atlas, cost).unwrap_or(f32::INFINITY);
+            }
+
+            legs.push(LegStats {
+                length: length,
+                time: time,
+                fastest_time: fastest(time),
+                energy: path.energy(i, j, atlas, cost)/JOULE_PER_KCAL,
+                water: cost.fluid(time),
+            });
+        }
+
+        Self {
+            length: path.len(),
+            time: time,
+            fastest_time: fastest(time),
+            ascent: path.elevation(atlas, cost.ascent_threshold()),
+            descent: path.descent(atlas, cost.ascent_threshold()),
+            energy: energy,
+            water: cost.fluid(time),
+            max_slope: max_slope,
+            slopes: SlopeHistogram::new(&points, atlas, cost),
+            legs: legs,
+        }
+    }
+
+    // Time as hours, minutes and seconds
+    pub fn format_time(&self) -> String {
+        PathStats::format_duration(self.time)
+    }
+
+    fn format_duration(time: f32) -> String {
+        match time as usize {
+            t if t >= 3600 => {
+                format!("{} hr {} min {} sec", t/3600, (t%3600)/60, t%60)
+            },
+            t if t >= 60 => {
+                format!("{} min {} sec", t/60, t%60)
+            },
+            t => {
+                format!("{} sec", t)
+            },
+        }
+    }
+
+    pub fn print_summary(&self) {
+        println!("Length: {}m", self.length);
+        println!("Time: {}", self.format_time());
+        if let Some(t) = self.fastest_time {
+            println!("Time of the fastest in the group: {}",
+                     PathStats::format_duration(t));
+        }
+        println!("Total elevation: {}m", self.ascent);
+        println!("Total descent: {}m", self.descent);
+        println!("Max slope: {:.0}°", self.max_slope);
+        println!("Energy: {:.0} kcal", self.energy);
+        println!("Water: {:.1} l", self.water);
+    }
+
+    // Print length, time, energy and water intake of each leg between the
+    // waypoints. With a group, the time of the fastest member is shown
+    // too.
+    pub fn print_legs(&self) {
+        let group = self.fastest_time.is_some();
+
+        print!("Leg    Length (m)    Time (min)");
+        if group {
+            print!("    Fastest (min)    Spread (min)");
+        }
+        println!("    Energy (kcal)    Water (l)");
+
+        for (l, leg) in self.legs.iter().enumerate() {
+            print!("{:3}    {:10.0}    {:10.0}", l + 1, leg.length,
+                   leg.time/60.0);
+            if let Some(t) = leg.fastest_time {
+                print!("    {:13.0}    {:12.0}", t/60.0,
+                       (leg.time - t)/60.0);
+            }
+            println!("    {:13.0}    {:9.1}", leg.energy, leg.water);
+        }
+    }
+
+    // One line summary, e.g. for the description of an exported track
+    pub fn summary_line(&self) -> String {
+        format!("{:.1} km, {}, {:.0} m ascent, {:.0} m descent",
+                self.length/1000.0, self.format_time(), self.ascent,
+                self.descent)
+    }
+}
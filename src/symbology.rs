@@ -0,0 +1,199 @@
+// Presets. The colorblind preset uses the Okabe-Ito palette, which can be
+// told apart with the common forms of color blindness. The high contrast
+// preset has wider lines and stronger label halos.
+pub const SYMBOLOGY_PRESETS: &[&str] = &["default", "colorblind",
+                                         "high-contrast"];
+
+pub type Rgb = (u8, u8, u8);
+
+// Color and width (pixels) of a line layer
+#[derive(Clone, Copy, Debug)]
+pub struct LineStyle {
+    pub color: Rgb,
+    pub width: f32,
+}
+
+// Line layers of the map
+#[derive(Clone, Copy, Debug)]
+pub enum LineLayer {
+    Track,
+    Night,
+    Compare,
+    Areas,
+    Corridor,
+    Gates,
+    PinAreas,
+    Selection,
+}
+
+const LINE_LAYERS: &[(&str, LineLayer)] = &[
+    ("track", LineLayer::Track),
+    ("night", LineLayer::Night),
+    ("compare", LineLayer::Compare),
+    ("areas", LineLayer::Areas),
+    ("corridor", LineLayer::Corridor),
+    ("gates", LineLayer::Gates),
+    ("pin_areas", LineLayer::PinAreas),
+    ("selection", LineLayer::Selection),
+];
+
+// Colors, line widths and point sizes (pixels) of the map layers, and the
+// width of the halo around labels
+#[derive(Clone, Debug)]
+pub struct Symbology {
+    lines: [LineStyle; 8],
+    pub waypoint_color: Rgb,
+    pub waypoint_size: f32,
+    pub marker_size: f32,
+    pub poi_size: f32,
+    pub label_halo: f32,
+}
+
+const fn line(color: Rgb, width: f32) -> LineStyle {
+    LineStyle {
+        color: color,
+        width: width,
+    }
+}
+
+impl Default for Symbology {
+    fn default() -> Self {
+        Self {
+            lines: [
+                line((255, 0, 0), 3.0),
+                line((20, 20, 120), 3.0),
+                line((30, 90, 220), 3.0),
+                line((255, 0, 0), 1.5),
+                line((20, 140, 40), 2.0),
+                line((230, 140, 0), 3.0),
+                line((140, 60, 200), 2.0),
+                line((255, 200, 0), 4.0),
+            ],
+            waypoint_color: (255, 0, 0),
+            waypoint_size: 8.0,
+            marker_size: 7.0,
+            poi_size: 6.0,
+            label_halo: 2.0,
+        }
+    }
+}
+
+impl Symbology {
+    // A preset with settings overridden by a comma separated list of
+    // key=value, e.g. "track.color=#0072b2,track.width=4". The keys are
+    // <layer>.color and <layer>.width of the line layers, waypoint.color,
+    // waypoint.size, marker.size, poi.size and label.halo.
+    pub fn new(preset: &str, overrides: &str) -> Result<Self, String> {
+        let mut s = Symbology::preset(preset)?;
+
+        let list = overrides.split(',').map(|o| o.trim());
+
+        for o in list.filter(|o| !o.is_empty()) {
+            let (key, value) = o.split_once('=')
+                .ok_or(format!("Expected key=value, got '{}'", o))?;
+            s.set(key.trim(), value.trim())?;
+        }
+
+        Ok(s)
+    }
+
+    fn preset(name: &str) -> Result<Self, String> {
+        let s = match name {
+            "default" => Self::default(),
+            "colorblind" => Self {
+                lines: [
+                    line((0, 114, 178), 3.0),
+                    line((0, 0, 0), 3.0),
+                    line((86, 180, 233), 3.0),
+                    line((213, 94, 0), 1.5),
+                    line((0, 158, 115), 2.0),
+                    line((230, 159, 0), 3.0),
+                    line((204, 121, 167), 2.0),
+                    line((240, 228, 66), 4.0),
+                ],
+                waypoint_color: (213, 94, 0),
+                waypoint_size: 8.0,
+                marker_size: 7.0,
+                poi_size: 6.0,
+                label_halo: 2.0,
+            },
+            "high-contrast" => Self {
+                lines: [
+                    line((255, 0, 255), 5.0),
+                    line((0, 0, 0), 5.0),
+                    line((0, 0, 255), 4.0),
+                    line((255, 0, 255), 2.5),
+                    line((0, 0, 0), 3.0),
+                    line((255, 120, 0), 5.0),
+                    line((120, 0, 160), 3.0),
+                    line((255, 255, 0), 6.0),
+                ],
+                waypoint_color: (255, 0, 255),
+                waypoint_size: 11.0,
+                marker_size: 10.0,
+                poi_size: 8.0,
+                label_halo: 3.0,
+            },
+            _ => {
+                return Err(format!("Unknown symbology '{}'. Expected one of \
+                                    {}", name, SYMBOLOGY_PRESETS.join(", ")));
+            },
+        };
+
+        Ok(s)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let size = || value.parse::<f32>().ok()
+            .filter(|v| *v >= 0.0)
+            .ok_or(format!("Invalid size '{}'", value));
+
+        match key {
+            "waypoint.color" => self.waypoint_color = parse_color(value)?,
+            "waypoint.size" => self.waypoint_size = size()?,
+            "marker.size" => self.marker_size = size()?,
+            "poi.size" => self.poi_size = size()?,
+            "label.halo" => self.label_halo = size()?,
+            _ => {
+                let (name, attr) = key.split_once('.')
+                    .ok_or(format!("Unknown symbol setting '{}'", key))?;
+                let layer = LINE_LAYERS.iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, l)| *l)
+                    .ok_or(format!("Unknown layer '{}'", name))?;
+                let style = &mut self.lines[layer as usize];
+
+                match attr {
+                    "color" => style.color = parse_color(value)?,
+                    "width" => style.width = size()?,
+                    _ => {
+                        return Err(format!("Unknown symbol setting '{}'",
+                                           key));
+                    },
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    pub fn line(&self, layer: LineLayer) -> LineStyle {
+        self.lines[layer as usize]
+    }
+}
+
+// Color given as #rrggbb
+fn parse_color(value: &str) -> Result<Rgb, String> {
+    let err = || format!("Invalid color '{}', expected #rrggbb", value);
+    let hex = value.strip_prefix('#').ok_or_else(err)?;
+
+    if hex.len() != 6 {
+        return Err(err());
+    }
+
+    let channel = |i: usize| hex.get(i..i + 2)
+        .and_then(|h| u8::from_str_radix(h, 16).ok())
+        .ok_or_else(err);
+
+    Ok((channel(0)?, channel(2)?, channel(4)?))
+}
@@ -0,0 +1,170 @@
+use crate::barrier::Barrier;
+use crate::elevation::ElevationSource;
+
+use hoydedata::Coord;
+
+pub const TERRAINS: [&str; 4] = ["plane", "ridge", "valley", "fractal"];
+
+// Elevation of the terrain origin (meters)
+const BASE_HEIGHT: f32 = 500.0;
+// Height and half width of the ridge (meters)
+const RIDGE_HEIGHT: f32 = 300.0;
+const RIDGE_WIDTH: f32 = 500.0;
+// Depth and half width of the valley (meters)
+const VALLEY_DEPTH: f32 = 300.0;
+const VALLEY_WIDTH: f32 = 800.0;
+// Half length of the river, and width of the bridge across it (meters)
+const RIVER_LENGTH: f32 = 10000.0;
+const BRIDGE_WIDTH: f32 = 20.0;
+// Height and wavelength of the largest fractal octave (meters)
+const FRACTAL_HEIGHT: f32 = 200.0;
+const FRACTAL_WAVELENGTH: f32 = 2000.0;
+const FRACTAL_OCTAVES: usize = 6;
+// Distance used for numerical gradients (meters)
+const GRADIENT_STEP: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TerrainKind {
+    // Flat terrain
+    Plane,
+    // A ridge running north-south through the origin
+    Ridge,
+    // A valley running north-south through the origin, with a river along
+    // the valley floor. The river is bridged at the origin.
+    Valley,
+    // Random hills, reproducible from the seed
+    Fractal,
+}
+
+// Synthetic terrain given by a formula, for testing the algorithms
+// deterministically without real map data. The terrain is laid out
+// relative to an origin coordinate.
+pub struct SyntheticTerrain {
+    pub kind: TerrainKind,
+    pub origin: Coord,
+    pub seed: u64,
+}
+
+impl SyntheticTerrain {
+    pub fn new(name: &str, origin: Coord, seed: u64) -> Result<Self, String> {
+        let kind = match name {
+            "plane" => TerrainKind::Plane,
+            "ridge" => TerrainKind::Ridge,
+            "valley" => TerrainKind::Valley,
+            "fractal" => TerrainKind::Fractal,
+            _ => {
+                return Err(format!("Unknown terrain '{}'. Expected one of {}",
+                                   name, TERRAINS.join(", ")));
+            },
+        };
+
+        Ok(Self {
+            kind: kind,
+            origin: origin,
+            seed: seed,
+        })
+    }
+
+    // Barriers belonging to the terrain
+    pub fn barriers(&self) -> Vec<Barrier> {
+        if self.kind != TerrainKind::Valley {
+            return vec![];
+        }
+
+        let o = self.origin;
+        let gap = BRIDGE_WIDTH/2.0;
+
+        vec![
+            Barrier::from_vec(vec![o + Coord::new(0.0, -RIVER_LENGTH),
+                                   o + Coord::new(0.0, -gap)]),
+            Barrier::from_vec(vec![o + Coord::new(0.0, gap),
+                                   o + Coord::new(0.0, RIVER_LENGTH)]),
+        ]
+    }
+
+    fn height(&self, c: &Coord) -> f32 {
+        let x = c.e - self.origin.e;
+        let y = c.n - self.origin.n;
+
+        match self.kind {
+            TerrainKind::Plane => BASE_HEIGHT,
+            TerrainKind::Ridge => {
+                let r = x/RIDGE_WIDTH;
+                BASE_HEIGHT + RIDGE_HEIGHT*(-r*r).exp()
+            },
+            TerrainKind::Valley => {
+                let r = x/VALLEY_WIDTH;
+                BASE_HEIGHT - VALLEY_DEPTH*(-r*r).exp()
+            },
+            TerrainKind::Fractal => BASE_HEIGHT + self.fractal(x, y),
+        }
+    }
+
+    // Sum of value noise octaves, each half the wavelength and height of
+    // the previous one
+    fn fractal(&self, x: f32, y: f32) -> f32 {
+        let mut h = 0.0;
+        let mut amplitude = FRACTAL_HEIGHT;
+        let mut wavelength = FRACTAL_WAVELENGTH;
+
+        for octave in 0..FRACTAL_OCTAVES {
+            h += amplitude*self.value_noise(x/wavelength, y/wavelength,
+                                            octave as u64);
+            amplitude *= 0.5;
+            wavelength *= 0.5;
+        }
+
+        h
+    }
+
+    // Smoothly interpolated random values (-1..1) at integer grid points
+    fn value_noise(&self, x: f32, y: f32, octave: u64) -> f32 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        // Smoothstep, for continuous gradients at the grid lines
+        let (sx, sy) = (fx*fx*(3.0 - 2.0*fx), fy*fy*(3.0 - 2.0*fy));
+        let (ix, iy) = (x0 as i64, y0 as i64);
+
+        let v00 = self.random(ix, iy, octave);
+        let v10 = self.random(ix + 1, iy, octave);
+        let v01 = self.random(ix, iy + 1, octave);
+        let v11 = self.random(ix + 1, iy + 1, octave);
+
+        let v0 = v00 + (v10 - v00)*sx;
+        let v1 = v01 + (v11 - v01)*sx;
+
+        v0 + (v1 - v0)*sy
+    }
+
+    // Random value (-1..1) for a grid point, from a hash of the seed
+    fn random(&self, ix: i64, iy: i64, octave: u64) -> f32 {
+        let mut h = self.seed
+            ^ (ix as u64).wrapping_mul(0x9e3779b97f4a7c15)
+            ^ (iy as u64).wrapping_mul(0xc2b2ae3d27d4eb4f)
+            ^ octave.wrapping_mul(0x165667b19e3779f9);
+
+        // splitmix64 finalizer
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+        h ^= h >> 31;
+
+        ((h >> 40) as f32)/((1u64 << 23) as f32) - 1.0
+    }
+}
+
+impl ElevationSource for SyntheticTerrain {
+    fn lookup(&self, c: &Coord) -> Option<f32> {
+        Some(self.height(c))
+    }
+
+    fn lookup_with_gradient(&self, c: &Coord) -> Option<(f32, f32, f32)> {
+        let d = GRADIENT_STEP;
+        let de = Coord::new(d, 0.0);
+        let dn = Coord::new(0.0, d);
+
+        let dx = (self.height(&(*c + de)) - self.height(&(*c - de)))/(2.0*d);
+        let dy = (self.height(&(*c + dn)) - self.height(&(*c - dn)))/(2.0*d);
+
+        Some((self.height(c), dx, dy))
+    }
+}
@@ -0,0 +1,100 @@
+use serde_json::{Map, Value};
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+
+pub const UPLOAD_SERVICES: [&str; 1] = ["komoot"];
+
+// Upload endpoints of the services
+const KOMOOT_URL: &str = "https://api.komoot.de/v007/tours/";
+
+// OAuth tokens are stored by service in ~/.config/stivalg/tokens.json
+fn token_dir() -> Result<String, String> {
+    let home = env::var("HOME")
+        .map_err(|_| "HOME is not set, unable to find tokens".to_string())?;
+
+    Ok(format!("{}/.config/stivalg", home))
+}
+
+fn read_tokens() -> Result<Map<String, Value>, String> {
+    let fname = format!("{}/tokens.json", token_dir()?);
+
+    match fs::read_to_string(&fname) {
+        Ok(data) => serde_json::from_str(&data)
+            .map_err(|e| format!("Invalid {}: {}", fname, e)),
+        Err(_) => Ok(Map::new()),
+    }
+}
+
+fn check_service(service: &str) -> Result<(), String> {
+    if UPLOAD_SERVICES.contains(&service) {
+        Ok(())
+    }
+    // Uploads to Strava become recorded activities, not planned routes
+    else if service == "strava" {
+        Err("Uploading routes to strava is not supported".to_string())
+    }
+    else {
+        Err(format!("Unknown service '{}'. Expected one of {}", service,
+                    UPLOAD_SERVICES.join(", ")))
+    }
+}
+
+// Store the OAuth access token of a service
+pub fn store_token(service: &str, token: &str) -> Result<(), String> {
+    check_service(service)?;
+
+    let dir = token_dir()?;
+    let fname = format!("{}/tokens.json", dir);
+    let mut tokens = read_tokens()?;
+    tokens.insert(service.to_string(), Value::String(token.to_string()));
+
+    fs::create_dir_all(&dir)
+        .map_err(|e| format!("Unable to create {}: {}", dir, e))?;
+
+    // The tokens are only readable by the user
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        options.mode(0o600);
+        if fs::metadata(&fname).is_ok() {
+            fs::set_permissions(&fname, fs::Permissions::from_mode(0o600))
+                .map_err(|e| format!("Unable to protect {}: {}", fname, e))?;
+        }
+    }
+
+    options.open(&fname)
+        .and_then(|mut f| f.write_all(Value::Object(tokens).to_string()
+                                      .as_bytes()))
+        .map_err(|e| format!("Unable to write {}: {}", fname, e))
+}
+
+// Upload a GPX track to a service as a route. Returns the response of the
+// service.
+pub fn upload_gpx(service: &str, gpx: &[u8], name: &str)
+                  -> Result<String, String> {
+    check_service(service)?;
+
+    let tokens = read_tokens()?;
+    let Some(token) = tokens.get(service).and_then(|t| t.as_str()) else {
+        return Err(format!("No token for {}. Store one with 'upload token \
+                            {} <token>'", service, service));
+    };
+    let auth = format!("Bearer {}", token);
+
+    let response = ureq::post(KOMOOT_URL)
+        .query("data_type", "gpx")
+        .query("name", name)
+        .set("Authorization", &auth)
+        .set("Content-Type", "application/gpx+xml")
+        .send_bytes(gpx)
+        .map_err(|e| format!("Upload to {} failed: {}", service, e))?;
+    let mut text = String::new();
+    response.into_reader().read_to_string(&mut text)
+        .map_err(|e| format!("Upload to {} failed: {}", service, e))?;
+
+    Ok(text)
+}